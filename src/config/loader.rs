@@ -1,4 +1,4 @@
-use super::models::Config;
+use super::models::{ApiKeyConfig, Config};
 use crate::types::Result;
 use crate::types::ProxyError;
 use std::fs;
@@ -12,7 +12,7 @@ pub fn load_config<P: AsRef<Path>>(path: P) -> Result<Config> {
     let expanded = expand_env_vars(&content);
 
     // Try YAML first, then JSON
-    let config: Config = if path.as_ref().extension().and_then(|s| s.to_str()) == Some("json") {
+    let mut config: Config = if path.as_ref().extension().and_then(|s| s.to_str()) == Some("json") {
         serde_json::from_str(&expanded)
             .map_err(|e| ProxyError::Config(format!("Failed to parse JSON config: {}", e)))?
     } else {
@@ -20,6 +20,11 @@ pub fn load_config<P: AsRef<Path>>(path: P) -> Result<Config> {
             .map_err(|e| ProxyError::Config(format!("Failed to parse YAML config: {}", e)))?
     };
 
+    if let Some(keys_file) = config.auth.keys_file.clone() {
+        config.auth.keys.extend(load_keys_file(&keys_file)?);
+    }
+    config.clamp_retry_backoff();
+
     // Validate configuration
     config
         .validate()
@@ -28,6 +33,14 @@ pub fn load_config<P: AsRef<Path>>(path: P) -> Result<Config> {
     Ok(config)
 }
 
+/// Client API keys kept outside the main config: a YAML or JSON list of keys
+fn load_keys_file(path: &str) -> Result<Vec<ApiKeyConfig>> {
+    let content = fs::read_to_string(path)
+        .map_err(|e| ProxyError::Config(format!("Failed to read keys file '{}': {}", path, e)))?;
+    serde_yaml::from_str(&expand_env_vars(&content))
+        .map_err(|e| ProxyError::Config(format!("Failed to parse keys file '{}': {}", path, e)))
+}
+
 fn expand_env_vars(content: &str) -> String {
     let mut result = content.to_string();
 
@@ -102,4 +115,37 @@ mod tests {
         let output = expand_env_vars(input);
         assert_eq!(output, "key: ");
     }
+
+    #[test]
+    fn test_keys_file_adds_client_keys() {
+        let dir = std::env::temp_dir().join(format!("llm-proxy-keys-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let keys_file = dir.join("keys.yaml");
+        std::fs::write(
+            &keys_file,
+            "- name: batch\n  key: batch-key\n  allowed_models: [\"gpt-*\"]\n  metadata: {team: data}\n",
+        )
+        .unwrap();
+        let config_file = dir.join("config.yaml");
+        std::fs::write(
+            &config_file,
+            format!(
+                "server: {{}}\nmodels:\n  gpt-4:\n    backend_type: openai\n    endpoint: http://localhost/v1\nauth:\n  keys_file: {}\n  keys:\n    - name: app\n      key: app-key\n      allowed_models: [gpt-4]\n",
+                keys_file.display()
+            ),
+        )
+        .unwrap();
+
+        let config = load_config(&config_file).unwrap();
+        let names: Vec<_> = config.auth.keys.iter().map(|k| k.name.as_str()).collect();
+        assert_eq!(names, vec!["app", "batch"]);
+        assert_eq!(config.auth.keys[1].metadata["team"], "data");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_example_config_is_valid() {
+        let config = load_config(concat!(env!("CARGO_MANIFEST_DIR"), "/config/example-config.yaml"));
+        assert!(config.is_ok(), "{:?}", config.err());
+    }
 }
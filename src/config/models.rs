@@ -1,3 +1,5 @@
+use crate::types::{Protocol, TokenUsage};
+use base64::Engine;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::time::Duration;
@@ -8,6 +10,21 @@ pub struct Config {
     #[serde(default)]
     pub logging: LoggingConfig,
     pub models: HashMap<String, ModelConfig>,
+    /// Content-based routing rules, evaluated in order before model lookup
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub routing_rules: Vec<RoutingRule>,
+    /// How requests are assigned a priority class for queueing
+    #[serde(default)]
+    pub priority: PriorityConfig,
+    /// Client API keys; once any key is configured, requests without a valid key are rejected
+    #[serde(default)]
+    pub auth: AuthConfig,
+    /// Backend accounts shared by several models, by name
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub accounts: HashMap<String, AccountConfig>,
+    /// Spend tracking and budgets, priced with each model's `pricing`
+    #[serde(default)]
+    pub spend: SpendConfig,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -16,6 +33,19 @@ pub struct ServerConfig {
     pub host: String,
     #[serde(default = "default_port")]
     pub port: u16,
+    /// How long in-flight requests and streams may run after SIGTERM/SIGINT before
+    /// they are cut off
+    #[serde(default = "default_drain_timeout_seconds")]
+    pub drain_timeout_seconds: u64,
+    /// Serve HTTPS instead of plain HTTP
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>,
+}
+
+impl ServerConfig {
+    pub fn drain_timeout(&self) -> Duration {
+        Duration::from_secs(self.drain_timeout_seconds)
+    }
 }
 
 fn default_host() -> String {
@@ -26,6 +56,34 @@ fn default_port() -> u16 {
     8080
 }
 
+fn default_drain_timeout_seconds() -> u64 {
+    30
+}
+
+/// Certificate and key of the listener, and the CA client certificates are checked against
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TlsConfig {
+    /// PEM certificate chain, leaf first
+    pub cert_path: String,
+    /// PEM private key (PKCS#8, PKCS#1 or SEC1)
+    pub key_path: String,
+    /// PEM CA certificates client certificates must chain to; enables mTLS
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_ca_path: Option<String>,
+    /// Reject connections without a client certificate. When false, clients without one
+    /// are let through and authenticate with a key or token instead.
+    #[serde(default = "default_true")]
+    pub require_client_cert: bool,
+    /// How often the files are checked for changes; changed files are loaded without a
+    /// restart, for new connections
+    #[serde(default = "default_tls_reload_interval_seconds")]
+    pub reload_interval_seconds: u64,
+}
+
+fn default_tls_reload_interval_seconds() -> u64 {
+    10
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct LoggingConfig {
     #[serde(default = "default_true")]
@@ -60,7 +118,14 @@ fn default_log_level() -> String {
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ModelConfig {
     pub backend_type: BackendType,
+    /// Single upstream URL; may be omitted when `endpoints` is set
+    #[serde(default)]
     pub endpoint: String,
+    /// Upstream replicas/accounts serving this model; takes precedence over `endpoint`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub endpoints: Vec<EndpointConfig>,
+    #[serde(default)]
+    pub load_balancing: LoadBalancing,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub api_key: Option<String>,
     /// Optional: The actual model name to send to the backend
@@ -69,14 +134,70 @@ pub struct ModelConfig {
     pub target_model: Option<String>,
     #[serde(default = "default_timeout")]
     pub timeout_seconds: u64,
+    /// Per-phase timeouts and the overall request deadline
+    #[serde(default)]
+    pub timeouts: TimeoutConfig,
     #[serde(default)]
     pub retry: RetryConfig,
+    /// Limit on simultaneous upstream requests per endpoint, with a bounded wait queue
+    #[serde(default)]
+    pub concurrency: ConcurrencyConfig,
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerConfig,
+    #[serde(default)]
+    pub health_check: HealthCheckConfig,
+    #[serde(default)]
+    pub hedging: HedgingConfig,
     #[serde(default = "default_true")]
     pub ssl_verify: bool,
+    /// Trusted CAs, client certificate, key pinning and minimum TLS version for the
+    /// model's endpoints
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<UpstreamTlsConfig>,
+    /// Outbound proxy for the model's endpoints. Without one, connections are direct
+    /// even when proxy environment variables are set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<OutboundProxyConfig>,
     #[serde(default)]
     pub headers: HeaderConfig,
     #[serde(default)]
     pub transforms: TransformConfig,
+    /// Replace personal data in message content with placeholders before it is sent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pii: Option<PiiConfig>,
+    /// Models to try, in order, when this model still fails after its own retries.
+    /// Each entry must be another key of the models map.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub fallbacks: Vec<String>,
+    /// Weighted backends sharing this model name (traffic splitting / canaries).
+    /// Each variant inherits every setting it does not override.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub variants: Vec<VariantConfig>,
+    /// How requests are assigned to variants
+    #[serde(default)]
+    pub sticky: StickyKey,
+    /// Mirror a sample of this model's traffic to a candidate model
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub shadow: Option<ShadowConfig>,
+    /// Total tokens (prompt plus output) the model accepts; unset means unlimited
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context_window: Option<usize>,
+    /// Most output tokens the model generates in one response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<usize>,
+    /// Larger-context model to route requests to when they won't fit `context_window`.
+    /// Without one, such requests are rejected.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub overflow_model: Option<String>,
+    /// Rate limits on this model across all clients
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Backend account (a key of `accounts`) whose limits this model shares with others
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub account: Option<String>,
+    /// Token prices used to compute the cost of each response
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pricing: Option<PricingConfig>,
 }
 
 fn default_timeout() -> u64 {
@@ -84,10 +205,123 @@ fn default_timeout() -> u64 {
 }
 
 impl ModelConfig {
+    /// A model at one endpoint with every other setting at its default, for tests to
+    /// override with `..ModelConfig::test(...)`
+    #[cfg(test)]
+    pub(crate) fn test(backend_type: BackendType, endpoint: impl Into<String>) -> Self {
+        ModelConfig {
+            backend_type,
+            endpoint: endpoint.into(),
+            endpoints: Vec::new(),
+            load_balancing: LoadBalancing::default(),
+            api_key: None,
+            target_model: None,
+            timeout_seconds: default_timeout(),
+            timeouts: TimeoutConfig::default(),
+            retry: RetryConfig::default(),
+            concurrency: ConcurrencyConfig::default(),
+            circuit_breaker: CircuitBreakerConfig::default(),
+            health_check: HealthCheckConfig::default(),
+            hedging: HedgingConfig::default(),
+            ssl_verify: true,
+            tls: None,
+            proxy: None,
+            headers: HeaderConfig::default(),
+            transforms: TransformConfig::default(),
+            pii: None,
+            fallbacks: Vec::new(),
+            variants: Vec::new(),
+            sticky: StickyKey::default(),
+            shadow: None,
+            context_window: None,
+            max_output_tokens: None,
+            overflow_model: None,
+            rate_limit: None,
+            account: None,
+            pricing: None,
+        }
+    }
+
     pub fn timeout_duration(&self) -> Duration {
         Duration::from_secs(self.timeout_seconds)
     }
 
+    pub fn connect_timeout(&self) -> Duration {
+        Duration::from_millis(self.timeouts.connect_ms)
+    }
+
+    pub fn first_byte_timeout(&self) -> Duration {
+        self.timeouts
+            .first_byte_ms
+            .map_or_else(|| self.timeout_duration(), Duration::from_millis)
+    }
+
+    pub fn idle_stream_timeout(&self) -> Duration {
+        self.timeouts
+            .idle_stream_ms
+            .map_or_else(|| self.timeout_duration(), Duration::from_millis)
+    }
+
+    pub fn total_timeout(&self) -> Option<Duration> {
+        self.timeouts.total_ms.map(Duration::from_millis)
+    }
+
+    pub fn deadline(&self) -> Option<Duration> {
+        self.timeouts.deadline_ms.map(Duration::from_millis)
+    }
+
+    /// Upstream endpoints for this model, with the model-level API key and
+    /// concurrency limit applied to endpoints that do not set their own
+    pub fn resolved_endpoints(&self) -> Vec<EndpointConfig> {
+        if self.endpoints.is_empty() {
+            return vec![EndpointConfig {
+                url: self.endpoint.clone(),
+                api_key: self.api_key.clone(),
+                weight: default_weight(),
+                max_concurrent: self.concurrency.max_concurrent,
+            }];
+        }
+
+        self.endpoints
+            .iter()
+            .map(|endpoint| EndpointConfig {
+                api_key: endpoint.api_key.clone().or_else(|| self.api_key.clone()),
+                max_concurrent: endpoint.max_concurrent.or(self.concurrency.max_concurrent),
+                ..endpoint.clone()
+            })
+            .collect()
+    }
+
+    fn has_endpoint(&self) -> bool {
+        !self.endpoint.is_empty() || !self.endpoints.is_empty()
+    }
+
+    /// Effective configuration of one variant: this model with the variant's overrides
+    pub fn variant_config(&self, variant: &VariantConfig) -> ModelConfig {
+        let mut config = self.clone();
+        config.variants = Vec::new();
+        if let Some(backend_type) = &variant.backend_type {
+            config.backend_type = backend_type.clone();
+        }
+        if !variant.endpoint.is_empty() || !variant.endpoints.is_empty() {
+            config.endpoint = variant.endpoint.clone();
+            config.endpoints = variant.endpoints.clone();
+        }
+        if variant.api_key.is_some() {
+            config.api_key = variant.api_key.clone();
+        }
+        if variant.target_model.is_some() {
+            config.target_model = variant.target_model.clone();
+        }
+        if let Some(headers) = &variant.headers {
+            config.headers = headers.clone();
+        }
+        if let Some(transforms) = &variant.transforms {
+            config.transforms = transforms.clone();
+        }
+        config
+    }
+
     /// Get the target model name to send to the backend
     /// If target_model is specified, use that; otherwise use the incoming model name
     pub fn get_target_model<'a>(&'a self, incoming_model: &'a str) -> &'a str {
@@ -95,6 +329,424 @@ impl ModelConfig {
     }
 }
 
+/// How connections to a model's endpoints are verified and authenticated
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UpstreamTlsConfig {
+    /// PEM CA certificates trusted in place of the public roots
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_bundle: Option<String>,
+    /// PEM certificate chain presented to endpoints that require client certificates
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_cert: Option<String>,
+    /// PEM private key of `client_cert`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_key: Option<String>,
+    /// Base64 SHA-256 digests of SubjectPublicKeyInfo. The chain an endpoint presents
+    /// must contain a certificate with one of these keys, on top of being trusted.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pinned_spki_sha256: Vec<String>,
+    #[serde(default)]
+    pub min_version: TlsVersion,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub enum TlsVersion {
+    #[default]
+    #[serde(rename = "1.2")]
+    Tls12,
+    #[serde(rename = "1.3")]
+    Tls13,
+}
+
+/// HTTP or SOCKS proxy a model's connections go through
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct OutboundProxyConfig {
+    /// `http://`, `https://`, `socks5://` or `socks5h://` (names resolved by the proxy)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(default, skip_serializing)]
+    pub password: Option<String>,
+    /// Hosts connected to directly: domains (with their subdomains), IPs, CIDR blocks,
+    /// or `*`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub no_proxy: Vec<String>,
+    /// Use the proxies in `HTTP_PROXY`, `HTTPS_PROXY`, `ALL_PROXY` and `NO_PROXY`
+    /// instead of `url`
+    #[serde(default)]
+    pub from_env: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EndpointConfig {
+    pub url: String,
+    /// Overrides the model-level `api_key` for this endpoint
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    /// Relative share of traffic for `weighted_random` balancing
+    #[serde(default = "default_weight")]
+    pub weight: u32,
+    /// Overrides the model-level `concurrency.max_concurrent` for this endpoint
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent: Option<usize>,
+}
+
+fn default_weight() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LoadBalancing {
+    #[default]
+    RoundRobin,
+    WeightedRandom,
+    LeastInFlight,
+    EwmaLatency,
+}
+
+/// Scheduling class of a request. Higher classes leave concurrency queues first.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Priority {
+    Low,
+    #[default]
+    Normal,
+    High,
+}
+
+impl Priority {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Priority::Low => "low",
+            Priority::Normal => "normal",
+            Priority::High => "high",
+        }
+    }
+}
+
+/// Where a request's priority comes from: the priority header, capped at the client's
+/// maximum, then the client's default. Clients without a `priority` of their own get
+/// `default` and `max` from here.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PriorityConfig {
+    #[serde(default = "default_priority_header")]
+    pub header: String,
+    #[serde(default)]
+    pub default: Priority,
+    /// Highest class the header may ask for; defaults to `default`, so the header can
+    /// only lower it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<Priority>,
+}
+
+impl Default for PriorityConfig {
+    fn default() -> Self {
+        Self {
+            header: default_priority_header(),
+            default: Priority::default(),
+            max: None,
+        }
+    }
+}
+
+impl PriorityConfig {
+    /// The policy of clients that set none
+    pub fn client(&self) -> ClientPriority {
+        ClientPriority {
+            default: self.default,
+            max: self.max,
+        }
+    }
+}
+
+/// Priority classes one client may use
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ClientPriority {
+    /// Class of the client's requests that do not send the priority header
+    #[serde(default)]
+    pub default: Priority,
+    /// Highest class the header may ask for; defaults to `default`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max: Option<Priority>,
+}
+
+impl ClientPriority {
+    pub fn max(&self) -> Priority {
+        self.max.unwrap_or(self.default)
+    }
+}
+
+fn default_priority_header() -> String {
+    "x-llm-proxy-priority".to_string()
+}
+
+/// Virtual API keys clients present as `Authorization: Bearer` or `x-api-key`
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AuthConfig {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub keys: Vec<ApiKeyConfig>,
+    /// YAML or JSON file with a list of further keys, read at startup
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keys_file: Option<String>,
+    /// Bearer JWTs accepted alongside (or instead of) the keys
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jwt: Option<JwtConfig>,
+    /// Clients identified by their TLS client certificate (`server.tls.client_ca_path`)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub client_certs: Vec<ClientCertConfig>,
+}
+
+impl AuthConfig {
+    pub fn is_enabled(&self) -> bool {
+        !self.keys.is_empty()
+            || self.keys_file.is_some()
+            || self.jwt.is_some()
+            || !self.client_certs.is_empty()
+    }
+}
+
+/// Permissions of the client presenting a certificate with a given subject
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ClientCertConfig {
+    /// Subject common name (`svc-search`) or the whole subject (`O=Acme, CN=svc-search`).
+    /// Also names the client in logs, rate limits and budgets.
+    pub subject: String,
+    pub allowed_models: Vec<String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub aliases: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub metadata: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimitConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<ClientPriority>,
+}
+
+impl ClientCertConfig {
+    /// The certificate's permissions in the form client keys have them
+    pub fn client(&self) -> ApiKeyConfig {
+        ApiKeyConfig {
+            name: self.subject.clone(),
+            key: String::new(),
+            allowed_models: self.allowed_models.clone(),
+            aliases: self.aliases.clone(),
+            metadata: self.metadata.clone(),
+            rate_limit: self.rate_limit.clone(),
+            priority: self.priority,
+        }
+    }
+}
+
+/// Prefix of the client names of JWT clients, so a token's subject cannot take on a
+/// configured key's or certificate's rate limits and budgets
+pub const JWT_CLIENT_PREFIX: &str = "jwt:";
+
+/// Validation of bearer JWTs and the mapping of their claims to a client's permissions
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct JwtConfig {
+    /// Local JWKS file with the signing keys
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jwks_file: Option<String>,
+    /// JWKS endpoint with the signing keys, fetched on first use
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jwks_url: Option<String>,
+    /// How long fetched or loaded signing keys are used before being read again.
+    /// Tokens signed with an unknown key also trigger a refresh.
+    #[serde(default = "default_jwks_refresh_seconds")]
+    pub jwks_refresh_seconds: u64,
+    /// Required `iss`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub issuer: Option<String>,
+    /// Accepted `aud` values; empty accepts any audience
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub audience: Vec<String>,
+    /// Accepted signing algorithms
+    #[serde(default = "default_jwt_algorithms")]
+    pub algorithms: Vec<String>,
+    /// Clock skew tolerated on `exp` and `nbf`
+    #[serde(default = "default_jwt_leeway_seconds")]
+    pub leeway_seconds: u64,
+    /// Claim naming the client in logs, rate limits and spend
+    #[serde(default = "default_identity_claim")]
+    pub identity_claim: String,
+    /// Claim with the client's groups, a string or a list of strings
+    #[serde(default = "default_groups_claim")]
+    pub groups_claim: String,
+    /// Client metadata taken from claims, e.g. `team: department` for team budgets
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub metadata_claims: HashMap<String, String>,
+    /// Models every valid token may use, in addition to those of its groups
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_models: Vec<String>,
+    /// Permissions granted by group membership
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub groups: Vec<JwtGroupConfig>,
+}
+
+/// Models and rate limits of the clients in one group
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct JwtGroupConfig {
+    pub group: String,
+    #[serde(default)]
+    pub allowed_models: Vec<String>,
+    /// Rate limits of each client in the group (not shared by the group). Clients in
+    /// several groups get the limits of the first listed group that sets them.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Priority classes of clients in the group, taken from the first listed group
+    /// that sets them
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<ClientPriority>,
+}
+
+fn default_jwks_refresh_seconds() -> u64 {
+    3600
+}
+
+fn default_jwt_algorithms() -> Vec<String> {
+    vec!["RS256".to_string()]
+}
+
+fn default_jwt_leeway_seconds() -> u64 {
+    60
+}
+
+fn default_identity_claim() -> String {
+    "sub".to_string()
+}
+
+fn default_groups_claim() -> String {
+    "groups".to_string()
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ApiKeyConfig {
+    /// Identifies the key in logs in place of the key itself
+    pub name: String,
+    #[serde(skip_serializing)]
+    pub key: String,
+    /// Models the key may request: names, `prefix*` patterns or `*` for all
+    pub allowed_models: Vec<String>,
+    /// Requested model names resolved to a different model for this key
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub aliases: HashMap<String, String>,
+    /// Free-form attributes such as `team` and `owner`
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub metadata: HashMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit: Option<RateLimitConfig>,
+    /// Priority classes the client may use, in place of the global `priority` ones
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<ClientPriority>,
+}
+
+impl ApiKeyConfig {
+    /// Whether the key may request `model` (the name the client sent)
+    pub fn allows_model(&self, model: &str) -> bool {
+        self.allowed_models.iter().any(|pattern| match pattern.strip_suffix('*') {
+            Some(prefix) => model.starts_with(prefix),
+            None => pattern == model,
+        })
+    }
+
+    /// The model a request for `model` is served by for this key
+    pub fn resolve_alias<'a>(&'a self, model: &'a str) -> &'a str {
+        self.aliases.get(model).map(String::as_str).unwrap_or(model)
+    }
+}
+
+impl std::fmt::Debug for ApiKeyConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ApiKeyConfig")
+            .field("name", &self.name)
+            .field("key", &"[REDACTED]")
+            .field("allowed_models", &self.allowed_models)
+            .field("aliases", &self.aliases)
+            .field("metadata", &self.metadata)
+            .field("rate_limit", &self.rate_limit)
+            .field("priority", &self.priority)
+            .finish()
+    }
+}
+
+/// Token-bucket rate limits. Each bucket holds one minute's allowance and refills
+/// continuously, so short bursts up to the full allowance are accepted.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub requests_per_minute: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_tokens_per_minute: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_tokens_per_minute: Option<u64>,
+}
+
+/// A provider account (API key or organization) whose limits span several models
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AccountConfig {
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+}
+
+/// Token prices in USD per million tokens
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PricingConfig {
+    #[serde(default)]
+    pub input_per_million: f64,
+    #[serde(default)]
+    pub output_per_million: f64,
+    /// Price of prompt tokens read from the backend's prompt cache; unset means the input price
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cached_input_per_million: Option<f64>,
+}
+
+impl PricingConfig {
+    /// Cost of a response's usage in USD
+    pub fn cost(&self, usage: &TokenUsage) -> f64 {
+        let cached = usage.cached_input_tokens.min(usage.input_tokens);
+        let cached_price = self.cached_input_per_million.unwrap_or(self.input_per_million);
+        ((usage.input_tokens - cached) as f64 * self.input_per_million
+            + cached as f64 * cached_price
+            + usage.output_tokens as f64 * self.output_per_million)
+            / 1_000_000.0
+    }
+}
+
+/// Where spend is persisted and the budgets enforced on it
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SpendConfig {
+    /// JSON file the spend of the current day and month is kept in across restarts;
+    /// unset keeps it in memory only
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub store: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub budgets: Vec<BudgetConfig>,
+}
+
+/// A spend limit on one client key, team (the `team` metadata of client keys) or
+/// model. Exactly one of `key`, `team` and `model` is set.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BudgetConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub team: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    /// USD per UTC day
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily_usd: Option<f64>,
+    /// USD per UTC calendar month
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monthly_usd: Option<f64>,
+    /// Cheaper model to serve requests with once the budget is spent. Without one,
+    /// requests are rejected until the window resets.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub downgrade_to: Option<String>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 #[serde(rename_all = "lowercase")]
 pub enum BackendType {
@@ -103,6 +755,24 @@ pub enum BackendType {
     Ollama,
 }
 
+impl BackendType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            BackendType::OpenAI => "openai",
+            BackendType::Anthropic => "anthropic",
+            BackendType::Ollama => "ollama",
+        }
+    }
+
+    /// Wire protocol spoken by this backend (Ollama is driven through its OpenAI-compatible API)
+    pub fn protocol(&self) -> Protocol {
+        match self {
+            BackendType::OpenAI | BackendType::Ollama => Protocol::OpenAI,
+            BackendType::Anthropic => Protocol::Anthropic,
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct RetryConfig {
     #[serde(default = "default_max_attempts")]
@@ -111,6 +781,21 @@ pub struct RetryConfig {
     pub backoff_ms: u64,
     #[serde(default = "default_max_backoff_ms")]
     pub max_backoff_ms: u64,
+    /// Upstream response statuses that are retried
+    #[serde(default = "default_retryable_statuses")]
+    pub retryable_statuses: Vec<u16>,
+    /// Upstream error types (`error.type` or `error.code` in the response body) that
+    /// are retried whatever the status
+    #[serde(default = "default_retryable_error_types")]
+    pub retryable_error_types: Vec<String>,
+    #[serde(default = "default_true")]
+    pub retry_on_timeout: bool,
+    /// Retry a streaming response that fails before its first chunk arrives
+    #[serde(default = "default_true")]
+    pub retry_streams_before_first_byte: bool,
+    /// Retries allowed for one client request across its fallback models and hedges
+    #[serde(default)]
+    pub budget: Option<usize>,
 }
 
 impl Default for RetryConfig {
@@ -119,6 +804,11 @@ impl Default for RetryConfig {
             max_attempts: 3,
             backoff_ms: 1000,
             max_backoff_ms: 10000,
+            retryable_statuses: default_retryable_statuses(),
+            retryable_error_types: default_retryable_error_types(),
+            retry_on_timeout: true,
+            retry_streams_before_first_byte: true,
+            budget: None,
         }
     }
 }
@@ -135,117 +825,1892 @@ fn default_max_backoff_ms() -> u64 {
     10000
 }
 
+fn default_retryable_statuses() -> Vec<u16> {
+    // 529 is Anthropic's "overloaded"
+    vec![429, 500, 502, 503, 504, 529]
+}
+
+fn default_retryable_error_types() -> Vec<String> {
+    vec!["overloaded_error".to_string()]
+}
+
+/// Per-endpoint circuit breaker settings (opt-in)
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct HeaderConfig {
-    #[serde(default)]
-    pub mode: HeaderMode,
-    #[serde(default)]
-    pub force: HashMap<String, String>,
+pub struct CircuitBreakerConfig {
     #[serde(default)]
-    pub add: HashMap<String, String>,
-    #[serde(default)]
-    pub drop: Vec<String>,
+    pub enabled: bool,
+    /// Open after this many failures in a row
+    #[serde(default = "default_consecutive_failures")]
+    pub consecutive_failures: u32,
+    /// Open when the failure ratio over the window reaches this value (0.0-1.0)
+    #[serde(default = "default_error_rate_threshold")]
+    pub error_rate_threshold: f64,
+    /// Number of most recent requests considered for the error rate
+    #[serde(default = "default_window_size")]
+    pub window_size: usize,
+    /// Minimum requests in the window before the error rate is evaluated
+    #[serde(default = "default_min_requests")]
+    pub min_requests: usize,
+    /// How long the circuit stays open before allowing a probe
+    #[serde(default = "default_open_seconds")]
+    pub open_seconds: u64,
+    /// Concurrent probe requests allowed while half-open
+    #[serde(default = "default_half_open_max_requests")]
+    pub half_open_max_requests: u32,
 }
 
-impl Default for HeaderConfig {
+impl Default for CircuitBreakerConfig {
     fn default() -> Self {
         Self {
-            mode: HeaderMode::Passthrough,
-            force: HashMap::new(),
-            add: HashMap::new(),
-            drop: Vec::new(),
+            enabled: false,
+            consecutive_failures: 5,
+            error_rate_threshold: 0.5,
+            window_size: 20,
+            min_requests: 10,
+            open_seconds: 30,
+            half_open_max_requests: 1,
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
-#[serde(rename_all = "lowercase")]
-pub enum HeaderMode {
-    Whitelist,
-    Blacklist,
-    Passthrough,
+fn default_consecutive_failures() -> u32 {
+    5
 }
 
-impl Default for HeaderMode {
-    fn default() -> Self {
-        HeaderMode::Passthrough
-    }
+fn default_error_rate_threshold() -> f64 {
+    0.5
+}
+
+fn default_window_size() -> usize {
+    20
+}
+
+fn default_min_requests() -> usize {
+    10
+}
+
+fn default_open_seconds() -> u64 {
+    30
+}
+
+fn default_half_open_max_requests() -> u32 {
+    1
 }
 
+/// Periodic active probes of each endpoint (opt-in)
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct TransformConfig {
+pub struct HealthCheckConfig {
     #[serde(default)]
-    pub request: Vec<Transform>,
+    pub enabled: bool,
     #[serde(default)]
-    pub response: Vec<Transform>,
+    pub probe: HealthProbe,
+    /// Path requested by `get` probes, resolved against the endpoint URL's origin
+    #[serde(default = "default_health_path")]
+    pub path: String,
+    #[serde(default = "default_health_interval_seconds")]
+    pub interval_seconds: u64,
+    #[serde(default = "default_health_timeout_seconds")]
+    pub timeout_seconds: u64,
+    /// Consecutive successful probes before an unhealthy endpoint rejoins rotation
+    #[serde(default = "default_healthy_threshold")]
+    pub healthy_threshold: u32,
+    /// Consecutive failed probes before an endpoint is removed from rotation
+    #[serde(default = "default_unhealthy_threshold")]
+    pub unhealthy_threshold: u32,
 }
 
-impl Default for TransformConfig {
+impl Default for HealthCheckConfig {
     fn default() -> Self {
         Self {
-            request: Vec::new(),
-            response: Vec::new(),
+            enabled: false,
+            probe: HealthProbe::default(),
+            path: default_health_path(),
+            interval_seconds: default_health_interval_seconds(),
+            timeout_seconds: default_health_timeout_seconds(),
+            healthy_threshold: default_healthy_threshold(),
+            unhealthy_threshold: default_unhealthy_threshold(),
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(tag = "type", rename_all = "snake_case")]
-pub enum Transform {
-    Regex {
-        pattern: String,
-        replacement: String,
-    },
-    JsonPathDrop {
-        path: String,
-    },
-    JsonPathAdd {
-        path: String,
-        value: serde_json::Value,
-    },
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum HealthProbe {
+    /// `GET` the configured path; any 2xx is healthy
+    #[default]
+    Get,
+    /// Send a one-token completion to the endpoint itself
+    Completion,
 }
 
-impl Config {
-    pub fn validate(&self) -> Result<(), String> {
-        if self.models.is_empty() {
-            return Err("At least one model must be configured".to_string());
-        }
+fn default_health_path() -> String {
+    "/v1/models".to_string()
+}
 
-        for (model_name, model_config) in &self.models {
-            if model_config.endpoint.is_empty() {
-                return Err(format!("Model '{}' has empty endpoint", model_name));
-            }
+fn default_health_interval_seconds() -> u64 {
+    30
+}
 
-            if model_config.timeout_seconds == 0 {
-                return Err(format!(
-                    "Model '{}' has invalid timeout (must be > 0)",
-                    model_name
-                ));
-            }
+fn default_health_timeout_seconds() -> u64 {
+    5
+}
 
-            if model_config.retry.max_attempts == 0 {
-                return Err(format!(
-                    "Model '{}' has invalid retry max_attempts (must be > 0)",
-                    model_name
-                ));
-            }
+fn default_healthy_threshold() -> u32 {
+    2
+}
 
-            // Validate regex patterns
-            for (idx, transform) in model_config.transforms.request.iter().enumerate() {
-                if let Transform::Regex { pattern, .. } = transform {
-                    regex::Regex::new(pattern)
-                        .map_err(|e| format!("Invalid regex in model '{}' request transform {}: {}", model_name, idx, e))?;
-                }
-            }
+fn default_unhealthy_threshold() -> u32 {
+    3
+}
 
-            for (idx, transform) in model_config.transforms.response.iter().enumerate() {
-                if let Transform::Regex { pattern, .. } = transform {
-                    regex::Regex::new(pattern)
-                        .map_err(|e| format!("Invalid regex in model '{}' response transform {}: {}", model_name, idx, e))?;
-                }
-            }
-        }
+/// Shadow traffic: a sampled copy of each request is sent to `model` in the background
+/// and both responses are recorded to `output` as JSON lines
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ShadowConfig {
+    pub model: String,
+    /// Fraction of requests to mirror (0.0-1.0)
+    #[serde(default = "default_shadow_sample_rate")]
+    pub sample_rate: f64,
+    /// JSONL file the comparison records are appended to
+    #[serde(default = "default_shadow_output")]
+    pub output: String,
+}
 
-        Ok(())
+fn default_shadow_sample_rate() -> f64 {
+    1.0
+}
+
+fn default_shadow_output() -> String {
+    "shadow.jsonl".to_string()
+}
+
+/// One weighted backend of a model. Unset fields inherit from the model.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VariantConfig {
+    /// Reported in the `x-llm-proxy-variant` response header and request logs
+    pub name: String,
+    /// Relative share of traffic; 0 drains the variant
+    pub weight: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backend_type: Option<BackendType>,
+    #[serde(default)]
+    pub endpoint: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub endpoints: Vec<EndpointConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_model: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub headers: Option<HeaderConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transforms: Option<TransformConfig>,
+}
+
+/// Request property hashed to pin a client to one variant. Requests without
+/// the property (and `random`) are assigned randomly by weight.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "by", rename_all = "snake_case")]
+pub enum StickyKey {
+    #[default]
+    Random,
+    /// OpenAI `user` or Anthropic `metadata.user_id`
+    User,
+    /// The client's API key (`Authorization: Bearer` or `x-api-key`)
+    ApiKey,
+    Header { name: String },
+}
+
+/// Per-endpoint concurrency limit. Requests over the limit wait in a queue ordered by
+/// priority, then arrival; when the queue is full or the wait times out they are rejected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConcurrencyConfig {
+    /// Simultaneous requests per endpoint (endpoints may override); unset means unlimited
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent: Option<usize>,
+    /// Requests allowed to wait per endpoint once it is at its limit
+    #[serde(default = "default_max_queue")]
+    pub max_queue: usize,
+    /// Longest a request waits in the queue
+    #[serde(default = "default_queue_timeout_ms")]
+    pub queue_timeout_ms: u64,
+    /// Share of `max_concurrent` that low-priority requests may occupy at once
+    #[serde(default = "default_low_priority_share")]
+    pub low_priority_share: f64,
+    /// A queued request moves up one priority class for every this many milliseconds
+    /// it has waited, so lower classes are not starved
+    #[serde(default = "default_aging_ms")]
+    pub aging_ms: u64,
+    /// Adjust the limit to the endpoint's behaviour instead of holding it fixed
+    #[serde(default)]
+    pub adaptive: AdaptiveConcurrencyConfig,
+}
+
+impl Default for ConcurrencyConfig {
+    fn default() -> Self {
+        Self {
+            max_concurrent: None,
+            max_queue: default_max_queue(),
+            queue_timeout_ms: default_queue_timeout_ms(),
+            low_priority_share: default_low_priority_share(),
+            aging_ms: default_aging_ms(),
+            adaptive: AdaptiveConcurrencyConfig::default(),
+        }
+    }
+}
+
+impl ConcurrencyConfig {
+    pub fn queue_timeout(&self) -> Duration {
+        Duration::from_millis(self.queue_timeout_ms)
+    }
+
+    pub fn aging(&self) -> Duration {
+        Duration::from_millis(self.aging_ms)
+    }
+}
+
+fn default_max_queue() -> usize {
+    100
+}
+
+fn default_queue_timeout_ms() -> u64 {
+    30_000
+}
+
+fn default_low_priority_share() -> f64 {
+    1.0
+}
+
+fn default_aging_ms() -> u64 {
+    10_000
+}
+
+/// AIMD concurrency control (opt-in). The limit starts at `max_concurrent` (or
+/// `min_limit` when unset), grows by one after a limit's worth of successes with
+/// stable latency, and is cut by `backoff_ratio` on overload: the errors that are
+/// retried (429, 529, 5xx, timeouts, connection failures).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdaptiveConcurrencyConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_min_limit")]
+    pub min_limit: usize,
+    #[serde(default = "default_max_limit")]
+    pub max_limit: usize,
+    /// Factor the limit is multiplied by on overload
+    #[serde(default = "default_backoff_ratio")]
+    pub backoff_ratio: f64,
+    /// Responses slower than this multiple of the average latency stop the limit
+    /// from growing
+    #[serde(default = "default_latency_tolerance")]
+    pub latency_tolerance: f64,
+}
+
+impl Default for AdaptiveConcurrencyConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            min_limit: default_min_limit(),
+            max_limit: default_max_limit(),
+            backoff_ratio: default_backoff_ratio(),
+            latency_tolerance: default_latency_tolerance(),
+        }
+    }
+}
+
+fn default_min_limit() -> usize {
+    1
+}
+
+fn default_max_limit() -> usize {
+    64
+}
+
+fn default_backoff_ratio() -> f64 {
+    0.75
+}
+
+fn default_latency_tolerance() -> f64 {
+    2.0
+}
+
+/// Timeouts for the phases of an upstream request, in milliseconds. Unset
+/// `first_byte_ms` and `idle_stream_ms` fall back to the model's `timeout_seconds`;
+/// unset `total_ms` and `deadline_ms` mean no limit.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeoutConfig {
+    /// Establishing the TCP/TLS connection
+    #[serde(default = "default_connect_ms")]
+    pub connect_ms: u64,
+    /// From sending the request until the response headers arrive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub first_byte_ms: Option<u64>,
+    /// Longest gap between two body chunks, so long streams stay alive while they progress
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub idle_stream_ms: Option<u64>,
+    /// One attempt from sending the request to the end of the response body
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_ms: Option<u64>,
+    /// Whole request routed to this model, across retries, hedges and fallbacks
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deadline_ms: Option<u64>,
+}
+
+impl Default for TimeoutConfig {
+    fn default() -> Self {
+        Self {
+            connect_ms: default_connect_ms(),
+            first_byte_ms: None,
+            idle_stream_ms: None,
+            total_ms: None,
+            deadline_ms: None,
+        }
+    }
+}
+
+fn default_connect_ms() -> u64 {
+    10_000
+}
+
+/// Duplicate slow requests to a second endpoint or model (opt-in).
+/// The first successful response wins and the other request is cancelled.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HedgingConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    /// Send the hedge when neither response headers nor, for streams, the first
+    /// body chunk have arrived after this long
+    #[serde(default = "default_hedge_delay_ms")]
+    pub delay_ms: u64,
+    /// Model to hedge to; defaults to another endpoint of the same model
+    #[serde(default)]
+    pub model: Option<String>,
+}
+
+impl Default for HedgingConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            delay_ms: default_hedge_delay_ms(),
+            model: None,
+        }
+    }
+}
+
+impl HedgingConfig {
+    pub fn delay(&self) -> Duration {
+        Duration::from_millis(self.delay_ms)
+    }
+}
+
+fn default_hedge_delay_ms() -> u64 {
+    500
+}
+
+/// Sends requests matching all of `match` to `target`. The first matching rule wins.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RoutingRule {
+    #[serde(default)]
+    pub name: Option<String>,
+    /// Requested model names this rule applies to; empty applies it to every request.
+    /// Names here do not need to be configured models.
+    #[serde(default)]
+    pub models: Vec<String>,
+    #[serde(rename = "match", default)]
+    pub conditions: RuleConditions,
+    /// Configured model that serves matching requests
+    pub target: String,
+}
+
+/// Request properties a routing rule matches on; unset conditions match anything
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RuleConditions {
+    /// Header values that must match exactly (header names are case-insensitive)
+    pub headers: HashMap<String, String>,
+    /// OpenAI `user` field or Anthropic `metadata.user_id`
+    pub user: Option<String>,
+    pub has_images: Option<bool>,
+    pub has_tools: Option<bool>,
+    /// Bounds on the estimated prompt size in tokens (inclusive)
+    pub min_prompt_tokens: Option<usize>,
+    pub max_prompt_tokens: Option<usize>,
+    pub stream: Option<bool>,
+}
+
+impl RoutingRule {
+    /// Name used in logs: the configured name, or the target model
+    pub fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.target)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HeaderConfig {
+    #[serde(default)]
+    pub mode: HeaderMode,
+    #[serde(default)]
+    pub force: HashMap<String, String>,
+    #[serde(default)]
+    pub add: HashMap<String, String>,
+    #[serde(default)]
+    pub drop: Vec<String>,
+}
+
+impl Default for HeaderConfig {
+    fn default() -> Self {
+        Self {
+            mode: HeaderMode::Passthrough,
+            force: HashMap::new(),
+            add: HashMap::new(),
+            drop: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum HeaderMode {
+    Whitelist,
+    Blacklist,
+    #[default]
+    Passthrough,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TransformConfig {
+    #[serde(default)]
+    pub request: Vec<Transform>,
+    #[serde(default)]
+    pub response: Vec<Transform>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Transform {
+    Regex {
+        pattern: String,
+        replacement: String,
+    },
+    JsonPathDrop {
+        path: String,
+    },
+    JsonPathAdd {
+        path: String,
+        value: serde_json::Value,
+    },
+}
+
+/// Personal data detected in message content and replaced with per-request
+/// placeholders such as `[EMAIL_1]`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PiiConfig {
+    #[serde(default = "default_pii_detectors")]
+    pub detectors: Vec<PiiDetector>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub custom: Vec<CustomPiiDetector>,
+    /// Put the original values back in place of placeholders in responses
+    #[serde(default)]
+    pub restore: bool,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PiiDetector {
+    Email,
+    Phone,
+    /// Card numbers passing the Luhn check
+    CreditCard,
+    /// IBANs passing the mod-97 check
+    Iban,
+    /// IPv4 and IPv6 addresses
+    IpAddress,
+}
+
+impl PiiDetector {
+    pub const ALL: [PiiDetector; 5] = [
+        PiiDetector::Email,
+        PiiDetector::Phone,
+        PiiDetector::CreditCard,
+        PiiDetector::Iban,
+        PiiDetector::IpAddress,
+    ];
+
+    /// Placeholder prefix, e.g. `CREDIT_CARD` in `[CREDIT_CARD_1]`
+    pub fn label(&self) -> &'static str {
+        match self {
+            PiiDetector::Email => "EMAIL",
+            PiiDetector::Phone => "PHONE",
+            PiiDetector::CreditCard => "CREDIT_CARD",
+            PiiDetector::Iban => "IBAN",
+            PiiDetector::IpAddress => "IP_ADDRESS",
+        }
+    }
+}
+
+fn default_pii_detectors() -> Vec<PiiDetector> {
+    PiiDetector::ALL.to_vec()
+}
+
+/// A detector for values of a custom kind, like employee ids
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CustomPiiDetector {
+    /// Placeholder prefix, uppercased: `employee_id` gives `[EMPLOYEE_ID_1]`
+    pub name: String,
+    /// Matches the value. With a capture group, only the first group's text is
+    /// replaced, so `password:\s*(\S+)` keeps the `password:` label.
+    pub pattern: String,
+}
+
+impl CustomPiiDetector {
+    pub fn label(&self) -> String {
+        self.name.to_uppercase()
+    }
+}
+
+impl Config {
+    /// Lower each model's retry `backoff_ms` to its `max_backoff_ms`, with a warning.
+    /// Backoff was always capped at the maximum, so such configs keep loading and
+    /// behave as before.
+    pub fn clamp_retry_backoff(&mut self) {
+        for (model_name, model_config) in &mut self.models {
+            let retry = &mut model_config.retry;
+            if retry.backoff_ms > retry.max_backoff_ms {
+                tracing::warn!(
+                    model = %model_name,
+                    backoff_ms = retry.backoff_ms,
+                    max_backoff_ms = retry.max_backoff_ms,
+                    "Retry backoff_ms exceeds max_backoff_ms; using max_backoff_ms"
+                );
+                retry.backoff_ms = retry.max_backoff_ms;
+            }
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.models.is_empty() {
+            return Err("At least one model must be configured".to_string());
+        }
+
+        for (model_name, model_config) in &self.models {
+            // Variants that all bring their own endpoints make the model-level one optional
+            let variants_have_endpoints = !model_config.variants.is_empty()
+                && model_config
+                    .variants
+                    .iter()
+                    .all(|v| !v.endpoint.is_empty() || !v.endpoints.is_empty());
+            if !model_config.has_endpoint() && !variants_have_endpoints {
+                return Err(format!("Model '{}' has empty endpoint", model_name));
+            }
+
+            if !model_config.variants.is_empty() {
+                let mut names = std::collections::HashSet::new();
+                for variant in &model_config.variants {
+                    if variant.name.is_empty() || !names.insert(variant.name.as_str()) {
+                        return Err(format!(
+                            "Model '{}' variant names must be non-empty and unique",
+                            model_name
+                        ));
+                    }
+                    if variant.endpoints.iter().any(|e| e.url.is_empty() || e.weight == 0) {
+                        return Err(format!(
+                            "Model '{}' variant '{}' has an endpoint with empty url or zero weight",
+                            model_name, variant.name
+                        ));
+                    }
+                }
+                if model_config.variants.iter().all(|v| v.weight == 0) {
+                    return Err(format!(
+                        "Model '{}' variants must have a total weight > 0",
+                        model_name
+                    ));
+                }
+                if let StickyKey::Header { name } = &model_config.sticky {
+                    if http::HeaderName::from_bytes(name.as_bytes()).is_err() {
+                        return Err(format!(
+                            "Model '{}' sticky header '{}' is not a valid header name",
+                            model_name, name
+                        ));
+                    }
+                }
+            }
+
+            for (idx, endpoint) in model_config.endpoints.iter().enumerate() {
+                if endpoint.url.is_empty() {
+                    return Err(format!("Model '{}' endpoint {} has empty url", model_name, idx));
+                }
+                if endpoint.weight == 0 {
+                    return Err(format!(
+                        "Model '{}' endpoint {} has invalid weight (must be > 0)",
+                        model_name, idx
+                    ));
+                }
+            }
+
+            if model_config.timeout_seconds == 0 {
+                return Err(format!(
+                    "Model '{}' has invalid timeout (must be > 0)",
+                    model_name
+                ));
+            }
+
+            if let Some(tls) = &model_config.tls {
+                if !model_config.ssl_verify {
+                    return Err(format!(
+                        "Model '{}' cannot combine tls with ssl_verify: false",
+                        model_name
+                    ));
+                }
+                if tls.client_cert.is_some() != tls.client_key.is_some() {
+                    return Err(format!(
+                        "Model '{}' tls needs both client_cert and client_key, or neither",
+                        model_name
+                    ));
+                }
+                let valid_pin = |pin: &String| {
+                    base64::engine::general_purpose::STANDARD
+                        .decode(pin)
+                        .is_ok_and(|digest| digest.len() == 32)
+                };
+                if let Some(pin) = tls.pinned_spki_sha256.iter().find(|pin| !valid_pin(pin)) {
+                    return Err(format!(
+                        "Model '{}' has invalid pinned_spki_sha256 '{}' (must be a base64 SHA-256 digest)",
+                        model_name, pin
+                    ));
+                }
+            }
+
+            if let Some(proxy) = &model_config.proxy {
+                if proxy.url.is_some() == proxy.from_env {
+                    return Err(format!(
+                        "Model '{}' proxy needs exactly one of url and from_env",
+                        model_name
+                    ));
+                }
+                if let Some(url) = &proxy.url {
+                    let scheme = reqwest::Url::parse(url).map(|url| url.scheme().to_string());
+                    if !scheme.is_ok_and(|scheme| ["http", "https", "socks5", "socks5h"].contains(&scheme.as_str())) {
+                        return Err(format!(
+                            "Model '{}' has invalid proxy url '{}' (must be http, https, socks5 or socks5h)",
+                            model_name, url
+                        ));
+                    }
+                }
+                if proxy.password.is_some() && proxy.username.is_none() {
+                    return Err(format!("Model '{}' proxy password needs a username", model_name));
+                }
+                if proxy.from_env && (proxy.username.is_some() || !proxy.no_proxy.is_empty()) {
+                    return Err(format!(
+                        "Model '{}' proxy from_env takes credentials and no_proxy from the environment",
+                        model_name
+                    ));
+                }
+            }
+
+            if let Some(rate_limit) = &model_config.rate_limit {
+                validate_rate_limit(rate_limit, &format!("Model '{}'", model_name))?;
+            }
+            if let Some(pricing) = &model_config.pricing {
+                let prices = [
+                    Some(pricing.input_per_million),
+                    Some(pricing.output_per_million),
+                    pricing.cached_input_per_million,
+                ];
+                if prices.into_iter().flatten().any(|price| !price.is_finite() || price < 0.0) {
+                    return Err(format!(
+                        "Model '{}' has an invalid price (must be >= 0)",
+                        model_name
+                    ));
+                }
+            }
+            if let Some(account) = &model_config.account {
+                if !self.accounts.contains_key(account) {
+                    return Err(format!(
+                        "Model '{}' uses unknown account '{}'",
+                        model_name, account
+                    ));
+                }
+            }
+
+            if model_config.retry.max_attempts == 0 {
+                return Err(format!(
+                    "Model '{}' has invalid retry max_attempts (must be > 0)",
+                    model_name
+                ));
+            }
+
+            let retry = &model_config.retry;
+            if let Some(status) = retry
+                .retryable_statuses
+                .iter()
+                .find(|status| !(400..=599).contains(*status))
+            {
+                return Err(format!(
+                    "Model '{}' has invalid retryable status {} (must be 400-599)",
+                    model_name, status
+                ));
+            }
+            if retry.retryable_error_types.iter().any(|t| t.trim().is_empty()) {
+                return Err(format!(
+                    "Model '{}' has an empty retryable error type",
+                    model_name
+                ));
+            }
+
+            let limits = model_config
+                .resolved_endpoints()
+                .into_iter()
+                .filter_map(|e| e.max_concurrent);
+            if limits.chain(model_config.concurrency.max_concurrent).any(|n| n == 0)
+                || model_config.concurrency.queue_timeout_ms == 0
+                || model_config.concurrency.aging_ms == 0
+            {
+                return Err(format!(
+                    "Model '{}' concurrency max_concurrent, queue_timeout_ms and aging_ms must be > 0",
+                    model_name
+                ));
+            }
+            let adaptive = &model_config.concurrency.adaptive;
+            if adaptive.enabled
+                && (adaptive.min_limit == 0
+                    || adaptive.max_limit < adaptive.min_limit
+                    || !(adaptive.backoff_ratio > 0.0 && adaptive.backoff_ratio < 1.0)
+                    || adaptive.latency_tolerance < 1.0)
+            {
+                return Err(format!(
+                    "Model '{}' adaptive concurrency needs 0 < min_limit <= max_limit, backoff_ratio in (0, 1) and latency_tolerance >= 1",
+                    model_name
+                ));
+            }
+            let share = model_config.concurrency.low_priority_share;
+            if !(share > 0.0 && share <= 1.0) {
+                return Err(format!(
+                    "Model '{}' concurrency low_priority_share must be in (0, 1]",
+                    model_name
+                ));
+            }
+
+            let timeouts = &model_config.timeouts;
+            if timeouts.connect_ms == 0
+                || [
+                    timeouts.first_byte_ms,
+                    timeouts.idle_stream_ms,
+                    timeouts.total_ms,
+                    timeouts.deadline_ms,
+                ]
+                .contains(&Some(0))
+            {
+                return Err(format!(
+                    "Model '{}' has invalid timeouts (must be > 0)",
+                    model_name
+                ));
+            }
+
+            let breaker = &model_config.circuit_breaker;
+            if breaker.enabled {
+                if breaker.consecutive_failures == 0
+                    || breaker.window_size == 0
+                    || breaker.half_open_max_requests == 0
+                {
+                    return Err(format!(
+                        "Model '{}' circuit_breaker consecutive_failures, window_size and half_open_max_requests must be > 0",
+                        model_name
+                    ));
+                }
+                if !(breaker.error_rate_threshold > 0.0 && breaker.error_rate_threshold <= 1.0) {
+                    return Err(format!(
+                        "Model '{}' circuit_breaker error_rate_threshold must be in (0, 1]",
+                        model_name
+                    ));
+                }
+            }
+
+            let health = &model_config.health_check;
+            if health.enabled {
+                if health.interval_seconds == 0
+                    || health.timeout_seconds == 0
+                    || health.healthy_threshold == 0
+                    || health.unhealthy_threshold == 0
+                {
+                    return Err(format!(
+                        "Model '{}' health_check interval, timeout and thresholds must be > 0",
+                        model_name
+                    ));
+                }
+                if health.probe == HealthProbe::Get && !health.path.starts_with('/') {
+                    return Err(format!(
+                        "Model '{}' health_check path must start with '/'",
+                        model_name
+                    ));
+                }
+            }
+
+            if let (Some(window), Some(max_output)) =
+                (model_config.context_window, model_config.max_output_tokens)
+            {
+                if max_output >= window {
+                    return Err(format!(
+                        "Model '{}' max_output_tokens must be less than context_window",
+                        model_name
+                    ));
+                }
+            }
+
+            if let Some(overflow) = &model_config.overflow_model {
+                let Some(window) = model_config.context_window else {
+                    return Err(format!(
+                        "Model '{}' overflow_model requires context_window",
+                        model_name
+                    ));
+                };
+                let Some(overflow_config) = self.models.get(overflow) else {
+                    return Err(format!(
+                        "Model '{}' overflow_model '{}' is not a configured model",
+                        model_name, overflow
+                    ));
+                };
+                // Strictly growing windows also rule out escalation cycles
+                if overflow_config.context_window.is_some_and(|w| w <= window) {
+                    return Err(format!(
+                        "Model '{}' overflow_model '{}' must have a larger context_window",
+                        model_name, overflow
+                    ));
+                }
+            }
+
+            if let Some(shadow) = &model_config.shadow {
+                if shadow.model == *model_name || !self.models.contains_key(&shadow.model) {
+                    return Err(format!(
+                        "Model '{}' shadow model '{}' must be another configured model",
+                        model_name, shadow.model
+                    ));
+                }
+                if !(0.0..=1.0).contains(&shadow.sample_rate) {
+                    return Err(format!(
+                        "Model '{}' shadow sample_rate must be between 0 and 1",
+                        model_name
+                    ));
+                }
+            }
+
+            if let Some(hedge_model) = &model_config.hedging.model {
+                if hedge_model == model_name {
+                    return Err(format!(
+                        "Model '{}' cannot hedge to itself (omit hedging.model to use another endpoint)",
+                        model_name
+                    ));
+                }
+                if !self.models.contains_key(hedge_model) {
+                    return Err(format!(
+                        "Model '{}' hedges to unknown model '{}'",
+                        model_name, hedge_model
+                    ));
+                }
+            }
+
+            for fallback in &model_config.fallbacks {
+                if fallback == model_name {
+                    return Err(format!(
+                        "Model '{}' lists itself as a fallback",
+                        model_name
+                    ));
+                }
+                if !self.models.contains_key(fallback) {
+                    return Err(format!(
+                        "Model '{}' has unknown fallback model '{}'",
+                        model_name, fallback
+                    ));
+                }
+            }
+
+            // Validate regex patterns
+            for (idx, transform) in model_config.transforms.request.iter().enumerate() {
+                if let Transform::Regex { pattern, .. } = transform {
+                    regex::Regex::new(pattern)
+                        .map_err(|e| format!("Invalid regex in model '{}' request transform {}: {}", model_name, idx, e))?;
+                }
+            }
+
+            for (idx, transform) in model_config.transforms.response.iter().enumerate() {
+                if let Transform::Regex { pattern, .. } = transform {
+                    regex::Regex::new(pattern)
+                        .map_err(|e| format!("Invalid regex in model '{}' response transform {}: {}", model_name, idx, e))?;
+                }
+            }
+
+            if let Some(pii) = &model_config.pii {
+                if pii.detectors.is_empty() && pii.custom.is_empty() {
+                    return Err(format!("Model '{}' pii needs at least one detector", model_name));
+                }
+                let mut labels: std::collections::HashSet<String> =
+                    pii.detectors.iter().map(|detector| detector.label().to_string()).collect();
+                for custom in &pii.custom {
+                    let valid_name = !custom.name.is_empty()
+                        && custom.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+                    if !valid_name || !labels.insert(custom.label()) {
+                        return Err(format!(
+                            "Model '{}' pii detector names must be unique and use only letters, digits and '_'",
+                            model_name
+                        ));
+                    }
+                    regex::Regex::new(&custom.pattern).map_err(|e| {
+                        format!("Invalid regex in model '{}' pii detector '{}': {}", model_name, custom.name, e)
+                    })?;
+                }
+            }
+        }
+
+        if http::HeaderName::from_bytes(self.priority.header.as_bytes()).is_err() {
+            return Err(format!(
+                "Priority header '{}' is not a valid header name",
+                self.priority.header
+            ));
+        }
+        validate_priority(&self.priority.client(), "Priority config")?;
+
+        for (idx, rule) in self.routing_rules.iter().enumerate() {
+            if !self.models.contains_key(&rule.target) {
+                return Err(format!(
+                    "Routing rule {} targets unknown model '{}'",
+                    idx, rule.target
+                ));
+            }
+            if let (Some(min), Some(max)) = (
+                rule.conditions.min_prompt_tokens,
+                rule.conditions.max_prompt_tokens,
+            ) {
+                if min > max {
+                    return Err(format!(
+                        "Routing rule {} has min_prompt_tokens greater than max_prompt_tokens",
+                        idx
+                    ));
+                }
+            }
+        }
+
+        for (name, account) in &self.accounts {
+            validate_rate_limit(&account.rate_limit, &format!("Account '{}'", name))?;
+        }
+
+        let mut names = std::collections::HashSet::new();
+        let mut keys = std::collections::HashSet::new();
+        for api_key in &self.auth.keys {
+            if api_key.name.is_empty() || !names.insert(api_key.name.as_str()) {
+                return Err("Client API key names must be non-empty and unique".to_string());
+            }
+            if api_key.name.starts_with(JWT_CLIENT_PREFIX) {
+                return Err(format!(
+                    "Client API key '{}' may not use the '{}' prefix of JWT clients",
+                    api_key.name, JWT_CLIENT_PREFIX
+                ));
+            }
+            // An empty key usually means its environment variable is not set
+            if api_key.key.trim().is_empty() || !keys.insert(api_key.key.as_str()) {
+                return Err(format!(
+                    "Client API key '{}' must be non-empty and unique",
+                    api_key.name
+                ));
+            }
+            if api_key.allowed_models.is_empty() {
+                return Err(format!(
+                    "Client API key '{}' has no allowed_models",
+                    api_key.name
+                ));
+            }
+            if let Some(rate_limit) = &api_key.rate_limit {
+                validate_rate_limit(rate_limit, &format!("Client API key '{}'", api_key.name))?;
+            }
+            if let Some(priority) = &api_key.priority {
+                validate_priority(priority, &format!("Client API key '{}'", api_key.name))?;
+            }
+            if let Some(target) = api_key
+                .aliases
+                .values()
+                .find(|target| !self.models.contains_key(*target))
+            {
+                return Err(format!(
+                    "Client API key '{}' aliases unknown model '{}'",
+                    api_key.name, target
+                ));
+            }
+        }
+
+        if let Some(jwt) = &self.auth.jwt {
+            if jwt.jwks_file.is_some() == jwt.jwks_url.is_some() {
+                return Err("JWT auth needs exactly one of jwks_file and jwks_url".to_string());
+            }
+            if jwt.jwks_refresh_seconds == 0 {
+                return Err("JWT auth has invalid jwks_refresh_seconds (must be > 0)".to_string());
+            }
+            if jwt.algorithms.is_empty() {
+                return Err("JWT auth needs at least one algorithm".to_string());
+            }
+            if let Some(algorithm) = jwt
+                .algorithms
+                .iter()
+                .find(|algorithm| algorithm.parse::<jsonwebtoken::Algorithm>().is_err())
+            {
+                return Err(format!("JWT auth has unknown algorithm '{}'", algorithm));
+            }
+            // Shared secrets have no place in a JWKS of public keys
+            if let Some(algorithm) = jwt.algorithms.iter().find(|algorithm| algorithm.starts_with("HS")) {
+                return Err(format!("JWT auth does not support symmetric algorithm '{}'", algorithm));
+            }
+            for group in &jwt.groups {
+                if group.group.is_empty() {
+                    return Err("JWT auth groups must be named".to_string());
+                }
+                if let Some(rate_limit) = &group.rate_limit {
+                    validate_rate_limit(rate_limit, &format!("JWT group '{}'", group.group))?;
+                }
+                if let Some(priority) = &group.priority {
+                    validate_priority(priority, &format!("JWT group '{}'", group.group))?;
+                }
+            }
+        }
+
+        if let Some(tls) = &self.server.tls {
+            if tls.cert_path.is_empty() || tls.key_path.is_empty() {
+                return Err("Server TLS needs cert_path and key_path".to_string());
+            }
+            if tls.reload_interval_seconds == 0 {
+                return Err("Server TLS has invalid reload_interval_seconds (must be > 0)".to_string());
+            }
+        }
+        let client_ca = self.server.tls.as_ref().and_then(|tls| tls.client_ca_path.as_ref());
+        if !self.auth.client_certs.is_empty() && client_ca.is_none() {
+            return Err("Client certificates need server.tls.client_ca_path".to_string());
+        }
+        for cert in &self.auth.client_certs {
+            if cert.subject.is_empty() || !names.insert(cert.subject.as_str()) {
+                return Err(
+                    "Client certificate subjects must be non-empty and unique among client names"
+                        .to_string(),
+                );
+            }
+            if cert.subject.starts_with(JWT_CLIENT_PREFIX) {
+                return Err(format!(
+                    "Client certificate '{}' may not use the '{}' prefix of JWT clients",
+                    cert.subject, JWT_CLIENT_PREFIX
+                ));
+            }
+            if cert.allowed_models.is_empty() {
+                return Err(format!("Client certificate '{}' has no allowed_models", cert.subject));
+            }
+            if let Some(rate_limit) = &cert.rate_limit {
+                validate_rate_limit(rate_limit, &format!("Client certificate '{}'", cert.subject))?;
+            }
+            if let Some(priority) = &cert.priority {
+                validate_priority(priority, &format!("Client certificate '{}'", cert.subject))?;
+            }
+            if let Some(target) = cert.aliases.values().find(|target| !self.models.contains_key(*target)) {
+                return Err(format!(
+                    "Client certificate '{}' aliases unknown model '{}'",
+                    cert.subject, target
+                ));
+            }
+        }
+
+        for (idx, budget) in self.spend.budgets.iter().enumerate() {
+            let scopes = [&budget.key, &budget.team, &budget.model];
+            if scopes.iter().filter(|scope| scope.is_some()).count() != 1 {
+                return Err(format!(
+                    "Budget {} must set exactly one of key, team and model",
+                    idx
+                ));
+            }
+            if let Some(key) = &budget.key {
+                if !names.contains(key.as_str()) {
+                    return Err(format!("Budget {} references unknown client API key '{}'", idx, key));
+                }
+            }
+            if let Some(model) = budget
+                .model
+                .iter()
+                .chain(&budget.downgrade_to)
+                .find(|model| !self.models.contains_key(*model))
+            {
+                return Err(format!("Budget {} references unknown model '{}'", idx, model));
+            }
+            let limits = [budget.daily_usd, budget.monthly_usd];
+            let invalid = |limit: f64| !limit.is_finite() || limit <= 0.0;
+            if limits.iter().all(Option::is_none) || limits.into_iter().flatten().any(invalid) {
+                return Err(format!(
+                    "Budget {} needs a daily_usd or monthly_usd limit (must be > 0)",
+                    idx
+                ));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn validate_priority(priority: &ClientPriority, owner: &str) -> Result<(), String> {
+    if priority.max() < priority.default {
+        return Err(format!("{} has a priority max below its default", owner));
+    }
+    Ok(())
+}
+
+fn validate_rate_limit(rate_limit: &RateLimitConfig, owner: &str) -> Result<(), String> {
+    let limits = [
+        rate_limit.requests_per_minute,
+        rate_limit.input_tokens_per_minute,
+        rate_limit.output_tokens_per_minute,
+    ];
+    if limits.contains(&Some(0)) {
+        return Err(format!("{} has a rate limit of 0 (must be > 0)", owner));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(yaml: &str) -> Config {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_validate_fallbacks() {
+        let config = parse(
+            r#"
+server: {}
+models:
+  primary:
+    backend_type: openai
+    endpoint: http://localhost/v1/chat/completions
+    fallbacks: [secondary]
+  secondary:
+    backend_type: anthropic
+    endpoint: http://localhost/v1/messages
+"#,
+        );
+        assert!(config.validate().is_ok());
+        assert_eq!(config.models["primary"].fallbacks, vec!["secondary"]);
+    }
+
+    #[test]
+    fn test_validate_rejects_unknown_fallback() {
+        let config = parse(
+            r#"
+server: {}
+models:
+  primary:
+    backend_type: openai
+    endpoint: http://localhost/v1/chat/completions
+    fallbacks: [missing]
+"#,
+        );
+        let err = config.validate().unwrap_err();
+        assert!(err.contains("unknown fallback model 'missing'"));
+    }
+
+    #[test]
+    fn test_validate_rejects_self_fallback() {
+        let config = parse(
+            r#"
+server: {}
+models:
+  primary:
+    backend_type: openai
+    endpoint: http://localhost/v1/chat/completions
+    fallbacks: [primary]
+"#,
+        );
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_resolved_endpoints_inherit_api_key() {
+        let config = parse(
+            r#"
+server: {}
+models:
+  llama:
+    backend_type: openai
+    api_key: shared
+    load_balancing: least_in_flight
+    endpoints:
+      - url: http://vllm-1/v1/chat/completions
+      - url: http://vllm-2/v1/chat/completions
+        api_key: own
+        weight: 3
+"#,
+        );
+        assert!(config.validate().is_ok());
+
+        let model = &config.models["llama"];
+        assert_eq!(model.load_balancing, LoadBalancing::LeastInFlight);
+
+        let endpoints = model.resolved_endpoints();
+        assert_eq!(endpoints.len(), 2);
+        assert_eq!(endpoints[0].api_key.as_deref(), Some("shared"));
+        assert_eq!(endpoints[0].weight, 1);
+        assert_eq!(endpoints[1].api_key.as_deref(), Some("own"));
+        assert_eq!(endpoints[1].weight, 3);
+    }
+
+    #[test]
+    fn test_concurrency_limits() {
+        let mut config = parse(
+            r#"
+server: {}
+models:
+  llama:
+    backend_type: openai
+    concurrency:
+      max_concurrent: 2
+    endpoints:
+      - url: http://vllm-1/v1/chat/completions
+      - url: http://vllm-2/v1/chat/completions
+        max_concurrent: 8
+"#,
+        );
+        assert!(config.validate().is_ok());
+
+        let model = &config.models["llama"];
+        assert_eq!(model.concurrency.max_queue, 100);
+        let limits: Vec<_> = model
+            .resolved_endpoints()
+            .iter()
+            .map(|e| e.max_concurrent)
+            .collect();
+        assert_eq!(limits, vec![Some(2), Some(8)]);
+
+        config.models.get_mut("llama").unwrap().endpoints[1].max_concurrent = Some(0);
+        assert!(config.validate().is_err());
+        config.models.get_mut("llama").unwrap().endpoints[1].max_concurrent = None;
+        config.models.get_mut("llama").unwrap().concurrency.low_priority_share = 0.0;
+        assert!(config.validate().is_err());
+        config.models.get_mut("llama").unwrap().concurrency.low_priority_share = 1.0;
+
+        let adaptive = &mut config.models.get_mut("llama").unwrap().concurrency.adaptive;
+        adaptive.enabled = true;
+        assert!(config.validate().is_ok());
+        config.models.get_mut("llama").unwrap().concurrency.adaptive.max_limit = 0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        let mut config = parse(
+            r#"
+server: {}
+models:
+  claude:
+    backend_type: anthropic
+    endpoint: https://api.anthropic.com/v1/messages
+    retry:
+      retryable_statuses: [429, 529]
+      retryable_error_types: [overloaded_error, rate_limit_error]
+      retry_on_timeout: false
+      budget: 4
+"#,
+        );
+        assert!(config.validate().is_ok());
+
+        let retry = &config.models["claude"].retry;
+        assert_eq!(retry.max_attempts, 3);
+        assert_eq!(retry.retryable_statuses, vec![429, 529]);
+        assert!(!retry.retry_on_timeout);
+        assert!(retry.retry_streams_before_first_byte);
+        assert_eq!(retry.budget, Some(4));
+
+        let retry = &mut config.models.get_mut("claude").unwrap().retry;
+        retry.retryable_statuses.push(200);
+        assert!(config.validate().is_err());
+        let retry = &mut config.models.get_mut("claude").unwrap().retry;
+        retry.retryable_statuses.pop();
+        retry.retryable_error_types.push(" ".to_string());
+        assert!(config.validate().is_err());
+        let retry = &mut config.models.get_mut("claude").unwrap().retry;
+        retry.retryable_error_types.pop();
+        retry.backoff_ms = 15_000;
+        config.clamp_retry_backoff();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.models["claude"].retry.backoff_ms, 10_000);
+    }
+
+    #[test]
+    fn test_client_keys() {
+        let mut config = parse(
+            r#"
+server: {}
+models:
+  gpt-4:
+    backend_type: openai
+    endpoint: https://api.openai.com/v1/chat/completions
+  gpt-4-mini:
+    backend_type: openai
+    endpoint: https://api.openai.com/v1/chat/completions
+auth:
+  keys:
+    - name: search
+      key: sk-search
+      allowed_models: [gpt-4, "claude-*"]
+      aliases:
+        gpt-4: gpt-4-mini
+      metadata:
+        team: search
+"#,
+        );
+        assert!(config.validate().is_ok());
+        assert!(config.auth.is_enabled());
+
+        let key = &config.auth.keys[0];
+        assert!(key.allows_model("gpt-4"));
+        assert!(key.allows_model("claude-3-haiku"));
+        assert!(!key.allows_model("gpt-4-mini"));
+        assert_eq!(key.resolve_alias("gpt-4"), "gpt-4-mini");
+        // Keys never show up when the config is printed or serialized
+        assert!(!format!("{:?}", config).contains("sk-search"));
+        assert!(!serde_json::to_string(&config).unwrap().contains("sk-search"));
+
+        let mut duplicate = config.auth.keys[0].clone();
+        duplicate.name = "other".to_string();
+        config.auth.keys.push(duplicate);
+        assert!(config.validate().is_err());
+        config.auth.keys.pop();
+
+        config.auth.keys[0].key = String::new();
+        assert!(config.validate().is_err());
+        config.auth.keys[0].key = "sk-search".to_string();
+        // Names of JWT clients are reserved
+        let name = std::mem::replace(&mut config.auth.keys[0].name, "jwt:svc".to_string());
+        assert!(config.validate().is_err());
+        config.auth.keys[0].name = name;
+        config.auth.keys[0].aliases.insert("fast".to_string(), "missing".to_string());
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_rate_limits_and_accounts() {
+        let mut config = parse(
+            r#"
+server: {}
+accounts:
+  openai-prod:
+    rate_limit:
+      requests_per_minute: 500
+      input_tokens_per_minute: 200000
+models:
+  gpt-4:
+    backend_type: openai
+    endpoint: https://api.openai.com/v1/chat/completions
+    account: openai-prod
+    rate_limit:
+      output_tokens_per_minute: 20000
+auth:
+  keys:
+    - name: batch
+      key: sk-batch
+      allowed_models: ["*"]
+      rate_limit:
+        requests_per_minute: 10
+"#,
+        );
+        assert!(config.validate().is_ok());
+        assert_eq!(config.accounts["openai-prod"].rate_limit.requests_per_minute, Some(500));
+        assert_eq!(
+            config.models["gpt-4"].rate_limit.as_ref().unwrap().output_tokens_per_minute,
+            Some(20000)
+        );
+
+        config.models.get_mut("gpt-4").unwrap().account = Some("missing".to_string());
+        assert!(config.validate().is_err());
+        config.models.get_mut("gpt-4").unwrap().account = None;
+        config.auth.keys[0].rate_limit.as_mut().unwrap().requests_per_minute = Some(0);
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_jwt_auth() {
+        let mut config = parse(
+            r#"
+server: {}
+models:
+  gpt-4o:
+    backend_type: openai
+    endpoint: https://api.openai.com/v1/chat/completions
+auth:
+  jwt:
+    jwks_url: https://idp.example.com/.well-known/jwks.json
+    issuer: https://idp.example.com/
+    audience: [llm-proxy]
+    algorithms: [RS256, ES256]
+    metadata_claims:
+      team: department
+    groups:
+      - group: ml-platform
+        allowed_models: ["*"]
+        rate_limit:
+          requests_per_minute: 60
+"#,
+        );
+        assert!(config.validate().is_ok());
+        assert!(config.auth.is_enabled());
+        let jwt = config.auth.jwt.as_ref().unwrap();
+        assert_eq!(jwt.identity_claim, "sub");
+        assert_eq!(jwt.leeway_seconds, 60);
+        assert_eq!(jwt.groups[0].allowed_models, vec!["*"]);
+
+        let jwt = config.auth.jwt.as_mut().unwrap();
+        jwt.jwks_file = Some("/etc/llm-proxy/jwks.json".to_string());
+        assert!(config.validate().is_err());
+        let jwt = config.auth.jwt.as_mut().unwrap();
+        jwt.jwks_file = None;
+        jwt.algorithms = vec!["HS256".to_string()];
+        assert!(config.validate().is_err());
+        config.auth.jwt.as_mut().unwrap().algorithms = vec!["XX999".to_string()];
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_upstream_tls() {
+        let mut config = parse(
+            r#"
+server: {}
+models:
+  internal:
+    backend_type: openai
+    endpoint: https://llm.internal.example.com/v1/chat/completions
+    tls:
+      ca_bundle: /etc/llm-proxy/internal-ca.pem
+      client_cert: /etc/llm-proxy/client.pem
+      client_key: /etc/llm-proxy/client.key
+      pinned_spki_sha256: ["47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU="]
+      min_version: "1.3"
+"#,
+        );
+        assert!(config.validate().is_ok());
+        let tls = config.models["internal"].tls.as_ref().unwrap();
+        assert_eq!(tls.min_version, TlsVersion::Tls13);
+
+        let model = config.models.get_mut("internal").unwrap();
+        model.tls.as_mut().unwrap().pinned_spki_sha256 = vec!["not-a-digest".to_string()];
+        assert!(config.validate().is_err());
+        let model = config.models.get_mut("internal").unwrap();
+        let tls = model.tls.as_mut().unwrap();
+        tls.pinned_spki_sha256.clear();
+        tls.client_key = None;
+        assert!(config.validate().is_err());
+        let model = config.models.get_mut("internal").unwrap();
+        model.tls.as_mut().unwrap().client_cert = None;
+        assert!(config.validate().is_ok());
+        config.models.get_mut("internal").unwrap().ssl_verify = false;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_outbound_proxy() {
+        let mut config = parse(
+            r#"
+server: {}
+models:
+  gpt-4o:
+    backend_type: openai
+    endpoint: https://api.openai.com/v1/chat/completions
+    proxy:
+      url: http://proxy.corp.example.com:3128
+      username: llm-proxy
+      password: secret
+      no_proxy: [localhost, .internal.example.com, 10.0.0.0/8]
+"#,
+        );
+        assert!(config.validate().is_ok());
+        assert!(!serde_json::to_string(&config).unwrap().contains("secret"));
+
+        let proxy = config.models.get_mut("gpt-4o").unwrap().proxy.as_mut().unwrap();
+        proxy.url = Some("socks5h://proxy.corp.example.com:1080".to_string());
+        assert!(config.validate().is_ok());
+        let proxy = config.models.get_mut("gpt-4o").unwrap().proxy.as_mut().unwrap();
+        proxy.url = Some("ftp://proxy.corp.example.com".to_string());
+        assert!(config.validate().is_err());
+        let proxy = config.models.get_mut("gpt-4o").unwrap().proxy.as_mut().unwrap();
+        proxy.url = None;
+        assert!(config.validate().is_err());
+        let proxy = config.models.get_mut("gpt-4o").unwrap().proxy.as_mut().unwrap();
+        proxy.from_env = true;
+        assert!(config.validate().is_err());
+        let proxy = config.models.get_mut("gpt-4o").unwrap().proxy.as_mut().unwrap();
+        proxy.username = None;
+        proxy.password = None;
+        proxy.no_proxy.clear();
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_pii() {
+        let mut config = parse(
+            r#"
+server: {}
+models:
+  gpt-4o:
+    backend_type: openai
+    endpoint: https://api.openai.com/v1/chat/completions
+    pii:
+      custom:
+        - name: employee_id
+          pattern: 'EMP-\d{6}'
+      restore: true
+"#,
+        );
+        assert!(config.validate().is_ok());
+        let pii = config.models["gpt-4o"].pii.as_ref().unwrap();
+        assert_eq!(pii.detectors, PiiDetector::ALL.to_vec());
+        assert_eq!(pii.custom[0].label(), "EMPLOYEE_ID");
+
+        let pii = config.models.get_mut("gpt-4o").unwrap().pii.as_mut().unwrap();
+        pii.custom[0].name = "email".to_string();
+        assert!(config.validate().is_err());
+        let pii = config.models.get_mut("gpt-4o").unwrap().pii.as_mut().unwrap();
+        pii.custom[0].name = "employee id".to_string();
+        assert!(config.validate().is_err());
+        let pii = config.models.get_mut("gpt-4o").unwrap().pii.as_mut().unwrap();
+        pii.custom[0].name = "employee_id".to_string();
+        pii.custom[0].pattern = "EMP-(".to_string();
+        assert!(config.validate().is_err());
+        let pii = config.models.get_mut("gpt-4o").unwrap().pii.as_mut().unwrap();
+        pii.detectors.clear();
+        pii.custom.clear();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_server_tls_and_client_certs() {
+        let mut config = parse(
+            r#"
+server:
+  tls:
+    cert_path: /etc/llm-proxy/tls/server.pem
+    key_path: /etc/llm-proxy/tls/server.key
+    client_ca_path: /etc/llm-proxy/tls/clients-ca.pem
+models:
+  gpt-4o:
+    backend_type: openai
+    endpoint: https://api.openai.com/v1/chat/completions
+auth:
+  client_certs:
+    - subject: svc-search
+      allowed_models: [gpt-4o]
+      metadata:
+        team: search
+spend:
+  budgets:
+    - key: svc-search
+      daily_usd: 10
+"#,
+        );
+        assert!(config.validate().is_ok());
+        assert!(config.auth.is_enabled());
+        let tls = config.server.tls.as_ref().unwrap();
+        assert!(tls.require_client_cert);
+        assert_eq!(tls.reload_interval_seconds, 10);
+        assert_eq!(config.auth.client_certs[0].client().name, "svc-search");
+
+        config.auth.client_certs[0].aliases = [("gpt-4o".to_string(), "gpt-5".to_string())].into();
+        assert!(config.validate().is_err());
+        config.auth.client_certs[0].aliases.clear();
+        config.server.tls.as_mut().unwrap().client_ca_path = None;
+        assert!(config.validate().is_err());
+        config.server.tls = None;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_pricing_and_budgets() {
+        let mut config = parse(
+            r#"
+server: {}
+models:
+  gpt-4o:
+    backend_type: openai
+    endpoint: https://api.openai.com/v1/chat/completions
+    pricing:
+      input_per_million: 2.5
+      output_per_million: 10.0
+      cached_input_per_million: 1.25
+  gpt-4o-mini:
+    backend_type: openai
+    endpoint: https://api.openai.com/v1/chat/completions
+auth:
+  keys:
+    - name: research
+      key: sk-research
+      allowed_models: ["*"]
+      metadata:
+        team: research
+spend:
+  store: /var/lib/llm-proxy/spend.json
+  budgets:
+    - key: research
+      daily_usd: 50
+      downgrade_to: gpt-4o-mini
+    - team: research
+      monthly_usd: 1000
+"#,
+        );
+        assert!(config.validate().is_ok());
+        let pricing = config.models["gpt-4o"].pricing.as_ref().unwrap();
+        assert_eq!(pricing.cached_input_per_million, Some(1.25));
+        assert_eq!(config.spend.budgets[0].daily_usd, Some(50.0));
+        assert_eq!(config.spend.budgets[1].team.as_deref(), Some("research"));
+
+        config.spend.budgets[0].downgrade_to = Some("missing".to_string());
+        assert!(config.validate().is_err());
+        config.spend.budgets[0].downgrade_to = None;
+        config.spend.budgets[0].key = Some("unknown".to_string());
+        assert!(config.validate().is_err());
+        config.spend.budgets[0].team = Some("research".to_string());
+        config.spend.budgets[0].key = None;
+        config.spend.budgets[0].model = Some("gpt-4o".to_string());
+        assert!(config.validate().is_err());
+        config.spend.budgets[0].model = None;
+        config.spend.budgets[0].daily_usd = Some(0.0);
+        assert!(config.validate().is_err());
+        config.spend.budgets[0].daily_usd = Some(50.0);
+        config.models.get_mut("gpt-4o").unwrap().pricing.as_mut().unwrap().output_per_million = -1.0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_priority_config() {
+        let mut config = parse(
+            r#"
+server: {}
+priority:
+  default: low
+auth:
+  keys:
+    - name: team
+      key: team-key
+      allowed_models: ["*"]
+      priority:
+        default: normal
+        max: high
+models:
+  llama:
+    backend_type: openai
+    endpoint: http://localhost/v1/chat/completions
+"#,
+        );
+        assert!(config.validate().is_ok());
+        assert_eq!(config.priority.header, "x-llm-proxy-priority");
+        assert_eq!(config.priority.client().max(), Priority::Low);
+        let team = config.auth.keys[0].priority.unwrap();
+        assert_eq!((team.default, team.max()), (Priority::Normal, Priority::High));
+
+        config.auth.keys[0].priority = Some(ClientPriority {
+            default: Priority::High,
+            max: Some(Priority::Normal),
+        });
+        assert!(config.validate().is_err());
+        config.auth.keys[0].priority = None;
+
+        config.priority.max = Some(Priority::Low);
+        config.priority.default = Priority::High;
+        assert!(config.validate().is_err());
+        config.priority.default = Priority::Low;
+
+        config.priority.header = "bad header".to_string();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_requires_an_endpoint() {
+        let config = parse(
+            r#"
+server: {}
+models:
+  llama:
+    backend_type: openai
+"#,
+        );
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_circuit_breaker() {
+        let mut config = parse(
+            r#"
+server: {}
+models:
+  primary:
+    backend_type: openai
+    endpoint: http://localhost/v1/chat/completions
+    circuit_breaker:
+      enabled: true
+      consecutive_failures: 3
+"#,
+        );
+        assert!(config.validate().is_ok());
+        let breaker = &config.models["primary"].circuit_breaker;
+        assert_eq!(breaker.consecutive_failures, 3);
+        assert_eq!(breaker.open_seconds, 30);
+
+        config.models.get_mut("primary").unwrap().circuit_breaker.error_rate_threshold = 1.5;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_health_check_defaults_and_validation() {
+        let mut config = parse(
+            r#"
+server: {}
+models:
+  primary:
+    backend_type: openai
+    endpoint: http://localhost/v1/chat/completions
+    health_check:
+      enabled: true
+      probe: completion
+"#,
+        );
+        assert!(config.validate().is_ok());
+        let health = &config.models["primary"].health_check;
+        assert_eq!(health.probe, HealthProbe::Completion);
+        assert_eq!(health.unhealthy_threshold, 3);
+
+        let health = &mut config.models.get_mut("primary").unwrap().health_check;
+        health.probe = HealthProbe::Get;
+        health.path = "health".to_string();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_routing_rules() {
+        let mut config = parse(
+            r#"
+server: {}
+models:
+  primary:
+    backend_type: openai
+    endpoint: http://localhost/v1/chat/completions
+routing_rules:
+  - name: vision
+    models: [chat]
+    match:
+      has_images: true
+      headers:
+        X-Team: research
+    target: primary
+"#,
+        );
+        assert!(config.validate().is_ok());
+        let rule = &config.routing_rules[0];
+        assert_eq!(rule.conditions.has_images, Some(true));
+        assert_eq!(rule.conditions.headers["X-Team"], "research");
+        assert!(rule.conditions.stream.is_none());
+
+        config.routing_rules[0].target = "missing".to_string();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_variants() {
+        let mut config = parse(
+            r#"
+server: {}
+models:
+  chat:
+    backend_type: openai
+    api_key: base-key
+    target_model: gpt-4o
+    sticky:
+      by: header
+      name: X-Session-Id
+    variants:
+      - name: stable
+        weight: 90
+        endpoint: https://api.openai.com/v1/chat/completions
+      - name: canary
+        weight: 10
+        backend_type: anthropic
+        endpoint: https://api.anthropic.com/v1/messages
+        target_model: claude-3-5-sonnet
+"#,
+        );
+        assert!(config.validate().is_ok());
+        let model = &config.models["chat"];
+        assert_eq!(
+            model.sticky,
+            StickyKey::Header {
+                name: "X-Session-Id".to_string()
+            }
+        );
+
+        let canary = model.variant_config(&model.variants[1]);
+        assert_eq!(canary.backend_type, BackendType::Anthropic);
+        assert_eq!(canary.target_model.as_deref(), Some("claude-3-5-sonnet"));
+        assert_eq!(canary.api_key.as_deref(), Some("base-key"));
+        assert!(canary.variants.is_empty());
+        let stable = model.variant_config(&model.variants[0]);
+        assert_eq!(stable.target_model.as_deref(), Some("gpt-4o"));
+
+        let chat = config.models.get_mut("chat").unwrap();
+        chat.variants[1].name = "stable".to_string();
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_shadow() {
+        let mut config = parse(
+            r#"
+server: {}
+models:
+  primary:
+    backend_type: openai
+    endpoint: http://localhost/v1/chat/completions
+    shadow:
+      model: candidate
+      sample_rate: 0.25
+  candidate:
+    backend_type: anthropic
+    endpoint: http://localhost/v1/messages
+"#,
+        );
+        assert!(config.validate().is_ok());
+        let shadow = config.models["primary"].shadow.as_ref().unwrap();
+        assert_eq!(shadow.output, "shadow.jsonl");
+
+        config.models.get_mut("primary").unwrap().shadow.as_mut().unwrap().sample_rate = 2.0;
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_context_window() {
+        let mut config = parse(
+            r#"
+server: {}
+models:
+  small:
+    backend_type: openai
+    endpoint: http://localhost/v1/chat/completions
+    context_window: 8192
+    max_output_tokens: 1024
+    overflow_model: large
+  large:
+    backend_type: openai
+    endpoint: http://localhost/v1/chat/completions
+    context_window: 128000
+"#,
+        );
+        assert!(config.validate().is_ok());
+
+        // Escalating to a model that is not larger
+        config.models.get_mut("large").unwrap().context_window = Some(8192);
+        assert!(config.validate().is_err());
+        config.models.get_mut("large").unwrap().context_window = None;
+        assert!(config.validate().is_ok());
+
+        config.models.get_mut("small").unwrap().max_output_tokens = Some(8192);
+        assert!(config.validate().is_err());
+        config.models.get_mut("small").unwrap().max_output_tokens = None;
+
+        config.models.get_mut("small").unwrap().context_window = None;
+        assert!(config.validate().is_err());
     }
 }
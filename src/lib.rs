@@ -0,0 +1,8 @@
+pub mod backends;
+pub mod config;
+pub mod logging;
+pub mod proxy;
+pub mod server;
+pub mod streaming;
+pub mod transform;
+pub mod types;
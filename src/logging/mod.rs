@@ -1,3 +1,5 @@
 pub mod request_logger;
+pub mod shadow_log;
 
 pub use request_logger::RequestLogger;
+pub use shadow_log::{ShadowLog, ShadowOutcome, ShadowRecord};
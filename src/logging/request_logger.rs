@@ -1,10 +1,16 @@
 use crate::config::LoggingConfig;
+use crate::types::TokenUsage;
 use chrono::{DateTime, Utc};
 use http::header::HeaderMap;
 use serde::Serialize;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::OnceLock;
 use std::time::Instant;
 
+/// Status logged for requests the client abandoned (nginx convention)
+pub const CLIENT_CLOSED_REQUEST: u16 = 499;
+
 #[derive(Debug, Serialize)]
 pub struct RequestLog {
     pub timestamp: DateTime<Utc>,
@@ -12,12 +18,24 @@ pub struct RequestLog {
     pub path: String,
     pub model: Option<String>,
     pub backend: Option<String>,
+    /// Name of the client API key the request presented
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub headers: Option<HashMap<String, String>>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub body: Option<String>,
     pub status_code: u16,
     pub duration_ms: u64,
+    /// Hedge requests sent for this request
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hedges: Option<u32>,
+    /// The client went away before the response was complete
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub cancelled: bool,
+    /// Tokens consumed up to the point of cancellation, reported or estimated
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<TokenUsage>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub error: Option<String>,
 }
@@ -52,6 +70,8 @@ pub struct UpstreamResponseLog {
 pub struct RequestLogger {
     config: LoggingConfig,
     start_time: Instant,
+    hedges: AtomicU32,
+    client: OnceLock<String>,
 }
 
 impl RequestLogger {
@@ -59,9 +79,25 @@ impl RequestLogger {
         Self {
             config,
             start_time: Instant::now(),
+            hedges: AtomicU32::new(0),
+            client: OnceLock::new(),
         }
     }
 
+    /// Count a hedge request sent on behalf of this request
+    pub fn record_hedge(&self) {
+        self.hedges.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn hedges(&self) -> u32 {
+        self.hedges.load(Ordering::Relaxed)
+    }
+
+    /// Attribute this request to a client API key, by the key's name
+    pub fn set_client(&self, name: &str) {
+        let _ = self.client.set(name.to_string());
+    }
+
     pub fn log_request(
         &self,
         method: &str,
@@ -190,10 +226,14 @@ impl RequestLogger {
             path: path.to_string(),
             model: model.map(|s| s.to_string()),
             backend: backend.map(|s| s.to_string()),
+            client: self.client.get().cloned(),
             headers: None,
             body: None,
             status_code,
             duration_ms,
+            hedges: Some(self.hedges()).filter(|&n| n > 0),
+            cancelled: false,
+            usage: None,
             error: error.map(|s| s.to_string()),
         };
 
@@ -206,6 +246,39 @@ impl RequestLogger {
         }
     }
 
+    /// Log a request the client abandoned, with status 499 (client closed request)
+    pub fn log_cancelled(
+        &self,
+        method: &str,
+        path: &str,
+        model: Option<&str>,
+        backend: Option<&str>,
+        usage: Option<TokenUsage>,
+    ) {
+        if !self.config.enabled {
+            return;
+        }
+
+        let log = RequestLog {
+            timestamp: Utc::now(),
+            method: method.to_string(),
+            path: path.to_string(),
+            model: model.map(|s| s.to_string()),
+            backend: backend.map(|s| s.to_string()),
+            client: self.client.get().cloned(),
+            headers: None,
+            body: None,
+            status_code: CLIENT_CLOSED_REQUEST,
+            duration_ms: self.start_time.elapsed().as_millis() as u64,
+            hedges: Some(self.hedges()).filter(|&n| n > 0),
+            cancelled: true,
+            usage,
+            error: None,
+        };
+
+        tracing::info!(log = ?log, "Request cancelled by client");
+    }
+
     fn headers_to_map(headers: &HeaderMap) -> HashMap<String, String> {
         headers
             .iter()
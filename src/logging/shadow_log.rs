@@ -0,0 +1,114 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use crate::types::TokenUsage;
+
+/// One mirrored request: the client-facing response and the shadow model's response
+#[derive(Debug, Serialize)]
+pub struct ShadowRecord {
+    pub timestamp: DateTime<Utc>,
+    pub request: Value,
+    pub primary: ShadowOutcome,
+    pub shadow: ShadowOutcome,
+}
+
+/// What one side of a mirrored request returned
+#[derive(Debug, Default, Serialize)]
+pub struct ShadowOutcome {
+    pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    pub latency_ms: u64,
+    pub stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub usage: Option<TokenUsage>,
+    /// Full JSON body of non-streaming responses
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Append-only JSONL file of shadow comparison records, opened on first write
+pub struct ShadowLog {
+    path: PathBuf,
+    file: Mutex<Option<tokio::fs::File>>,
+}
+
+impl ShadowLog {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+            file: Mutex::new(None),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub async fn append(&self, record: &ShadowRecord) -> std::io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+
+        let mut file = self.file.lock().await;
+        if file.is_none() {
+            *file = Some(
+                tokio::fs::OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)
+                    .await?,
+            );
+        }
+        let file = file.as_mut().expect("shadow log opened above");
+        // One write per record keeps lines whole
+        file.write_all(&line).await?;
+        file.flush().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_append_writes_json_lines() {
+        let path = std::env::temp_dir().join(format!("shadow-log-test-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let log = ShadowLog::new(&path);
+
+        for i in 0..2 {
+            let record = ShadowRecord {
+                timestamp: Utc::now(),
+                request: json!({"n": i}),
+                primary: ShadowOutcome {
+                    model: "a".to_string(),
+                    status: Some(200),
+                    ..ShadowOutcome::default()
+                },
+                shadow: ShadowOutcome {
+                    model: "b".to_string(),
+                    error: Some("boom".to_string()),
+                    ..ShadowOutcome::default()
+                },
+            };
+            log.append(&record).await.unwrap();
+        }
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<Value> = contents.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1]["request"]["n"], 1);
+        assert_eq!(lines[0]["shadow"]["error"], "boom");
+        assert!(lines[0]["primary"].get("error").is_none());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
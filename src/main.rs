@@ -1,25 +1,25 @@
 use anyhow::Result;
+use futures::FutureExt;
+use std::future::IntoFuture;
+use std::sync::Arc;
+use std::time::Duration;
 use axum::{
     routing::{get, post},
     Router,
 };
-use std::sync::Arc;
 use tower_http::cors::CorsLayer;
 use tower_http::trace::TraceLayer;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
-mod config;
-mod types;
-mod proxy;
-mod transform;
-mod logging;
-mod backends;
-mod server;
-mod streaming;
+use llm_proxy_rust::config::load_config;
+use llm_proxy_rust::proxy::{spawn_health_checks, spawn_spend_persistence};
+use llm_proxy_rust::server::{
+    chat_completions_handler, live_handler, messages_handler, ready_handler, serve_tls, shutdown_signal,
+    spawn_tls_reload, status_handler, AppState, TlsListener,
+};
 
-use config::load_config;
-use proxy::ModelRouter;
-use server::{chat_completions_handler, AppState};
+/// How long connections may linger after remaining requests were terminated
+const TERMINATE_GRACE: Duration = Duration::from_secs(5);
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -42,23 +42,32 @@ async fn main() -> Result<()> {
         config.models.len()
     );
 
-    // Create model router
-    let router = Arc::new(ModelRouter::new(&config)?);
+    // Build application state and model router
+    let app_state = AppState::new(config.clone())?;
+    let router = app_state.router.clone();
     tracing::info!("Model router initialized with models: {:?}", router.list_models());
 
-    // Build application state
-    let app_state = AppState {
-        router: router.clone(),
-        config: Arc::new(config.clone()),
-    };
+    // Start active health checks for models that enable them
+    let health_checks = spawn_health_checks(&router);
+    if !health_checks.is_empty() {
+        tracing::info!("Started health checks for {} models", health_checks.len());
+    }
+
+    // Persist spend periodically when a spend store is configured
+    let spend = app_state.spend.clone();
+    spawn_spend_persistence(spend.clone());
+
+    let shutdown = app_state.shutdown.clone();
 
     // Build application router
     let app = Router::new()
         .route("/health", get(health_check))
+        .route("/health/live", get(live_handler))
+        .route("/health/ready", get(ready_handler))
         .route("/models", get(list_models))
+        .route("/status", get(status_handler))
         .route("/v1/chat/completions", post(chat_completions_handler))
-        // TODO: Add Anthropic endpoints
-        // .route("/v1/messages", post(server::anthropic::messages))
+        .route("/v1/messages", post(messages_handler))
         .layer(CorsLayer::permissive())
         .layer(TraceLayer::new_for_http())
         .with_state(app_state);
@@ -68,7 +77,39 @@ async fn main() -> Result<()> {
     tracing::info!("Starting server on {}", addr);
 
     let listener = tokio::net::TcpListener::bind(&addr).await?;
-    axum::serve(listener, app).await?;
+    let drain_timeout = config.server.drain_timeout();
+    let signal = {
+        let shutdown = shutdown.clone();
+        async move {
+            shutdown_signal().await;
+            shutdown.begin_drain(drain_timeout);
+        }
+    };
+    let server = match &config.server.tls {
+        Some(tls_config) => {
+            let tls = Arc::new(TlsListener::new(tls_config)?);
+            spawn_tls_reload(tls.clone());
+            tracing::info!(
+                client_certs = tls_config.client_ca_path.is_some(),
+                "Serving HTTPS"
+            );
+            serve_tls(listener, app, tls, signal).boxed()
+        }
+        None => axum::serve(listener, app).with_graceful_shutdown(signal).into_future().boxed(),
+    };
+
+    // Terminated requests end right away; don't wait forever on clients that stop reading
+    tokio::select! {
+        result = server => result?,
+        _ = async {
+            shutdown.terminated().await;
+            tokio::time::sleep(TERMINATE_GRACE).await;
+        } => tracing::warn!("Connections still open after termination, exiting"),
+    }
+    if let Err(e) = spend.flush().await {
+        tracing::warn!(error = %e, "Failed to write spend store");
+    }
+    tracing::info!("Server stopped");
 
     Ok(())
 }
@@ -0,0 +1,344 @@
+use crate::config::{ApiKeyConfig, AuthConfig};
+use crate::proxy::jwt::JwtValidator;
+use crate::types::{ProxyError, Result};
+use http::HeaderMap;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A client the proxy authenticated: by a configured key, or by a JWT whose claims
+/// were mapped to a key's permissions
+#[derive(Debug, Clone)]
+pub struct Principal {
+    pub key: Arc<ApiKeyConfig>,
+    /// Claims of the client's token; `None` for configured keys
+    pub claims: Option<Arc<Value>>,
+}
+
+/// The subject of the certificate a client presented on a mutual-TLS connection,
+/// attached to each of the connection's requests
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientIdentity {
+    /// The whole subject, e.g. `O=Acme, CN=svc-search`
+    pub subject: String,
+    pub common_name: Option<String>,
+}
+
+/// Client API keys by key value, client certificates by subject, and the validator of
+/// client JWTs
+#[derive(Default)]
+pub struct KeyStore {
+    keys: HashMap<String, Arc<ApiKeyConfig>>,
+    certs: HashMap<String, Arc<ApiKeyConfig>>,
+    jwt: Option<JwtValidator>,
+    enabled: bool,
+}
+
+impl KeyStore {
+    pub fn new(config: &AuthConfig) -> Result<Self> {
+        Ok(Self {
+            keys: config
+                .keys
+                .iter()
+                .map(|key| (key.key.clone(), Arc::new(key.clone())))
+                .collect(),
+            certs: config
+                .client_certs
+                .iter()
+                .map(|cert| (cert.subject.clone(), Arc::new(cert.client())))
+                .collect(),
+            jwt: config.jwt.as_ref().map(JwtValidator::new).transpose()?,
+            enabled: config.is_enabled(),
+        })
+    }
+
+    /// The client a request comes from, by its connection's client certificate or else
+    /// by the key or JWT it presents. `Ok(None)` when no client keys, certificates or
+    /// JWT auth are configured and the proxy is open.
+    pub async fn authenticate(
+        &self,
+        headers: &HeaderMap,
+        identity: Option<&ClientIdentity>,
+    ) -> Result<Option<Principal>> {
+        if !self.enabled {
+            return Ok(None);
+        }
+        if let Some(config) = identity.and_then(|identity| self.client_cert(identity)) {
+            return Ok(Some(Principal {
+                key: config.clone(),
+                claims: None,
+            }));
+        }
+        let key = client_api_key(headers)
+            .ok_or_else(|| ProxyError::Unauthorized("Missing API key".to_string()))?;
+        if let Some(config) = self.keys.get(key) {
+            return Ok(Some(Principal {
+                key: config.clone(),
+                claims: None,
+            }));
+        }
+        match &self.jwt {
+            Some(jwt) if JwtValidator::is_jwt(key) => {
+                let claims = jwt.validate(key).await?;
+                Ok(Some(Principal {
+                    key: Arc::new(jwt.client(&claims)?),
+                    claims: Some(Arc::new(claims)),
+                }))
+            }
+            _ => Err(ProxyError::Unauthorized("Invalid API key".to_string())),
+        }
+    }
+
+    /// The configured certificate client matching the subject's common name or the whole subject
+    fn client_cert(&self, identity: &ClientIdentity) -> Option<&Arc<ApiKeyConfig>> {
+        identity
+            .common_name
+            .as_ref()
+            .and_then(|name| self.certs.get(name))
+            .or_else(|| self.certs.get(&identity.subject))
+    }
+}
+
+impl std::fmt::Debug for KeyStore {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeyStore")
+            .field("keys", &self.keys.values().collect::<Vec<_>>())
+            .field("certs", &self.certs.keys().collect::<Vec<_>>())
+            .field("jwt", &self.jwt)
+            .field("enabled", &self.enabled)
+            .finish()
+    }
+}
+
+/// Check that `key` may request `model` and resolve the key's alias for it
+pub fn authorize<'a>(key: &'a ApiKeyConfig, model: &'a str) -> Result<&'a str> {
+    if !key.allows_model(model) {
+        return Err(ProxyError::Forbidden(format!(
+            "Client '{}' may not use model '{}'",
+            key.name, model
+        )));
+    }
+    Ok(key.resolve_alias(model))
+}
+
+/// The API key the client sent, in either protocol's header
+pub fn client_api_key(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get(http::header::AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.strip_prefix("Bearer "))
+        .or_else(|| headers.get("x-api-key").and_then(|v| v.to_str().ok()))
+        .map(str::trim)
+}
+
+/// Client headers without the client's proxy key, which must not reach backends
+pub fn without_client_key(headers: &HeaderMap) -> HeaderMap {
+    let mut headers = headers.clone();
+    headers.remove(http::header::AUTHORIZATION);
+    headers.remove("x-api-key");
+    headers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ClientCertConfig;
+
+    fn store() -> KeyStore {
+        KeyStore::new(&AuthConfig {
+            keys: vec![ApiKeyConfig {
+                name: "search-team".to_string(),
+                key: "sk-search".to_string(),
+                allowed_models: vec!["gpt-4".to_string(), "claude-*".to_string()],
+                aliases: [("gpt-4".to_string(), "gpt-4-mini".to_string())].into(),
+                metadata: [("team".to_string(), "search".to_string())].into(),
+                rate_limit: None,
+                priority: None,
+            }],
+            keys_file: None,
+            jwt: None,
+            client_certs: Vec::new(),
+        })
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_authenticate() {
+        let store = store();
+        let mut headers = HeaderMap::new();
+        assert!(matches!(store.authenticate(&headers, None).await, Err(ProxyError::Unauthorized(_))));
+
+        headers.insert("authorization", "Bearer sk-other".parse().unwrap());
+        assert!(matches!(store.authenticate(&headers, None).await, Err(ProxyError::Unauthorized(_))));
+
+        headers.insert("authorization", "Bearer sk-search".parse().unwrap());
+        assert_eq!(store.authenticate(&headers, None).await.unwrap().unwrap().key.name, "search-team");
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", "sk-search".parse().unwrap());
+        assert!(store.authenticate(&headers, None).await.unwrap().is_some());
+
+        // Without keys the proxy stays open
+        let open = KeyStore::new(&AuthConfig::default()).unwrap();
+        assert!(open.authenticate(&HeaderMap::new(), None).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_jwt_subject_does_not_share_a_key_clients_limits() {
+        use crate::config::{Config, RateLimitConfig};
+        use crate::proxy::jwt::tests::{claims, jwt_config, write_jwks, TestKey};
+        use crate::proxy::RateLimits;
+
+        let signing_key = TestKey::generate("key-1");
+        let path = write_jwks("jwks-collision", &[&signing_key]);
+        let store = KeyStore::new(&AuthConfig {
+            keys: vec![ApiKeyConfig {
+                name: "svc-search".to_string(),
+                key: "sk-search".to_string(),
+                allowed_models: vec!["*".to_string()],
+                aliases: HashMap::new(),
+                metadata: HashMap::new(),
+                rate_limit: Some(RateLimitConfig {
+                    requests_per_minute: Some(1),
+                    ..RateLimitConfig::default()
+                }),
+                priority: None,
+            }],
+            keys_file: None,
+            jwt: Some(jwt_config(Some(path.clone()), None)),
+            client_certs: Vec::new(),
+        })
+        .unwrap();
+        let config: Config = serde_yaml::from_str("server: {}\nmodels: {}\n").unwrap();
+        let limits = RateLimits::new(&config);
+
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", "Bearer sk-search".parse().unwrap());
+        let key = store.authenticate(&headers, None).await.unwrap().unwrap();
+        assert!(limits.admit_key(&key.key, 0).is_ok());
+        assert!(limits.admit_key(&key.key, 0).is_err());
+
+        // A token whose subject is the key's name is a different client
+        let token = signing_key.sign(&claims("svc-search", &["ml"]));
+        headers.insert("authorization", format!("Bearer {}", token).parse().unwrap());
+        let jwt = store.authenticate(&headers, None).await.unwrap().unwrap();
+        assert_ne!(jwt.key.name, key.key.name);
+        assert!(limits.admit_key(&jwt.key, 0).is_ok());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_jwt_alongside_keys() {
+        use crate::proxy::jwt::tests::{claims, jwt_config, write_jwks, TestKey};
+
+        let signing_key = TestKey::generate("key-1");
+        let path = write_jwks("jwks-auth", &[&signing_key]);
+        let store = KeyStore::new(&AuthConfig {
+            keys: vec![ApiKeyConfig {
+                name: "static".to_string(),
+                key: "sk-static".to_string(),
+                allowed_models: vec!["*".to_string()],
+                aliases: HashMap::new(),
+                metadata: HashMap::new(),
+                rate_limit: None,
+                priority: None,
+            }],
+            keys_file: None,
+            jwt: Some(jwt_config(Some(path.clone()), None)),
+            client_certs: Vec::new(),
+        })
+        .unwrap();
+
+        let mut headers = HeaderMap::new();
+        let token = signing_key.sign(&claims("svc-search", &["ml"]));
+        headers.insert("authorization", format!("Bearer {}", token).parse().unwrap());
+        let principal = store.authenticate(&headers, None).await.unwrap().unwrap();
+        assert_eq!(principal.key.name, "jwt:svc-search");
+        assert_eq!(principal.claims.unwrap()["groups"][0], "ml");
+        assert!(authorize(&principal.key, "claude-3-haiku").is_ok());
+        assert!(matches!(authorize(&principal.key, "gpt-4o"), Err(ProxyError::Forbidden(_))));
+
+        headers.insert("authorization", "Bearer sk-static".parse().unwrap());
+        assert_eq!(store.authenticate(&headers, None).await.unwrap().unwrap().key.name, "static");
+
+        headers.insert("authorization", format!("Bearer {}x", token).parse().unwrap());
+        assert!(matches!(store.authenticate(&headers, None).await, Err(ProxyError::Unauthorized(_))));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_authenticate_client_certs() {
+        let store = KeyStore::new(&AuthConfig {
+            keys: vec![ApiKeyConfig {
+                name: "static".to_string(),
+                key: "sk-static".to_string(),
+                allowed_models: vec!["*".to_string()],
+                aliases: HashMap::new(),
+                metadata: HashMap::new(),
+                rate_limit: None,
+                priority: None,
+            }],
+            keys_file: None,
+            jwt: None,
+            client_certs: vec![
+                ClientCertConfig {
+                    subject: "svc-search".to_string(),
+                    allowed_models: vec!["claude-*".to_string()],
+                    ..Default::default()
+                },
+                ClientCertConfig {
+                    subject: "O=Acme, CN=batch".to_string(),
+                    allowed_models: vec!["gpt-4".to_string()],
+                    ..Default::default()
+                },
+            ],
+        })
+        .unwrap();
+        let identity = |subject: &str, common_name: &str| ClientIdentity {
+            subject: subject.to_string(),
+            common_name: Some(common_name.to_string()),
+        };
+
+        let principal = store
+            .authenticate(&HeaderMap::new(), Some(&identity("O=Other, CN=svc-search", "svc-search")))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(principal.key.name, "svc-search");
+        assert!(authorize(&principal.key, "claude-3-haiku").is_ok());
+        assert!(matches!(authorize(&principal.key, "gpt-4"), Err(ProxyError::Forbidden(_))));
+
+        let principal = store
+            .authenticate(&HeaderMap::new(), Some(&identity("O=Acme, CN=batch", "batch")))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(principal.key.name, "O=Acme, CN=batch");
+
+        // Unknown certificates fall back to the key the request presents
+        let unknown = identity("O=Acme, CN=unknown", "unknown");
+        let result = store.authenticate(&HeaderMap::new(), Some(&unknown)).await;
+        assert!(matches!(result, Err(ProxyError::Unauthorized(_))));
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", "sk-static".parse().unwrap());
+        let principal = store.authenticate(&headers, Some(&unknown)).await.unwrap().unwrap();
+        assert_eq!(principal.key.name, "static");
+    }
+
+    #[test]
+    fn test_authorize_models_and_aliases() {
+        let store = store();
+        let key = store.keys["sk-search"].clone();
+        assert_eq!(authorize(&key, "gpt-4").unwrap(), "gpt-4-mini");
+        assert_eq!(authorize(&key, "claude-3-opus").unwrap(), "claude-3-opus");
+        assert!(matches!(authorize(&key, "gpt-4o"), Err(ProxyError::Forbidden(_))));
+    }
+
+    #[test]
+    fn test_key_is_not_printed() {
+        let store = store();
+        let printed = format!("{:?}", store);
+        assert!(printed.contains("search-team"));
+        assert!(!printed.contains("sk-search"));
+    }
+}
@@ -0,0 +1,362 @@
+use crate::config::{CircuitBreakerConfig, ConcurrencyConfig, EndpointConfig, LoadBalancing};
+use crate::proxy::circuit_breaker::{CircuitBreaker, CircuitStatus};
+use crate::proxy::health::{EndpointHealth, HealthStatus};
+use crate::proxy::limiter::{ConcurrencyLimiter, LimitPermit, LimiterStatus};
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Smoothing factor for the latency moving average (weight of the newest sample)
+const EWMA_ALPHA: f64 = 0.3;
+
+/// One upstream replica of a model, with the live statistics used for balancing
+#[derive(Debug)]
+pub struct Endpoint {
+    pub url: String,
+    pub api_key: Option<String>,
+    pub weight: u32,
+    in_flight: AtomicUsize,
+    /// Moving average of time-to-response-headers in microseconds; 0 until first sample
+    ewma_latency_us: AtomicU64,
+    pub circuit: CircuitBreaker,
+    pub health: EndpointHealth,
+    pub limiter: Arc<ConcurrencyLimiter>,
+}
+
+/// Point-in-time view of an endpoint for the status endpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct EndpointStatus {
+    pub url: String,
+    pub weight: u32,
+    pub in_flight: usize,
+    pub ewma_latency_ms: Option<f64>,
+    pub circuit: CircuitStatus,
+    pub health: HealthStatus,
+    pub concurrency: LimiterStatus,
+}
+
+impl Endpoint {
+    pub fn new(
+        config: &EndpointConfig,
+        circuit_breaker: &CircuitBreakerConfig,
+        concurrency: &ConcurrencyConfig,
+    ) -> Self {
+        Self {
+            url: config.url.clone(),
+            api_key: config.api_key.clone(),
+            weight: config.weight,
+            in_flight: AtomicUsize::new(0),
+            ewma_latency_us: AtomicU64::new(0),
+            circuit: CircuitBreaker::new(circuit_breaker.clone()),
+            health: EndpointHealth::new(),
+            limiter: Arc::new(ConcurrencyLimiter::new(
+                &config.url,
+                config.max_concurrent,
+                concurrency,
+            )),
+        }
+    }
+
+    /// Whether the endpoint is in rotation: passing health checks and circuit not open
+    pub fn is_available(&self) -> bool {
+        self.health.is_healthy() && self.circuit.is_available()
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    pub fn ewma_latency(&self) -> Option<Duration> {
+        match self.ewma_latency_us.load(Ordering::Relaxed) {
+            0 => None,
+            us => Some(Duration::from_micros(us)),
+        }
+    }
+
+    pub fn record_latency(&self, latency: Duration) {
+        let sample = latency.as_micros().max(1) as u64;
+        let _ = self
+            .ewma_latency_us
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+                Some(if current == 0 {
+                    sample
+                } else {
+                    (EWMA_ALPHA * sample as f64 + (1.0 - EWMA_ALPHA) * current as f64) as u64
+                })
+            });
+    }
+
+    pub fn status(&self) -> EndpointStatus {
+        EndpointStatus {
+            url: self.url.clone(),
+            weight: self.weight,
+            in_flight: self.in_flight(),
+            ewma_latency_ms: self.ewma_latency().map(|d| d.as_secs_f64() * 1000.0),
+            circuit: self.circuit.status(),
+            health: self.health.status(),
+            concurrency: self.limiter.status(),
+        }
+    }
+
+    /// Count a request as in flight until the returned guard is dropped
+    pub fn start_request(self: &Arc<Self>) -> InFlightGuard {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlightGuard {
+            endpoint: self.clone(),
+            permit: None,
+        }
+    }
+
+    /// Like `start_request`, additionally holding a concurrency slot until the guard drops
+    pub fn start_limited_request(self: &Arc<Self>, permit: LimitPermit) -> InFlightGuard {
+        let mut guard = self.start_request();
+        guard.permit = Some(permit);
+        guard
+    }
+}
+
+/// Keeps an endpoint's in-flight count raised (and its concurrency slot taken) for the
+/// lifetime of a request or its stream
+#[derive(Debug)]
+pub struct InFlightGuard {
+    endpoint: Arc<Endpoint>,
+    permit: Option<LimitPermit>,
+}
+
+impl InFlightGuard {
+    pub fn endpoint(&self) -> &Arc<Endpoint> {
+        &self.endpoint
+    }
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.endpoint.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Picks an endpoint for each attempt according to the model's balancing strategy
+#[derive(Debug)]
+pub struct LoadBalancer {
+    strategy: LoadBalancing,
+    next: AtomicUsize,
+}
+
+impl LoadBalancer {
+    pub fn new(strategy: LoadBalancing) -> Self {
+        Self {
+            strategy,
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn strategy(&self) -> LoadBalancing {
+        self.strategy
+    }
+
+    /// Select an endpoint index, preferring endpoints not listed in `exclude`
+    /// (the ones already tried for this request). Once every endpoint has been
+    /// tried, all of them become eligible again. Unhealthy endpoints and endpoints
+    /// whose circuit is open are never selected; `None` means none is available.
+    /// Endpoints at their concurrency limit are only picked (to queue) when every
+    /// available endpoint is at its limit.
+    pub fn select(&self, endpoints: &[Arc<Endpoint>], exclude: &[usize]) -> Option<usize> {
+        let mut available: Vec<usize> = (0..endpoints.len())
+            .filter(|&idx| endpoints[idx].is_available())
+            .collect();
+        if available.iter().any(|&idx| endpoints[idx].limiter.has_capacity()) {
+            available.retain(|&idx| endpoints[idx].limiter.has_capacity());
+        }
+        let mut candidates: Vec<usize> = available
+            .iter()
+            .copied()
+            .filter(|idx| !exclude.contains(idx))
+            .collect();
+        if candidates.is_empty() {
+            candidates = available;
+        }
+        match candidates.len() {
+            0 => return None,
+            1 => return Some(candidates[0]),
+            _ => {}
+        }
+
+        // Rotating start point so ties are spread instead of always hitting the first replica
+        let offset = self.next.fetch_add(1, Ordering::Relaxed) % candidates.len();
+        candidates.rotate_left(offset);
+
+        let idx = match self.strategy {
+            LoadBalancing::RoundRobin => candidates[0],
+            LoadBalancing::WeightedRandom => {
+                let total: u64 = candidates.iter().map(|&i| endpoints[i].weight as u64).sum();
+                let mut pick = (rand::random::<f64>() * total as f64) as u64;
+                for &idx in &candidates {
+                    let weight = endpoints[idx].weight as u64;
+                    if pick < weight {
+                        return Some(idx);
+                    }
+                    pick -= weight;
+                }
+                candidates[candidates.len() - 1]
+            }
+            LoadBalancing::LeastInFlight => candidates
+                .iter()
+                .copied()
+                .min_by_key(|&i| endpoints[i].in_flight())
+                .unwrap_or(candidates[0]),
+            LoadBalancing::EwmaLatency => candidates
+                .iter()
+                .copied()
+                // Unmeasured endpoints score 0 so they get probed first
+                .min_by_key(|&i| {
+                    let latency = endpoints[i].ewma_latency().map_or(0, |d| d.as_micros());
+                    latency * (endpoints[i].in_flight() as u128 + 1)
+                })
+                .unwrap_or(candidates[0]),
+        };
+        Some(idx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Priority;
+
+    fn endpoints(weights: &[u32]) -> Vec<Arc<Endpoint>> {
+        weights
+            .iter()
+            .enumerate()
+            .map(|(i, &weight)| {
+                Arc::new(Endpoint::new(
+                    &EndpointConfig {
+                        url: format!("http://replica-{}", i),
+                        api_key: None,
+                        weight,
+                        max_concurrent: None,
+                    },
+                    &CircuitBreakerConfig {
+                        enabled: true,
+                        consecutive_failures: 1,
+                        ..CircuitBreakerConfig::default()
+                    },
+                    &ConcurrencyConfig::default(),
+                ))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_round_robin_cycles() {
+        let balancer = LoadBalancer::new(LoadBalancing::RoundRobin);
+        let eps = endpoints(&[1, 1, 1]);
+        let picks: Vec<usize> = (0..6).map(|_| balancer.select(&eps, &[]).unwrap()).collect();
+        assert_eq!(picks, vec![0, 1, 2, 0, 1, 2]);
+    }
+
+    #[test]
+    fn test_select_prefers_untried_endpoints() {
+        let balancer = LoadBalancer::new(LoadBalancing::RoundRobin);
+        let eps = endpoints(&[1, 1]);
+        for _ in 0..4 {
+            assert_eq!(balancer.select(&eps, &[0]), Some(1));
+        }
+        // Everything tried: fall back to the full set
+        let pick = balancer.select(&eps, &[0, 1]).unwrap();
+        assert!(pick < 2);
+    }
+
+    #[test]
+    fn test_weighted_random_respects_weights() {
+        let balancer = LoadBalancer::new(LoadBalancing::WeightedRandom);
+        let eps = endpoints(&[1, 9]);
+        let heavy = (0..1000).filter(|_| balancer.select(&eps, &[]) == Some(1)).count();
+        assert!(heavy > 800, "heavy endpoint picked {} times", heavy);
+    }
+
+    #[test]
+    fn test_least_in_flight() {
+        let balancer = LoadBalancer::new(LoadBalancing::LeastInFlight);
+        let eps = endpoints(&[1, 1, 1]);
+        let _a = eps[0].start_request();
+        let _b = eps[2].start_request();
+        assert_eq!(balancer.select(&eps, &[]), Some(1));
+    }
+
+    #[test]
+    fn test_skips_open_circuits() {
+        let balancer = LoadBalancer::new(LoadBalancing::RoundRobin);
+        let eps = endpoints(&[1, 1]);
+        eps[0].circuit.try_acquire().unwrap().record_failure();
+        for _ in 0..4 {
+            assert_eq!(balancer.select(&eps, &[]), Some(1));
+        }
+        // Already-tried endpoints are still preferred over open circuits
+        assert_eq!(balancer.select(&eps, &[1]), Some(1));
+
+        eps[1].circuit.try_acquire().unwrap().record_failure();
+        assert_eq!(balancer.select(&eps, &[]), None);
+    }
+
+    #[tokio::test]
+    async fn test_prefers_endpoints_below_concurrency_limit() {
+        let balancer = LoadBalancer::new(LoadBalancing::RoundRobin);
+        let config = |i: usize| EndpointConfig {
+            url: format!("http://replica-{}", i),
+            api_key: None,
+            weight: 1,
+            max_concurrent: Some(1),
+        };
+        let eps: Vec<Arc<Endpoint>> = (0..2)
+            .map(|i| {
+                Arc::new(Endpoint::new(
+                    &config(i),
+                    &CircuitBreakerConfig::default(),
+                    &ConcurrencyConfig::default(),
+                ))
+            })
+            .collect();
+
+        let held = eps[0].limiter.acquire(Priority::Normal, None).await.unwrap();
+        for _ in 0..4 {
+            assert_eq!(balancer.select(&eps, &[]), Some(1));
+        }
+        // Both full: still pick one so the request can queue
+        let _other = eps[1].limiter.acquire(Priority::Normal, None).await.unwrap();
+        assert!(balancer.select(&eps, &[]).is_some());
+        drop(held);
+        assert_eq!(balancer.select(&eps, &[]), Some(0));
+    }
+
+    #[test]
+    fn test_in_flight_guard_releases() {
+        let eps = endpoints(&[1]);
+        {
+            let _guard = eps[0].start_request();
+            assert_eq!(eps[0].in_flight(), 1);
+        }
+        assert_eq!(eps[0].in_flight(), 0);
+    }
+
+    #[test]
+    fn test_ewma_prefers_fast_endpoint() {
+        let balancer = LoadBalancer::new(LoadBalancing::EwmaLatency);
+        let eps = endpoints(&[1, 1]);
+        eps[0].record_latency(Duration::from_millis(500));
+        eps[1].record_latency(Duration::from_millis(50));
+        for _ in 0..4 {
+            assert_eq!(balancer.select(&eps, &[]), Some(1));
+        }
+    }
+
+    #[test]
+    fn test_ewma_smoothing() {
+        let eps = endpoints(&[1]);
+        assert!(eps[0].ewma_latency().is_none());
+        eps[0].record_latency(Duration::from_millis(100));
+        eps[0].record_latency(Duration::from_millis(200));
+        let latency = eps[0].ewma_latency().unwrap().as_millis();
+        assert!((129..=131).contains(&latency), "latency {}", latency);
+    }
+}
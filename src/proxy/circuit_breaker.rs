@@ -0,0 +1,311 @@
+use crate::config::CircuitBreakerConfig;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CircuitStatus {
+    pub state: CircuitState,
+    pub consecutive_failures: u32,
+    pub window_error_rate: f64,
+    pub times_opened: u64,
+}
+
+#[derive(Debug)]
+struct Inner {
+    state: CircuitState,
+    consecutive_failures: u32,
+    /// Outcomes of the most recent requests, `true` for failure
+    window: VecDeque<bool>,
+    opened_at: Option<Instant>,
+    half_open_in_flight: u32,
+    times_opened: u64,
+}
+
+/// Per-endpoint circuit breaker.
+///
+/// Opens after `consecutive_failures` failures in a row, or when the failure rate over
+/// the last `window_size` requests reaches `error_rate_threshold`. After `open_seconds`
+/// a limited number of probe requests are let through (half-open); a successful probe
+/// closes the circuit, a failed one re-opens it.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    config: CircuitBreakerConfig,
+    inner: Arc<Mutex<Inner>>,
+}
+
+/// Permission to send one request through the breaker.
+///
+/// Dropping a permit without recording an outcome (e.g. the client went away)
+/// releases its half-open probe slot without affecting the circuit.
+#[derive(Debug)]
+pub struct CircuitPermit {
+    inner: Option<Arc<Mutex<Inner>>>,
+    config: CircuitBreakerConfig,
+    probe: bool,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config,
+            inner: Arc::new(Mutex::new(Inner {
+                state: CircuitState::Closed,
+                consecutive_failures: 0,
+                window: VecDeque::new(),
+                opened_at: None,
+                half_open_in_flight: 0,
+                times_opened: 0,
+            })),
+        }
+    }
+
+    fn open_duration(&self) -> Duration {
+        Duration::from_secs(self.config.open_seconds)
+    }
+
+    /// Whether a request could currently be admitted (does not reserve a probe slot)
+    pub fn is_available(&self) -> bool {
+        if !self.config.enabled {
+            return true;
+        }
+        let inner = self.inner.lock().unwrap();
+        match inner.state {
+            CircuitState::Closed => true,
+            CircuitState::Open => inner
+                .opened_at
+                .is_some_and(|at| at.elapsed() >= self.open_duration()),
+            CircuitState::HalfOpen => inner.half_open_in_flight < self.config.half_open_max_requests,
+        }
+    }
+
+    /// Reserve permission to send a request, or `None` when the circuit is open
+    pub fn try_acquire(&self) -> Option<CircuitPermit> {
+        if !self.config.enabled {
+            return Some(CircuitPermit {
+                inner: None,
+                config: self.config.clone(),
+                probe: false,
+            });
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        if inner.state == CircuitState::Open
+            && inner
+                .opened_at
+                .is_some_and(|at| at.elapsed() >= self.open_duration())
+        {
+            inner.state = CircuitState::HalfOpen;
+            inner.half_open_in_flight = 0;
+        }
+
+        let probe = match inner.state {
+            CircuitState::Closed => false,
+            CircuitState::Open => return None,
+            CircuitState::HalfOpen => {
+                if inner.half_open_in_flight >= self.config.half_open_max_requests {
+                    return None;
+                }
+                inner.half_open_in_flight += 1;
+                true
+            }
+        };
+
+        Some(CircuitPermit {
+            inner: Some(self.inner.clone()),
+            config: self.config.clone(),
+            probe,
+        })
+    }
+
+    pub fn state(&self) -> CircuitState {
+        self.inner.lock().unwrap().state
+    }
+
+    pub fn status(&self) -> CircuitStatus {
+        let inner = self.inner.lock().unwrap();
+        CircuitStatus {
+            state: inner.state,
+            consecutive_failures: inner.consecutive_failures,
+            window_error_rate: error_rate(&inner.window),
+            times_opened: inner.times_opened,
+        }
+    }
+}
+
+impl CircuitPermit {
+    pub fn record_success(mut self) {
+        if let Some(inner) = self.inner.take() {
+            let mut inner = inner.lock().unwrap();
+            if self.probe {
+                inner.half_open_in_flight = inner.half_open_in_flight.saturating_sub(1);
+            }
+            push_outcome(&mut inner, &self.config, false);
+            inner.consecutive_failures = 0;
+            if inner.state == CircuitState::HalfOpen {
+                tracing::info!("Circuit closed after successful probe");
+                inner.state = CircuitState::Closed;
+                inner.window.clear();
+                inner.opened_at = None;
+            }
+        }
+    }
+
+    pub fn record_failure(mut self) {
+        if let Some(inner) = self.inner.take() {
+            let mut inner = inner.lock().unwrap();
+            if self.probe {
+                inner.half_open_in_flight = inner.half_open_in_flight.saturating_sub(1);
+            }
+            push_outcome(&mut inner, &self.config, true);
+            inner.consecutive_failures += 1;
+
+            let trip = match inner.state {
+                CircuitState::HalfOpen => true,
+                CircuitState::Closed => {
+                    inner.consecutive_failures >= self.config.consecutive_failures
+                        || (inner.window.len() >= self.config.min_requests
+                            && error_rate(&inner.window) >= self.config.error_rate_threshold)
+                }
+                CircuitState::Open => false,
+            };
+            if trip {
+                tracing::warn!(
+                    consecutive_failures = inner.consecutive_failures,
+                    error_rate = error_rate(&inner.window),
+                    "Circuit opened"
+                );
+                inner.state = CircuitState::Open;
+                inner.opened_at = Some(Instant::now());
+                inner.times_opened += 1;
+            }
+        }
+    }
+}
+
+impl Drop for CircuitPermit {
+    fn drop(&mut self) {
+        if let Some(inner) = self.inner.take() {
+            if self.probe {
+                let mut inner = inner.lock().unwrap();
+                inner.half_open_in_flight = inner.half_open_in_flight.saturating_sub(1);
+            }
+        }
+    }
+}
+
+fn push_outcome(inner: &mut Inner, config: &CircuitBreakerConfig, failed: bool) {
+    inner.window.push_back(failed);
+    while inner.window.len() > config.window_size {
+        inner.window.pop_front();
+    }
+}
+
+fn error_rate(window: &VecDeque<bool>) -> f64 {
+    if window.is_empty() {
+        return 0.0;
+    }
+    window.iter().filter(|failed| **failed).count() as f64 / window.len() as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> CircuitBreakerConfig {
+        CircuitBreakerConfig {
+            enabled: true,
+            consecutive_failures: 3,
+            error_rate_threshold: 0.5,
+            window_size: 10,
+            min_requests: 6,
+            open_seconds: 0,
+            half_open_max_requests: 1,
+        }
+    }
+
+    #[test]
+    fn test_opens_after_consecutive_failures() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            open_seconds: 60,
+            ..config()
+        });
+        for _ in 0..3 {
+            breaker.try_acquire().unwrap().record_failure();
+        }
+        assert_eq!(breaker.state(), CircuitState::Open);
+        assert!(breaker.try_acquire().is_none());
+        assert!(!breaker.is_available());
+        assert_eq!(breaker.status().times_opened, 1);
+    }
+
+    #[test]
+    fn test_opens_on_error_rate() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            consecutive_failures: 100,
+            open_seconds: 60,
+            ..config()
+        });
+        for _ in 0..3 {
+            breaker.try_acquire().unwrap().record_success();
+            breaker.try_acquire().unwrap().record_failure();
+        }
+        assert_eq!(breaker.state(), CircuitState::Open);
+    }
+
+    #[test]
+    fn test_half_open_probe_closes_circuit() {
+        let breaker = CircuitBreaker::new(config());
+        for _ in 0..3 {
+            breaker.try_acquire().unwrap().record_failure();
+        }
+        // open_seconds = 0: the next acquire is a half-open probe
+        let probe = breaker.try_acquire().unwrap();
+        assert_eq!(breaker.state(), CircuitState::HalfOpen);
+        assert!(breaker.try_acquire().is_none(), "only one probe at a time");
+        probe.record_success();
+        assert_eq!(breaker.state(), CircuitState::Closed);
+    }
+
+    #[test]
+    fn test_failed_probe_reopens() {
+        let breaker = CircuitBreaker::new(config());
+        for _ in 0..3 {
+            breaker.try_acquire().unwrap().record_failure();
+        }
+        breaker.try_acquire().unwrap().record_failure();
+        assert_eq!(breaker.status().times_opened, 2);
+    }
+
+    #[test]
+    fn test_dropped_probe_releases_slot() {
+        let breaker = CircuitBreaker::new(config());
+        for _ in 0..3 {
+            breaker.try_acquire().unwrap().record_failure();
+        }
+        drop(breaker.try_acquire().unwrap());
+        assert!(breaker.try_acquire().is_some());
+    }
+
+    #[test]
+    fn test_disabled_never_opens() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            enabled: false,
+            ..config()
+        });
+        for _ in 0..10 {
+            breaker.try_acquire().unwrap().record_failure();
+        }
+        assert_eq!(breaker.state(), CircuitState::Closed);
+        assert!(breaker.is_available());
+    }
+}
@@ -1,36 +1,124 @@
-use crate::config::ModelConfig;
+use crate::config::{LoadBalancing, ModelConfig, OutboundProxyConfig};
+use crate::proxy::balancer::{Endpoint, EndpointStatus, LoadBalancer};
+use crate::proxy::tls::client_tls_config;
+use crate::transform::PiiRedactor;
 use crate::types::{ProxyError, Result};
-use reqwest::{Client, ClientBuilder};
+use reqwest::{Client, ClientBuilder, NoProxy, Proxy};
+use serde::Serialize;
 use std::sync::Arc;
 use std::time::Duration;
 
+/// Point-in-time view of a model's endpoints for the status endpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct ModelStatus {
+    pub backend: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub variant: Option<String>,
+    pub load_balancing: LoadBalancing,
+    pub endpoints: Vec<EndpointStatus>,
+}
+
+/// The configured proxy for all of a model's requests, except to `no_proxy` hosts
+fn outbound_proxy(config: &OutboundProxyConfig) -> Result<Proxy> {
+    let url = config.url.as_deref().unwrap_or_default();
+    let mut proxy = Proxy::all(url)
+        .map_err(|e| ProxyError::Config(format!("Invalid proxy url '{}': {}", url, e)))?;
+    if let Some(username) = &config.username {
+        proxy = proxy.basic_auth(username, config.password.as_deref().unwrap_or_default());
+    }
+    Ok(proxy.no_proxy(NoProxy::from_string(&config.no_proxy.join(","))))
+}
+
 pub struct ProxyClient {
     client: Client,
     config: Arc<ModelConfig>,
+    endpoints: Vec<Arc<Endpoint>>,
+    balancer: LoadBalancer,
+    variant: Option<String>,
+    /// Built once from the model's `pii` config, as its patterns are costly to compile
+    pii: Option<Arc<PiiRedactor>>,
 }
 
 impl ProxyClient {
     pub fn new(config: Arc<ModelConfig>) -> Result<Self> {
+        // Other phases are timed per attempt; a client-wide timeout would cut off long streams
         let mut builder = ClientBuilder::new()
-            .timeout(config.timeout_duration())
-            .connect_timeout(Duration::from_secs(10))
+            .connect_timeout(config.connect_timeout())
             .pool_max_idle_per_host(10)
             .pool_idle_timeout(Duration::from_secs(90));
 
         // Disable SSL verification if configured
         if !config.ssl_verify {
             tracing::warn!(
-                endpoint = %config.endpoint,
+                endpoint = %config.resolved_endpoints()[0].url,
                 "SSL verification is disabled for this backend"
             );
             builder = builder.danger_accept_invalid_certs(true);
         }
+        if let Some(tls) = &config.tls {
+            builder = builder.use_preconfigured_tls(client_tls_config(tls)?);
+        }
+        // Proxy environment variables only apply to models that opt in
+        match &config.proxy {
+            Some(proxy) if proxy.from_env => {}
+            Some(proxy) => builder = builder.proxy(outbound_proxy(proxy)?),
+            None => builder = builder.no_proxy(),
+        }
 
         let client = builder
             .build()
             .map_err(|e| ProxyError::Config(format!("Failed to create HTTP client: {}", e)))?;
 
-        Ok(Self { client, config })
+        let endpoints = config
+            .resolved_endpoints()
+            .iter()
+            .map(|endpoint| {
+                Arc::new(Endpoint::new(
+                    endpoint,
+                    &config.circuit_breaker,
+                    &config.concurrency,
+                ))
+            })
+            .collect();
+        let balancer = LoadBalancer::new(config.load_balancing);
+        let pii = config.pii.as_ref().map(PiiRedactor::new).transpose()?.map(Arc::new);
+
+        Ok(Self {
+            client,
+            config,
+            endpoints,
+            balancer,
+            variant: None,
+            pii,
+        })
+    }
+
+    /// Client for one weighted variant of a model; `config` is the merged variant config
+    pub fn for_variant(config: Arc<ModelConfig>, variant: &str) -> Result<Self> {
+        let mut client = Self::new(config)?;
+        client.variant = Some(variant.to_string());
+        Ok(client)
+    }
+
+    /// Name of the variant this client serves, if the model is split into variants
+    pub fn variant(&self) -> Option<&str> {
+        self.variant.as_deref()
+    }
+
+    /// Backend name for logs, including the variant (e.g. `anthropic/canary`)
+    pub fn backend_label(&self) -> String {
+        match &self.variant {
+            Some(variant) => format!("{}/{}", self.config.backend_type.as_str(), variant),
+            None => self.config.backend_type.as_str().to_string(),
+        }
+    }
+
+    /// Name for status output: the model, plus the variant if there is one
+    pub fn label(&self, model: &str) -> String {
+        match &self.variant {
+            Some(variant) => format!("{}/{}", model, variant),
+            None => model.to_string(),
+        }
     }
 
     pub fn client(&self) -> &Client {
@@ -41,31 +129,65 @@ impl ProxyClient {
         &self.config
     }
 
+    /// URL of the first configured endpoint
     pub fn endpoint(&self) -> &str {
-        &self.config.endpoint
+        &self.endpoints[0].url
+    }
+
+    /// Redactor for the model's `pii` config, if it has one
+    pub fn pii(&self) -> Option<&Arc<PiiRedactor>> {
+        self.pii.as_ref()
     }
 
     pub fn api_key(&self) -> Option<&str> {
         self.config.api_key.as_deref()
     }
+
+    pub fn endpoints(&self) -> &[Arc<Endpoint>] {
+        &self.endpoints
+    }
+
+    pub fn status(&self) -> ModelStatus {
+        ModelStatus {
+            backend: self.config.backend_type.as_str(),
+            variant: self.variant.clone(),
+            load_balancing: self.config.load_balancing,
+            endpoints: self.endpoints.iter().map(|e| e.status()).collect(),
+        }
+    }
+
+    /// Pick the endpoint for the next attempt, avoiding the indices in `tried` when possible.
+    /// Fails fast when every endpoint is unhealthy or has an open circuit.
+    pub fn select_endpoint(&self, tried: &[usize]) -> Result<(usize, Arc<Endpoint>)> {
+        let idx = self.balancer.select(&self.endpoints, tried).ok_or_else(|| {
+            if self.endpoints.iter().all(|e| !e.health.is_healthy()) {
+                ProxyError::NoHealthyEndpoint(format!(
+                    "all {} endpoint(s) failing health checks",
+                    self.endpoints.len()
+                ))
+            } else {
+                ProxyError::CircuitOpen(format!(
+                    "all {} endpoint(s) unavailable",
+                    self.endpoints.len()
+                ))
+            }
+        })?;
+        Ok((idx, self.endpoints[idx].clone()))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{BackendType, HeaderConfig, RetryConfig, TransformConfig};
+    use crate::config::{BackendType, CircuitBreakerConfig, EndpointConfig, TlsVersion, UpstreamTlsConfig};
+    use crate::proxy::tls::{read_certs, spki_sha256};
 
     fn create_test_config(ssl_verify: bool) -> ModelConfig {
         ModelConfig {
-            backend_type: BackendType::OpenAI,
-            endpoint: "https://api.openai.com/v1/chat/completions".to_string(),
             api_key: Some("test-key".to_string()),
-            target_model: None,
             timeout_seconds: 30,
-            retry: RetryConfig::default(),
             ssl_verify,
-            headers: HeaderConfig::default(),
-            transforms: TransformConfig::default(),
+            ..ModelConfig::test(BackendType::OpenAI, "https://api.openai.com/v1/chat/completions")
         }
     }
 
@@ -83,6 +205,123 @@ mod tests {
         assert!(client.is_ok());
     }
 
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tls");
+
+    fn fixture(name: &str) -> String {
+        format!("{}/{}", FIXTURES, name)
+    }
+
+    /// An HTTPS server with a certificate from the test CA
+    async fn tls_server(require_client_cert: bool) -> String {
+        use crate::config::TlsConfig;
+        use crate::server::{serve_tls, TlsListener};
+
+        let tls = TlsListener::new(&TlsConfig {
+            cert_path: fixture("server.pem"),
+            key_path: fixture("server.key"),
+            client_ca_path: require_client_cert.then(|| fixture("ca.pem")),
+            require_client_cert,
+            reload_interval_seconds: 10,
+        })
+        .unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("https://localhost:{}/", listener.local_addr().unwrap().port());
+        let app = axum::Router::new().route("/", axum::routing::get(|| async { "ok" }));
+        tokio::spawn(serve_tls(listener, app, Arc::new(tls), std::future::pending()));
+        url
+    }
+
+    async fn get_with(tls: Option<UpstreamTlsConfig>, url: &str) -> Result<String> {
+        let mut config = create_test_config(true);
+        config.tls = tls;
+        let client = ProxyClient::new(Arc::new(config))?;
+        Ok(client.client().get(url).send().await?.text().await?)
+    }
+
+    #[tokio::test]
+    async fn test_upstream_ca_bundle_and_pinning() {
+        let url = tls_server(false).await;
+        // The test CA is not a public root
+        assert!(get_with(None, &url).await.is_err());
+
+        let trusted = UpstreamTlsConfig {
+            ca_bundle: Some(fixture("ca.pem")),
+            ..Default::default()
+        };
+        assert_eq!(get_with(Some(trusted.clone()), &url).await.unwrap(), "ok");
+        let tls13 = UpstreamTlsConfig {
+            min_version: TlsVersion::Tls13,
+            ..trusted.clone()
+        };
+        assert_eq!(get_with(Some(tls13), &url).await.unwrap(), "ok");
+
+        let pin = |name: &str| spki_sha256(&read_certs(&fixture(name)).unwrap()[0]).unwrap();
+        let pinned = UpstreamTlsConfig {
+            pinned_spki_sha256: vec![pin("client.pem"), pin("server.pem")],
+            ..trusted.clone()
+        };
+        assert_eq!(get_with(Some(pinned), &url).await.unwrap(), "ok");
+        let mispinned = UpstreamTlsConfig {
+            pinned_spki_sha256: vec![pin("server-renewed.pem")],
+            ..trusted
+        };
+        assert!(get_with(Some(mispinned), &url).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_upstream_client_certificate() {
+        let url = tls_server(true).await;
+        let trusted = UpstreamTlsConfig {
+            ca_bundle: Some(fixture("ca.pem")),
+            ..Default::default()
+        };
+        assert!(get_with(Some(trusted.clone()), &url).await.is_err());
+
+        let with_cert = UpstreamTlsConfig {
+            client_cert: Some(fixture("client.pem")),
+            client_key: Some(fixture("client.key")),
+            ..trusted
+        };
+        assert_eq!(get_with(Some(with_cert), &url).await.unwrap(), "ok");
+    }
+
+    #[tokio::test]
+    async fn test_outbound_proxy_and_no_proxy() {
+        let mut proxy = mockito::Server::new_async().await;
+        let mut direct = mockito::Server::new_async().await;
+        // "user:secret"
+        let via_proxy = proxy
+            .mock("GET", "/v1/models")
+            .match_header("proxy-authorization", "Basic dXNlcjpzZWNyZXQ=")
+            .with_body("proxy")
+            .expect(1)
+            .create_async()
+            .await;
+        direct.mock("GET", "/v1/models").with_body("direct").create_async().await;
+        let url = format!("{}/v1/models", direct.url());
+
+        let client = |no_proxy: &[&str]| {
+            let mut config = create_test_config(true);
+            config.proxy = Some(OutboundProxyConfig {
+                url: Some(proxy.url()),
+                username: Some("user".to_string()),
+                password: Some("secret".to_string()),
+                no_proxy: no_proxy.iter().map(|host| host.to_string()).collect(),
+                from_env: false,
+            });
+            ProxyClient::new(Arc::new(config)).unwrap()
+        };
+        let get = |client: ProxyClient| {
+            let url = url.clone();
+            async move { client.client().get(url).send().await.unwrap().text().await.unwrap() }
+        };
+
+        assert_eq!(get(client(&[])).await, "proxy");
+        assert_eq!(get(client(&["localhost", "127.0.0.0/8"])).await, "direct");
+        assert_eq!(get(ProxyClient::new(Arc::new(create_test_config(true))).unwrap()).await, "direct");
+        via_proxy.assert_async().await;
+    }
+
     #[test]
     fn test_client_accessors() {
         let config = Arc::new(create_test_config(true));
@@ -94,4 +333,49 @@ mod tests {
         );
         assert_eq!(client.api_key(), Some("test-key"));
     }
+
+    #[test]
+    fn test_select_endpoint_rotates_replicas() {
+        let mut config = create_test_config(true);
+        config.endpoints = vec![
+            EndpointConfig {
+                url: "http://replica-a/v1".to_string(),
+                api_key: None,
+                weight: 1,
+                max_concurrent: None,
+            },
+            EndpointConfig {
+                url: "http://replica-b/v1".to_string(),
+                api_key: Some("key-b".to_string()),
+                weight: 1,
+                max_concurrent: None,
+            },
+        ];
+        let client = ProxyClient::new(Arc::new(config)).unwrap();
+
+        assert_eq!(client.endpoints().len(), 2);
+        let (first, endpoint) = client.select_endpoint(&[]).unwrap();
+        let (second, _) = client.select_endpoint(&[first]).unwrap();
+        assert_ne!(first, second);
+        let expected_key = if first == 0 { "test-key" } else { "key-b" };
+        assert_eq!(endpoint.api_key.as_deref(), Some(expected_key));
+    }
+
+    #[test]
+    fn test_select_endpoint_fails_fast_when_circuit_open() {
+        let mut config = create_test_config(true);
+        config.circuit_breaker = CircuitBreakerConfig {
+            enabled: true,
+            consecutive_failures: 1,
+            ..CircuitBreakerConfig::default()
+        };
+        let client = ProxyClient::new(Arc::new(config)).unwrap();
+
+        let (_, endpoint) = client.select_endpoint(&[]).unwrap();
+        endpoint.circuit.try_acquire().unwrap().record_failure();
+
+        let err = client.select_endpoint(&[]).unwrap_err();
+        assert!(matches!(err, ProxyError::CircuitOpen(_)));
+        assert_eq!(err.status_code(), 503);
+    }
 }
@@ -0,0 +1,101 @@
+use crate::types::{ProxyError, TimeoutPhase};
+
+/// Fragments of upstream error bodies that indicate the prompt overflowed the context window
+const CONTEXT_LENGTH_MARKERS: &[&str] = &[
+    "context_length_exceeded",
+    "maximum context length",
+    "context window",
+    "prompt is too long",
+    "too many tokens",
+];
+
+/// Whether a request that failed on one model (after its retries) should move on to
+/// the next model in the fallback chain.
+///
+/// Server errors, timeouts, rate limits and context overflows are model-specific and may
+/// succeed elsewhere; other client errors would fail the same way on every model.
+pub fn should_fallback(error: &ProxyError) -> bool {
+    match error {
+        // Once the request deadline has passed there is no time left for another model
+        ProxyError::Timeout(phase) => *phase != TimeoutPhase::Deadline,
+        ProxyError::MaxRetriesExceeded(_)
+        | ProxyError::Backend(_)
+        | ProxyError::StreamStart(_)
+        | ProxyError::CircuitOpen(_)
+        | ProxyError::NoHealthyEndpoint(_)
+        | ProxyError::QueueFull { .. }
+        | ProxyError::QueueTimeout { .. }
+        | ProxyError::RateLimited { .. }
+        | ProxyError::BudgetExceeded { .. }
+        // A fallback model that can't be served, like one missing from the config
+        | ProxyError::ModelNotFound(_) => true,
+        ProxyError::Http(e) => e.is_timeout() || e.is_connect() || e.is_request(),
+        ProxyError::Upstream { status, message, .. } => {
+            *status == 429 || *status >= 500 || is_context_length_error(*status, message)
+        }
+        _ => false,
+    }
+}
+
+/// Whether an upstream error reports that the request exceeded the model's context window
+pub fn is_context_length_error(status: u16, message: &str) -> bool {
+    if !matches!(status, 400 | 413 | 422) {
+        return false;
+    }
+    let message = message.to_lowercase();
+    CONTEXT_LENGTH_MARKERS
+        .iter()
+        .any(|marker| message.contains(marker))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_should_fallback_on_transient_errors() {
+        assert!(should_fallback(&ProxyError::Timeout(TimeoutPhase::IdleStream)));
+        assert!(!should_fallback(&ProxyError::Timeout(TimeoutPhase::Deadline)));
+        assert!(should_fallback(&ProxyError::MaxRetriesExceeded(3)));
+        assert!(should_fallback(&ProxyError::ModelNotFound("ghost".to_string())));
+        assert!(should_fallback(&ProxyError::Upstream {
+            status: 429,
+            message: "rate limited".to_string(),
+            retry_after: None
+        }));
+        assert!(should_fallback(&ProxyError::Upstream {
+            status: 503,
+            message: "unavailable".to_string(),
+            retry_after: None
+        }));
+    }
+
+    #[test]
+    fn test_should_fallback_on_context_length() {
+        assert!(should_fallback(&ProxyError::Upstream {
+            status: 400,
+            message: r#"{"error":{"code":"context_length_exceeded"}}"#.to_string(),
+            retry_after: None
+        }));
+        assert!(should_fallback(&ProxyError::Upstream {
+            status: 400,
+            message: "prompt is too long: 250000 tokens > 200000 maximum".to_string(),
+            retry_after: None
+        }));
+    }
+
+    #[test]
+    fn test_should_not_fallback_on_client_errors() {
+        assert!(!should_fallback(&ProxyError::Upstream {
+            status: 400,
+            message: "invalid temperature".to_string(),
+            retry_after: None
+        }));
+        assert!(!should_fallback(&ProxyError::Upstream {
+            status: 401,
+            message: "bad key".to_string(),
+            retry_after: None
+        }));
+        assert!(!should_fallback(&ProxyError::InvalidRequest("bad".to_string())));
+    }
+}
@@ -0,0 +1,311 @@
+use crate::config::{HealthCheckConfig, HealthProbe};
+use crate::proxy::balancer::Endpoint;
+use crate::proxy::upstream::{apply_api_key, ANTHROPIC_VERSION};
+use crate::proxy::{ModelRouter, ProxyClient};
+use crate::types::{Protocol, ProxyError, Result};
+use chrono::{DateTime, Utc};
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::Serialize;
+use serde_json::json;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// Result of an endpoint's active health checks. Endpoints start out healthy so
+/// traffic flows before the first probe completes.
+#[derive(Debug)]
+pub struct EndpointHealth {
+    inner: Mutex<HealthStatus>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthStatus {
+    pub healthy: bool,
+    pub consecutive_successes: u32,
+    pub consecutive_failures: u32,
+    pub last_error: Option<String>,
+    pub last_checked: Option<DateTime<Utc>>,
+}
+
+impl EndpointHealth {
+    pub fn new() -> Self {
+        Self {
+            inner: Mutex::new(HealthStatus {
+                healthy: true,
+                consecutive_successes: 0,
+                consecutive_failures: 0,
+                last_error: None,
+                last_checked: None,
+            }),
+        }
+    }
+
+    pub fn is_healthy(&self) -> bool {
+        self.inner.lock().unwrap().healthy
+    }
+
+    pub fn status(&self) -> HealthStatus {
+        self.inner.lock().unwrap().clone()
+    }
+
+    /// Record a probe outcome, returning the new health if it changed
+    pub fn record(
+        &self,
+        outcome: std::result::Result<(), String>,
+        config: &HealthCheckConfig,
+    ) -> Option<bool> {
+        let mut inner = self.inner.lock().unwrap();
+        inner.last_checked = Some(Utc::now());
+        let was_healthy = inner.healthy;
+
+        match outcome {
+            Ok(()) => {
+                inner.consecutive_successes += 1;
+                inner.consecutive_failures = 0;
+                inner.last_error = None;
+                if inner.consecutive_successes >= config.healthy_threshold {
+                    inner.healthy = true;
+                }
+            }
+            Err(error) => {
+                inner.consecutive_failures += 1;
+                inner.consecutive_successes = 0;
+                inner.last_error = Some(error);
+                if inner.consecutive_failures >= config.unhealthy_threshold {
+                    inner.healthy = false;
+                }
+            }
+        }
+
+        (inner.healthy != was_healthy).then_some(inner.healthy)
+    }
+}
+
+impl Default for EndpointHealth {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Start a background probe loop for every model with health checks enabled
+pub fn spawn_health_checks(router: &ModelRouter) -> Vec<JoinHandle<()>> {
+    router
+        .clients()
+        .filter(|(_, client)| client.config().health_check.enabled)
+        .map(|(model, client)| {
+            let model = model.to_string();
+            let client = client.clone();
+            tokio::spawn(async move {
+                let interval = Duration::from_secs(client.config().health_check.interval_seconds);
+                let mut ticker = tokio::time::interval(interval);
+                ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+                loop {
+                    ticker.tick().await;
+                    check_model(&model, &client).await;
+                }
+            })
+        })
+        .collect()
+}
+
+/// Probe every endpoint of a model once, concurrently, and record the results
+pub async fn check_model(model: &str, client: &Arc<ProxyClient>) {
+    let config = &client.config().health_check;
+    let probes = client.endpoints().iter().map(|endpoint| async move {
+        let outcome = probe_endpoint(model, client, endpoint)
+            .await
+            .map_err(|e| e.to_string());
+        if let Err(error) = &outcome {
+            tracing::debug!(model = %model, endpoint = %endpoint.url, error = %error, "Health probe failed");
+        }
+        match endpoint.health.record(outcome, config) {
+            Some(true) => {
+                tracing::info!(model = %model, endpoint = %endpoint.url, "Endpoint is healthy again")
+            }
+            Some(false) => {
+                tracing::warn!(model = %model, endpoint = %endpoint.url, "Endpoint marked unhealthy")
+            }
+            None => {}
+        }
+    });
+    futures::future::join_all(probes).await;
+}
+
+/// Run one health probe against an endpoint; any 2xx response is healthy
+pub async fn probe_endpoint(model: &str, client: &ProxyClient, endpoint: &Endpoint) -> Result<()> {
+    let config = client.config();
+    let health = &config.health_check;
+    let protocol = config.backend_type.protocol();
+
+    let mut headers = HeaderMap::new();
+    if let Some(api_key) = endpoint.api_key.as_deref() {
+        apply_api_key(&mut headers, protocol, api_key)?;
+    }
+    if protocol == Protocol::Anthropic {
+        headers.insert(
+            HeaderName::from_static("anthropic-version"),
+            HeaderValue::from_static(ANTHROPIC_VERSION),
+        );
+    }
+
+    let request = match health.probe {
+        HealthProbe::Get => {
+            let url = reqwest::Url::parse(&endpoint.url)
+                .and_then(|url| url.join(&health.path))
+                .map_err(|e| ProxyError::Config(format!("Invalid health check URL: {}", e)))?;
+            client.client().get(url)
+        }
+        // The same minimal body is valid for both OpenAI and Anthropic chat APIs
+        HealthProbe::Completion => client.client().post(&endpoint.url).json(&json!({
+            "model": config.get_target_model(model),
+            "messages": [{"role": "user", "content": "ping"}],
+            "max_tokens": 1
+        })),
+    };
+
+    let response = request
+        .headers(headers)
+        .timeout(Duration::from_secs(health.timeout_seconds))
+        .send()
+        .await?;
+
+    let status = response.status();
+    if !status.is_success() {
+        return Err(ProxyError::Upstream {
+            status: status.as_u16(),
+            message: response.text().await.unwrap_or_default(),
+            retry_after: None,
+        });
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    fn config(healthy_threshold: u32, unhealthy_threshold: u32) -> HealthCheckConfig {
+        HealthCheckConfig {
+            enabled: true,
+            healthy_threshold,
+            unhealthy_threshold,
+            ..HealthCheckConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_thresholds() {
+        let health = EndpointHealth::new();
+        let config = config(2, 2);
+        assert!(health.is_healthy());
+
+        assert_eq!(health.record(Err("down".to_string()), &config), None);
+        assert!(health.is_healthy());
+        assert_eq!(health.record(Err("down".to_string()), &config), Some(false));
+        assert_eq!(health.status().last_error.as_deref(), Some("down"));
+
+        assert_eq!(health.record(Ok(()), &config), None);
+        assert!(!health.is_healthy());
+        assert_eq!(health.record(Ok(()), &config), Some(true));
+        assert!(health.status().last_error.is_none());
+    }
+
+    #[test]
+    fn test_success_resets_failure_streak() {
+        let health = EndpointHealth::new();
+        let config = config(1, 2);
+        health.record(Err("down".to_string()), &config);
+        health.record(Ok(()), &config);
+        health.record(Err("down".to_string()), &config);
+        assert!(health.is_healthy());
+    }
+
+    fn router(yaml: &str) -> ModelRouter {
+        let config: Config = serde_yaml::from_str(yaml).unwrap();
+        ModelRouter::new(&config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_get_probe_marks_endpoint_unhealthy() {
+        let mut server_a = mockito::Server::new_async().await;
+        let mut server_b = mockito::Server::new_async().await;
+        let healthy = server_a
+            .mock("GET", "/healthz")
+            .match_header("authorization", "Bearer key")
+            .with_status(200)
+            .create_async()
+            .await;
+        let broken = server_b
+            .mock("GET", "/healthz")
+            .with_status(503)
+            .create_async()
+            .await;
+
+        let router = router(&format!(
+            r#"
+server: {{}}
+models:
+  llama:
+    backend_type: openai
+    api_key: key
+    endpoints:
+      - url: {a}/v1/chat/completions
+      - url: {b}/v1/chat/completions
+    health_check:
+      enabled: true
+      path: /healthz
+      unhealthy_threshold: 1
+"#,
+            a = server_a.url(),
+            b = server_b.url()
+        ));
+        let client = router.get_client("llama").unwrap();
+        check_model("llama", &client).await;
+
+        healthy.assert_async().await;
+        broken.assert_async().await;
+        let endpoints = client.endpoints();
+        assert!(endpoints[0].health.is_healthy());
+        assert!(!endpoints[1].health.is_healthy());
+        assert_eq!(client.select_endpoint(&[]).unwrap().0, 0);
+    }
+
+    #[tokio::test]
+    async fn test_completion_probe() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/v1/messages")
+            .match_header("x-api-key", "key")
+            .match_header("anthropic-version", ANTHROPIC_VERSION)
+            .match_body(mockito::Matcher::PartialJson(json!({
+                "model": "claude-3-haiku",
+                "max_tokens": 1
+            })))
+            .with_status(200)
+            .with_body("{}")
+            .create_async()
+            .await;
+
+        let router = router(&format!(
+            r#"
+server: {{}}
+models:
+  claude:
+    backend_type: anthropic
+    endpoint: {}/v1/messages
+    api_key: key
+    target_model: claude-3-haiku
+    health_check:
+      enabled: true
+      probe: completion
+"#,
+            server.url()
+        ));
+        let client = router.get_client("claude").unwrap();
+        probe_endpoint("claude", &client, &client.endpoints()[0])
+            .await
+            .unwrap();
+        mock.assert_async().await;
+    }
+}
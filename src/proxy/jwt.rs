@@ -0,0 +1,412 @@
+use crate::config::{ApiKeyConfig, JwtConfig, JWT_CLIENT_PREFIX};
+use crate::transform::claim;
+use crate::types::{ProxyError, Result};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{Algorithm, DecodingKey, Validation};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+/// Least time between two JWKS reads, so tokens with made-up key ids cannot make the
+/// proxy hammer the identity provider
+const MIN_REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+const JWKS_FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Validates bearer JWTs against the signing keys of a JWKS file or URL and maps their
+/// claims to the permissions of a client
+pub struct JwtValidator {
+    config: JwtConfig,
+    validation: Validation,
+    http: reqwest::Client,
+    keys: RwLock<JwkSet>,
+    /// When the keys were last read; held while reading them
+    refreshed: tokio::sync::Mutex<Option<Instant>>,
+}
+
+impl JwtValidator {
+    /// Validator for `config`. A JWKS file is read right away; a JWKS URL on first use.
+    pub fn new(config: &JwtConfig) -> Result<Self> {
+        let algorithms = config
+            .algorithms
+            .iter()
+            .map(|algorithm| {
+                algorithm
+                    .parse::<Algorithm>()
+                    .map_err(|e| ProxyError::Config(format!("Invalid JWT algorithm '{}': {}", algorithm, e)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut validation = Validation::new(algorithms.first().copied().unwrap_or(Algorithm::RS256));
+        validation.algorithms = algorithms;
+        validation.leeway = config.leeway_seconds;
+        validation.validate_nbf = true;
+        let mut required = vec!["exp"];
+        if let Some(issuer) = &config.issuer {
+            validation.set_issuer(&[issuer]);
+            required.push("iss");
+        }
+        if config.audience.is_empty() {
+            validation.validate_aud = false;
+        } else {
+            validation.set_audience(&config.audience);
+            required.push("aud");
+        }
+        validation.set_required_spec_claims(&required);
+
+        let (keys, refreshed) = match &config.jwks_file {
+            Some(path) => (read_jwks_file(path)?, Some(Instant::now())),
+            None => (JwkSet { keys: Vec::new() }, None),
+        };
+
+        Ok(Self {
+            config: config.clone(),
+            validation,
+            http: reqwest::Client::builder()
+                .timeout(JWKS_FETCH_TIMEOUT)
+                .build()
+                .map_err(|e| ProxyError::Config(format!("Failed to create JWKS client: {}", e)))?,
+            keys: RwLock::new(keys),
+            refreshed: tokio::sync::Mutex::new(refreshed),
+        })
+    }
+
+    /// Whether a bearer credential is a JWT rather than a plain API key
+    pub fn is_jwt(token: &str) -> bool {
+        token.split('.').count() == 3
+    }
+
+    /// The claims of a token with a valid signature, issuer, audience and lifetime
+    pub async fn validate(&self, token: &str) -> Result<Value> {
+        let header = jsonwebtoken::decode_header(token)
+            .map_err(|e| ProxyError::Unauthorized(format!("Invalid token: {}", e)))?;
+        let key = self.decoding_key(header.kid.as_deref()).await?;
+        jsonwebtoken::decode::<Value>(token, &key, &self.validation)
+            .map(|data| data.claims)
+            .map_err(|e| ProxyError::Unauthorized(format!("Invalid token: {}", e)))
+    }
+
+    /// The client a token's claims describe: named by the identity claim (as `jwt:<sub>`),
+    /// allowed the models of its groups and given the rate limits and priority of the
+    /// first of its groups that sets them
+    pub fn client(&self, claims: &Value) -> Result<ApiKeyConfig> {
+        let config = &self.config;
+        let name = claim(claims, &config.identity_claim)
+            .and_then(Value::as_str)
+            .filter(|name| !name.is_empty())
+            .ok_or_else(|| {
+                ProxyError::Unauthorized(format!("Token has no '{}' claim", config.identity_claim))
+            })?;
+        let groups: Vec<&str> = match claim(claims, &config.groups_claim) {
+            Some(Value::String(group)) => vec![group.as_str()],
+            Some(Value::Array(groups)) => groups.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        let matched: Vec<_> = config
+            .groups
+            .iter()
+            .filter(|group| groups.contains(&group.group.as_str()))
+            .collect();
+
+        Ok(ApiKeyConfig {
+            name: format!("{}{}", JWT_CLIENT_PREFIX, name),
+            key: String::new(),
+            allowed_models: config
+                .allowed_models
+                .iter()
+                .chain(matched.iter().flat_map(|group| &group.allowed_models))
+                .cloned()
+                .collect(),
+            aliases: HashMap::new(),
+            metadata: config
+                .metadata_claims
+                .iter()
+                .filter_map(|(field, claim_name)| {
+                    let value = claim(claims, claim_name)?.as_str()?;
+                    Some((field.clone(), value.to_string()))
+                })
+                .collect(),
+            rate_limit: matched.iter().find_map(|group| group.rate_limit.clone()),
+            priority: matched.iter().find_map(|group| group.priority),
+        })
+    }
+
+    async fn decoding_key(&self, kid: Option<&str>) -> Result<DecodingKey> {
+        let stale = self
+            .refreshed
+            .lock()
+            .await
+            .is_none_or(|at| at.elapsed() >= Duration::from_secs(self.config.jwks_refresh_seconds));
+        if stale {
+            self.refresh().await;
+        }
+        if let Some(key) = self.find_key(kid)? {
+            return Ok(key);
+        }
+        // The provider may have rotated in a new signing key
+        self.refresh().await;
+        self.find_key(kid)?
+            .ok_or_else(|| ProxyError::Unauthorized("Token signed with an unknown key".to_string()))
+    }
+
+    fn find_key(&self, kid: Option<&str>) -> Result<Option<DecodingKey>> {
+        let keys = self.keys.read().unwrap();
+        let jwk = match kid {
+            Some(kid) => keys.find(kid),
+            // Tokens without a key id are only accepted from a single-key set
+            None if keys.keys.len() == 1 => keys.keys.first(),
+            None => None,
+        };
+        jwk.map(DecodingKey::from_jwk)
+            .transpose()
+            .map_err(|e| ProxyError::Unauthorized(format!("Unusable signing key: {}", e)))
+    }
+
+    /// Read the signing keys again, keeping the current ones if that fails
+    async fn refresh(&self) {
+        let mut refreshed = self.refreshed.lock().await;
+        if refreshed.is_some_and(|at| at.elapsed() < MIN_REFRESH_INTERVAL) {
+            return;
+        }
+        match self.fetch().await {
+            Ok(keys) => {
+                tracing::debug!(keys = keys.keys.len(), "Loaded JWT signing keys");
+                *self.keys.write().unwrap() = keys;
+            }
+            Err(e) => tracing::warn!(error = %e, "Failed to load JWT signing keys"),
+        }
+        *refreshed = Some(Instant::now());
+    }
+
+    async fn fetch(&self) -> Result<JwkSet> {
+        if let Some(path) = &self.config.jwks_file {
+            let bytes = tokio::fs::read(path).await?;
+            return Ok(serde_json::from_slice(&bytes)?);
+        }
+        let url = self.config.jwks_url.as_deref().unwrap_or_default();
+        Ok(self.http.get(url).send().await?.error_for_status()?.json().await?)
+    }
+}
+
+impl std::fmt::Debug for JwtValidator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("JwtValidator")
+            .field("issuer", &self.config.issuer)
+            .field("audience", &self.config.audience)
+            .finish()
+    }
+}
+
+fn read_jwks_file(path: &str) -> Result<JwkSet> {
+    let bytes = std::fs::read(path)
+        .map_err(|e| ProxyError::Config(format!("Failed to read JWKS file '{}': {}", path, e)))?;
+    serde_json::from_slice(&bytes)
+        .map_err(|e| ProxyError::Config(format!("Invalid JWKS file '{}': {}", path, e)))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::config::{JwtGroupConfig, RateLimitConfig};
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use jsonwebtoken::{EncodingKey, Header};
+    use ring::rand::SystemRandom;
+    use ring::signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING};
+    use serde_json::json;
+
+    /// A locally generated P-256 signing key and its JWK
+    pub(crate) struct TestKey {
+        kid: String,
+        encoding: EncodingKey,
+        pub jwk: Value,
+    }
+
+    impl TestKey {
+        pub(crate) fn generate(kid: &str) -> Self {
+            let rng = SystemRandom::new();
+            let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+            let pair = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref(), &rng).unwrap();
+            // Uncompressed point: 0x04 || x || y
+            let point = pair.public_key().as_ref();
+            Self {
+                kid: kid.to_string(),
+                encoding: EncodingKey::from_ec_der(pkcs8.as_ref()),
+                jwk: json!({
+                    "kty": "EC",
+                    "crv": "P-256",
+                    "alg": "ES256",
+                    "use": "sig",
+                    "kid": kid,
+                    "x": URL_SAFE_NO_PAD.encode(&point[1..33]),
+                    "y": URL_SAFE_NO_PAD.encode(&point[33..]),
+                }),
+            }
+        }
+
+        pub(crate) fn sign(&self, claims: &Value) -> String {
+            let mut header = Header::new(Algorithm::ES256);
+            header.kid = Some(self.kid.clone());
+            jsonwebtoken::encode(&header, claims, &self.encoding).unwrap()
+        }
+    }
+
+    pub(crate) fn jwks(keys: &[&TestKey]) -> String {
+        json!({"keys": keys.iter().map(|key| key.jwk.clone()).collect::<Vec<_>>()}).to_string()
+    }
+
+    pub(crate) fn write_jwks(name: &str, keys: &[&TestKey]) -> String {
+        let path = std::env::temp_dir().join(format!("llm-proxy-{}-{}.json", name, std::process::id()));
+        std::fs::write(&path, jwks(keys)).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    pub(crate) fn now() -> u64 {
+        chrono::Utc::now().timestamp() as u64
+    }
+
+    pub(crate) fn jwt_config(jwks_file: Option<String>, jwks_url: Option<String>) -> JwtConfig {
+        JwtConfig {
+            jwks_file,
+            jwks_url,
+            jwks_refresh_seconds: 3600,
+            issuer: Some("https://idp.example.com/".to_string()),
+            audience: vec!["llm-proxy".to_string()],
+            algorithms: vec!["ES256".to_string()],
+            leeway_seconds: 60,
+            identity_claim: "sub".to_string(),
+            groups_claim: "groups".to_string(),
+            metadata_claims: [("team".to_string(), "department".to_string())].into(),
+            allowed_models: vec!["gpt-4o-mini".to_string()],
+            groups: vec![
+                JwtGroupConfig {
+                    group: "ml".to_string(),
+                    allowed_models: vec!["claude-*".to_string()],
+                    rate_limit: Some(RateLimitConfig {
+                        requests_per_minute: Some(1),
+                        ..Default::default()
+                    }),
+                    priority: None,
+                },
+                JwtGroupConfig {
+                    group: "admins".to_string(),
+                    allowed_models: vec!["*".to_string()],
+                    rate_limit: None,
+                    priority: None,
+                },
+            ],
+        }
+    }
+
+    pub(crate) fn claims(sub: &str, groups: &[&str]) -> Value {
+        json!({
+            "sub": sub,
+            "iss": "https://idp.example.com/",
+            "aud": "llm-proxy",
+            "exp": now() + 300,
+            "groups": groups,
+            "department": "research",
+        })
+    }
+
+    #[tokio::test]
+    async fn test_valid_token_from_jwks_file() {
+        let key = TestKey::generate("key-1");
+        let path = write_jwks("jwks-valid", &[&key]);
+        let validator = JwtValidator::new(&jwt_config(Some(path.clone()), None)).unwrap();
+
+        let claims = validator.validate(&key.sign(&claims("svc-search", &["ml"]))).await.unwrap();
+        assert_eq!(claims["sub"], "svc-search");
+        let client = validator.client(&claims).unwrap();
+        assert_eq!(client.name, "jwt:svc-search");
+        assert!(client.allows_model("gpt-4o-mini"));
+        assert!(client.allows_model("claude-3-opus"));
+        assert!(!client.allows_model("gpt-4o"));
+        assert_eq!(client.metadata["team"], "research");
+        assert_eq!(client.rate_limit.unwrap().requests_per_minute, Some(1));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_rejected_tokens() {
+        let key = TestKey::generate("key-1");
+        let other = TestKey::generate("key-1");
+        let path = write_jwks("jwks-rejected", &[&key]);
+        let validator = JwtValidator::new(&jwt_config(Some(path.clone()), None)).unwrap();
+        let rejected = |token: String| {
+            let validator = &validator;
+            async move { matches!(validator.validate(&token).await, Err(ProxyError::Unauthorized(_))) }
+        };
+
+        let mut expired = claims("svc", &[]);
+        expired["exp"] = json!(now() - 120);
+        assert!(rejected(key.sign(&expired)).await);
+        // Within the clock skew allowance
+        expired["exp"] = json!(now() - 30);
+        assert!(!rejected(key.sign(&expired)).await);
+
+        let mut not_yet = claims("svc", &[]);
+        not_yet["nbf"] = json!(now() + 600);
+        assert!(rejected(key.sign(&not_yet)).await);
+
+        let mut wrong_issuer = claims("svc", &[]);
+        wrong_issuer["iss"] = json!("https://evil.example.com/");
+        assert!(rejected(key.sign(&wrong_issuer)).await);
+
+        let mut wrong_audience = claims("svc", &[]);
+        wrong_audience["aud"] = json!("other-service");
+        assert!(rejected(key.sign(&wrong_audience)).await);
+
+        // Same key id, different key
+        assert!(rejected(other.sign(&claims("svc", &[]))).await);
+        assert!(rejected("not.a.token".to_string()).await);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_jwks_url_fetched_and_refreshed_for_new_keys() {
+        let old = TestKey::generate("old");
+        let new = TestKey::generate("new");
+        let mut server = mockito::Server::new_async().await;
+        let first = server
+            .mock("GET", "/jwks")
+            .with_header("content-type", "application/json")
+            .with_body(jwks(&[&old]))
+            .expect(1)
+            .create_async()
+            .await;
+        let validator = JwtValidator::new(&jwt_config(None, Some(format!("{}/jwks", server.url())))).unwrap();
+
+        assert!(validator.validate(&old.sign(&claims("svc", &[]))).await.is_ok());
+        assert!(validator.validate(&old.sign(&claims("svc", &[]))).await.is_ok());
+        first.assert_async().await;
+        first.remove_async().await;
+
+        // A rotated-in key is picked up once the refresh interval allows another read
+        server
+            .mock("GET", "/jwks")
+            .with_header("content-type", "application/json")
+            .with_body(jwks(&[&old, &new]))
+            .create_async()
+            .await;
+        assert!(validator.validate(&new.sign(&claims("svc", &[]))).await.is_err());
+        *validator.refreshed.lock().await = Some(Instant::now() - MIN_REFRESH_INTERVAL);
+        assert!(validator.validate(&new.sign(&claims("svc", &[]))).await.is_ok());
+    }
+
+    #[test]
+    fn test_client_needs_identity() {
+        let key = TestKey::generate("key-1");
+        let path = write_jwks("jwks-identity", &[&key]);
+        let validator = JwtValidator::new(&jwt_config(Some(path.clone()), None)).unwrap();
+        let mut claims = claims("svc", &["admins", "ml"]);
+        // Group permissions add up; rate limits come from the first listed group that has them
+        let client = validator.client(&claims).unwrap();
+        assert!(client.allows_model("gpt-4o"));
+        assert!(client.rate_limit.is_some());
+
+        claims.as_object_mut().unwrap().remove("sub");
+        assert!(matches!(validator.client(&claims), Err(ProxyError::Unauthorized(_))));
+        std::fs::remove_file(path).unwrap();
+    }
+}
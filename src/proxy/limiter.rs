@@ -0,0 +1,706 @@
+use crate::config::{AdaptiveConcurrencyConfig, ConcurrencyConfig, Priority};
+use crate::types::{ProxyError, Result, TimeoutPhase};
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+/// Smoothing factor for the queue wait moving average (weight of the newest sample)
+const WAIT_EWMA_ALPHA: f64 = 0.2;
+
+/// Smoothing factor for the adaptive limiter's latency baseline; slow, so a gradual
+/// slowdown still registers against it
+const LATENCY_EWMA_ALPHA: f64 = 0.05;
+
+/// Counting semaphore for one endpoint with a bounded wait queue.
+///
+/// Queued requests are admitted by priority class, then in arrival order. Waiting
+/// raises a request's class over time so low-priority traffic is not starved, and
+/// low-priority requests may be capped to a share of the limit. With adaptive control
+/// the limit follows the outcomes reported through `record_success` and `record_overload`.
+/// Without a limit every request is admitted immediately.
+#[derive(Debug)]
+pub struct ConcurrencyLimiter {
+    endpoint: String,
+    max_queue: usize,
+    queue_timeout: Duration,
+    aging: Duration,
+    state: Mutex<LimiterState>,
+    next_id: AtomicU64,
+    /// Moving average of the queue wait of admitted requests, in microseconds
+    avg_wait_us: AtomicU64,
+    rejected: AtomicU64,
+    timed_out: AtomicU64,
+}
+
+#[derive(Debug)]
+struct LimiterState {
+    limit: Option<usize>,
+    /// Share of the limit low-priority requests may hold at once
+    low_share: Option<f64>,
+    in_use: usize,
+    low_in_use: usize,
+    queue: Vec<Waiter>,
+    adaptive: Option<AdaptiveState>,
+}
+
+/// Bookkeeping for AIMD control of the limit
+#[derive(Debug)]
+struct AdaptiveState {
+    config: AdaptiveConcurrencyConfig,
+    /// Moving average of successful response latency
+    baseline: Option<Duration>,
+    /// Successes with stable latency since the limit last changed
+    successes: usize,
+    last_decrease: Option<Instant>,
+}
+
+#[derive(Debug)]
+struct Waiter {
+    id: u64,
+    priority: Priority,
+    queued_at: Instant,
+    grant: oneshot::Sender<()>,
+}
+
+impl LimiterState {
+    fn has_room(&self) -> bool {
+        self.limit.is_none_or(|limit| self.in_use < limit)
+    }
+
+    fn low_limit(&self) -> Option<usize> {
+        let (limit, share) = (self.limit?, self.low_share?);
+        Some(((limit as f64 * share) as usize).max(1))
+    }
+
+    /// Whether a request of this class could take a free slot
+    fn admits(&self, priority: Priority) -> bool {
+        self.has_room()
+            && (priority != Priority::Low || self.low_limit().is_none_or(|low| self.low_in_use < low))
+    }
+
+    fn take(&mut self, priority: Priority) {
+        self.in_use += 1;
+        if priority == Priority::Low {
+            self.low_in_use += 1;
+        }
+    }
+}
+
+/// Point-in-time view of a limiter for the status endpoint
+#[derive(Debug, Clone, Serialize)]
+pub struct LimiterStatus {
+    /// Current limit; moves between the configured bounds when `adaptive`
+    pub limit: Option<usize>,
+    pub adaptive: bool,
+    pub in_use: usize,
+    pub low_priority_limit: Option<usize>,
+    pub low_priority_in_use: usize,
+    pub queued: usize,
+    pub queued_by_priority: QueuedByPriority,
+    pub max_queue: usize,
+    pub avg_queue_wait_ms: f64,
+    pub rejected: u64,
+    pub timed_out: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct QueuedByPriority {
+    pub high: usize,
+    pub normal: usize,
+    pub low: usize,
+}
+
+/// A slot on the endpoint, released (and handed to the next waiter) on drop
+#[derive(Debug)]
+pub struct LimitPermit {
+    limiter: Arc<ConcurrencyLimiter>,
+    priority: Priority,
+}
+
+impl Drop for LimitPermit {
+    fn drop(&mut self) {
+        self.limiter
+            .release(&mut self.limiter.state.lock().unwrap(), self.priority);
+    }
+}
+
+impl ConcurrencyLimiter {
+    pub fn new(endpoint: &str, limit: Option<usize>, config: &ConcurrencyConfig) -> Self {
+        let adaptive = &config.adaptive;
+        let limit = if adaptive.enabled {
+            Some(
+                limit
+                    .unwrap_or(adaptive.min_limit)
+                    .clamp(adaptive.min_limit, adaptive.max_limit),
+            )
+        } else {
+            limit
+        };
+        Self {
+            endpoint: endpoint.to_string(),
+            max_queue: config.max_queue,
+            queue_timeout: config.queue_timeout(),
+            aging: config.aging(),
+            state: Mutex::new(LimiterState {
+                limit,
+                low_share: Some(config.low_priority_share).filter(|&share| share < 1.0),
+                in_use: 0,
+                low_in_use: 0,
+                queue: Vec::new(),
+                adaptive: adaptive.enabled.then(|| AdaptiveState {
+                    config: adaptive.clone(),
+                    baseline: None,
+                    successes: 0,
+                    last_decrease: None,
+                }),
+            }),
+            next_id: AtomicU64::new(0),
+            avg_wait_us: AtomicU64::new(0),
+            rejected: AtomicU64::new(0),
+            timed_out: AtomicU64::new(0),
+        }
+    }
+
+    /// Whether a request would be admitted without queueing
+    pub fn has_capacity(&self) -> bool {
+        // Waiters left in the queue while there is room are capped low-priority ones
+        self.state.lock().unwrap().has_room()
+    }
+
+    /// Take a slot, waiting in line behind earlier and higher-priority requests if the
+    /// endpoint is at its limit. Fails with `QueueFull` when the line is full and
+    /// `QueueTimeout` when the wait exceeds the queue timeout (or `Timeout` if the
+    /// request deadline comes first).
+    pub async fn acquire(
+        self: &Arc<Self>,
+        priority: Priority,
+        deadline: Option<Instant>,
+    ) -> Result<LimitPermit> {
+        let mut queued = {
+            let mut state = self.state.lock().unwrap();
+            if state.admits(priority) {
+                state.take(priority);
+                return Ok(self.permit(priority));
+            }
+            if state.queue.len() >= self.max_queue {
+                self.rejected.fetch_add(1, Ordering::Relaxed);
+                return Err(ProxyError::QueueFull {
+                    endpoint: self.endpoint.clone(),
+                    retry_after: self.retry_after(),
+                });
+            }
+            let (grant, receiver) = oneshot::channel();
+            let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+            state.queue.push(Waiter {
+                id,
+                priority,
+                queued_at: Instant::now(),
+                grant,
+            });
+            QueuedRequest {
+                limiter: self,
+                id,
+                priority,
+                receiver: Some(receiver),
+            }
+        };
+
+        let started = Instant::now();
+        let (wait, phase) = match deadline {
+            Some(deadline) if deadline < started + self.queue_timeout => {
+                (deadline.saturating_duration_since(started), Some(TimeoutPhase::Deadline))
+            }
+            _ => (self.queue_timeout, None),
+        };
+        let granted = tokio::time::timeout(wait, queued.wait()).await;
+        match granted {
+            Ok(()) => {
+                let waited = started.elapsed();
+                self.record_wait(waited);
+                tracing::debug!(
+                    endpoint = %self.endpoint,
+                    priority = priority.as_str(),
+                    wait_ms = waited.as_millis() as u64,
+                    "Admitted from queue"
+                );
+                Ok(self.permit(priority))
+            }
+            Err(_) => {
+                // Dropping the queued request leaves the line (or passes on a late grant)
+                drop(queued);
+                self.timed_out.fetch_add(1, Ordering::Relaxed);
+                Err(match phase {
+                    Some(phase) => ProxyError::Timeout(phase),
+                    None => ProxyError::QueueTimeout {
+                        endpoint: self.endpoint.clone(),
+                        retry_after: self.retry_after(),
+                    },
+                })
+            }
+        }
+    }
+
+    pub fn status(&self) -> LimiterStatus {
+        let state = self.state.lock().unwrap();
+        let mut queued_by_priority = QueuedByPriority::default();
+        for waiter in &state.queue {
+            match waiter.priority {
+                Priority::High => queued_by_priority.high += 1,
+                Priority::Normal => queued_by_priority.normal += 1,
+                Priority::Low => queued_by_priority.low += 1,
+            }
+        }
+        LimiterStatus {
+            limit: state.limit,
+            adaptive: state.adaptive.is_some(),
+            in_use: state.in_use,
+            low_priority_limit: state.low_limit(),
+            low_priority_in_use: state.low_in_use,
+            queued: state.queue.len(),
+            queued_by_priority,
+            max_queue: self.max_queue,
+            avg_queue_wait_ms: self.avg_wait_us.load(Ordering::Relaxed) as f64 / 1000.0,
+            rejected: self.rejected.load(Ordering::Relaxed),
+            timed_out: self.timed_out.load(Ordering::Relaxed),
+        }
+    }
+
+    fn permit(self: &Arc<Self>, priority: Priority) -> LimitPermit {
+        LimitPermit {
+            limiter: self.clone(),
+            priority,
+        }
+    }
+
+    /// Report a successful response and how long its headers took. With adaptive
+    /// control, a limit's worth of these at stable latency raises the limit by one.
+    pub fn record_success(&self, latency: Duration) {
+        let mut state = self.state.lock().unwrap();
+        let (Some(limit), in_use) = (state.limit, state.in_use) else {
+            return;
+        };
+        let Some(adaptive) = state.adaptive.as_mut() else {
+            return;
+        };
+
+        let baseline = *adaptive.baseline.get_or_insert(latency);
+        adaptive.baseline = Some(
+            baseline.mul_f64(1.0 - LATENCY_EWMA_ALPHA) + latency.mul_f64(LATENCY_EWMA_ALPHA),
+        );
+        if latency > baseline.mul_f64(adaptive.config.latency_tolerance) {
+            // Latency is climbing: hold the limit where it is
+            adaptive.successes = 0;
+            return;
+        }
+        // Only grow a limit that is actually being used
+        if in_use * 2 < limit || limit >= adaptive.config.max_limit {
+            return;
+        }
+        adaptive.successes += 1;
+        if adaptive.successes >= limit {
+            adaptive.successes = 0;
+            state.limit = Some(limit + 1);
+            tracing::debug!(endpoint = %self.endpoint, limit = limit + 1, "Concurrency limit raised");
+            self.admit_waiters(&mut state);
+        }
+    }
+
+    /// Report an overload signal (rate limit, overload, timeout or server error). With
+    /// adaptive control the limit is cut, at most once per typical response time so a
+    /// burst of failures from one window counts once.
+    pub fn record_overload(&self) {
+        let mut state = self.state.lock().unwrap();
+        let Some(limit) = state.limit else {
+            return;
+        };
+        let Some(adaptive) = state.adaptive.as_mut() else {
+            return;
+        };
+
+        let cooldown = adaptive.baseline.unwrap_or_default();
+        if adaptive
+            .last_decrease
+            .is_some_and(|last| last.elapsed() < cooldown)
+        {
+            return;
+        }
+        adaptive.last_decrease = Some(Instant::now());
+        adaptive.successes = 0;
+        let reduced = ((limit as f64 * adaptive.config.backoff_ratio) as usize)
+            .max(adaptive.config.min_limit);
+        if reduced < limit {
+            state.limit = Some(reduced);
+            tracing::info!(endpoint = %self.endpoint, limit = reduced, "Concurrency limit lowered");
+        }
+    }
+
+    /// Give a slot back and admit as many waiters as now fit
+    fn release(&self, state: &mut LimiterState, priority: Priority) {
+        state.in_use -= 1;
+        if priority == Priority::Low {
+            state.low_in_use -= 1;
+        }
+        self.admit_waiters(state);
+    }
+
+    fn admit_waiters(&self, state: &mut LimiterState) {
+        while let Some(pos) = self.next_waiter(state) {
+            let waiter = state.queue.remove(pos);
+            // A waiter whose request was dropped no longer takes the slot
+            if waiter.grant.send(()).is_ok() {
+                state.take(waiter.priority);
+            }
+        }
+    }
+
+    /// The waiter to admit next: the highest class after aging among those that fit,
+    /// earliest first
+    fn next_waiter(&self, state: &LimiterState) -> Option<usize> {
+        let now = Instant::now();
+        state
+            .queue
+            .iter()
+            .enumerate()
+            .filter(|(_, waiter)| state.admits(waiter.priority))
+            .max_by_key(|(_, waiter)| {
+                let waited = now.saturating_duration_since(waiter.queued_at);
+                let boost = (waited.as_nanos() / self.aging.as_nanos().max(1)) as u64;
+                let class = (waiter.priority as u64 + boost).min(Priority::High as u64);
+                (class, std::cmp::Reverse(waiter.id))
+            })
+            .map(|(pos, _)| pos)
+    }
+
+    fn record_wait(&self, wait: Duration) {
+        let sample = wait.as_micros().max(1) as u64;
+        let _ = self
+            .avg_wait_us
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |current| {
+                Some(if current == 0 {
+                    sample
+                } else {
+                    (WAIT_EWMA_ALPHA * sample as f64 + (1.0 - WAIT_EWMA_ALPHA) * current as f64)
+                        as u64
+                })
+            });
+    }
+
+    /// Suggested client back-off: the typical queue wait, at least one second
+    fn retry_after(&self) -> Duration {
+        Duration::from_micros(self.avg_wait_us.load(Ordering::Relaxed)).max(Duration::from_secs(1))
+    }
+}
+
+/// A request waiting in line. Dropping it before it is admitted removes it from the
+/// queue, or releases the slot if one was granted in the meantime.
+struct QueuedRequest<'a> {
+    limiter: &'a ConcurrencyLimiter,
+    id: u64,
+    priority: Priority,
+    receiver: Option<oneshot::Receiver<()>>,
+}
+
+impl QueuedRequest<'_> {
+    async fn wait(&mut self) {
+        if let Some(receiver) = self.receiver.as_mut() {
+            // The sender is only dropped without sending when the waiter is removed,
+            // which happens in `Drop` after this future is gone
+            let _ = receiver.await;
+            self.receiver = None;
+        }
+    }
+}
+
+impl Drop for QueuedRequest<'_> {
+    fn drop(&mut self) {
+        let Some(mut receiver) = self.receiver.take() else {
+            return;
+        };
+        let mut state = self.limiter.state.lock().unwrap();
+        if let Some(pos) = state.queue.iter().position(|w| w.id == self.id) {
+            state.queue.remove(pos);
+        } else if receiver.try_recv().is_ok() {
+            self.limiter.release(&mut state, self.priority);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(limit: usize, max_queue: usize, queue_timeout_ms: u64) -> Arc<ConcurrencyLimiter> {
+        Arc::new(ConcurrencyLimiter::new(
+            "http://replica",
+            Some(limit),
+            &ConcurrencyConfig {
+                max_concurrent: Some(limit),
+                max_queue,
+                queue_timeout_ms,
+                ..ConcurrencyConfig::default()
+            },
+        ))
+    }
+
+    /// Queue a request in the background, recording when it is admitted
+    async fn enqueue(
+        limiter: &Arc<ConcurrencyLimiter>,
+        priority: Priority,
+        label: &'static str,
+        order: &Arc<Mutex<Vec<&'static str>>>,
+    ) -> tokio::task::JoinHandle<()> {
+        let queued_before = limiter.status().queued;
+        let (limiter_, order) = (limiter.clone(), order.clone());
+        let handle = tokio::spawn(async move {
+            let _permit = limiter_.acquire(priority, None).await.unwrap();
+            order.lock().unwrap().push(label);
+        });
+        while limiter.status().queued == queued_before {
+            tokio::task::yield_now().await;
+        }
+        handle
+    }
+
+    #[tokio::test]
+    async fn test_queue_is_fifo() {
+        let limiter = limiter(1, 10, 5_000);
+        let first = limiter.acquire(Priority::Normal, None).await.unwrap();
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let mut waiters = Vec::new();
+        for i in 0..3 {
+            let (queued, order) = (limiter.clone(), order.clone());
+            waiters.push(tokio::spawn(async move {
+                let _permit = queued.acquire(Priority::Normal, None).await.unwrap();
+                order.lock().unwrap().push(i);
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }));
+            // Make sure each waiter is queued before the next one
+            while limiter.status().queued < i + 1 {
+                tokio::task::yield_now().await;
+            }
+        }
+
+        drop(first);
+        for waiter in waiters {
+            waiter.await.unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), vec![0, 1, 2]);
+        let status = limiter.status();
+        assert_eq!((status.in_use, status.queued), (0, 0));
+        assert!(status.avg_queue_wait_ms > 0.0);
+    }
+
+    #[tokio::test]
+    async fn test_higher_priority_leaves_queue_first() {
+        let limiter = limiter(1, 10, 5_000);
+        let held = limiter.acquire(Priority::Normal, None).await.unwrap();
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let waiters = vec![
+            enqueue(&limiter, Priority::Low, "low", &order).await,
+            enqueue(&limiter, Priority::Normal, "normal", &order).await,
+            enqueue(&limiter, Priority::High, "high", &order).await,
+        ];
+        let queued = limiter.status().queued_by_priority;
+        assert_eq!((queued.high, queued.normal, queued.low), (1, 1, 1));
+
+        drop(held);
+        for waiter in waiters {
+            waiter.await.unwrap();
+        }
+        assert_eq!(*order.lock().unwrap(), vec!["high", "normal", "low"]);
+    }
+
+    #[tokio::test]
+    async fn test_waiting_raises_priority() {
+        let limiter = Arc::new(ConcurrencyLimiter::new(
+            "http://replica",
+            Some(1),
+            &ConcurrencyConfig {
+                aging_ms: 20,
+                ..ConcurrencyConfig::default()
+            },
+        ));
+        let held = limiter.acquire(Priority::Normal, None).await.unwrap();
+
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let low = enqueue(&limiter, Priority::Low, "low", &order).await;
+        // Two aging periods lift the low request level with fresh high ones
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let high = enqueue(&limiter, Priority::High, "high", &order).await;
+
+        drop(held);
+        low.await.unwrap();
+        high.await.unwrap();
+        assert_eq!(*order.lock().unwrap(), vec!["low", "high"]);
+    }
+
+    #[tokio::test]
+    async fn test_low_priority_capped_to_share() {
+        let limiter = Arc::new(ConcurrencyLimiter::new(
+            "http://replica",
+            Some(4),
+            &ConcurrencyConfig {
+                low_priority_share: 0.5,
+                ..ConcurrencyConfig::default()
+            },
+        ));
+        let mut low = vec![
+            limiter.acquire(Priority::Low, None).await.unwrap(),
+            limiter.acquire(Priority::Low, None).await.unwrap(),
+        ];
+
+        // A third low request queues although slots are free...
+        let order = Arc::new(Mutex::new(Vec::new()));
+        let queued_low = enqueue(&limiter, Priority::Low, "low", &order).await;
+        assert!(limiter.has_capacity());
+        // ...which other classes still get
+        let _normal = limiter.acquire(Priority::Normal, None).await.unwrap();
+        let status = limiter.status();
+        assert_eq!((status.in_use, status.low_priority_in_use), (3, 2));
+        assert_eq!(status.low_priority_limit, Some(2));
+
+        low.pop();
+        queued_low.await.unwrap();
+        assert_eq!(*order.lock().unwrap(), vec!["low"]);
+        drop(low);
+        assert_eq!(limiter.status().low_priority_in_use, 0);
+    }
+
+    #[tokio::test]
+    async fn test_rejects_when_queue_full() {
+        let limiter = limiter(1, 1, 5_000);
+        let _held = limiter.acquire(Priority::Normal, None).await.unwrap();
+        let queued = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire(Priority::Normal, None).await.map(|_| ()) }
+        });
+        while limiter.status().queued == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        let err = limiter.acquire(Priority::Normal, None).await.unwrap_err();
+        assert!(matches!(err, ProxyError::QueueFull { .. }));
+        assert_eq!(err.status_code(), 429);
+        assert!(err.retry_after().unwrap() >= Duration::from_secs(1));
+        assert_eq!(limiter.status().rejected, 1);
+        queued.abort();
+    }
+
+    #[tokio::test]
+    async fn test_queue_timeout_and_cancelled_waiters() {
+        let limiter = limiter(1, 10, 50);
+        let held = limiter.acquire(Priority::Normal, None).await.unwrap();
+
+        let err = limiter.acquire(Priority::Normal, None).await.unwrap_err();
+        assert!(matches!(err, ProxyError::QueueTimeout { .. }));
+        assert_eq!(err.status_code(), 503);
+
+        // A deadline sooner than the queue timeout is reported as such
+        let deadline = Instant::now() + Duration::from_millis(10);
+        let err = limiter.acquire(Priority::Normal, Some(deadline)).await.unwrap_err();
+        assert!(matches!(err, ProxyError::Timeout(TimeoutPhase::Deadline)));
+
+        // An abandoned waiter leaves the queue and does not leak the slot
+        let abandoned = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire(Priority::Normal, None).await.map(|_| ()) }
+        });
+        while limiter.status().queued == 0 {
+            tokio::task::yield_now().await;
+        }
+        abandoned.abort();
+        let _ = abandoned.await;
+        assert_eq!(limiter.status().queued, 0);
+
+        drop(held);
+        assert_eq!(limiter.status().in_use, 0);
+        assert!(limiter.has_capacity());
+    }
+
+    fn adaptive(limit: Option<usize>) -> Arc<ConcurrencyLimiter> {
+        Arc::new(ConcurrencyLimiter::new(
+            "http://replica",
+            limit,
+            &ConcurrencyConfig {
+                adaptive: AdaptiveConcurrencyConfig {
+                    enabled: true,
+                    min_limit: 2,
+                    max_limit: 5,
+                    backoff_ratio: 0.5,
+                    latency_tolerance: 2.0,
+                },
+                ..ConcurrencyConfig::default()
+            },
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_adaptive_limit_grows_with_stable_latency() {
+        let limiter = adaptive(None);
+        assert_eq!(limiter.status().limit, Some(2));
+        assert!(limiter.status().adaptive);
+
+        // An idle endpoint does not grow its limit
+        for _ in 0..10 {
+            limiter.record_success(Duration::from_millis(100));
+        }
+        assert_eq!(limiter.status().limit, Some(2));
+
+        let _busy = limiter.acquire(Priority::Normal, None).await.unwrap();
+        limiter.record_success(Duration::from_millis(100));
+        limiter.record_success(Duration::from_millis(100));
+        assert_eq!(limiter.status().limit, Some(3));
+
+        // Slow responses hold it
+        for _ in 0..10 {
+            limiter.record_success(Duration::from_secs(1));
+        }
+        assert_eq!(limiter.status().limit, Some(3));
+
+        let _busy_too = limiter.acquire(Priority::Normal, None).await.unwrap();
+        for _ in 0..100 {
+            limiter.record_success(Duration::from_millis(100));
+        }
+        assert_eq!(limiter.status().limit, Some(5));
+    }
+
+    #[tokio::test]
+    async fn test_adaptive_limit_shrinks_on_overload() {
+        let limiter = adaptive(Some(5));
+        assert_eq!(limiter.status().limit, Some(5));
+        limiter.record_overload();
+        assert_eq!(limiter.status().limit, Some(2));
+        limiter.record_overload();
+        assert_eq!(limiter.status().limit, Some(2));
+
+        // Overload lowers the limit below what is in use; nothing more is admitted
+        let limiter = adaptive(Some(4));
+        let _a = limiter.acquire(Priority::Normal, None).await.unwrap();
+        let _b = limiter.acquire(Priority::Normal, None).await.unwrap();
+        let _c = limiter.acquire(Priority::Normal, None).await.unwrap();
+        limiter.record_overload();
+        assert_eq!(limiter.status().limit, Some(2));
+        assert!(!limiter.has_capacity());
+    }
+
+    #[tokio::test]
+    async fn test_unlimited_admits_everything() {
+        let limiter = Arc::new(ConcurrencyLimiter::new(
+            "http://replica",
+            None,
+            &ConcurrencyConfig::default(),
+        ));
+        let permits: Vec<_> = futures::future::join_all((0..50).map(|_| limiter.acquire(Priority::Normal, None)))
+            .await
+            .into_iter()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(limiter.status().in_use, 50);
+        drop(permits);
+        assert_eq!(limiter.status().in_use, 0);
+    }
+}
@@ -1,7 +1,38 @@
+pub mod auth;
+pub mod balancer;
+pub mod circuit_breaker;
 pub mod client;
+pub mod fallback;
+pub mod health;
+pub mod jwt;
+pub mod limiter;
+pub mod priority;
+pub mod rate_limit;
 pub mod retry;
 pub mod router;
+pub mod rules;
+pub mod spend;
+pub mod timeouts;
+pub mod tls;
+pub mod tokens;
+pub mod upstream;
+pub mod variants;
 
-pub use client::ProxyClient;
-pub use retry::retry_with_backoff;
+pub use auth::{authorize, client_api_key, without_client_key, ClientIdentity, KeyStore, Principal};
+pub use balancer::{Endpoint, EndpointStatus, InFlightGuard, LoadBalancer};
+pub use circuit_breaker::{CircuitBreaker, CircuitPermit, CircuitState, CircuitStatus};
+pub use client::{ModelStatus, ProxyClient};
+pub use fallback::should_fallback;
+pub use health::{spawn_health_checks, EndpointHealth, HealthStatus};
+pub use jwt::JwtValidator;
+pub use limiter::{ConcurrencyLimiter, LimitPermit, LimiterStatus, QueuedByPriority};
+pub use priority::request_priority;
+pub use rate_limit::{RateLimitCharge, RateLimiter, RateLimits};
+pub use retry::{advised_delay, is_retryable, retry_with_backoff, should_retry, RetryBudget};
 pub use router::ModelRouter;
+pub use rules::{find_rule, RequestFacts};
+pub use spend::{spawn_spend_persistence, SpendEntry, SpendTracker};
+pub use timeouts::{request_deadline, AttemptTimer, TIMEOUT_HEADER};
+pub use tokens::{estimate_prompt_tokens, estimate_text_tokens, requested_output_tokens};
+pub use variants::{Variant, VariantSet};
+pub use upstream::{build_upstream_request, send_upstream, UpstreamRequest, UpstreamResponse};
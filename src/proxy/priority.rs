@@ -0,0 +1,94 @@
+use crate::config::{ApiKeyConfig, Priority, PriorityConfig};
+use http::HeaderMap;
+
+/// Priority class of a client request: the class the priority header asks for, capped
+/// at the authenticated client's maximum, or else the client's default. Clients without
+/// a policy of their own (and unauthenticated requests) get the global one.
+pub fn request_priority(config: &PriorityConfig, client: Option<&ApiKeyConfig>, headers: &HeaderMap) -> Priority {
+    let policy = client
+        .and_then(|client| client.priority)
+        .unwrap_or_else(|| config.client());
+    let requested = headers
+        .get(config.header.as_str())
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| match v.trim().to_ascii_lowercase().as_str() {
+            "low" => Some(Priority::Low),
+            "normal" => Some(Priority::Normal),
+            "high" => Some(Priority::High),
+            _ => None,
+        });
+
+    requested.map_or(policy.default, |requested| requested.min(policy.max()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ClientPriority;
+
+    fn client(default: Priority, max: Option<Priority>) -> ApiKeyConfig {
+        ApiKeyConfig {
+            name: "client".to_string(),
+            key: "client-key".to_string(),
+            allowed_models: vec!["*".to_string()],
+            aliases: Default::default(),
+            metadata: Default::default(),
+            rate_limit: None,
+            priority: Some(ClientPriority { default, max }),
+        }
+    }
+
+    fn with_header(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("x-llm-proxy-priority", value.parse().unwrap());
+        headers
+    }
+
+    #[test]
+    fn test_priority_from_client() {
+        let config = PriorityConfig::default();
+        let headers = HeaderMap::new();
+        assert_eq!(request_priority(&config, None, &headers), Priority::Normal);
+
+        let interactive = client(Priority::High, None);
+        assert_eq!(request_priority(&config, Some(&interactive), &headers), Priority::High);
+
+        let batch = client(Priority::Low, None);
+        assert_eq!(request_priority(&config, Some(&batch), &headers), Priority::Low);
+
+        // Clients without a policy get the global one
+        let mut plain = client(Priority::Low, None);
+        plain.priority = None;
+        assert_eq!(request_priority(&config, Some(&plain), &headers), Priority::Normal);
+    }
+
+    #[test]
+    fn test_priority_header_capped_at_client_max() {
+        let config = PriorityConfig::default();
+        let batch = client(Priority::Low, None);
+        assert_eq!(request_priority(&config, Some(&batch), &with_header("high")), Priority::Low);
+
+        let interactive = client(Priority::High, None);
+        assert_eq!(request_priority(&config, Some(&interactive), &with_header("Low")), Priority::Low);
+
+        let flexible = client(Priority::Normal, Some(Priority::High));
+        assert_eq!(request_priority(&config, Some(&flexible), &with_header("high")), Priority::High);
+        assert_eq!(request_priority(&config, Some(&flexible), &with_header("low")), Priority::Low);
+
+        // Unknown values are ignored
+        assert_eq!(request_priority(&config, Some(&flexible), &with_header("urgent")), Priority::Normal);
+    }
+
+    #[test]
+    fn test_priority_header_capped_without_client() {
+        let config = PriorityConfig::default();
+        assert_eq!(request_priority(&config, None, &with_header("high")), Priority::Normal);
+        assert_eq!(request_priority(&config, None, &with_header("low")), Priority::Low);
+
+        let config = PriorityConfig {
+            max: Some(Priority::High),
+            ..PriorityConfig::default()
+        };
+        assert_eq!(request_priority(&config, None, &with_header("high")), Priority::High);
+    }
+}
@@ -0,0 +1,459 @@
+use crate::config::{ApiKeyConfig, Config, RateLimitConfig};
+use crate::types::{ProxyError, RateLimitKind, RateLimitState, Result, TokenUsage};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Bucket holding a minute's allowance, refilled continuously. The level may go
+/// negative when actual usage turns out higher than charged; the debt is paid off
+/// by later refills.
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: f64,
+    per_sec: f64,
+    state: Mutex<BucketState>,
+}
+
+#[derive(Debug)]
+struct BucketState {
+    level: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn per_minute(limit: u64) -> Self {
+        Self {
+            capacity: limit as f64,
+            per_sec: limit as f64 / 60.0,
+            state: Mutex::new(BucketState {
+                level: limit as f64,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// Current level, after refilling for the time since the last update
+    fn level(&self, state: &mut BucketState) -> f64 {
+        let now = Instant::now();
+        let refill = now.duration_since(state.updated).as_secs_f64() * self.per_sec;
+        state.level = (state.level + refill).min(self.capacity);
+        state.updated = now;
+        state.level
+    }
+
+    /// How long until `amount` (at most a full bucket) is available
+    fn wait(&self, amount: f64) -> Duration {
+        let mut state = self.state.lock().unwrap();
+        let missing = amount.min(self.capacity) - self.level(&mut state);
+        Duration::from_secs_f64((missing / self.per_sec).max(0.0))
+    }
+
+    /// Remove `amount` if `needed` (at most a full bucket) is available, checking and
+    /// taking under one lock so concurrent requests cannot both pass on the same level
+    fn try_take(&self, needed: f64, amount: f64) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let level = self.level(&mut state);
+        if level < needed.min(self.capacity) {
+            return false;
+        }
+        state.level = level - amount;
+        true
+    }
+
+    fn is_full(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        self.level(&mut state) >= self.capacity
+    }
+
+    /// Remove (or, when negative, return) `amount` from the bucket
+    fn take(&self, amount: f64) {
+        let mut state = self.state.lock().unwrap();
+        let level = self.level(&mut state);
+        state.level = (level - amount).min(self.capacity);
+    }
+
+    fn state(&self, kind: RateLimitKind) -> RateLimitState {
+        let mut state = self.state.lock().unwrap();
+        let level = self.level(&mut state);
+        RateLimitState {
+            kind,
+            limit: self.capacity as u64,
+            remaining: level.max(0.0) as u64,
+            reset: Duration::from_secs_f64((self.capacity - level) / self.per_sec),
+        }
+    }
+}
+
+/// Request, input-token and output-token limits of one scope: a client key, a model
+/// or a backend account
+#[derive(Debug)]
+pub struct RateLimiter {
+    scope: String,
+    requests: Option<TokenBucket>,
+    input_tokens: Option<TokenBucket>,
+    output_tokens: Option<TokenBucket>,
+}
+
+impl RateLimiter {
+    pub fn new(scope: impl Into<String>, config: &RateLimitConfig) -> Self {
+        Self {
+            scope: scope.into(),
+            requests: config.requests_per_minute.map(TokenBucket::per_minute),
+            input_tokens: config.input_tokens_per_minute.map(TokenBucket::per_minute),
+            output_tokens: config.output_tokens_per_minute.map(TokenBucket::per_minute),
+        }
+    }
+
+    /// What admitting a request with `prompt_tokens` needs available in each bucket, and
+    /// what it takes. Output tokens are unknown up front, so the output limit only has
+    /// to be out of debt.
+    fn charges(&self, prompt_tokens: u64) -> impl Iterator<Item = (&TokenBucket, f64, f64)> {
+        let prompt_tokens = prompt_tokens as f64;
+        [
+            self.requests.as_ref().map(|b| (b, 1.0, 1.0)),
+            self.input_tokens.as_ref().map(|b| (b, prompt_tokens, prompt_tokens)),
+            self.output_tokens.as_ref().map(|b| (b, 1.0, 0.0)),
+        ]
+        .into_iter()
+        .flatten()
+    }
+
+    /// How long a request with `prompt_tokens` must wait to be admitted
+    fn wait(&self, prompt_tokens: u64) -> Duration {
+        self.charges(prompt_tokens)
+            .map(|(bucket, needed, _)| bucket.wait(needed))
+            .max()
+            .unwrap_or(Duration::ZERO)
+    }
+
+    /// Every bucket is full, so the limiter is no different from a new one
+    fn is_idle(&self) -> bool {
+        [&self.requests, &self.input_tokens, &self.output_tokens]
+            .into_iter()
+            .flatten()
+            .all(TokenBucket::is_full)
+    }
+
+    fn states(&self) -> Vec<RateLimitState> {
+        [
+            (&self.requests, RateLimitKind::Requests),
+            (&self.input_tokens, RateLimitKind::InputTokens),
+            (&self.output_tokens, RateLimitKind::OutputTokens),
+        ]
+        .into_iter()
+        .filter_map(|(bucket, kind)| bucket.as_ref().map(|b| b.state(kind)))
+        .collect()
+    }
+}
+
+/// What one request was charged against rate limits: a request and its estimated
+/// prompt tokens. Settled against the actual usage once the response is complete;
+/// dropped unsettled (the request failed), the prompt tokens are given back.
+#[derive(Debug, Default)]
+pub struct RateLimitCharge {
+    limiters: Vec<Arc<RateLimiter>>,
+    prompt_tokens: u64,
+    settled: bool,
+}
+
+impl RateLimitCharge {
+    /// Add the limiters of another charge for the same request
+    pub fn merge(&mut self, mut other: RateLimitCharge) {
+        self.limiters.append(&mut other.limiters);
+        self.prompt_tokens = self.prompt_tokens.max(other.prompt_tokens);
+    }
+
+    /// Replace the estimated prompt tokens with the actual usage
+    pub fn settle(mut self, usage: TokenUsage) {
+        for limiter in &self.limiters {
+            if let Some(bucket) = &limiter.input_tokens {
+                bucket.take(usage.input_tokens as f64 - self.prompt_tokens as f64);
+            }
+            if let Some(bucket) = &limiter.output_tokens {
+                bucket.take(usage.output_tokens as f64);
+            }
+        }
+        self.settled = true;
+    }
+}
+
+impl Drop for RateLimitCharge {
+    fn drop(&mut self) {
+        if self.settled {
+            return;
+        }
+        for bucket in self.limiters.iter().filter_map(|l| l.input_tokens.as_ref()) {
+            bucket.take(-(self.prompt_tokens as f64));
+        }
+    }
+}
+
+/// Fewest client limiters kept before idle ones are swept
+const MIN_KEY_SWEEP: usize = 256;
+
+/// Rate limiters of every client key, model and backend account
+#[derive(Debug, Default)]
+pub struct RateLimits {
+    keys: Mutex<KeyLimiters>,
+    /// By model: the model's own limiter and its account's
+    models: HashMap<String, Vec<Arc<RateLimiter>>>,
+}
+
+impl RateLimits {
+    pub fn new(config: &Config) -> Self {
+        let accounts: HashMap<_, _> = config
+            .accounts
+            .iter()
+            .map(|(name, account)| {
+                let limiter = RateLimiter::new(format!("account '{}'", name), &account.rate_limit);
+                (name.as_str(), Arc::new(limiter))
+            })
+            .collect();
+        let models = config
+            .models
+            .iter()
+            .map(|(name, model)| {
+                let own = model
+                    .rate_limit
+                    .as_ref()
+                    .map(|limit| Arc::new(RateLimiter::new(format!("model '{}'", name), limit)));
+                let account = model
+                    .account
+                    .as_deref()
+                    .and_then(|account| accounts.get(account).cloned());
+                (name.clone(), own.into_iter().chain(account).collect())
+            })
+            .collect();
+
+        Self {
+            keys: Mutex::default(),
+            models,
+        }
+    }
+
+    /// Admit a request under its client key's limits
+    pub fn admit_key(&self, key: &ApiKeyConfig, prompt_tokens: u64) -> Result<RateLimitCharge> {
+        let limiter = key
+            .rate_limit
+            .as_ref()
+            .map(|limit| self.keys.lock().unwrap().get_or_insert(&key.name, limit));
+        admit(limiter.into_iter().collect(), prompt_tokens)
+    }
+
+    /// Admit a request to a model under the limits of the model and its account
+    pub fn admit_model(&self, model: &str, prompt_tokens: u64) -> Result<RateLimitCharge> {
+        admit(self.models.get(model).cloned().unwrap_or_default(), prompt_tokens)
+    }
+}
+
+/// Limiters of clients by name, created on a client's first request since clients
+/// authenticated by JWT are not known up front. Limiters that are idle, with every
+/// bucket refilled, are swept whenever their number doubles since the last sweep.
+#[derive(Debug, Default)]
+struct KeyLimiters {
+    by_name: HashMap<String, Arc<RateLimiter>>,
+    sweep_at: usize,
+}
+
+impl KeyLimiters {
+    fn get_or_insert(&mut self, name: &str, limit: &RateLimitConfig) -> Arc<RateLimiter> {
+        if let Some(limiter) = self.by_name.get(name) {
+            return limiter.clone();
+        }
+        if self.by_name.len() >= self.sweep_at.max(MIN_KEY_SWEEP) {
+            // A limiter still held by a request may have charges to settle
+            self.by_name
+                .retain(|_, limiter| Arc::strong_count(limiter) > 1 || !limiter.is_idle());
+            self.sweep_at = self.by_name.len() * 2;
+        }
+        let limiter = Arc::new(RateLimiter::new(format!("client '{}'", name), limit));
+        self.by_name.insert(name.to_string(), limiter.clone());
+        limiter
+    }
+}
+
+/// Charge a request to every limiter, or reject it with the scope that has to be
+/// waited on longest. Each bucket is checked and charged atomically; if one comes up
+/// short, what was already taken from the others is given back.
+fn admit(limiters: Vec<Arc<RateLimiter>>, prompt_tokens: u64) -> Result<RateLimitCharge> {
+    let mut taken: Vec<(&TokenBucket, f64)> = Vec::new();
+    for limiter in &limiters {
+        for (bucket, needed, amount) in limiter.charges(prompt_tokens) {
+            if !bucket.try_take(needed, amount) {
+                for (bucket, amount) in taken {
+                    bucket.take(-amount);
+                }
+                return Err(rejection(&limiters, limiter, prompt_tokens));
+            }
+            taken.push((bucket, amount));
+        }
+    }
+    Ok(RateLimitCharge {
+        limiters,
+        prompt_tokens,
+        settled: false,
+    })
+}
+
+fn rejection(limiters: &[Arc<RateLimiter>], short: &RateLimiter, prompt_tokens: u64) -> ProxyError {
+    let (limiter, wait) = limiters
+        .iter()
+        .map(|limiter| (limiter.as_ref(), limiter.wait(prompt_tokens)))
+        .max_by_key(|(_, wait)| *wait)
+        .filter(|(_, wait)| !wait.is_zero())
+        .unwrap_or((short, Duration::ZERO));
+    tracing::warn!(scope = %limiter.scope, retry_after_ms = wait.as_millis(), "Rate limit exceeded");
+    ProxyError::RateLimited {
+        scope: limiter.scope.clone(),
+        retry_after: wait,
+        limits: limiter.states(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(requests: Option<u64>, input: Option<u64>, output: Option<u64>) -> Arc<RateLimiter> {
+        Arc::new(RateLimiter::new(
+            "model 'test'",
+            &RateLimitConfig {
+                requests_per_minute: requests,
+                input_tokens_per_minute: input,
+                output_tokens_per_minute: output,
+            },
+        ))
+    }
+
+    fn remaining(limiter: &RateLimiter, kind: RateLimitKind) -> u64 {
+        limiter.states().iter().find(|s| s.kind == kind).unwrap().remaining
+    }
+
+    #[test]
+    fn test_request_limit_rejects_with_retry_after() {
+        let limiter = limiter(Some(2), None, None);
+        admit(vec![limiter.clone()], 0).unwrap().settle(TokenUsage::default());
+        admit(vec![limiter.clone()], 0).unwrap().settle(TokenUsage::default());
+
+        let Err(ProxyError::RateLimited { scope, retry_after, limits }) = admit(vec![limiter], 0) else {
+            panic!("expected a rate limit rejection");
+        };
+        assert_eq!(scope, "model 'test'");
+        // Two per minute refill one request every 30s
+        assert!(retry_after > Duration::from_secs(29) && retry_after <= Duration::from_secs(30));
+        assert_eq!(limits[0].kind, RateLimitKind::Requests);
+        assert_eq!(limits[0].limit, 2);
+        assert_eq!(limits[0].remaining, 0);
+    }
+
+    #[test]
+    fn test_tokens_reconciled_with_actual_usage() {
+        let limiter = limiter(None, Some(1000), Some(500));
+        let charge = admit(vec![limiter.clone()], 300).unwrap();
+        assert_eq!(remaining(&limiter, RateLimitKind::InputTokens), 700);
+
+        charge.settle(TokenUsage {
+            input_tokens: 250,
+            output_tokens: 600,
+            cached_input_tokens: 0,
+        });
+        assert_eq!(remaining(&limiter, RateLimitKind::InputTokens), 750);
+        assert_eq!(remaining(&limiter, RateLimitKind::OutputTokens), 0);
+
+        // Output tokens are in debt until refilled
+        assert!(matches!(
+            admit(vec![limiter], 10),
+            Err(ProxyError::RateLimited { .. })
+        ));
+    }
+
+    #[test]
+    fn test_failed_request_returns_prompt_tokens() {
+        let limiter = limiter(Some(10), Some(1000), None);
+        drop(admit(vec![limiter.clone()], 400).unwrap());
+        assert_eq!(remaining(&limiter, RateLimitKind::InputTokens), 1000);
+        // The request itself still counts
+        assert_eq!(remaining(&limiter, RateLimitKind::Requests), 9);
+    }
+
+    #[test]
+    fn test_prompt_larger_than_limit_needs_a_full_bucket() {
+        let limiter = limiter(None, Some(100), None);
+        admit(vec![limiter.clone()], 500).unwrap().settle(TokenUsage {
+            input_tokens: 500,
+            ..TokenUsage::default()
+        });
+        assert!(admit(vec![limiter], 500).is_err());
+    }
+
+    #[test]
+    fn test_rejection_charges_nothing() {
+        let open = limiter(Some(10), None, None);
+        let exhausted = limiter(Some(1), None, None);
+        admit(vec![exhausted.clone()], 0).unwrap().settle(TokenUsage::default());
+
+        assert!(admit(vec![open.clone(), exhausted], 0).is_err());
+        assert_eq!(remaining(&open, RateLimitKind::Requests), 10);
+    }
+
+    #[test]
+    fn test_concurrent_requests_never_overshoot() {
+        let requests = limiter(Some(20), None, None);
+        let tokens = limiter(None, Some(1000), None);
+        let barrier = Arc::new(std::sync::Barrier::new(64));
+        let handles: Vec<_> = (0..64)
+            .map(|_| {
+                let limiters = vec![requests.clone(), tokens.clone()];
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    admit(limiters, 100).map(|charge| {
+                        charge.settle(TokenUsage {
+                            input_tokens: 100,
+                            ..TokenUsage::default()
+                        })
+                    })
+                })
+            })
+            .collect();
+        let admitted = handles
+            .into_iter()
+            .filter_map(|handle| handle.join().unwrap().ok())
+            .count();
+
+        // The token limit allows ten, and requests turned away by it are not charged
+        // to the request limit
+        assert_eq!(admitted, 10);
+        assert_eq!(remaining(&requests, RateLimitKind::Requests), 10);
+    }
+
+    #[test]
+    fn test_idle_client_limiters_are_swept() {
+        let limits = RateLimits::default();
+        let key = |name: String| ApiKeyConfig {
+            name,
+            key: String::new(),
+            allowed_models: vec!["*".to_string()],
+            aliases: HashMap::new(),
+            metadata: HashMap::new(),
+            rate_limit: Some(RateLimitConfig {
+                requests_per_minute: Some(600_000),
+                ..RateLimitConfig::default()
+            }),
+            priority: None,
+        };
+        for i in 0..MIN_KEY_SWEEP - 1 {
+            limits.admit_key(&key(format!("sub-{}", i)), 0).unwrap().settle(TokenUsage::default());
+        }
+        let held = limits.admit_key(&key("busy".to_string()), 0).unwrap();
+        assert_eq!(limits.keys.lock().unwrap().by_name.len(), MIN_KEY_SWEEP);
+
+        // Refilled by now, so only the limiter a request still holds survives the sweep
+        std::thread::sleep(Duration::from_millis(20));
+        limits.admit_key(&key("new".to_string()), 0).unwrap().settle(TokenUsage::default());
+        let keys = limits.keys.lock().unwrap();
+        assert_eq!(keys.by_name.len(), 2);
+        assert!(keys.by_name.contains_key("busy"));
+        drop(held);
+    }
+}
@@ -1,10 +1,58 @@
 use crate::config::RetryConfig;
-use crate::types::{ProxyError, Result};
+use crate::types::{ProxyError, Result, TimeoutPhase};
+use chrono::{DateTime, Utc};
+use http::HeaderMap;
 use std::future::Future;
-use std::time::Duration;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
 use tokio::time::sleep;
 
-pub async fn retry_with_backoff<F, Fut, T>(config: &RetryConfig, mut operation: F) -> Result<T>
+/// Rate limits reported by providers as (remaining header, reset header)
+const RATE_LIMIT_HEADERS: &[(&str, &str)] = &[
+    ("x-ratelimit-remaining-requests", "x-ratelimit-reset-requests"),
+    ("x-ratelimit-remaining-tokens", "x-ratelimit-reset-tokens"),
+    ("anthropic-ratelimit-requests-remaining", "anthropic-ratelimit-requests-reset"),
+    ("anthropic-ratelimit-tokens-remaining", "anthropic-ratelimit-tokens-reset"),
+    ("anthropic-ratelimit-input-tokens-remaining", "anthropic-ratelimit-input-tokens-reset"),
+    ("anthropic-ratelimit-output-tokens-remaining", "anthropic-ratelimit-output-tokens-reset"),
+];
+
+/// Retries spent on one client request, shared by every model and hedge it tries
+#[derive(Debug, Default)]
+pub struct RetryBudget {
+    spent: AtomicUsize,
+}
+
+impl RetryBudget {
+    /// Take one retry unless `limit` retries have been spent already
+    fn try_spend(&self, limit: Option<usize>) -> bool {
+        match limit {
+            Some(limit) => self
+                .spent
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |spent| {
+                    (spent < limit).then_some(spent + 1)
+                })
+                .is_ok(),
+            None => {
+                self.spent.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+        }
+    }
+}
+
+/// Run `operation` until it succeeds, fails with an error the retry policy does not
+/// retry, runs out of attempts or the request runs out of retry `budget`.
+///
+/// Retries wait for the delay the server advised (capped at `max_backoff_ms`) or,
+/// without advice, for an exponential backoff. When the wait (or the server's advice)
+/// reaches past `deadline`, the last error is returned right away.
+pub async fn retry_with_backoff<F, Fut, T>(
+    config: &RetryConfig,
+    budget: &RetryBudget,
+    deadline: Option<Instant>,
+    mut operation: F,
+) -> Result<T>
 where
     F: FnMut() -> Fut,
     Fut: Future<Output = Result<T>>,
@@ -18,7 +66,7 @@ where
             Err(e) => {
                 attempt += 1;
 
-                if !is_retryable(&e) {
+                if !should_retry(config, &e) {
                     tracing::debug!(
                         error = %e,
                         "Error is not retryable"
@@ -35,7 +83,39 @@ where
                     return Err(last_error.unwrap_or(ProxyError::MaxRetriesExceeded(attempt)));
                 }
 
-                let delay = calculate_backoff(attempt, config);
+                let advised = match &e {
+                    ProxyError::Upstream { retry_after, .. } => *retry_after,
+                    _ => None,
+                };
+                let delay = match advised {
+                    Some(advised) => advised.min(Duration::from_millis(config.max_backoff_ms)),
+                    None => calculate_backoff(attempt, config),
+                };
+
+                if let Some(deadline) = deadline {
+                    // The server's own estimate counts even when the wait itself is capped
+                    let wait = advised.unwrap_or(delay);
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    if wait >= remaining {
+                        tracing::warn!(
+                            wait_ms = wait.as_millis(),
+                            remaining_ms = remaining.as_millis(),
+                            error = %e,
+                            "Retry would not finish before the request deadline, giving up"
+                        );
+                        return Err(e);
+                    }
+                }
+
+                if !budget.try_spend(config.budget) {
+                    tracing::warn!(
+                        budget = config.budget,
+                        error = %e,
+                        "Retry budget of the request exhausted"
+                    );
+                    return Err(e);
+                }
+
                 tracing::info!(
                     attempt = attempt,
                     delay_ms = delay.as_millis(),
@@ -50,28 +130,104 @@ where
     }
 }
 
-fn is_retryable(error: &ProxyError) -> bool {
+/// Whether an error is transient under the default retry policy. Decides what counts
+/// as an endpoint failure for the circuit breaker and concurrency limiter, whatever
+/// the model's own retry policy.
+pub fn is_retryable(error: &ProxyError) -> bool {
+    should_retry(&RetryConfig::default(), error)
+}
+
+/// Whether a model's retry policy retries an error
+pub fn should_retry(policy: &RetryConfig, error: &ProxyError) -> bool {
     match error {
-        ProxyError::Timeout => true,
-        ProxyError::Upstream { status, .. } => {
-            // Retry on common transient errors
-            matches!(
-                *status,
-                429 | // Too Many Requests
-                500 | // Internal Server Error
-                502 | // Bad Gateway
-                503 | // Service Unavailable
-                504   // Gateway Timeout
-            )
-        }
-        ProxyError::Http(e) => {
-            // Retry on network errors, timeouts, etc.
-            e.is_timeout() || e.is_connect() || e.is_request()
+        ProxyError::Timeout(phase) => policy.retry_on_timeout && *phase != TimeoutPhase::Deadline,
+        ProxyError::Upstream { status, message, .. } => {
+            policy.retryable_statuses.contains(status)
+                || upstream_error_types(message)
+                    .iter()
+                    .any(|kind| policy.retryable_error_types.contains(kind))
         }
+        ProxyError::StreamStart(_) => policy.retry_streams_before_first_byte,
+        ProxyError::Http(e) if e.is_timeout() => policy.retry_on_timeout,
+        // Network errors
+        ProxyError::Http(e) => e.is_connect() || e.is_request(),
         _ => false,
     }
 }
 
+/// Error kinds in an upstream error body: `error.type` (Anthropic's `overloaded_error`)
+/// and `error.code` (OpenAI's `rate_limit_exceeded`)
+fn upstream_error_types(message: &str) -> Vec<String> {
+    let Ok(body) = serde_json::from_str::<serde_json::Value>(message) else {
+        return Vec::new();
+    };
+    ["type", "code"]
+        .iter()
+        .filter_map(|field| body["error"][field].as_str())
+        .map(str::to_string)
+        .collect()
+}
+
+/// Delay the server advised before retrying: `retry-after-ms`, then `Retry-After`
+/// (seconds or HTTP date), then the latest reset time among the exhausted
+/// OpenAI `x-ratelimit-*` and Anthropic `anthropic-ratelimit-*` limits
+pub fn advised_delay(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok()).map(str::trim);
+
+    if let Some(ms) = header("retry-after-ms").and_then(|v| v.parse::<f64>().ok()) {
+        return duration_from_secs(ms / 1000.0);
+    }
+    if let Some(value) = header("retry-after") {
+        if let Ok(secs) = value.parse::<f64>() {
+            return duration_from_secs(secs);
+        }
+        if let Ok(date) = DateTime::parse_from_rfc2822(value) {
+            return Some(until(date.with_timezone(&Utc)));
+        }
+    }
+
+    RATE_LIMIT_HEADERS
+        .iter()
+        .filter(|(remaining, _)| header(remaining).and_then(|v| v.parse::<u64>().ok()) == Some(0))
+        .filter_map(|(_, reset)| header(reset).and_then(parse_reset))
+        .max()
+}
+
+/// Reset values are durations like `1s`, `6m0s` or `250ms` (OpenAI) or RFC 3339
+/// timestamps (Anthropic)
+fn parse_reset(value: &str) -> Option<Duration> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Some(until(time.with_timezone(&Utc)));
+    }
+
+    let mut total = 0.0;
+    let mut rest = value;
+    while !rest.is_empty() {
+        let number_end = rest.find(|c: char| !c.is_ascii_digit() && c != '.')?;
+        let number: f64 = rest[..number_end].parse().ok()?;
+        rest = &rest[number_end..];
+        let unit_end = rest.find(|c: char| c.is_ascii_digit()).unwrap_or(rest.len());
+        total += number
+            * match &rest[..unit_end] {
+                "h" => 3600.0,
+                "m" => 60.0,
+                "s" => 1.0,
+                "ms" => 0.001,
+                _ => return None,
+            };
+        rest = &rest[unit_end..];
+    }
+    duration_from_secs(total)
+}
+
+fn duration_from_secs(secs: f64) -> Option<Duration> {
+    Duration::try_from_secs_f64(secs).ok()
+}
+
+fn until(time: DateTime<Utc>) -> Duration {
+    (time - Utc::now()).to_std().unwrap_or(Duration::ZERO)
+}
+
 fn calculate_backoff(attempt: usize, config: &RetryConfig) -> Duration {
     // Exponential backoff with jitter
     let base_delay = config.backoff_ms * (2_u64.pow(attempt as u32 - 1));
@@ -91,30 +247,36 @@ mod tests {
 
     #[test]
     fn test_is_retryable_timeout() {
-        assert!(is_retryable(&ProxyError::Timeout));
+        assert!(is_retryable(&ProxyError::Timeout(TimeoutPhase::FirstByte)));
+        assert!(!is_retryable(&ProxyError::Timeout(TimeoutPhase::Deadline)));
     }
 
     #[test]
     fn test_is_retryable_upstream_errors() {
         assert!(is_retryable(&ProxyError::Upstream {
             status: 429,
-            message: "Too many requests".to_string()
+            message: "Too many requests".to_string(),
+            retry_after: None
         }));
         assert!(is_retryable(&ProxyError::Upstream {
             status: 500,
-            message: "Internal error".to_string()
+            message: "Internal error".to_string(),
+            retry_after: None
         }));
         assert!(is_retryable(&ProxyError::Upstream {
             status: 502,
-            message: "Bad gateway".to_string()
+            message: "Bad gateway".to_string(),
+            retry_after: None
         }));
         assert!(is_retryable(&ProxyError::Upstream {
             status: 503,
-            message: "Service unavailable".to_string()
+            message: "Service unavailable".to_string(),
+            retry_after: None
         }));
         assert!(is_retryable(&ProxyError::Upstream {
             status: 504,
-            message: "Gateway timeout".to_string()
+            message: "Gateway timeout".to_string(),
+            retry_after: None
         }));
     }
 
@@ -128,11 +290,13 @@ mod tests {
         )));
         assert!(!is_retryable(&ProxyError::Upstream {
             status: 400,
-            message: "Bad request".to_string()
+            message: "Bad request".to_string(),
+            retry_after: None
         }));
         assert!(!is_retryable(&ProxyError::Upstream {
             status: 401,
-            message: "Unauthorized".to_string()
+            message: "Unauthorized".to_string(),
+            retry_after: None
         }));
     }
 
@@ -142,6 +306,7 @@ mod tests {
             max_attempts: 3,
             backoff_ms: 1000,
             max_backoff_ms: 10000,
+            ..RetryConfig::default()
         };
 
         // First retry: ~1000ms
@@ -163,6 +328,7 @@ mod tests {
             max_attempts: 10,
             backoff_ms: 1000,
             max_backoff_ms: 5000,
+            ..RetryConfig::default()
         };
 
         // Large attempt number should be capped
@@ -176,14 +342,15 @@ mod tests {
             max_attempts: 3,
             backoff_ms: 10,
             max_backoff_ms: 100,
+            ..RetryConfig::default()
         };
 
         let mut attempts = 0;
-        let result = retry_with_backoff(&config, || {
+        let result = retry_with_backoff(&config, &RetryBudget::default(), None, || {
             attempts += 1;
             async move {
                 if attempts < 2 {
-                    Err(ProxyError::Timeout)
+                    Err(ProxyError::Timeout(TimeoutPhase::Total))
                 } else {
                     Ok(42)
                 }
@@ -202,12 +369,13 @@ mod tests {
             max_attempts: 2,
             backoff_ms: 10,
             max_backoff_ms: 100,
+            ..RetryConfig::default()
         };
 
         let mut attempts = 0;
-        let result = retry_with_backoff(&config, || {
+        let result = retry_with_backoff(&config, &RetryBudget::default(), None, || {
             attempts += 1;
-            async move { Err::<(), _>(ProxyError::Timeout) }
+            async move { Err::<(), _>(ProxyError::Timeout(TimeoutPhase::Total)) }
         })
         .await;
 
@@ -221,10 +389,11 @@ mod tests {
             max_attempts: 3,
             backoff_ms: 10,
             max_backoff_ms: 100,
+            ..RetryConfig::default()
         };
 
         let mut attempts = 0;
-        let result = retry_with_backoff(&config, || {
+        let result = retry_with_backoff(&config, &RetryBudget::default(), None, || {
             attempts += 1;
             async move { Err::<(), _>(ProxyError::InvalidRequest("bad".to_string())) }
         })
@@ -233,4 +402,188 @@ mod tests {
         assert!(result.is_err());
         assert_eq!(attempts, 1); // Should not retry
     }
+
+    fn headers(pairs: &[(&str, &str)]) -> HeaderMap {
+        pairs
+            .iter()
+            .map(|(name, value)| {
+                (
+                    http::HeaderName::from_bytes(name.as_bytes()).unwrap(),
+                    value.parse().unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    fn rate_limited(retry_after: Option<Duration>) -> ProxyError {
+        ProxyError::Upstream {
+            status: 429,
+            message: "rate limited".to_string(),
+            retry_after,
+        }
+    }
+
+    #[test]
+    fn test_is_retryable_overloaded() {
+        assert!(is_retryable(&ProxyError::Upstream {
+            status: 529,
+            message: "Overloaded".to_string(),
+            retry_after: None
+        }));
+        assert!(is_retryable(&ProxyError::Upstream {
+            status: 400,
+            message: r#"{"type":"error","error":{"type":"overloaded_error"}}"#.to_string(),
+            retry_after: None
+        }));
+    }
+
+    #[test]
+    fn test_advised_delay_from_retry_after() {
+        assert_eq!(
+            advised_delay(&headers(&[("retry-after", "2")])),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(
+            advised_delay(&headers(&[("retry-after-ms", "150"), ("retry-after", "2")])),
+            Some(Duration::from_millis(150))
+        );
+        let date = (Utc::now() + chrono::Duration::seconds(30)).to_rfc2822();
+        let delay = advised_delay(&headers(&[("retry-after", &date)])).unwrap();
+        assert!(delay > Duration::from_secs(25) && delay <= Duration::from_secs(30));
+        assert_eq!(advised_delay(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn test_advised_delay_from_rate_limit_resets() {
+        // Only exhausted limits count
+        let openai = headers(&[
+            ("x-ratelimit-remaining-requests", "10"),
+            ("x-ratelimit-reset-requests", "1m0s"),
+            ("x-ratelimit-remaining-tokens", "0"),
+            ("x-ratelimit-reset-tokens", "6.5s"),
+        ]);
+        assert_eq!(advised_delay(&openai), Some(Duration::from_millis(6500)));
+        assert_eq!(parse_reset("1h2m3s"), Some(Duration::from_secs(3723)));
+        assert_eq!(parse_reset("250ms"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_reset("soon"), None);
+
+        let reset = (Utc::now() + chrono::Duration::seconds(10)).to_rfc3339();
+        let anthropic = headers(&[
+            ("anthropic-ratelimit-input-tokens-remaining", "0"),
+            ("anthropic-ratelimit-input-tokens-reset", &reset),
+        ]);
+        let delay = advised_delay(&anthropic).unwrap();
+        assert!(delay > Duration::from_secs(5) && delay <= Duration::from_secs(10));
+    }
+
+    #[tokio::test]
+    async fn test_retry_waits_for_advised_delay_capped() {
+        let config = RetryConfig {
+            max_attempts: 2,
+            backoff_ms: 1,
+            max_backoff_ms: 200,
+            ..RetryConfig::default()
+        };
+
+        let started = Instant::now();
+        let mut attempts = 0;
+        let result = retry_with_backoff(&config, &RetryBudget::default(), None, || {
+            attempts += 1;
+            let attempt = attempts;
+            async move {
+                match attempt {
+                    1 => Err(rate_limited(Some(Duration::from_secs(60)))),
+                    _ => Ok(()),
+                }
+            }
+        })
+        .await;
+
+        assert!(result.is_ok());
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(200) && elapsed < Duration::from_secs(5));
+    }
+
+    #[tokio::test]
+    async fn test_retry_gives_up_when_advice_exceeds_budget() {
+        let config = RetryConfig {
+            max_attempts: 3,
+            backoff_ms: 1,
+            max_backoff_ms: 10,
+            ..RetryConfig::default()
+        };
+        let deadline = Instant::now() + Duration::from_secs(5);
+
+        let mut attempts = 0;
+        let result = retry_with_backoff(&config, &RetryBudget::default(), Some(deadline), || {
+            attempts += 1;
+            async move { Err::<(), _>(rate_limited(Some(Duration::from_secs(30)))) }
+        })
+        .await;
+
+        assert!(matches!(result, Err(ProxyError::Upstream { status: 429, .. })));
+        assert_eq!(attempts, 1);
+    }
+
+    #[test]
+    fn test_configured_retry_policy() {
+        let policy = RetryConfig {
+            retryable_statuses: vec![503],
+            retryable_error_types: vec!["rate_limit_exceeded".to_string()],
+            retry_on_timeout: false,
+            retry_streams_before_first_byte: false,
+            ..RetryConfig::default()
+        };
+        let upstream = |status, message: &str| ProxyError::Upstream {
+            status,
+            message: message.to_string(),
+            retry_after: None,
+        };
+
+        assert!(should_retry(&policy, &upstream(503, "")));
+        assert!(!should_retry(&policy, &upstream(502, "")));
+        assert!(should_retry(
+            &policy,
+            &upstream(400, r#"{"error":{"type":"requests","code":"rate_limit_exceeded"}}"#)
+        ));
+        // Error types are matched as fields, not anywhere in the body
+        assert!(!should_retry(&policy, &upstream(400, "rate_limit_exceeded")));
+        assert!(!should_retry(&policy, &ProxyError::Timeout(TimeoutPhase::FirstByte)));
+        assert!(!should_retry(&policy, &ProxyError::StreamStart("reset".to_string())));
+
+        let default = RetryConfig::default();
+        assert!(should_retry(&default, &ProxyError::StreamStart("reset".to_string())));
+        assert!(!should_retry(&default, &upstream(400, r#"{"error":{"code":"rate_limit_exceeded"}}"#)));
+    }
+
+    #[tokio::test]
+    async fn test_retry_budget_is_shared_by_the_request() {
+        let config = RetryConfig {
+            max_attempts: 3,
+            backoff_ms: 1,
+            max_backoff_ms: 10,
+            budget: Some(3),
+            ..RetryConfig::default()
+        };
+        let budget = RetryBudget::default();
+
+        // The first model spends two retries, leaving one for the next
+        let mut attempts = 0;
+        let result = retry_with_backoff(&config, &budget, None, || {
+            attempts += 1;
+            async move { Err::<(), _>(rate_limited(None)) }
+        })
+        .await;
+        assert!(result.is_err());
+        assert_eq!(attempts, 3);
+
+        let mut attempts = 0;
+        let result = retry_with_backoff(&config, &budget, None, || {
+            attempts += 1;
+            async move { Err::<(), _>(rate_limited(None)) }
+        })
+        .await;
+        assert!(matches!(result, Err(ProxyError::Upstream { status: 429, .. })));
+        assert_eq!(attempts, 2);
+    }
 }
@@ -1,33 +1,69 @@
-use crate::config::{Config, ModelConfig};
+use crate::config::{Config, ModelConfig, RoutingRule};
+use crate::proxy::client::ModelStatus;
+use crate::proxy::rules::find_rule;
+use crate::proxy::tokens::{estimate_prompt_tokens, requested_output_tokens};
+use crate::proxy::variants::VariantSet;
 use crate::proxy::ProxyClient;
 use crate::types::{ProxyError, Result};
-use std::collections::HashMap;
+use http::HeaderMap;
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 
 pub struct ModelRouter {
     clients: HashMap<String, Arc<ProxyClient>>,
+    variants: HashMap<String, VariantSet>,
+    rules: Vec<RoutingRule>,
 }
 
 impl ModelRouter {
     pub fn new(config: &Config) -> Result<Self> {
         let mut clients = HashMap::new();
+        let mut variants = HashMap::new();
 
         for (model_name, model_config) in &config.models {
             let client = Arc::new(ProxyClient::new(Arc::new(model_config.clone()))?);
             clients.insert(model_name.clone(), client);
 
+            if let Some(set) = VariantSet::new(model_config)? {
+                for variant in set.variants() {
+                    tracing::info!(
+                        model = %model_name,
+                        variant = %variant.name,
+                        weight = variant.weight,
+                        backend = ?variant.client.config().backend_type,
+                        "Registered model variant"
+                    );
+                }
+                variants.insert(model_name.clone(), set);
+            }
+
             let target = model_config.target_model.as_deref().unwrap_or("(same)");
             tracing::info!(
                 model = %model_name,
                 target_model = %target,
                 backend = ?model_config.backend_type,
-                endpoint = %model_config.endpoint,
+                endpoints = ?model_config.resolved_endpoints().iter().map(|e| e.url.as_str()).collect::<Vec<_>>(),
+                load_balancing = ?model_config.load_balancing,
                 ssl_verify = model_config.ssl_verify,
                 "Registered model route"
             );
         }
 
-        Ok(Self { clients })
+        for rule in &config.routing_rules {
+            tracing::info!(
+                rule = %rule.label(),
+                models = ?rule.models,
+                target = %rule.target,
+                "Registered routing rule"
+            );
+        }
+
+        Ok(Self {
+            clients,
+            variants,
+            rules: config.routing_rules.clone(),
+        })
     }
 
     pub fn get_client(&self, model: &str) -> Result<Arc<ProxyClient>> {
@@ -37,12 +73,109 @@ impl ModelRouter {
             .ok_or_else(|| ProxyError::ModelNotFound(model.to_string()))
     }
 
+    /// Client that should serve this request: the chosen variant for models split
+    /// into variants, otherwise the model's own client
+    pub fn select_client(
+        &self,
+        model: &str,
+        headers: &HeaderMap,
+        request: &Value,
+    ) -> Result<Arc<ProxyClient>> {
+        match self.variants.get(model) {
+            Some(set) => Ok(set.select(model, headers, request).client.clone()),
+            None => self.get_client(model),
+        }
+    }
+
     pub fn get_config(&self, model: &str) -> Result<&ModelConfig> {
         let client = self.clients.get(model)
             .ok_or_else(|| ProxyError::ModelNotFound(model.to_string()))?;
         Ok(client.config())
     }
 
+    /// Apply the routing rules to a request, returning the configured model that should
+    /// serve it. Requests no rule matches keep the model they asked for.
+    pub fn route(&self, requested_model: &str, headers: &HeaderMap, request: &Value) -> String {
+        match find_rule(&self.rules, requested_model, headers, request) {
+            Some(rule) => {
+                tracing::debug!(
+                    requested_model = %requested_model,
+                    rule = %rule.label(),
+                    target = %rule.target,
+                    "Routing rule matched"
+                );
+                rule.target.clone()
+            }
+            None => requested_model.to_string(),
+        }
+    }
+
+    /// Make sure a request fits the model's context window, escalating through
+    /// `overflow_model`s until one is large enough. Returns the model to use.
+    ///
+    /// A request needs room for its estimated prompt plus the output it may generate:
+    /// its `max_tokens` (capped at the model's `max_output_tokens`), or the model's
+    /// `max_output_tokens` when it sets none.
+    pub fn fit_context(&self, model: &str, request: &Value) -> Result<String> {
+        let mut model = model.to_string();
+        let mut prompt_tokens = None;
+
+        loop {
+            let config = self.get_config(&model)?;
+            let Some(window) = config.context_window else {
+                return Ok(model);
+            };
+            let prompt = *prompt_tokens.get_or_insert_with(|| estimate_prompt_tokens(request));
+            let output = match (requested_output_tokens(request), config.max_output_tokens) {
+                (Some(requested), Some(max)) => requested.min(max),
+                (requested, max) => requested.or(max).unwrap_or(0),
+            };
+            if prompt + output <= window {
+                return Ok(model);
+            }
+
+            match &config.overflow_model {
+                Some(overflow) => {
+                    tracing::info!(
+                        model = %model,
+                        overflow_model = %overflow,
+                        prompt_tokens = prompt,
+                        output_tokens = output,
+                        context_window = window,
+                        "Request exceeds context window, escalating"
+                    );
+                    model = overflow.clone();
+                }
+                None => {
+                    return Err(ProxyError::InvalidRequest(format!(
+                        "Request needs about {} prompt + {} output tokens, which exceeds the {}-token context window of model '{}'",
+                        prompt, output, window, model
+                    )))
+                }
+            }
+        }
+    }
+
+    /// Models to attempt for a request: the requested model followed by its fallbacks
+    pub fn fallback_chain(&self, model: &str) -> Result<Vec<String>> {
+        let config = self.get_config(model)?;
+        let mut chain = vec![model.to_string()];
+        chain.extend(config.fallbacks.iter().cloned());
+        Ok(chain)
+    }
+
+    /// Every client that serves traffic, with its model name. Models split into
+    /// variants yield one client per variant instead of their own.
+    pub fn clients(&self) -> impl Iterator<Item = (&str, &Arc<ProxyClient>)> {
+        self.clients.iter().flat_map(move |(name, client)| {
+            let clients: Vec<&Arc<ProxyClient>> = match self.variants.get(name) {
+                Some(set) => set.variants().iter().map(|v| &v.client).collect(),
+                None => vec![client],
+            };
+            clients.into_iter().map(move |client| (name.as_str(), client))
+        })
+    }
+
     pub fn list_models(&self) -> Vec<String> {
         self.clients.keys().cloned().collect()
     }
@@ -50,41 +183,34 @@ impl ModelRouter {
     pub fn has_model(&self, model: &str) -> bool {
         self.clients.contains_key(model)
     }
+
+    /// Live endpoint statistics for every model (and variant), sorted by name
+    pub fn status(&self) -> BTreeMap<String, ModelStatus> {
+        self.clients()
+            .map(|(name, client)| (client.label(name), client.status()))
+            .collect()
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::config::{BackendType, HeaderConfig, RetryConfig, ServerConfig, LoggingConfig, TransformConfig};
+    use crate::config::{AuthConfig, BackendType, LoggingConfig, PriorityConfig, ServerConfig, SpendConfig};
 
     fn create_test_config() -> Config {
         let mut models = HashMap::new();
         models.insert(
             "gpt-4".to_string(),
             ModelConfig {
-                backend_type: BackendType::OpenAI,
-                endpoint: "https://api.openai.com/v1/chat/completions".to_string(),
                 api_key: Some("test-key-1".to_string()),
-                target_model: None,
-                timeout_seconds: 60,
-                retry: RetryConfig::default(),
-                ssl_verify: true,
-                headers: HeaderConfig::default(),
-                transforms: TransformConfig::default(),
+                ..ModelConfig::test(BackendType::OpenAI, "https://api.openai.com/v1/chat/completions")
             },
         );
         models.insert(
             "claude-3".to_string(),
             ModelConfig {
-                backend_type: BackendType::Anthropic,
-                endpoint: "https://api.anthropic.com/v1/messages".to_string(),
                 api_key: Some("test-key-2".to_string()),
-                target_model: None,
-                timeout_seconds: 60,
-                retry: RetryConfig::default(),
-                ssl_verify: true,
-                headers: HeaderConfig::default(),
-                transforms: TransformConfig::default(),
+                ..ModelConfig::test(BackendType::Anthropic, "https://api.anthropic.com/v1/messages")
             },
         );
 
@@ -92,9 +218,16 @@ mod tests {
             server: ServerConfig {
                 host: "0.0.0.0".to_string(),
                 port: 8080,
+                drain_timeout_seconds: 30,
+                tls: None,
             },
             logging: LoggingConfig::default(),
             models,
+            routing_rules: Vec::new(),
+            priority: PriorityConfig::default(),
+            auth: AuthConfig::default(),
+            accounts: HashMap::new(),
+            spend: SpendConfig::default(),
         }
     }
 
@@ -153,15 +286,9 @@ mod tests {
         models.insert(
             "gpt-4".to_string(),
             ModelConfig {
-                backend_type: BackendType::Ollama,
-                endpoint: "http://localhost:11434/api/generate".to_string(),
-                api_key: None,
                 target_model: Some("llama3-70b".to_string()),
-                timeout_seconds: 60,
-                retry: RetryConfig::default(),
                 ssl_verify: false,
-                headers: HeaderConfig::default(),
-                transforms: TransformConfig::default(),
+                ..ModelConfig::test(BackendType::Ollama, "http://localhost:11434/api/generate")
             },
         );
 
@@ -169,9 +296,16 @@ mod tests {
             server: ServerConfig {
                 host: "0.0.0.0".to_string(),
                 port: 8080,
+                drain_timeout_seconds: 30,
+                tls: None,
             },
             logging: LoggingConfig::default(),
             models,
+            routing_rules: Vec::new(),
+            priority: PriorityConfig::default(),
+            auth: AuthConfig::default(),
+            accounts: HashMap::new(),
+            spend: SpendConfig::default(),
         };
 
         let router = ModelRouter::new(&config).unwrap();
@@ -190,4 +324,75 @@ mod tests {
         // No target_model specified, should use incoming model name
         assert_eq!(model_config.get_target_model("gpt-4"), "gpt-4");
     }
+
+    #[test]
+    fn test_fallback_chain_order() {
+        let mut config = create_test_config();
+        config.models.get_mut("gpt-4").unwrap().fallbacks = vec!["claude-3".to_string()];
+
+        let router = ModelRouter::new(&config).unwrap();
+        assert_eq!(router.fallback_chain("gpt-4").unwrap(), vec!["gpt-4", "claude-3"]);
+        assert_eq!(router.fallback_chain("claude-3").unwrap(), vec!["claude-3"]);
+        assert!(router.fallback_chain("unknown").is_err());
+    }
+
+    #[test]
+    fn test_route_applies_rules() {
+        let mut config = create_test_config();
+        config.routing_rules = serde_yaml::from_str(
+            r#"
+- models: [chat]
+  match:
+    headers:
+      X-Team: research
+  target: claude-3
+- models: [chat]
+  target: gpt-4
+"#,
+        )
+        .unwrap();
+        let router = ModelRouter::new(&config).unwrap();
+        let request = serde_json::json!({"model": "chat", "messages": []});
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-team", "research".parse().unwrap());
+        assert_eq!(router.route("chat", &headers, &request), "claude-3");
+        assert_eq!(router.route("chat", &HeaderMap::new(), &request), "gpt-4");
+        // Unmatched requests keep their model
+        assert_eq!(router.route("claude-3", &HeaderMap::new(), &request), "claude-3");
+    }
+
+    #[test]
+    fn test_fit_context_escalates_or_rejects() {
+        let mut config = create_test_config();
+        let gpt4 = config.models.get_mut("gpt-4").unwrap();
+        gpt4.context_window = Some(1000);
+        gpt4.max_output_tokens = Some(200);
+        gpt4.overflow_model = Some("claude-3".to_string());
+        config.models.get_mut("claude-3").unwrap().context_window = Some(10_000);
+        let router = ModelRouter::new(&config).unwrap();
+
+        let request = |chars: usize, max_tokens: Option<u64>| {
+            let mut request = serde_json::json!({
+                "messages": [{"role": "user", "content": "a".repeat(chars)}]
+            });
+            if let Some(max_tokens) = max_tokens {
+                request["max_tokens"] = max_tokens.into();
+            }
+            request
+        };
+
+        // ~500 prompt tokens plus the 200-token output reservation fit
+        assert_eq!(router.fit_context("gpt-4", &request(2000, None)).unwrap(), "gpt-4");
+        // A large max_tokens is capped at max_output_tokens
+        assert_eq!(router.fit_context("gpt-4", &request(2000, Some(4000))).unwrap(), "gpt-4");
+        // ~900 prompt tokens leave no room for the output
+        assert_eq!(router.fit_context("gpt-4", &request(3600, None)).unwrap(), "claude-3");
+        // ...unless the client asks for little output
+        assert_eq!(router.fit_context("gpt-4", &request(3600, Some(50))).unwrap(), "gpt-4");
+
+        let err = router.fit_context("gpt-4", &request(40_000, None)).unwrap_err();
+        assert!(matches!(err, ProxyError::InvalidRequest(_)));
+        assert!(err.to_string().contains("claude-3"));
+    }
 }
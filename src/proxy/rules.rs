@@ -0,0 +1,185 @@
+use crate::config::{RoutingRule, RuleConditions};
+use crate::proxy::tokens::{estimate_prompt_tokens, has_images};
+use http::HeaderMap;
+use serde_json::Value;
+use std::cell::OnceCell;
+
+/// Request properties routing rules match on. Expensive ones are computed on first use.
+pub struct RequestFacts<'a> {
+    headers: &'a HeaderMap,
+    request: &'a Value,
+    has_images: OnceCell<bool>,
+    prompt_tokens: OnceCell<usize>,
+}
+
+impl<'a> RequestFacts<'a> {
+    pub fn new(headers: &'a HeaderMap, request: &'a Value) -> Self {
+        Self {
+            headers,
+            request,
+            has_images: OnceCell::new(),
+            prompt_tokens: OnceCell::new(),
+        }
+    }
+
+    /// OpenAI `user` or Anthropic `metadata.user_id`
+    pub fn user(&self) -> Option<&str> {
+        self.request
+            .get("user")
+            .or_else(|| self.request.pointer("/metadata/user_id"))
+            .and_then(Value::as_str)
+    }
+
+    pub fn has_tools(&self) -> bool {
+        self.request
+            .get("tools")
+            .and_then(Value::as_array)
+            .is_some_and(|tools| !tools.is_empty())
+    }
+
+    pub fn has_images(&self) -> bool {
+        *self.has_images.get_or_init(|| has_images(self.request))
+    }
+
+    pub fn prompt_tokens(&self) -> usize {
+        *self
+            .prompt_tokens
+            .get_or_init(|| estimate_prompt_tokens(self.request))
+    }
+
+    pub fn stream(&self) -> bool {
+        self.request
+            .get("stream")
+            .and_then(Value::as_bool)
+            .unwrap_or(false)
+    }
+
+    fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).and_then(|v| v.to_str().ok())
+    }
+}
+
+/// Whether a request satisfies every condition that is set
+pub fn conditions_match(conditions: &RuleConditions, facts: &RequestFacts) -> bool {
+    conditions
+        .headers
+        .iter()
+        .all(|(name, value)| facts.header(name) == Some(value.as_str()))
+        && conditions
+            .user
+            .as_deref()
+            .is_none_or(|user| facts.user() == Some(user))
+        && conditions
+            .has_tools
+            .is_none_or(|expected| facts.has_tools() == expected)
+        && conditions
+            .stream
+            .is_none_or(|expected| facts.stream() == expected)
+        && conditions
+            .has_images
+            .is_none_or(|expected| facts.has_images() == expected)
+        && conditions
+            .min_prompt_tokens
+            .is_none_or(|min| facts.prompt_tokens() >= min)
+        && conditions
+            .max_prompt_tokens
+            .is_none_or(|max| facts.prompt_tokens() <= max)
+}
+
+/// First rule that applies to the requested model and matches the request
+pub fn find_rule<'r>(
+    rules: &'r [RoutingRule],
+    requested_model: &str,
+    headers: &HeaderMap,
+    request: &Value,
+) -> Option<&'r RoutingRule> {
+    let facts = RequestFacts::new(headers, request);
+    rules.iter().find(|rule| {
+        (rule.models.is_empty() || rule.models.iter().any(|m| m == requested_model))
+            && conditions_match(&rule.conditions, &facts)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn rules(yaml: &str) -> Vec<RoutingRule> {
+        serde_yaml::from_str(yaml).unwrap()
+    }
+
+    #[test]
+    fn test_header_and_model_scope() {
+        let rules = rules(
+            r#"
+- models: [chat]
+  match:
+    headers:
+      X-Team: research
+  target: research-model
+"#,
+        );
+        let mut headers = HeaderMap::new();
+        headers.insert("x-team", "research".parse().unwrap());
+        let request = json!({"model": "chat", "messages": []});
+
+        let rule = find_rule(&rules, "chat", &headers, &request).unwrap();
+        assert_eq!(rule.target, "research-model");
+        assert!(find_rule(&rules, "other", &headers, &request).is_none());
+        assert!(find_rule(&rules, "chat", &HeaderMap::new(), &request).is_none());
+    }
+
+    #[test]
+    fn test_first_matching_rule_wins() {
+        let rules = rules(
+            r#"
+- name: vision
+  match: {has_images: true}
+  target: vision-model
+- name: long
+  match: {min_prompt_tokens: 1000}
+  target: long-context-model
+- name: default
+  target: small-model
+"#,
+        );
+        let headers = HeaderMap::new();
+
+        let image = json!({"messages": [{"role": "user", "content": [
+            {"type": "image_url", "image_url": {"url": "https://example.com/a.png"}}
+        ]}]});
+        assert_eq!(find_rule(&rules, "chat", &headers, &image).unwrap().label(), "vision");
+
+        let long = json!({"messages": [{"role": "user", "content": "x".repeat(8000)}]});
+        assert_eq!(find_rule(&rules, "chat", &headers, &long).unwrap().label(), "long");
+
+        let short = json!({"messages": [{"role": "user", "content": "hi"}]});
+        assert_eq!(find_rule(&rules, "chat", &headers, &short).unwrap().label(), "default");
+    }
+
+    #[test]
+    fn test_user_tools_and_stream() {
+        let rules = rules(
+            r#"
+- match:
+    user: alice
+    has_tools: true
+    stream: false
+  target: agent-model
+"#,
+        );
+        let headers = HeaderMap::new();
+        let openai = json!({"user": "alice", "tools": [{"type": "function"}], "messages": []});
+        assert!(find_rule(&rules, "chat", &headers, &openai).is_some());
+
+        let anthropic = json!({"metadata": {"user_id": "alice"}, "tools": [{"name": "t"}], "messages": []});
+        assert!(find_rule(&rules, "chat", &headers, &anthropic).is_some());
+
+        let streaming = json!({"user": "alice", "tools": [{"type": "function"}], "stream": true});
+        assert!(find_rule(&rules, "chat", &headers, &streaming).is_none());
+
+        let no_tools = json!({"user": "alice", "tools": []});
+        assert!(find_rule(&rules, "chat", &headers, &no_tools).is_none());
+    }
+}
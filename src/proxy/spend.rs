@@ -0,0 +1,466 @@
+use crate::config::{ApiKeyConfig, BudgetConfig, PricingConfig, SpendConfig};
+use crate::types::{ProxyError, Result, TokenUsage};
+use chrono::{DateTime, Datelike, NaiveDate, Utc};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+/// How often recorded spend is written to the store
+const FLUSH_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Spend in USD by scope (`key:<name>`, `team:<name>` or `model:<name>`), then by
+/// window (`YYYY-MM-DD` for days, `YYYY-MM` for months)
+type Ledger = HashMap<String, HashMap<String, f64>>;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Window {
+    Daily,
+    Monthly,
+}
+
+impl Window {
+    fn period(self, now: DateTime<Utc>) -> String {
+        match self {
+            Window::Daily => now.format("%Y-%m-%d").to_string(),
+            Window::Monthly => now.format("%Y-%m").to_string(),
+        }
+    }
+
+    /// Time until the window containing `now` ends
+    fn remaining(self, now: DateTime<Utc>) -> Duration {
+        let today = now.date_naive();
+        let next = match self {
+            Window::Daily => today.succ_opt(),
+            Window::Monthly if today.month() == 12 => NaiveDate::from_ymd_opt(today.year() + 1, 1, 1),
+            Window::Monthly => NaiveDate::from_ymd_opt(today.year(), today.month() + 1, 1),
+        };
+        next.and_then(|day| day.and_hms_opt(0, 0, 0))
+            .and_then(|start| (start.and_utc() - now).to_std().ok())
+            .unwrap_or_default()
+    }
+}
+
+/// Ledger scope and log label of a budget
+fn budget_scope(budget: &BudgetConfig) -> (String, String) {
+    if let Some(key) = &budget.key {
+        (format!("key:{}", key), format!("API key '{}'", key))
+    } else if let Some(team) = &budget.team {
+        (format!("team:{}", team), format!("team '{}'", team))
+    } else {
+        let model = budget.model.as_deref().unwrap_or_default();
+        (format!("model:{}", model), format!("model '{}'", model))
+    }
+}
+
+/// Whether a budget covers requests from `key` to `model`
+fn budget_applies(budget: &BudgetConfig, key: Option<&ApiKeyConfig>, model: &str) -> bool {
+    let team = key.and_then(|key| key.metadata.get("team"));
+    match (&budget.key, &budget.team, &budget.model) {
+        (Some(name), _, _) => key.is_some_and(|key| &key.name == name),
+        (_, Some(name), _) => team == Some(name),
+        (_, _, Some(name)) => name == model,
+        _ => false,
+    }
+}
+
+/// Spend per client key, team and model over the current day and month, and the
+/// budgets enforced on it. Recorded spend is kept in memory and written to the
+/// store periodically; windows that have ended are dropped.
+#[derive(Debug)]
+pub struct SpendTracker {
+    store: Option<PathBuf>,
+    budgets: Vec<BudgetConfig>,
+    ledger: Mutex<Ledger>,
+    dirty: AtomicBool,
+}
+
+impl SpendTracker {
+    /// Tracker with the spend of the current windows loaded from the store, if any
+    pub fn new(config: &SpendConfig) -> Self {
+        let store = config.store.as_ref().map(PathBuf::from);
+        let mut ledger = match &store {
+            Some(path) if path.exists() => match std::fs::read(path)
+                .map_err(ProxyError::from)
+                .and_then(|bytes| Ok(serde_json::from_slice::<Ledger>(&bytes)?))
+            {
+                Ok(ledger) => ledger,
+                Err(e) => {
+                    tracing::warn!(store = %path.display(), error = %e, "Failed to load spend store, starting from zero");
+                    Ledger::new()
+                }
+            },
+            _ => Ledger::new(),
+        };
+        prune(&mut ledger, Utc::now());
+
+        Self {
+            store,
+            budgets: config.budgets.clone(),
+            ledger: Mutex::new(ledger),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    /// USD spent by a scope (`key:<name>`, `team:<name>` or `model:<name>`) today and this month
+    pub fn spent(&self, scope: &str) -> (f64, f64) {
+        let now = Utc::now();
+        let ledger = self.ledger.lock().unwrap();
+        let spent = |window: Window| {
+            ledger
+                .get(scope)
+                .and_then(|windows| windows.get(&window.period(now)))
+                .copied()
+                .unwrap_or(0.0)
+        };
+        (spent(Window::Daily), spent(Window::Monthly))
+    }
+
+    /// The window of `budget` that is used up, the monthly one if both are
+    fn exceeded(&self, budget: &BudgetConfig, now: DateTime<Utc>) -> Option<Window> {
+        let (scope, _) = budget_scope(budget);
+        let ledger = self.ledger.lock().unwrap();
+        let windows = ledger.get(&scope)?;
+        [(Window::Monthly, budget.monthly_usd), (Window::Daily, budget.daily_usd)]
+            .into_iter()
+            .find_map(|(window, limit)| {
+                let spent = windows.get(&window.period(now)).copied().unwrap_or(0.0);
+                limit.filter(|limit| spent >= *limit).map(|_| window)
+            })
+    }
+
+    /// The model a request from `key` for `model` is served by under the budgets:
+    /// `model` itself, or the model a spent budget downgrades to. Rejected when a spent
+    /// budget has nothing cheaper to downgrade to.
+    pub fn enforce(&self, key: Option<&ApiKeyConfig>, model: &str) -> Result<String> {
+        let now = Utc::now();
+        let mut model = model.to_string();
+        let mut visited = Vec::new();
+        'downgrade: loop {
+            let exceeded: Vec<_> = self
+                .budgets
+                .iter()
+                .filter(|budget| budget_applies(budget, key, &model))
+                .filter_map(|budget| Some((budget, self.exceeded(budget, now)?)))
+                .collect();
+
+            for (budget, window) in &exceeded {
+                match &budget.downgrade_to {
+                    // Already served by the cheaper model
+                    Some(cheaper) if *cheaper == model => {}
+                    Some(cheaper) if !visited.contains(cheaper) => {
+                        tracing::info!(
+                            scope = %budget_scope(budget).1,
+                            model = %model,
+                            downgrade_to = %cheaper,
+                            "Budget exceeded, downgrading model"
+                        );
+                        visited.push(std::mem::replace(&mut model, cheaper.clone()));
+                        continue 'downgrade;
+                    }
+                    _ => {
+                        let (_, scope) = budget_scope(budget);
+                        tracing::warn!(scope = %scope, model = %model, "Budget exceeded");
+                        return Err(ProxyError::BudgetExceeded {
+                            scope,
+                            retry_after: window.remaining(now),
+                        });
+                    }
+                }
+            }
+            return Ok(model);
+        }
+    }
+
+    /// Reject a request from `key` to `model` when a budget covering the model is spent,
+    /// whether or not the budget downgrades. For models a request is moved to for other
+    /// reasons, like fallbacks, which are not downgraded in turn.
+    pub fn check(&self, key: Option<&ApiKeyConfig>, model: &str) -> Result<()> {
+        let now = Utc::now();
+        let exceeded = self
+            .budgets
+            .iter()
+            .filter(|budget| budget_applies(budget, key, model))
+            .find_map(|budget| Some((budget, self.exceeded(budget, now)?)));
+        match exceeded {
+            Some((budget, window)) => {
+                let (_, scope) = budget_scope(budget);
+                tracing::warn!(scope = %scope, model = %model, "Budget exceeded");
+                Err(ProxyError::BudgetExceeded {
+                    scope,
+                    retry_after: window.remaining(now),
+                })
+            }
+            None => Ok(()),
+        }
+    }
+
+    /// Add `cost` to the spend of the key, its team and the model that served the request
+    pub fn record(&self, key: Option<&ApiKeyConfig>, model: &str, cost: f64) {
+        let now = Utc::now();
+        let mut scopes = vec![format!("model:{}", model)];
+        if let Some(key) = key {
+            scopes.push(format!("key:{}", key.name));
+            if let Some(team) = key.metadata.get("team") {
+                scopes.push(format!("team:{}", team));
+            }
+        }
+
+        let mut ledger = self.ledger.lock().unwrap();
+        for scope in scopes {
+            let windows = ledger.entry(scope).or_default();
+            for window in [Window::Daily, Window::Monthly] {
+                *windows.entry(window.period(now)).or_default() += cost;
+            }
+        }
+        self.dirty.store(true, Ordering::Relaxed);
+    }
+
+    /// Spend of a request to `model` that is recorded once its usage is known
+    pub fn entry(
+        self: &Arc<Self>,
+        key: Option<&Arc<ApiKeyConfig>>,
+        model: &str,
+        pricing: Option<&PricingConfig>,
+    ) -> SpendEntry {
+        SpendEntry {
+            tracker: self.clone(),
+            key: key.cloned(),
+            model: model.to_string(),
+            pricing: pricing.cloned(),
+        }
+    }
+
+    /// Write the current windows to the store if anything was recorded since the last write
+    pub async fn flush(&self) -> std::io::Result<()> {
+        let Some(path) = &self.store else {
+            return Ok(());
+        };
+        if !self.dirty.swap(false, Ordering::Relaxed) {
+            return Ok(());
+        }
+        let json = {
+            let mut ledger = self.ledger.lock().unwrap();
+            prune(&mut ledger, Utc::now());
+            serde_json::to_vec_pretty(&*ledger)?
+        };
+        // Replace the store in one step so a crash never leaves it half written
+        let tmp = path.with_extension("tmp");
+        let result = match tokio::fs::write(&tmp, json).await {
+            Ok(()) => tokio::fs::rename(&tmp, path).await,
+            Err(e) => Err(e),
+        };
+        if result.is_err() {
+            self.dirty.store(true, Ordering::Relaxed);
+        }
+        result
+    }
+}
+
+/// Drop spend of days and months that have ended
+fn prune(ledger: &mut Ledger, now: DateTime<Utc>) {
+    let current = [Window::Daily.period(now), Window::Monthly.period(now)];
+    for windows in ledger.values_mut() {
+        windows.retain(|period, _| current.contains(period));
+    }
+    ledger.retain(|_, windows| !windows.is_empty());
+}
+
+/// Write recorded spend to the store periodically, if one is configured
+pub fn spawn_spend_persistence(tracker: Arc<SpendTracker>) -> Option<JoinHandle<()>> {
+    tracker.store.as_ref()?;
+    Some(tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(FLUSH_INTERVAL);
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            if let Err(e) = tracker.flush().await {
+                tracing::warn!(error = %e, "Failed to write spend store");
+            }
+        }
+    }))
+}
+
+/// The spend of one request, priced with the serving model's pricing once the
+/// response's usage is known. Models without pricing record nothing.
+#[derive(Debug)]
+pub struct SpendEntry {
+    tracker: Arc<SpendTracker>,
+    key: Option<Arc<ApiKeyConfig>>,
+    model: String,
+    pricing: Option<PricingConfig>,
+}
+
+impl SpendEntry {
+    /// Record the cost of `usage`, returning it
+    pub fn record(self, usage: &TokenUsage) -> Option<f64> {
+        let cost = self.pricing.as_ref()?.cost(usage);
+        self.tracker.record(self.key.as_deref(), &self.model, cost);
+        tracing::debug!(
+            model = %self.model,
+            client = ?self.key.as_ref().map(|key| &key.name),
+            input_tokens = usage.input_tokens,
+            output_tokens = usage.output_tokens,
+            cost_usd = cost,
+            "Recorded spend"
+        );
+        Some(cost)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn key(name: &str, team: Option<&str>) -> ApiKeyConfig {
+        ApiKeyConfig {
+            name: name.to_string(),
+            key: format!("sk-{}", name),
+            allowed_models: vec!["*".to_string()],
+            aliases: HashMap::new(),
+            metadata: team
+                .map(|team| HashMap::from([("team".to_string(), team.to_string())]))
+                .unwrap_or_default(),
+            rate_limit: None,
+            priority: None,
+        }
+    }
+
+    fn budget(scope: (&str, &str), daily_usd: Option<f64>, downgrade_to: Option<&str>) -> BudgetConfig {
+        let name = Some(scope.1.to_string());
+        BudgetConfig {
+            key: name.clone().filter(|_| scope.0 == "key"),
+            team: name.clone().filter(|_| scope.0 == "team"),
+            model: name.filter(|_| scope.0 == "model"),
+            daily_usd,
+            monthly_usd: None,
+            downgrade_to: downgrade_to.map(str::to_string),
+        }
+    }
+
+    fn tracker(budgets: Vec<BudgetConfig>) -> SpendTracker {
+        SpendTracker::new(&SpendConfig { store: None, budgets })
+    }
+
+    #[test]
+    fn test_cost_with_cached_tokens() {
+        let pricing = PricingConfig {
+            input_per_million: 3.0,
+            output_per_million: 15.0,
+            cached_input_per_million: Some(0.3),
+        };
+        let usage = TokenUsage {
+            input_tokens: 1_000_000,
+            output_tokens: 100_000,
+            cached_input_tokens: 500_000,
+        };
+        assert!((pricing.cost(&usage) - (1.5 + 0.15 + 1.5)).abs() < 1e-9);
+
+        // Without a cached price, cached tokens cost as much as other input
+        let pricing = PricingConfig {
+            cached_input_per_million: None,
+            ..pricing
+        };
+        assert!((pricing.cost(&usage) - 4.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_spend_recorded_per_key_team_and_model() {
+        let tracker = tracker(Vec::new());
+        let alice = key("alice", Some("research"));
+        tracker.record(Some(&alice), "gpt-4", 1.25);
+        tracker.record(Some(&key("bob", Some("research"))), "gpt-4", 0.75);
+        tracker.record(None, "claude", 0.5);
+
+        assert_eq!(tracker.spent("key:alice"), (1.25, 1.25));
+        assert_eq!(tracker.spent("team:research"), (2.0, 2.0));
+        assert_eq!(tracker.spent("model:gpt-4"), (2.0, 2.0));
+        assert_eq!(tracker.spent("model:claude"), (0.5, 0.5));
+        assert_eq!(tracker.spent("key:carol"), (0.0, 0.0));
+    }
+
+    #[test]
+    fn test_exceeded_budget_rejects_until_window_resets() {
+        let tracker = tracker(vec![budget(("team", "research"), Some(1.0), None)]);
+        let alice = key("alice", Some("research"));
+        let other = key("dave", Some("ops"));
+        assert_eq!(tracker.enforce(Some(&alice), "gpt-4").unwrap(), "gpt-4");
+
+        tracker.record(Some(&alice), "gpt-4", 1.0);
+        let Err(ProxyError::BudgetExceeded { scope, retry_after }) = tracker.enforce(Some(&alice), "gpt-4") else {
+            panic!("expected the team budget to be exceeded");
+        };
+        assert_eq!(scope, "team 'research'");
+        assert!(retry_after <= Duration::from_secs(24 * 3600));
+        // Other teams are unaffected
+        assert!(tracker.enforce(Some(&other), "gpt-4").is_ok());
+    }
+
+    #[test]
+    fn test_exceeded_budget_downgrades() {
+        let tracker = tracker(vec![
+            budget(("key", "alice"), Some(1.0), Some("gpt-4o-mini")),
+            budget(("model", "gpt-4o-mini"), Some(5.0), None),
+        ]);
+        let alice = key("alice", None);
+        tracker.record(Some(&alice), "gpt-4", 2.0);
+
+        // The key's budget stays exceeded on the cheaper model, which keeps serving it
+        assert_eq!(tracker.enforce(Some(&alice), "gpt-4").unwrap(), "gpt-4o-mini");
+        assert_eq!(tracker.enforce(None, "gpt-4").unwrap(), "gpt-4");
+
+        // Until the cheaper model's own budget is spent as well
+        tracker.record(None, "gpt-4o-mini", 5.0);
+        assert!(matches!(
+            tracker.enforce(Some(&alice), "gpt-4"),
+            Err(ProxyError::BudgetExceeded { .. })
+        ));
+    }
+
+    #[test]
+    fn test_downgrade_cycle_rejects() {
+        let tracker = tracker(vec![
+            budget(("model", "a"), Some(1.0), Some("b")),
+            budget(("model", "b"), Some(1.0), Some("a")),
+        ]);
+        tracker.record(None, "a", 1.0);
+        tracker.record(None, "b", 1.0);
+        assert!(tracker.enforce(None, "a").is_err());
+    }
+
+    #[test]
+    fn test_window_reset() {
+        let now = Utc.with_ymd_and_hms(2026, 12, 31, 23, 0, 0).unwrap();
+        assert_eq!(Window::Daily.period(now), "2026-12-31");
+        assert_eq!(Window::Monthly.period(now), "2026-12");
+        assert_eq!(Window::Daily.remaining(now), Duration::from_secs(3600));
+        assert_eq!(Window::Monthly.remaining(now), Duration::from_secs(3600));
+
+        let mut ledger = Ledger::from([(
+            "key:alice".to_string(),
+            HashMap::from([("2026-12-30".to_string(), 1.0), ("2026-12".to_string(), 3.0)]),
+        )]);
+        prune(&mut ledger, now);
+        assert_eq!(ledger["key:alice"], HashMap::from([("2026-12".to_string(), 3.0)]));
+    }
+
+    #[tokio::test]
+    async fn test_spend_persists_across_restarts() {
+        let path = std::env::temp_dir().join(format!("llm-proxy-spend-{}.json", std::process::id()));
+        let config = SpendConfig {
+            store: Some(path.to_string_lossy().into_owned()),
+            budgets: Vec::new(),
+        };
+        let tracker = SpendTracker::new(&config);
+        tracker.record(Some(&key("alice", None)), "gpt-4", 0.5);
+        tracker.flush().await.unwrap();
+
+        let reloaded = SpendTracker::new(&config);
+        assert_eq!(reloaded.spent("key:alice"), (0.5, 0.5));
+        assert_eq!(reloaded.spent("model:gpt-4"), (0.5, 0.5));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
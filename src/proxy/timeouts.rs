@@ -0,0 +1,201 @@
+use crate::config::ModelConfig;
+use crate::types::{ProxyError, Result, TimeoutPhase};
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+/// Request header clients use to shorten the request deadline, in milliseconds
+pub const TIMEOUT_HEADER: &str = "x-llm-proxy-timeout-ms";
+
+/// Timeouts of one attempt against an upstream endpoint.
+///
+/// Every phase is also bounded by the attempt's end: the earlier of its total timeout
+/// and the request deadline. Whichever limit is hit first is named in the error.
+#[derive(Debug, Clone, Copy)]
+pub struct AttemptTimer {
+    first_byte: Duration,
+    idle_stream: Duration,
+    end: Option<(Instant, TimeoutPhase)>,
+}
+
+impl AttemptTimer {
+    /// Start timing an attempt now
+    pub fn start(config: &ModelConfig, deadline: Option<Instant>) -> Self {
+        let total = config
+            .total_timeout()
+            .map(|total| (Instant::now() + total, TimeoutPhase::Total));
+        let deadline = deadline.map(|deadline| (deadline, TimeoutPhase::Deadline));
+        let end = match (total, deadline) {
+            (Some(total), Some(deadline)) => Some(if deadline.0 <= total.0 { deadline } else { total }),
+            (total, deadline) => total.or(deadline),
+        };
+
+        Self {
+            first_byte: config.first_byte_timeout(),
+            idle_stream: config.idle_stream_timeout(),
+            end,
+        }
+    }
+
+    /// Wait for the response headers
+    pub async fn first_byte<T>(&self, future: impl Future<Output = Result<T>>) -> Result<T> {
+        self.within(self.first_byte, TimeoutPhase::FirstByte, future).await
+    }
+
+    /// Relay a response body, failing when it stalls for longer than the idle timeout
+    /// or runs past the attempt's end
+    pub fn body<S>(self, body: S) -> impl Stream<Item = Result<Bytes>> + Send + 'static
+    where
+        S: Stream<Item = reqwest::Result<Bytes>> + Send + 'static,
+    {
+        async_stream::stream! {
+            futures::pin_mut!(body);
+            loop {
+                let next = self.within(self.idle_stream, TimeoutPhase::IdleStream, async {
+                    Ok(body.next().await)
+                });
+                match next.await {
+                    Ok(Some(chunk)) => yield chunk.map_err(ProxyError::from),
+                    Ok(None) => return,
+                    Err(e) => {
+                        yield Err(e);
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    async fn within<T>(
+        &self,
+        limit: Duration,
+        phase: TimeoutPhase,
+        future: impl Future<Output = Result<T>>,
+    ) -> Result<T> {
+        let (limit, phase) = match self.end {
+            Some((end, end_phase)) => {
+                let remaining = end.saturating_duration_since(Instant::now());
+                if remaining < limit {
+                    (remaining, end_phase)
+                } else {
+                    (limit, phase)
+                }
+            }
+            None => (limit, phase),
+        };
+        tokio::time::timeout(limit, future)
+            .await
+            .map_err(|_| ProxyError::Timeout(phase))?
+    }
+}
+
+/// Deadline for a whole request: the model's `deadline_ms`, shortened by the client's
+/// timeout header if it asks for less
+pub fn request_deadline(
+    config: &ModelConfig,
+    headers: &http::HeaderMap,
+    started: Instant,
+) -> Option<Instant> {
+    let requested = headers
+        .get(TIMEOUT_HEADER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse::<u64>().ok())
+        .map(Duration::from_millis);
+    let limit = match (config.deadline(), requested) {
+        (Some(configured), Some(requested)) => Some(configured.min(requested)),
+        (configured, requested) => configured.or(requested),
+    };
+    limit.map(|limit| started + limit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{BackendType, TimeoutConfig};
+
+    fn config(timeouts: TimeoutConfig) -> ModelConfig {
+        ModelConfig {
+            timeouts,
+            ..ModelConfig::test(BackendType::OpenAI, "http://localhost/v1/chat/completions")
+        }
+    }
+
+    #[tokio::test]
+    async fn test_phase_named_in_error() {
+        let config = config(TimeoutConfig {
+            first_byte_ms: Some(20),
+            ..TimeoutConfig::default()
+        });
+        let timer = AttemptTimer::start(&config, None);
+        let err = timer
+            .first_byte(async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Ok(())
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ProxyError::Timeout(TimeoutPhase::FirstByte)));
+
+        // A deadline sooner than the phase timeout wins
+        let timer = AttemptTimer::start(&config, Some(Instant::now() + Duration::from_millis(5)));
+        let err = timer
+            .first_byte(async {
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                Ok(())
+            })
+            .await
+            .unwrap_err();
+        assert!(matches!(err, ProxyError::Timeout(TimeoutPhase::Deadline)));
+    }
+
+    #[tokio::test]
+    async fn test_idle_stream_allows_slow_but_steady_body() {
+        let config = config(TimeoutConfig {
+            idle_stream_ms: Some(100),
+            ..TimeoutConfig::default()
+        });
+        // Five chunks 30ms apart take longer than the idle timeout in total
+        let steady = futures::stream::unfold(0, |n| async move {
+            if n == 5 {
+                return None;
+            }
+            tokio::time::sleep(Duration::from_millis(30)).await;
+            Some((Ok(Bytes::from("x")), n + 1))
+        });
+        let chunks: Vec<_> = AttemptTimer::start(&config, None).body(steady).collect().await;
+        assert_eq!(chunks.len(), 5);
+        assert!(chunks.iter().all(|c| c.is_ok()));
+
+        let stalled = futures::stream::once(async { Ok(Bytes::from("x")) })
+            .chain(futures::stream::pending());
+        let chunks: Vec<_> = AttemptTimer::start(&config, None).body(stalled).collect().await;
+        assert_eq!(chunks.len(), 2);
+        assert!(matches!(chunks[1], Err(ProxyError::Timeout(TimeoutPhase::IdleStream))));
+    }
+
+    #[test]
+    fn test_request_deadline_header_only_shortens() {
+        let config = config(TimeoutConfig {
+            deadline_ms: Some(10_000),
+            ..TimeoutConfig::default()
+        });
+        let started = Instant::now();
+        let mut headers = http::HeaderMap::new();
+        assert_eq!(
+            request_deadline(&config, &headers, started),
+            Some(started + Duration::from_secs(10))
+        );
+
+        headers.insert(TIMEOUT_HEADER, "2000".parse().unwrap());
+        assert_eq!(
+            request_deadline(&config, &headers, started),
+            Some(started + Duration::from_secs(2))
+        );
+        headers.insert(TIMEOUT_HEADER, "60000".parse().unwrap());
+        assert_eq!(
+            request_deadline(&config, &headers, started),
+            Some(started + Duration::from_secs(10))
+        );
+    }
+}
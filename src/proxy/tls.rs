@@ -0,0 +1,136 @@
+use base64::Engine;
+use std::sync::Arc;
+use tokio_rustls::rustls::{
+    self,
+    client::{
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        WebPkiServerVerifier,
+    },
+    pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+    DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+
+use crate::config::{TlsVersion, UpstreamTlsConfig};
+use crate::types::{ProxyError, Result};
+
+/// Certificates from a PEM file, which must contain at least one
+pub(crate) fn read_certs(path: &str) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<std::result::Result<Vec<_>, _>>())
+        .map_err(|e| ProxyError::Config(format!("Failed to read certificates from '{}': {}", path, e)))?;
+    if certs.is_empty() {
+        return Err(ProxyError::Config(format!("No certificates found in '{}'", path)));
+    }
+    Ok(certs)
+}
+
+pub(crate) fn read_key(path: &str) -> Result<PrivateKeyDer<'static>> {
+    PrivateKeyDer::from_pem_file(path)
+        .map_err(|e| ProxyError::Config(format!("Failed to read private key from '{}': {}", path, e)))
+}
+
+/// Base64 SHA-256 digest of a certificate's SubjectPublicKeyInfo, as pinned in
+/// `pinned_spki_sha256`
+pub fn spki_sha256(cert: &CertificateDer<'_>) -> Option<String> {
+    let (_, cert) = x509_parser::parse_x509_certificate(cert).ok()?;
+    let digest = ring::digest::digest(&ring::digest::SHA256, cert.public_key().raw);
+    Some(base64::engine::general_purpose::STANDARD.encode(digest))
+}
+
+/// TLS settings for a model's endpoints, which the model's HTTP client uses in place of
+/// its defaults
+pub fn client_tls_config(config: &UpstreamTlsConfig) -> Result<rustls::ClientConfig> {
+    let tls_error = |e: rustls::Error| ProxyError::Config(format!("Invalid upstream TLS settings: {}", e));
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let versions: &[&rustls::SupportedProtocolVersion] = match config.min_version {
+        TlsVersion::Tls12 => &[&rustls::version::TLS13, &rustls::version::TLS12],
+        TlsVersion::Tls13 => &[&rustls::version::TLS13],
+    };
+
+    let mut roots = RootCertStore::empty();
+    match &config.ca_bundle {
+        Some(path) => {
+            for cert in read_certs(path)? {
+                roots.add(cert).map_err(tls_error)?;
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+    let webpki = WebPkiServerVerifier::builder_with_provider(Arc::new(roots), provider.clone())
+        .build()
+        .map_err(|e| ProxyError::Config(format!("Invalid upstream CA bundle: {}", e)))?;
+    let verifier: Arc<dyn ServerCertVerifier> = match config.pinned_spki_sha256.is_empty() {
+        true => webpki,
+        false => Arc::new(PinnedVerifier {
+            inner: webpki,
+            pins: config.pinned_spki_sha256.clone(),
+        }),
+    };
+
+    let builder = rustls::ClientConfig::builder_with_provider(provider)
+        .with_protocol_versions(versions)
+        .map_err(tls_error)?
+        .dangerous()
+        .with_custom_certificate_verifier(verifier);
+    match (&config.client_cert, &config.client_key) {
+        (Some(cert), Some(key)) => builder
+            .with_client_auth_cert(read_certs(cert)?, read_key(key)?)
+            .map_err(tls_error),
+        _ => Ok(builder.with_no_client_auth()),
+    }
+}
+
+/// Regular chain verification, plus a check that the chain contains a pinned key
+#[derive(Debug)]
+struct PinnedVerifier {
+    inner: Arc<WebPkiServerVerifier>,
+    pins: Vec<String>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> std::result::Result<ServerCertVerified, rustls::Error> {
+        let verified = self
+            .inner
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)?;
+        let pinned = std::iter::once(end_entity)
+            .chain(intermediates)
+            .filter_map(spki_sha256)
+            .any(|digest| self.pins.contains(&digest));
+        if !pinned {
+            return Err(rustls::Error::General(format!(
+                "no certificate presented by {} has a pinned public key",
+                server_name.to_str()
+            )));
+        }
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> std::result::Result<HandshakeSignatureValid, rustls::Error> {
+        self.inner.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.inner.supported_verify_schemes()
+    }
+}
@@ -0,0 +1,163 @@
+use serde_json::Value;
+
+/// Rough characters-per-token ratio for English text with common BPE tokenizers
+const CHARS_PER_TOKEN: usize = 4;
+
+/// Framing overhead per message (role markers, separators)
+const TOKENS_PER_MESSAGE: usize = 4;
+
+/// Flat cost for an image block (about a 1024x1024 high-detail image)
+const TOKENS_PER_IMAGE: usize = 765;
+
+/// Cheap, tokenizer-free estimate of the prompt size of an OpenAI or Anthropic request.
+///
+/// Counts the text of messages, the system prompt and tool definitions. Intended for
+/// routing and limit decisions, not billing.
+pub fn estimate_prompt_tokens(request: &Value) -> usize {
+    let mut chars = 0;
+    let mut tokens = 0;
+
+    if let Some(messages) = request.get("messages").and_then(Value::as_array) {
+        tokens += messages.len() * TOKENS_PER_MESSAGE;
+        for message in messages {
+            if let Some(content) = message.get("content") {
+                count_content(content, &mut chars, &mut tokens);
+            }
+            if let Some(tool_calls) = message.get("tool_calls") {
+                chars += tool_calls.to_string().len();
+            }
+        }
+    }
+    if let Some(system) = request.get("system") {
+        count_content(system, &mut chars, &mut tokens);
+    }
+    if let Some(tools) = request.get("tools") {
+        chars += tools.to_string().len();
+    }
+
+    tokens + chars.div_ceil(CHARS_PER_TOKEN)
+}
+
+/// Estimate of the tokens in a piece of generated text
+pub fn estimate_text_tokens(text: &str) -> usize {
+    text.len().div_ceil(CHARS_PER_TOKEN)
+}
+
+/// Output tokens the client asked for: OpenAI `max_completion_tokens` or `max_tokens`,
+/// Anthropic `max_tokens`
+pub fn requested_output_tokens(request: &Value) -> Option<usize> {
+    request
+        .get("max_completion_tokens")
+        .or_else(|| request.get("max_tokens"))
+        .and_then(Value::as_u64)
+        .map(|n| n as usize)
+}
+
+/// Whether any message carries an image (OpenAI `image_url` parts or Anthropic `image` blocks)
+pub fn has_images(request: &Value) -> bool {
+    request
+        .get("messages")
+        .and_then(Value::as_array)
+        .is_some_and(|messages| {
+            messages
+                .iter()
+                .filter_map(|m| m.get("content"))
+                .any(contains_image)
+        })
+}
+
+fn is_image_block(block: &Value) -> bool {
+    matches!(
+        block.get("type").and_then(Value::as_str),
+        Some("image_url" | "image")
+    )
+}
+
+fn contains_image(content: &Value) -> bool {
+    content.as_array().is_some_and(|blocks| {
+        blocks.iter().any(|block| {
+            is_image_block(block)
+                // Anthropic tool results can carry images too
+                || block.get("content").is_some_and(contains_image)
+        })
+    })
+}
+
+fn count_content(content: &Value, chars: &mut usize, tokens: &mut usize) {
+    match content {
+        Value::String(text) => *chars += text.len(),
+        Value::Array(blocks) => {
+            for block in blocks {
+                if is_image_block(block) {
+                    *tokens += TOKENS_PER_IMAGE;
+                } else if let Some(text) = block.get("text").and_then(Value::as_str) {
+                    *chars += text.len();
+                } else if let Some(nested) = block.get("content") {
+                    count_content(nested, chars, tokens);
+                } else if let Some(input) = block.get("input") {
+                    *chars += input.to_string().len();
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_estimate_text_prompt() {
+        let request = json!({
+            "messages": [{"role": "user", "content": "a".repeat(400)}]
+        });
+        assert_eq!(estimate_prompt_tokens(&request), 100 + TOKENS_PER_MESSAGE);
+    }
+
+    #[test]
+    fn test_estimate_counts_system_and_images() {
+        let request = json!({
+            "system": "b".repeat(40),
+            "messages": [{
+                "role": "user",
+                "content": [
+                    {"type": "text", "text": "c".repeat(40)},
+                    {"type": "image", "source": {"type": "base64", "data": "x".repeat(10_000)}}
+                ]
+            }]
+        });
+        assert_eq!(
+            estimate_prompt_tokens(&request),
+            20 + TOKENS_PER_MESSAGE + TOKENS_PER_IMAGE
+        );
+    }
+
+    #[test]
+    fn test_requested_output_tokens() {
+        assert_eq!(requested_output_tokens(&json!({"max_tokens": 512})), Some(512));
+        assert_eq!(
+            requested_output_tokens(&json!({"max_tokens": 512, "max_completion_tokens": 256})),
+            Some(256)
+        );
+        assert_eq!(requested_output_tokens(&json!({})), None);
+    }
+
+    #[test]
+    fn test_has_images() {
+        assert!(has_images(&json!({
+            "messages": [{"role": "user", "content": [
+                {"type": "image_url", "image_url": {"url": "https://example.com/cat.png"}}
+            ]}]
+        })));
+        assert!(has_images(&json!({
+            "messages": [{"role": "user", "content": [
+                {"type": "tool_result", "tool_use_id": "t1", "content": [{"type": "image", "source": {}}]}
+            ]}]
+        })));
+        assert!(!has_images(&json!({
+            "messages": [{"role": "user", "content": "no pictures"}]
+        })));
+    }
+}
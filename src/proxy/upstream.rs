@@ -0,0 +1,503 @@
+use crate::config::{Priority, Transform};
+use crate::proxy::balancer::{Endpoint, InFlightGuard};
+use crate::proxy::limiter::LimitPermit;
+use crate::proxy::retry::{advised_delay, is_retryable};
+use crate::proxy::timeouts::AttemptTimer;
+use crate::proxy::ProxyClient;
+use crate::transform::{
+    apply_header_transforms, render_claims, render_claims_value, rewrite_model_field, translate_request,
+    JsonPathTransformer, PiiVault, RegexTransformer,
+};
+use crate::types::{Protocol, ProxyError, Result, TimeoutPhase};
+use bytes::Bytes;
+use http::header::{HeaderMap, HeaderName, HeaderValue};
+use serde_json::Value;
+use std::sync::Arc;
+use std::time::Instant;
+
+/// Headers describing the client connection rather than the request itself
+const CONNECTION_HEADERS: &[&str] = &[
+    "host",
+    "content-length",
+    "connection",
+    "keep-alive",
+    "proxy-connection",
+    "transfer-encoding",
+    "te",
+    "trailer",
+    "upgrade",
+    "accept-encoding",
+];
+
+pub const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+/// A request fully prepared for one backend: translated, aliased and transformed.
+/// Credentials are added per endpoint when it is sent.
+#[derive(Debug, Clone)]
+pub struct UpstreamRequest {
+    pub protocol: Protocol,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+}
+
+/// A successful upstream response, holding its endpoint's in-flight slot until dropped
+#[derive(Debug)]
+pub struct UpstreamResponse {
+    pub response: reqwest::Response,
+    pub in_flight: InFlightGuard,
+    /// Timeouts still running for reading the body
+    pub timer: AttemptTimer,
+}
+
+/// Prepare a client request for the given model's backend.
+///
+/// Runs per attempt so every model in a fallback chain applies its own protocol
+/// translation, `target_model` rewrite and transforms. `{{jwt.<claim>}}` placeholders
+/// in header values and JSONPath values are filled in from the client's token `claims`.
+/// Personal data in message content is swapped for placeholders kept in the request's `pii` vault.
+pub fn build_upstream_request(
+    model_name: &str,
+    client: &ProxyClient,
+    client_protocol: Protocol,
+    request: &Value,
+    incoming_headers: &HeaderMap,
+    claims: Option<&Value>,
+    pii: &PiiVault,
+) -> Result<UpstreamRequest> {
+    let config = client.config();
+
+    let mut request_json = translate_request(
+        request.clone(),
+        client_protocol,
+        config.backend_type.protocol(),
+    )?;
+
+    // Apply model aliasing (rewrite model field if target_model is specified)
+    let target_model = config.get_target_model(model_name);
+    tracing::debug!(
+        incoming_model = %model_name,
+        target_model = %target_model,
+        "Rewriting model field for backend"
+    );
+    request_json = rewrite_model_field(request_json, target_model)?;
+
+    if let Some(redactor) = client.pii() {
+        redactor.redact_request(&mut request_json, pii);
+    }
+
+    request_json = apply_body_transforms(request_json, &with_claims(&config.transforms.request, claims))?;
+
+    let mut header_config = config.headers.clone();
+    for value in header_config.add.values_mut().chain(header_config.force.values_mut()) {
+        *value = render_claims(value, claims);
+    }
+    let mut headers = apply_header_transforms(incoming_headers, &header_config)?;
+    for name in CONNECTION_HEADERS {
+        headers.remove(*name);
+    }
+    if !headers.contains_key(http::header::CONTENT_TYPE) {
+        headers.insert(
+            http::header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+    }
+
+    if config.backend_type.protocol() == Protocol::Anthropic
+        && !headers.contains_key("anthropic-version")
+    {
+        headers.insert(
+            HeaderName::from_static("anthropic-version"),
+            HeaderValue::from_static(ANTHROPIC_VERSION),
+        );
+    }
+
+    let body = serde_json::to_vec(&request_json)
+        .map_err(|e| ProxyError::Transform(format!("Failed to serialize request: {}", e)))?;
+
+    Ok(UpstreamRequest {
+        protocol: config.backend_type.protocol(),
+        headers,
+        body: Bytes::from(body),
+    })
+}
+
+/// Send a prepared request to one endpoint, turning non-success statuses into
+/// `ProxyError::Upstream`. Records the endpoint's in-flight count, latency, circuit
+/// breaker and concurrency limiter outcome; fails fast with `CircuitOpen` if the
+/// circuit is open, without queueing for a slot.
+///
+/// Waits for a slot, queued by `priority`, when the endpoint is at its concurrency
+/// limit. The attempt's timeouts start once the slot is taken and end no later
+/// than `deadline`.
+pub async fn send_upstream(
+    client: &ProxyClient,
+    endpoint: &Arc<Endpoint>,
+    request: &UpstreamRequest,
+    priority: Priority,
+    deadline: Option<Instant>,
+) -> Result<UpstreamResponse> {
+    // An open circuit fails fast rather than after a wait in the queue. It is checked
+    // again once the slot is granted, as it may have opened in the meantime.
+    if !endpoint.circuit.is_available() {
+        return Err(ProxyError::CircuitOpen(endpoint.url.clone()));
+    }
+    let slot = endpoint.limiter.acquire(priority, deadline).await?;
+    let permit = endpoint
+        .circuit
+        .try_acquire()
+        .ok_or_else(|| ProxyError::CircuitOpen(endpoint.url.clone()))?;
+
+    let timer = AttemptTimer::start(client.config(), deadline);
+    let started = Instant::now();
+    let result = send_to_endpoint(client, endpoint, request, slot, timer).await;
+    match &result {
+        // Transient errors (the same ones retries react to) count against the endpoint
+        // and signal overload to its limiter; client errors show the endpoint is up
+        Err(e) if is_retryable(e) => {
+            permit.record_failure();
+            endpoint.limiter.record_overload();
+        }
+        Err(_) => permit.record_success(),
+        Ok(_) => {
+            permit.record_success();
+            endpoint.limiter.record_success(started.elapsed());
+        }
+    }
+    result
+}
+
+async fn send_to_endpoint(
+    client: &ProxyClient,
+    endpoint: &Arc<Endpoint>,
+    request: &UpstreamRequest,
+    slot: LimitPermit,
+    timer: AttemptTimer,
+) -> Result<UpstreamResponse> {
+    let mut headers = request.headers.clone();
+    if let Some(api_key) = endpoint.api_key.as_deref() {
+        apply_api_key(&mut headers, request.protocol, api_key)?;
+    }
+
+    let in_flight = endpoint.start_limited_request(slot);
+    let started = Instant::now();
+    let send = client
+        .client()
+        .post(&endpoint.url)
+        .headers(headers)
+        .body(request.body.clone())
+        .send();
+    let response = timer
+        .first_byte(async {
+            send.await.map_err(|e| {
+                if e.is_connect() && e.is_timeout() {
+                    ProxyError::Timeout(TimeoutPhase::Connect)
+                } else {
+                    ProxyError::from(e)
+                }
+            })
+        })
+        .await?;
+    endpoint.record_latency(started.elapsed());
+
+    let status = response.status();
+    if !status.is_success() {
+        let retry_after = advised_delay(response.headers());
+        let message = response.text().await.unwrap_or_default();
+        return Err(ProxyError::Upstream {
+            status: status.as_u16(),
+            message,
+            retry_after,
+        });
+    }
+
+    Ok(UpstreamResponse {
+        response,
+        in_flight,
+        timer,
+    })
+}
+
+/// Add the API key in the form the backend expects
+pub fn apply_api_key(headers: &mut HeaderMap, protocol: Protocol, api_key: &str) -> Result<()> {
+    match protocol {
+        Protocol::OpenAI => {
+            headers.insert(
+                http::header::AUTHORIZATION,
+                format!("Bearer {}", api_key)
+                    .parse()
+                    .map_err(|e| ProxyError::Internal(format!("Invalid API key: {}", e)))?,
+            );
+        }
+        Protocol::Anthropic => {
+            headers.remove(http::header::AUTHORIZATION);
+            headers.insert(
+                HeaderName::from_static("x-api-key"),
+                api_key
+                    .parse()
+                    .map_err(|e| ProxyError::Internal(format!("Invalid API key: {}", e)))?,
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Transforms with the claim placeholders in their JSONPath values filled in
+fn with_claims(transforms: &[Transform], claims: Option<&Value>) -> Vec<Transform> {
+    transforms
+        .iter()
+        .map(|transform| match transform {
+            Transform::JsonPathAdd { path, value } => Transform::JsonPathAdd {
+                path: path.clone(),
+                value: render_claims_value(value, claims),
+            },
+            other => other.clone(),
+        })
+        .collect()
+}
+
+/// Apply regex and JSONPath transforms to a JSON body
+pub fn apply_body_transforms(mut json: Value, transforms: &[Transform]) -> Result<Value> {
+    if transforms.is_empty() {
+        return Ok(json);
+    }
+
+    // Apply regex transformations on the JSON string (only if there are regex transforms)
+    let regex_transformer = RegexTransformer::new(transforms)?;
+    if regex_transformer.has_transforms() {
+        let json_string = serde_json::to_string(&json)
+            .map_err(|e| ProxyError::Transform(format!("Failed to serialize JSON: {}", e)))?;
+        let transformed_string = regex_transformer.transform(&json_string);
+        json = serde_json::from_str(&transformed_string)
+            .map_err(|e| ProxyError::Transform(format!("Failed to parse transformed JSON: {}", e)))?;
+    }
+
+    // Apply JSONPath transformations
+    let jsonpath_transformer = JsonPathTransformer::new(transforms);
+    if jsonpath_transformer.has_transforms() {
+        json = jsonpath_transformer.transform(json)?;
+    }
+
+    Ok(json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{
+        BackendType, CircuitBreakerConfig, ConcurrencyConfig, EndpointConfig, ModelConfig, TransformConfig,
+    };
+    use serde_json::json;
+
+    fn create_client(backend_type: BackendType, target_model: Option<&str>) -> ProxyClient {
+        let config = ModelConfig {
+            api_key: Some("backend-key".to_string()),
+            target_model: target_model.map(str::to_string),
+            timeout_seconds: 30,
+            transforms: TransformConfig {
+                request: vec![Transform::JsonPathAdd {
+                    path: "$.metadata.proxy".to_string(),
+                    value: json!("llm-proxy"),
+                }],
+                response: Vec::new(),
+            },
+            ..ModelConfig::test(backend_type, "http://localhost:9/v1")
+        };
+        ProxyClient::new(Arc::new(config)).unwrap()
+    }
+
+    #[test]
+    fn test_build_openai_request() {
+        let client = create_client(BackendType::OpenAI, Some("llama3"));
+        let mut incoming = HeaderMap::new();
+        incoming.insert("host", "proxy.local".parse().unwrap());
+        incoming.insert("content-length", "42".parse().unwrap());
+
+        let request = json!({"model": "gpt-4", "messages": []});
+        let upstream =
+            build_upstream_request("gpt-4", &client, Protocol::OpenAI, &request, &incoming, None, &PiiVault::default())
+                .unwrap();
+
+        let body: Value = serde_json::from_slice(&upstream.body).unwrap();
+        assert_eq!(body["model"], "llama3");
+        assert_eq!(body["metadata"]["proxy"], "llm-proxy");
+        assert!(upstream.headers.get("host").is_none());
+        assert!(upstream.headers.get("content-length").is_none());
+    }
+
+    #[test]
+    fn test_token_claims_in_transforms() {
+        let mut config = create_client(BackendType::OpenAI, None).config().clone();
+        config.headers.force.insert("X-End-User".to_string(), "{{jwt.sub}}".to_string());
+        config.transforms.request.push(Transform::JsonPathAdd {
+            path: "$.metadata.groups".to_string(),
+            value: json!("{{jwt.groups}}"),
+        });
+        let client = ProxyClient::new(Arc::new(config)).unwrap();
+        let request = json!({"model": "gpt-4", "messages": []});
+        let claims = json!({"sub": "svc-search", "groups": ["search", "ml"]});
+
+        let upstream =
+            build_upstream_request("gpt-4", &client, Protocol::OpenAI, &request, &HeaderMap::new(), Some(&claims), &PiiVault::default())
+                .unwrap();
+        let body: Value = serde_json::from_slice(&upstream.body).unwrap();
+        assert_eq!(upstream.headers["x-end-user"], "svc-search");
+        assert_eq!(body["metadata"]["groups"], json!(["search", "ml"]));
+
+        // Requests without a token get empty values
+        let upstream =
+            build_upstream_request("gpt-4", &client, Protocol::OpenAI, &request, &HeaderMap::new(), None, &PiiVault::default())
+                .unwrap();
+        assert_eq!(upstream.headers["x-end-user"], "");
+    }
+
+    #[test]
+    fn test_build_anthropic_request_from_openai_client() {
+        let client = create_client(BackendType::Anthropic, Some("claude-3-haiku"));
+        let request = json!({
+            "model": "gpt-4",
+            "messages": [
+                {"role": "system", "content": "Be brief."},
+                {"role": "user", "content": "Hello"}
+            ]
+        });
+
+        let upstream = build_upstream_request(
+            "gpt-4",
+            &client,
+            Protocol::OpenAI,
+            &request,
+            &HeaderMap::new(),
+            None,
+            &PiiVault::default(),
+        )
+        .unwrap();
+
+        let body: Value = serde_json::from_slice(&upstream.body).unwrap();
+        assert_eq!(body["model"], "claude-3-haiku");
+        assert_eq!(body["system"], "Be brief.");
+        assert_eq!(upstream.protocol, Protocol::Anthropic);
+        assert_eq!(upstream.headers.get("anthropic-version").unwrap(), ANTHROPIC_VERSION);
+    }
+
+    #[test]
+    fn test_apply_body_transforms_regex_and_jsonpath() {
+        let transforms = vec![
+            Transform::Regex {
+                pattern: "secret".to_string(),
+                replacement: "[REDACTED]".to_string(),
+            },
+            Transform::JsonPathDrop {
+                path: "$.debug".to_string(),
+            },
+        ];
+
+        let output =
+            apply_body_transforms(json!({"text": "my secret", "debug": true}), &transforms).unwrap();
+        assert_eq!(output, json!({"text": "my [REDACTED]"}));
+    }
+
+    #[test]
+    fn test_apply_api_key_per_protocol() {
+        let mut headers = HeaderMap::new();
+        apply_api_key(&mut headers, Protocol::OpenAI, "k1").unwrap();
+        assert_eq!(headers.get("authorization").unwrap(), "Bearer k1");
+
+        apply_api_key(&mut headers, Protocol::Anthropic, "k2").unwrap();
+        assert_eq!(headers.get("x-api-key").unwrap(), "k2");
+        assert!(headers.get("authorization").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_send_upstream_uses_endpoint_key() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/replica")
+            .match_header("authorization", "Bearer replica-key")
+            .with_status(200)
+            .with_body("{}")
+            .create_async()
+            .await;
+
+        let mut client = create_client(BackendType::OpenAI, None);
+        let config = ModelConfig {
+            endpoints: vec![EndpointConfig {
+                url: format!("{}/replica", server.url()),
+                api_key: Some("replica-key".to_string()),
+                weight: 1,
+                max_concurrent: None,
+            }],
+            ..client.config().clone()
+        };
+        client = ProxyClient::new(Arc::new(config)).unwrap();
+
+        let request = build_upstream_request(
+            "gpt-4",
+            &client,
+            Protocol::OpenAI,
+            &json!({"model": "gpt-4", "messages": []}),
+            &HeaderMap::new(),
+            None,
+            &PiiVault::default(),
+        )
+        .unwrap();
+        let (_, endpoint) = client.select_endpoint(&[]).unwrap();
+
+        let response = send_upstream(&client, &endpoint, &request, Priority::Normal, None).await.unwrap();
+        assert_eq!(endpoint.in_flight(), 1);
+        drop(response);
+        assert_eq!(endpoint.in_flight(), 0);
+        assert!(endpoint.ewma_latency().is_some());
+        mock.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_send_upstream_trips_circuit() {
+        let mut server = mockito::Server::new_async().await;
+        let mock = server
+            .mock("POST", "/down")
+            .with_status(503)
+            .expect(2)
+            .create_async()
+            .await;
+
+        let config = ModelConfig {
+            endpoint: format!("{}/down", server.url()),
+            circuit_breaker: CircuitBreakerConfig {
+                enabled: true,
+                consecutive_failures: 2,
+                ..CircuitBreakerConfig::default()
+            },
+            concurrency: ConcurrencyConfig {
+                max_concurrent: Some(1),
+                ..ConcurrencyConfig::default()
+            },
+            ..create_client(BackendType::OpenAI, None).config().clone()
+        };
+        let client = ProxyClient::new(Arc::new(config)).unwrap();
+        let request = build_upstream_request(
+            "gpt-4",
+            &client,
+            Protocol::OpenAI,
+            &json!({"model": "gpt-4", "messages": []}),
+            &HeaderMap::new(),
+            None,
+            &PiiVault::default(),
+        )
+        .unwrap();
+        let endpoint = client.endpoints()[0].clone();
+
+        for _ in 0..2 {
+            let err = send_upstream(&client, &endpoint, &request, Priority::Normal, None).await.unwrap_err();
+            assert!(matches!(err, ProxyError::Upstream { status: 503, .. }));
+        }
+        // Third call never reaches the backend, nor waits for the busy endpoint's queue
+        let _busy = endpoint.limiter.acquire(Priority::High, None).await.unwrap();
+        let send = send_upstream(&client, &endpoint, &request, Priority::Normal, None);
+        let err = tokio::time::timeout(std::time::Duration::from_secs(1), send)
+            .await
+            .expect("queued behind an open circuit")
+            .unwrap_err();
+        assert!(matches!(err, ProxyError::CircuitOpen(_)));
+        mock.assert_async().await;
+    }
+}
@@ -0,0 +1,189 @@
+use crate::config::{ModelConfig, StickyKey};
+use crate::proxy::ProxyClient;
+use crate::types::Result;
+use http::HeaderMap;
+use serde_json::Value;
+use std::sync::Arc;
+
+/// One weighted backend of a model, with its own client built from the merged config
+pub struct Variant {
+    pub name: String,
+    pub weight: u32,
+    pub client: Arc<ProxyClient>,
+}
+
+/// The variants of one model and how requests are assigned to them
+pub struct VariantSet {
+    sticky: StickyKey,
+    variants: Vec<Variant>,
+    total_weight: u64,
+}
+
+impl VariantSet {
+    /// Build clients for a model's variants; `None` when the model has no variants
+    pub fn new(config: &ModelConfig) -> Result<Option<Self>> {
+        if config.variants.is_empty() {
+            return Ok(None);
+        }
+
+        let variants = config
+            .variants
+            .iter()
+            .map(|variant| {
+                let client = ProxyClient::for_variant(
+                    Arc::new(config.variant_config(variant)),
+                    &variant.name,
+                )?;
+                Ok(Variant {
+                    name: variant.name.clone(),
+                    weight: variant.weight,
+                    client: Arc::new(client),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let total_weight = variants.iter().map(|v| v.weight as u64).sum();
+
+        Ok(Some(Self {
+            sticky: config.sticky.clone(),
+            variants,
+            total_weight,
+        }))
+    }
+
+    pub fn variants(&self) -> &[Variant] {
+        &self.variants
+    }
+
+    /// Pick the variant for a request. Requests with the same sticky key always land on
+    /// the same variant as long as the weights do not change.
+    pub fn select(&self, model: &str, headers: &HeaderMap, request: &Value) -> &Variant {
+        let point = match sticky_value(&self.sticky, headers, request) {
+            Some(key) => fnv1a(model.bytes().chain([0]).chain(key.bytes())) % self.total_weight,
+            None => (rand::random::<f64>() * self.total_weight as f64) as u64,
+        };
+
+        let mut remaining = point;
+        for variant in &self.variants {
+            let weight = variant.weight as u64;
+            if remaining < weight {
+                return variant;
+            }
+            remaining -= weight;
+        }
+        // Only reachable through float rounding in the random case
+        self.variants
+            .iter()
+            .rev()
+            .find(|v| v.weight > 0)
+            .unwrap_or(&self.variants[0])
+    }
+}
+
+fn sticky_value(key: &StickyKey, headers: &HeaderMap, request: &Value) -> Option<String> {
+    let header = |name: &str| headers.get(name).and_then(|v| v.to_str().ok());
+    let value = match key {
+        StickyKey::Random => None,
+        StickyKey::User => request
+            .get("user")
+            .or_else(|| request.pointer("/metadata/user_id"))
+            .and_then(Value::as_str),
+        StickyKey::ApiKey => header("authorization")
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .or_else(|| header("x-api-key")),
+        StickyKey::Header { name } => header(name),
+    };
+    value.filter(|v| !v.is_empty()).map(str::to_string)
+}
+
+/// 64-bit FNV-1a: stable across builds and platforms, unlike `DefaultHasher`
+fn fnv1a(bytes: impl Iterator<Item = u8>) -> u64 {
+    bytes.fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn variant_set(sticky: &str, weights: (u32, u32)) -> VariantSet {
+        let config: ModelConfig = serde_yaml::from_str(&format!(
+            r#"
+backend_type: openai
+endpoint: http://stable/v1/chat/completions
+sticky: {}
+variants:
+  - name: stable
+    weight: {}
+  - name: canary
+    weight: {}
+    endpoint: http://canary/v1/chat/completions
+"#,
+            sticky, weights.0, weights.1
+        ))
+        .unwrap();
+        VariantSet::new(&config).unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_variant_clients_use_overrides() {
+        let set = variant_set("{by: random}", (1, 1));
+        assert_eq!(set.variants()[0].client.endpoint(), "http://stable/v1/chat/completions");
+        assert_eq!(set.variants()[1].client.endpoint(), "http://canary/v1/chat/completions");
+        assert_eq!(set.variants()[1].client.variant(), Some("canary"));
+    }
+
+    #[test]
+    fn test_random_split_follows_weights() {
+        let set = variant_set("{by: random}", (90, 10));
+        let request = json!({});
+        let canary = (0..2000)
+            .filter(|_| set.select("chat", &HeaderMap::new(), &request).name == "canary")
+            .count();
+        assert!((100..300).contains(&canary), "canary picked {} times", canary);
+    }
+
+    #[test]
+    fn test_sticky_by_user() {
+        let set = variant_set("{by: user}", (50, 50));
+        let headers = HeaderMap::new();
+        let picks: Vec<&str> = (0..50)
+            .map(|i| {
+                let request = json!({"user": format!("user-{}", i)});
+                let first = set.select("chat", &headers, &request).name.as_str();
+                for _ in 0..5 {
+                    assert_eq!(set.select("chat", &headers, &request).name, first);
+                }
+                first
+            })
+            .collect();
+        // Different users spread over both variants
+        assert!(picks.contains(&"stable") && picks.contains(&"canary"));
+    }
+
+    #[test]
+    fn test_sticky_by_header_and_api_key() {
+        let by_header = variant_set("{by: header, name: X-Session-Id}", (50, 50));
+        let by_key = variant_set("{by: api_key}", (50, 50));
+        let request = json!({});
+        for i in 0..20 {
+            let mut headers = HeaderMap::new();
+            headers.insert("x-session-id", format!("session-{}", i).parse().unwrap());
+            headers.insert("authorization", format!("Bearer key-{}", i).parse().unwrap());
+            let first = by_header.select("chat", &headers, &request).name.clone();
+            assert_eq!(by_header.select("chat", &headers, &request).name, first);
+            let first = by_key.select("chat", &headers, &request).name.clone();
+            assert_eq!(by_key.select("chat", &headers, &request).name, first);
+        }
+    }
+
+    #[test]
+    fn test_zero_weight_variant_is_drained() {
+        let set = variant_set("{by: user}", (1, 0));
+        for i in 0..100 {
+            let request = json!({"user": format!("user-{}", i)});
+            assert_eq!(set.select("chat", &HeaderMap::new(), &request).name, "stable");
+        }
+    }
+}
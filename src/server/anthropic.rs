@@ -1,2 +1,195 @@
-// Anthropic-compliant API endpoints - to be implemented
-// POST /v1/messages (streaming and non-streaming)
+use axum::{
+    extract::{rejection::JsonRejection, State},
+    http::HeaderMap,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use serde_json::Value;
+
+use crate::proxy::ClientIdentity;
+use crate::types::{Protocol, ProxyError};
+
+use super::{dispatch::dispatch, AppState};
+
+/// POST /v1/messages (streaming and non-streaming)
+///
+/// The body is relayed as sent, so fields the proxy has no type for, like `tools` or a
+/// `system` prompt made of blocks, reach the backend intact. Only `model` is required.
+/// Bodies that are not JSON get an Anthropic error like any other failure.
+pub async fn messages_handler(
+    State(state): State<AppState>,
+    headers: HeaderMap,
+    identity: Option<Extension<ClientIdentity>>,
+    body: std::result::Result<Json<Value>, JsonRejection>,
+) -> Response {
+    let request = match body {
+        Ok(Json(request)) => request,
+        Err(e) => return ProxyError::InvalidRequest(e.body_text()).into_protocol_response(Protocol::Anthropic),
+    };
+    let model = request.get("model").and_then(Value::as_str).unwrap_or_default();
+    tracing::info!("Received messages request for model: {}", model);
+
+    // Present on mutual-TLS connections
+    let identity = identity.as_ref().map(|Extension(identity)| identity);
+    match dispatch(&state, Protocol::Anthropic, "/v1/messages", &headers, identity, request).await {
+        Ok(response) => response.into_response(),
+        Err(e) => e.into_protocol_response(Protocol::Anthropic),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{BackendType, Config, ModelConfig};
+    use serde_json::json;
+
+    fn config(models: Vec<(&str, ModelConfig)>) -> Config {
+        let mut config: Config = serde_yaml::from_str("server: {}\nmodels: {}\n").unwrap();
+        for (name, model) in models {
+            config.models.insert(name.to_string(), model);
+        }
+        config
+    }
+
+    fn state(models: Vec<(&str, ModelConfig)>) -> AppState {
+        AppState::new(config(models)).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_tools_and_system_blocks_reach_backend() {
+        let request = json!({
+            "model": "claude-3",
+            "max_tokens": 256,
+            "system": [{"type": "text", "text": "Be brief", "cache_control": {"type": "ephemeral"}}],
+            "tools": [{
+                "name": "get_weather",
+                "description": "Current weather",
+                "input_schema": {"type": "object", "properties": {"city": {"type": "string"}}}
+            }],
+            "tool_choice": {"type": "auto"},
+            "messages": [{"role": "user", "content": "Weather in Oslo?"}]
+        });
+        let mut server = mockito::Server::new_async().await;
+        let backend = server
+            .mock("POST", "/v1/messages")
+            .match_body(mockito::Matcher::Json(request.clone()))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!({
+                    "id": "msg_1",
+                    "type": "message",
+                    "role": "assistant",
+                    "model": "claude-3",
+                    "content": [{"type": "tool_use", "id": "tu_1", "name": "get_weather", "input": {"city": "Oslo"}}],
+                    "stop_reason": "tool_use",
+                    "usage": {"input_tokens": 20, "output_tokens": 5}
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let model = ModelConfig::test(BackendType::Anthropic, format!("{}/v1/messages", server.url()));
+        let response =
+            messages_handler(State(state(vec![("claude-3", model)])), HeaderMap::new(), None, Ok(Json(request))).await;
+
+        backend.assert_async().await;
+        assert_eq!(response.status(), 200);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["content"][0]["input"]["city"], "Oslo");
+    }
+
+    #[tokio::test]
+    async fn test_missing_model_is_anthropic_error() {
+        let response = messages_handler(
+            State(state(vec![("claude-3", ModelConfig::test(BackendType::Anthropic, "http://localhost:9"))])),
+            HeaderMap::new(),
+            None,
+            Ok(Json(json!({"messages": []}))),
+        )
+        .await;
+
+        assert_eq!(response.status(), 400);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["type"], "error");
+    }
+
+    #[tokio::test]
+    async fn test_tools_rule_routes_anthropic_requests() {
+        let mut server = mockito::Server::new_async().await;
+        let reply = json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-3",
+            "content": [{"type": "text", "text": "Hi"}],
+            "stop_reason": "end_turn",
+            "usage": {"input_tokens": 5, "output_tokens": 1}
+        })
+        .to_string();
+        let plain = server
+            .mock("POST", "/plain")
+            .with_status(200)
+            .with_body(&reply)
+            .expect(1)
+            .create_async()
+            .await;
+        let tool = json!({"name": "search", "input_schema": {"type": "object"}});
+        let tools = server
+            .mock("POST", "/tools")
+            .match_body(mockito::Matcher::PartialJson(json!({"tools": [tool]})))
+            .with_status(200)
+            .with_body(&reply)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let mut config = config(vec![
+            ("claude-3", ModelConfig::test(BackendType::Anthropic, format!("{}/plain", server.url()))),
+            ("claude-tools", ModelConfig::test(BackendType::Anthropic, format!("{}/tools", server.url()))),
+        ]);
+        config.routing_rules =
+            serde_yaml::from_str("- models: [claude-3]\n  match: {has_tools: true}\n  target: claude-tools\n").unwrap();
+        let state = AppState::new(config).unwrap();
+
+        let request = json!({
+            "model": "claude-3",
+            "max_tokens": 64,
+            "messages": [{"role": "user", "content": "Find docs"}]
+        });
+        let mut with_tools = request.clone();
+        with_tools["tools"] = json!([tool]);
+
+        for (body, served) in [(with_tools, "claude-tools"), (request, "claude-3")] {
+            let response = messages_handler(State(state.clone()), HeaderMap::new(), None, Ok(Json(body))).await;
+            assert_eq!(response.status(), 200);
+            assert_eq!(response.headers()[crate::server::SERVED_MODEL_HEADER], served);
+        }
+        tools.assert_async().await;
+        plain.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_malformed_body_is_anthropic_error() {
+        use tower::ServiceExt;
+
+        let state = state(vec![("claude-3", ModelConfig::test(BackendType::Anthropic, "http://localhost:9"))]);
+        let app = axum::Router::new()
+            .route("/v1/messages", axum::routing::post(messages_handler))
+            .with_state(state);
+        let request = axum::http::Request::post("/v1/messages")
+            .header("content-type", "application/json")
+            .body(axum::body::Body::from("{\"model\": \"claude-3\","))
+            .unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), 400);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["type"], "error");
+        assert_eq!(json["error"]["type"], "invalid_request_error");
+    }
+}
@@ -0,0 +1,293 @@
+use bytes::Bytes;
+use futures::{stream::BoxStream, Stream, StreamExt};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+
+use crate::{
+    logging::RequestLogger,
+    proxy::{estimate_text_tokens, RateLimitCharge, SpendEntry},
+    streaming::StreamSummary,
+    types::{Result, TokenUsage},
+};
+
+use super::dispatch::RequestContext;
+
+/// Logs a request as cancelled when it is dropped before its response is ready,
+/// which is what happens when the client disconnects while the proxy waits on a
+/// backend. Dropping the request future also drops (and aborts) the upstream call.
+///
+/// The guard holds the request's rate-limit charges and the spend of the attempt
+/// waiting on a backend, so a cancelled request is billed for its prompt, which the
+/// backend may already have processed, as a cancelled stream is.
+pub(super) struct CancelGuard<'a> {
+    logger: &'a RequestLogger,
+    path: &'a str,
+    model: &'a str,
+    prompt_tokens: u64,
+    armed: bool,
+    /// What the request was charged to its client key's rate limits
+    charge: Mutex<RateLimitCharge>,
+    attempt: Mutex<Option<Attempt>>,
+}
+
+/// A model's rate-limit charge and spend, held while the request waits on its backend
+struct Attempt {
+    model: String,
+    charge: RateLimitCharge,
+    spend: SpendEntry,
+}
+
+impl<'a> CancelGuard<'a> {
+    pub fn new(ctx: &RequestContext<'a>, model: &'a str, charge: RateLimitCharge) -> Self {
+        Self {
+            logger: ctx.logger,
+            path: ctx.path,
+            model,
+            prompt_tokens: ctx.prompt_tokens,
+            armed: true,
+            charge: Mutex::new(charge),
+            attempt: Mutex::new(None),
+        }
+    }
+
+    /// Hold the charge and spend of an attempt about to wait on `model`'s backend
+    pub fn start_attempt(&self, model: &str, charge: RateLimitCharge, spend: SpendEntry) {
+        *self.attempt.lock().unwrap() = Some(Attempt {
+            model: model.to_string(),
+            charge,
+            spend,
+        });
+    }
+
+    /// The attempt's charge back once its backend answered or failed
+    pub fn end_attempt(&self) -> RateLimitCharge {
+        self.attempt
+            .lock()
+            .unwrap()
+            .take()
+            .map(|attempt| attempt.charge)
+            .unwrap_or_default()
+    }
+
+    /// The client key's charge, for the response that settles it
+    pub fn take_charge(&self) -> RateLimitCharge {
+        std::mem::take(&mut *self.charge.lock().unwrap())
+    }
+
+    /// The request ran to completion (successfully or not)
+    pub fn disarm(mut self) {
+        self.armed = false;
+    }
+}
+
+impl Drop for CancelGuard<'_> {
+    fn drop(&mut self) {
+        if !self.armed {
+            return;
+        }
+        // No output reached the proxy yet; the prompt may already have been processed
+        let usage = TokenUsage {
+            input_tokens: self.prompt_tokens,
+            ..TokenUsage::default()
+        };
+        std::mem::take(self.charge.get_mut().unwrap()).settle(usage);
+        let model = match self.attempt.get_mut().unwrap().take() {
+            Some(attempt) => {
+                attempt.charge.settle(usage);
+                attempt.spend.record(&usage);
+                attempt.model
+            }
+            None => self.model.to_string(),
+        };
+        tracing::info!(model = %model, "Client disconnected, upstream request aborted");
+        self.logger
+            .log_cancelled("POST", self.path, Some(&model), None, Some(usage));
+    }
+}
+
+/// A response stream relayed to the client. If the client disconnects, the stream is
+/// dropped before it ends, taking the upstream stream (and its connection) with it;
+/// the request is then logged as cancelled with the usage seen so far. Either way the
+/// request's rate-limit charge is settled, and its spend recorded, with the usage seen
+/// when it is dropped.
+pub(super) struct CancellableStream {
+    inner: BoxStream<'static, Result<Bytes>>,
+    summary: StreamSummary,
+    finished: bool,
+    logger: Arc<RequestLogger>,
+    path: String,
+    model: String,
+    backend: String,
+    /// Fallback for the input tokens if the stream does not report them
+    prompt_tokens: u64,
+    charge: Option<RateLimitCharge>,
+    spend: Option<SpendEntry>,
+}
+
+impl CancellableStream {
+    pub fn new<S>(
+        stream: S,
+        ctx: &RequestContext<'_>,
+        model: &str,
+        backend: &str,
+        charge: RateLimitCharge,
+        spend: SpendEntry,
+    ) -> Self
+    where
+        S: Stream<Item = Result<Bytes>> + Send + 'static,
+    {
+        Self {
+            inner: stream.boxed(),
+            summary: StreamSummary::new(),
+            finished: false,
+            logger: ctx.logger.clone(),
+            path: ctx.path.to_string(),
+            model: model.to_string(),
+            backend: backend.to_string(),
+            prompt_tokens: ctx.prompt_tokens,
+            charge: Some(charge),
+            spend: Some(spend),
+        }
+    }
+
+    /// Usage reported in the stream so far, filled in with estimates where the
+    /// backend has not reported it yet (OpenAI only reports usage at the end)
+    fn partial_usage(&self) -> TokenUsage {
+        let reported = self.summary.usage.unwrap_or_default();
+        TokenUsage {
+            input_tokens: match reported.input_tokens {
+                0 => self.prompt_tokens,
+                reported => reported,
+            },
+            output_tokens: reported
+                .output_tokens
+                .max(estimate_text_tokens(&self.summary.text) as u64),
+            cached_input_tokens: reported.cached_input_tokens,
+        }
+    }
+}
+
+impl Stream for CancellableStream {
+    type Item = Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let next = self.inner.poll_next_unpin(cx);
+        match &next {
+            Poll::Ready(Some(Ok(chunk))) => self.summary.push(chunk),
+            // Upstream failures end the stream; they are not cancellations
+            Poll::Ready(Some(Err(_)) | None) => self.finished = true,
+            Poll::Pending => {}
+        }
+        next
+    }
+}
+
+impl Drop for CancellableStream {
+    fn drop(&mut self) {
+        let usage = self.partial_usage();
+        if let Some(charge) = self.charge.take() {
+            charge.settle(usage);
+        }
+        if let Some(spend) = self.spend.take() {
+            spend.record(&usage);
+        }
+        if self.finished {
+            return;
+        }
+        tracing::info!(
+            model = %self.model,
+            output_tokens = usage.output_tokens,
+            "Client disconnected mid-stream, upstream stream closed"
+        );
+        self.logger.log_cancelled(
+            "POST",
+            &self.path,
+            Some(&self.model),
+            Some(&self.backend),
+            Some(usage),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{LoggingConfig, Priority, SpendConfig};
+    use crate::proxy::{estimate_prompt_tokens, RetryBudget, SpendTracker};
+    use crate::streaming::SseEvent;
+    use crate::types::Protocol;
+    use http::HeaderMap;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_partial_usage_of_cancelled_stream() {
+        let logger = Arc::new(RequestLogger::new(LoggingConfig::default()));
+        let headers = HeaderMap::new();
+        let request = json!({"model": "claude", "messages": [{"role": "user", "content": "Hello there"}]});
+        let ctx = RequestContext {
+            protocol: Protocol::Anthropic,
+            path: "/v1/messages",
+            headers: &headers,
+            forward_headers: &headers,
+            request: &request,
+            logger: &logger,
+            deadline: None,
+            priority: Priority::Normal,
+            retry_budget: RetryBudget::default(),
+            prompt_tokens: estimate_prompt_tokens(&request) as u64,
+            client_key: None,
+            claims: None,
+            pii: Arc::default(),
+        };
+
+        let events = [
+            SseEvent::named("message_start", r#"{"type":"message_start","message":{"usage":{"input_tokens":12,"output_tokens":1}}}"#),
+            SseEvent::named("content_block_delta", r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Sixteen chars..."}}"#),
+        ];
+        let upstream = futures::stream::iter(events.map(|e| Ok(e.to_bytes())))
+            .chain(futures::stream::pending());
+        let spend = Arc::new(SpendTracker::new(&SpendConfig::default())).entry(None, "claude", None);
+        let mut stream = CancellableStream::new(upstream, &ctx, "claude", "anthropic", RateLimitCharge::default(), spend);
+        stream.next().await.unwrap().unwrap();
+        stream.next().await.unwrap().unwrap();
+
+        assert!(!stream.finished);
+        assert_eq!(
+            stream.partial_usage(),
+            TokenUsage {
+                input_tokens: 12,
+                output_tokens: 4,
+                cached_input_tokens: 0,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_completed_stream_is_not_cancelled() {
+        let logger = Arc::new(RequestLogger::new(LoggingConfig::default()));
+        let headers = HeaderMap::new();
+        let request = json!({"model": "gpt", "messages": []});
+        let ctx = RequestContext {
+            protocol: Protocol::OpenAI,
+            path: "/v1/chat/completions",
+            headers: &headers,
+            forward_headers: &headers,
+            request: &request,
+            logger: &logger,
+            deadline: None,
+            priority: Priority::Normal,
+            retry_budget: RetryBudget::default(),
+            prompt_tokens: estimate_prompt_tokens(&request) as u64,
+            client_key: None,
+            claims: None,
+            pii: Arc::default(),
+        };
+
+        let upstream = futures::stream::iter([Ok(Bytes::from_static(b"data: [DONE]\n\n"))]);
+        let spend = Arc::new(SpendTracker::new(&SpendConfig::default())).entry(None, "gpt", None);
+        let mut stream = CancellableStream::new(upstream, &ctx, "gpt", "openai", RateLimitCharge::default(), spend);
+        while stream.next().await.is_some() {}
+        assert!(stream.finished);
+    }
+}
@@ -0,0 +1,1689 @@
+use axum::{body::Body, http::HeaderMap, response::Response};
+use bytes::Bytes;
+use futures::{stream::BoxStream, StreamExt};
+use http::header::{HeaderName, HeaderValue, CONTENT_LENGTH, CONTENT_TYPE, TRANSFER_ENCODING};
+use http::StatusCode;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::{
+    config::{ApiKeyConfig, Priority},
+    logging::RequestLogger,
+    proxy::{
+        authorize, build_upstream_request, estimate_prompt_tokens, request_deadline, request_priority, retry_with_backoff, send_upstream,
+        should_fallback, upstream::apply_body_transforms, without_client_key, ClientIdentity, InFlightGuard, ProxyClient,
+        RateLimitCharge, RetryBudget, UpstreamResponse,
+    },
+    streaming::{restore_pii_stream, translate_sse_stream},
+    transform::{translate_response, PiiVault},
+    types::{with_retry_after, Protocol, ProxyError, Result, TimeoutPhase, TokenUsage},
+};
+
+use super::{
+    cancel::{CancelGuard, CancellableStream},
+    shadow, AppState,
+};
+
+/// Response header naming the configured model that actually produced the response
+pub const SERVED_MODEL_HEADER: &str = "x-llm-proxy-model";
+
+/// Response header naming the variant that served the response, for models split into variants
+pub const SERVED_VARIANT_HEADER: &str = "x-llm-proxy-variant";
+
+/// What every attempt, hedge and fallback of one client request shares
+pub(super) struct RequestContext<'a> {
+    pub protocol: Protocol,
+    pub path: &'a str,
+    pub headers: &'a HeaderMap,
+    /// Client headers passed on to backends
+    pub forward_headers: &'a HeaderMap,
+    pub request: &'a Value,
+    pub logger: &'a Arc<RequestLogger>,
+    /// End of the whole request across retries, hedges and fallbacks
+    pub deadline: Option<Instant>,
+    /// Class the request queues in at endpoints that are at their concurrency limit
+    pub priority: Priority,
+    pub retry_budget: RetryBudget,
+    /// Estimated prompt size, charged to rate limits until the actual usage is known
+    pub prompt_tokens: u64,
+    /// Client key the request presented, which its spend is recorded against
+    pub client_key: Option<Arc<ApiKeyConfig>>,
+    /// Claims of the client's JWT, for transforms that refer to them
+    pub claims: Option<Arc<Value>>,
+    /// Personal data replaced in the request, shared by every model it is sent to
+    pub pii: Arc<PiiVault>,
+}
+
+/// Route a client request through the requested model and, if it keeps failing,
+/// through each of its configured fallbacks in order.
+pub async fn dispatch(
+    state: &AppState,
+    protocol: Protocol,
+    path: &str,
+    headers: &HeaderMap,
+    identity: Option<&ClientIdentity>,
+    request: Value,
+) -> Result<Response> {
+    let started = Instant::now();
+    let logger = Arc::new(RequestLogger::new(state.config.logging.clone()));
+    logger.log_request("POST", path, headers, None);
+
+    // Client certificates, keys and tokens are checked before anything else, routing included
+    let (client_key, claims) = match state.keys.authenticate(headers, identity).await? {
+        Some(principal) => (Some(principal.key), principal.claims),
+        None => (None, None),
+    };
+    let requested_model = request
+        .get("model")
+        .and_then(Value::as_str)
+        .ok_or_else(|| ProxyError::InvalidRequest("Missing 'model' field".to_string()))?;
+    let prompt_tokens = estimate_prompt_tokens(&request) as u64;
+    let (requested_model, key_charge) = match &client_key {
+        Some(key) => {
+            logger.set_client(&key.name);
+            let model = authorize(key, requested_model)?.to_string();
+            (model, state.rate_limits.admit_key(key, prompt_tokens)?)
+        }
+        None => (requested_model.to_string(), RateLimitCharge::default()),
+    };
+    // A client key only authenticates the client to the proxy
+    let forward_headers = match client_key {
+        Some(_) => Cow::Owned(without_client_key(headers)),
+        None => Cow::Borrowed(headers),
+    };
+    let routed_model = state.router.route(&requested_model, headers, &request);
+    let routed_model = budgeted_model(state, client_key.as_deref(), &routed_model, &request)?;
+
+    let ctx = RequestContext {
+        protocol,
+        path,
+        headers,
+        forward_headers: &forward_headers,
+        request: &request,
+        logger: &logger,
+        deadline: request_deadline(state.router.get_config(&routed_model)?, headers, started),
+        priority: request_priority(&state.config.priority, client_key.as_deref(), headers),
+        retry_budget: RetryBudget::default(),
+        prompt_tokens,
+        client_key,
+        claims,
+        pii: Arc::default(),
+    };
+    let shadow = shadow::start(state, &routed_model, protocol, &forward_headers, &request);
+    // Dropped along with this future if the client disconnects while waiting on a backend
+    let cancel = CancelGuard::new(&ctx, &routed_model, key_charge);
+    // Requests still waiting on a backend when the drain timeout expires are cut off
+    let result = tokio::select! {
+        result = serve_chain(state, &ctx, &routed_model, &cancel) => result,
+        _ = state.shutdown.terminated() => Err(ProxyError::ShuttingDown),
+    };
+    cancel.disarm();
+    match shadow {
+        Some(shadow) => shadow.observe(result, started),
+        None => result,
+    }
+}
+
+/// The model a request for `model` is served by: escalated to one whose context window
+/// fits the request, then held to the client's budgets. A model a budget downgrades to
+/// is fitted again, and if that escalates, the model it escalates to must be within
+/// budget itself.
+fn budgeted_model(state: &AppState, key: Option<&ApiKeyConfig>, model: &str, request: &Value) -> Result<String> {
+    let fitted = state.router.fit_context(model, request)?;
+    let allowed = state.spend.enforce(key, &fitted)?;
+    if allowed == fitted {
+        return Ok(fitted);
+    }
+    let refitted = state.router.fit_context(&allowed, request)?;
+    if refitted != allowed {
+        state.spend.check(key, &refitted)?;
+    }
+    Ok(refitted)
+}
+
+/// `routed_model` followed by its fallbacks, each fitted to the request the way the
+/// routed model was: a fallback whose context window is too small escalates to its
+/// `overflow_model`, and one with nowhere to escalate is left out of the chain.
+fn fitted_chain(state: &AppState, request: &Value, routed_model: &str) -> Result<Vec<String>> {
+    let mut chain: Vec<String> = Vec::new();
+    for (position, model) in state.router.fallback_chain(routed_model)?.into_iter().enumerate() {
+        let model = match position {
+            // Already fitted when it was routed
+            0 => model,
+            _ => match state.router.fit_context(&model, request) {
+                Ok(fitted) => fitted,
+                Err(e) => {
+                    tracing::warn!(fallback_model = %model, error = %e, "Skipping fallback model");
+                    continue;
+                }
+            },
+        };
+        if !chain.contains(&model) {
+            chain.push(model);
+        }
+    }
+    Ok(chain)
+}
+
+/// Serve a request from `routed_model`, moving down its fallback chain on failure.
+/// Each model is tried only while it (and its backend account) is within its rate
+/// limits, and fallbacks only while within the client's budgets; `cancel` holds what
+/// the request was charged to its client key's, and each attempt's charge and spend
+/// while it waits on a backend.
+async fn serve_chain(
+    state: &AppState,
+    ctx: &RequestContext<'_>,
+    routed_model: &str,
+    cancel: &CancelGuard<'_>,
+) -> Result<Response> {
+    let requested_model = ctx.request.get("model").and_then(Value::as_str).unwrap_or(routed_model);
+    let chain = fitted_chain(state, ctx.request, routed_model)?;
+    let (logger, path) = (ctx.logger, ctx.path);
+
+    for (position, model) in chain.iter().enumerate() {
+        // A model whose client can't be selected fails like any other model
+        let (client, result) = match state.router.select_client(model, ctx.headers, ctx.request) {
+            Err(e) => (None, Err(e)),
+            Ok(client) => {
+                let result = match ctx.deadline {
+                    Some(deadline) if deadline <= Instant::now() => {
+                        Err(ProxyError::Timeout(TimeoutPhase::Deadline))
+                    }
+                    _ => match budget_check(state, ctx, position, model)
+                        .and_then(|_| state.rate_limits.admit_model(model, ctx.prompt_tokens))
+                    {
+                        Ok(model_charge) => {
+                            let pricing = client.config().pricing.as_ref();
+                            let spend = state.spend.entry(ctx.client_key.as_ref(), model, pricing);
+                            cancel.start_attempt(model, model_charge, spend);
+                            let ready = forward(state, &client, model, ctx).await;
+                            let model_charge = cancel.end_attempt();
+                            ready.map(|ready| (ready, model_charge))
+                        }
+                        Err(e) => Err(e),
+                    },
+                };
+                (Some(client), result)
+            }
+        };
+        match result {
+            Ok((ready, model_charge)) => {
+                let served_model = ready.model.clone();
+                let served_backend = ready.client.backend_label();
+                let served_variant = ready.client.variant().map(str::to_string);
+                let mut charge = cancel.take_charge();
+                charge.merge(model_charge);
+                let mut response = finish(state, ready, ctx, charge).await?;
+                set_served_model(&mut response, &served_model, served_variant.as_deref());
+                if position > 0 {
+                    tracing::info!(
+                        requested_model = %requested_model,
+                        served_model = %served_model,
+                        "Request served by fallback model"
+                    );
+                }
+                logger.log_response("POST", path, Some(&served_model), Some(&served_backend), response.status().as_u16(), None);
+                return Ok(response);
+            }
+            Err(e) if position + 1 < chain.len() && should_fallback(&e) => {
+                tracing::warn!(
+                    requested_model = %requested_model,
+                    failed_model = %model,
+                    next_model = %chain[position + 1],
+                    error = %e,
+                    "Model failed, falling back"
+                );
+            }
+            Err(e) => {
+                let error = e.to_string();
+                let backend = client.as_ref().map(|client| client.backend_label());
+                logger.log_response("POST", path, Some(model), backend.as_deref(), e.status_code().as_u16(), Some(&error));
+
+                // Relay upstream error bodies verbatim when the client speaks the backend's
+                // protocol, keeping the backend's advice on when to retry
+                if let (Some(client), ProxyError::Upstream { status, message, retry_after }) = (&client, &e) {
+                    if client.config().backend_type.protocol() == ctx.protocol
+                        && serde_json::from_str::<Value>(message).is_ok()
+                    {
+                        let response = Response::builder()
+                            .status(*status)
+                            .header(CONTENT_TYPE, "application/json")
+                            .body(Body::from(message.clone()))
+                            .map_err(|e| ProxyError::Internal(format!("Failed to build response: {}", e)))?;
+                        let mut response = with_retry_after(response, *retry_after);
+                        set_served_model(&mut response, model, client.variant());
+                        return Ok(response);
+                    }
+                }
+                return Err(e);
+            }
+        }
+    }
+
+    Err(ProxyError::ModelNotFound(requested_model.to_string()))
+}
+
+/// Fallbacks are held to the client's budgets; the first model already was when routed
+fn budget_check(state: &AppState, ctx: &RequestContext<'_>, position: usize, model: &str) -> Result<()> {
+    match position {
+        0 => Ok(()),
+        _ => state.spend.check(ctx.client_key.as_deref(), model),
+    }
+}
+
+/// Serve a request from one model, without fallbacks or rate limits
+pub(super) async fn serve_model(
+    state: &AppState,
+    model: &str,
+    ctx: &RequestContext<'_>,
+) -> Result<Response> {
+    let client = state.router.select_client(model, ctx.headers, ctx.request)?;
+    let ready = forward(state, &client, model, ctx).await?;
+    finish(state, ready, ctx, RateLimitCharge::default()).await
+}
+
+/// An upstream response ready to relay: headers received and, for streams,
+/// the first body chunk as well
+struct Ready {
+    model: String,
+    client: Arc<ProxyClient>,
+    status: StatusCode,
+    headers: HeaderMap,
+    body: ReadyBody,
+    in_flight: InFlightGuard,
+}
+
+/// Response bodies, already guarded by the attempt's idle and total timeouts
+enum ReadyBody {
+    Stream(BoxStream<'static, Result<Bytes>>),
+    Buffered(BoxStream<'static, Result<Bytes>>),
+}
+
+impl ReadyBody {
+    /// Wait for a stream's first chunk, so that a stream failing right away fails the
+    /// attempt instead of reaching the client
+    async fn start(self) -> Result<Self> {
+        let ReadyBody::Stream(mut stream) = self else {
+            return Ok(self);
+        };
+        match stream.next().await {
+            Some(Err(e @ ProxyError::Timeout(_))) => Err(e),
+            Some(Err(e)) => Err(ProxyError::StreamStart(e.to_string())),
+            first => Ok(ReadyBody::Stream(futures::stream::iter(first).chain(stream).boxed())),
+        }
+    }
+}
+
+fn response_body(headers: &HeaderMap, body: BoxStream<'static, Result<Bytes>>) -> ReadyBody {
+    let is_stream = headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/event-stream"));
+    if is_stream {
+        ReadyBody::Stream(body)
+    } else {
+        ReadyBody::Buffered(body)
+    }
+}
+
+/// One attempt against a single model, hedged to a second endpoint or model
+/// when the model enables it
+async fn forward(
+    state: &AppState,
+    client: &Arc<ProxyClient>,
+    model: &str,
+    ctx: &RequestContext<'_>,
+) -> Result<Ready> {
+    let hedging = &client.config().hedging;
+    let tried = Mutex::new(Vec::new());
+    let primary = attempt(client.clone(), model, ctx, &tried);
+
+    // Hedging to the same model needs a second endpoint to go to
+    let hedge_target = match &hedging.model {
+        _ if !hedging.enabled => None,
+        Some(hedge_model) => Some((
+            state.router.select_client(hedge_model, ctx.headers, ctx.request)?,
+            hedge_model.as_str(),
+        )),
+        None if client.endpoints().len() > 1 => Some((client.clone(), model)),
+        None => None,
+    };
+    let Some((hedge_client, hedge_model)) = hedge_target else {
+        return primary.await;
+    };
+
+    tokio::pin!(primary);
+    tokio::select! {
+        result = &mut primary => return result,
+        _ = tokio::time::sleep(hedging.delay()) => {}
+    }
+
+    ctx.logger.record_hedge();
+    tracing::info!(
+        model = %model,
+        hedge_model = %hedge_model,
+        delay_ms = hedging.delay_ms,
+        "Primary request slow, sending hedge"
+    );
+    // A same-model hedge shares the tried list so it lands on a different endpoint
+    let hedge_tried = Mutex::new(Vec::new());
+    let hedge_tried = if hedge_model == model { &tried } else { &hedge_tried };
+    let hedge = attempt(hedge_client, hedge_model, ctx, hedge_tried);
+    tokio::pin!(hedge);
+
+    // The first success wins; returning drops (and so cancels) the other request
+    tokio::select! {
+        result = &mut primary => match result {
+            Ok(ready) => Ok(ready),
+            Err(e) => {
+                tracing::warn!(model = %model, error = %e, "Primary request failed, waiting for hedge");
+                hedge.await.or(Err(e))
+            }
+        },
+        result = &mut hedge => match result {
+            Ok(ready) => {
+                tracing::info!(model = %model, hedge_model = %hedge_model, "Hedged request won");
+                Ok(ready)
+            }
+            Err(e) => {
+                tracing::warn!(hedge_model = %hedge_model, error = %e, "Hedged request failed");
+                primary.await
+            }
+        },
+    }
+}
+
+/// Send a request to one model, including that model's retries, and wait until
+/// its response is ready to relay
+async fn attempt(
+    client: Arc<ProxyClient>,
+    model: &str,
+    ctx: &RequestContext<'_>,
+    tried: &Mutex<Vec<usize>>,
+) -> Result<Ready> {
+    let config = client.config();
+    let backend = client.backend_label();
+    let upstream = build_upstream_request(
+        model,
+        &client,
+        ctx.protocol,
+        ctx.request,
+        ctx.forward_headers,
+        ctx.claims.as_deref(),
+        &ctx.pii,
+    )?;
+    // Without a request deadline, server-advised waits beyond the model's timeout
+    // give up rather than retry
+    let budget = ctx
+        .deadline
+        .unwrap_or_else(|| Instant::now() + config.timeout_duration());
+
+    let start_streams = config.retry.retry_streams_before_first_byte;
+
+    // Each retry prefers a replica that has not been tried yet for this request
+    let (status, response_headers, body, in_flight) =
+        retry_with_backoff(&config.retry, &ctx.retry_budget, Some(budget), || {
+            let selected = client.select_endpoint(&tried.lock().unwrap());
+            if let Ok((idx, endpoint)) = &selected {
+                tried.lock().unwrap().push(*idx);
+                ctx.logger.log_upstream_request(
+                    model,
+                    &backend,
+                    &endpoint.url,
+                    &upstream.headers,
+                    std::str::from_utf8(&upstream.body).ok(),
+                );
+            }
+            let client = &client;
+            let upstream = &upstream;
+            async move {
+                let (_, endpoint) = selected?;
+                let UpstreamResponse {
+                    response,
+                    in_flight,
+                    timer,
+                } = send_upstream(client, &endpoint, upstream, ctx.priority, ctx.deadline).await?;
+                let status = response.status();
+                let headers = response.headers().clone();
+                let mut body = response_body(&headers, timer.body(response.bytes_stream()).boxed());
+                if start_streams {
+                    body = body.start().await?;
+                }
+                Ok((status, headers, body, in_flight))
+            }
+        })
+        .await?;
+    // Already started when the policy retries streams; the first chunk is buffered then
+    let body = body.start().await?;
+
+    Ok(Ready {
+        model: model.to_string(),
+        client,
+        status,
+        headers: response_headers,
+        body,
+        in_flight,
+    })
+}
+
+/// Translate and transform a ready upstream response into the client's response.
+/// Once the body is complete, the request's rate-limit `charge` is settled with its
+/// usage and its cost is recorded against the spend of its key, team and model.
+async fn finish(
+    state: &AppState,
+    ready: Ready,
+    ctx: &RequestContext<'_>,
+    charge: RateLimitCharge,
+) -> Result<Response> {
+    let Ready {
+        model,
+        client,
+        status,
+        headers,
+        body,
+        in_flight,
+    } = ready;
+    let protocol = ctx.protocol;
+    let config = client.config();
+    let backend = client.backend_label();
+    let backend_protocol = config.backend_type.protocol();
+    let spend = state.spend.entry(ctx.client_key.as_ref(), &model, config.pricing.as_ref());
+    let restore_pii = config.pii.as_ref().is_some_and(|pii| pii.restore) && !ctx.pii.is_empty();
+
+    let mut builder = Response::builder().status(status);
+    for (name, value) in &headers {
+        if name != CONTENT_LENGTH && name != TRANSFER_ENCODING {
+            builder = builder.header(name, value);
+        }
+    }
+
+    let body = match body {
+        ReadyBody::Stream(stream) => {
+            let stream = translate_sse_stream(stream, backend_protocol, protocol);
+            let stream = match restore_pii {
+                true => restore_pii_stream(stream, ctx.pii.clone()).boxed(),
+                false => stream.boxed(),
+            };
+            // Streams still running at the end of a shutdown drain get a final error event
+            let stream = state.shutdown.guard_stream(stream, protocol);
+            // Dropped mid-way when the client disconnects, which also closes the upstream stream
+            let stream = CancellableStream::new(stream, ctx, &model, &backend, charge, spend);
+            // The endpoint stays in flight until the stream is fully relayed or dropped
+            Body::from_stream(stream.map(move |chunk| {
+                let _ = &in_flight;
+                chunk
+            }))
+        }
+        ReadyBody::Buffered(mut stream) => {
+            let mut bytes = Vec::new();
+            while let Some(chunk) = stream.next().await {
+                let chunk = chunk.map_err(|e| match e {
+                    ProxyError::Timeout(_) => e,
+                    e => ProxyError::Backend(format!("Failed to read response: {}", e)),
+                })?;
+                bytes.extend_from_slice(&chunk);
+            }
+            drop(in_flight);
+            let usage = serde_json::from_slice::<Value>(&bytes)
+                .ok()
+                .and_then(|json| TokenUsage::from_response(&json))
+                .unwrap_or(TokenUsage {
+                    input_tokens: ctx.prompt_tokens,
+                    ..TokenUsage::default()
+                });
+            charge.settle(usage);
+            spend.record(&usage);
+
+            ctx.logger.log_upstream_response(
+                &model,
+                &backend,
+                status.as_u16(),
+                &headers,
+                std::str::from_utf8(&bytes).ok(),
+                None,
+            );
+
+            if backend_protocol == protocol && config.transforms.response.is_empty() && !restore_pii {
+                Body::from(bytes)
+            } else {
+                let json: Value = serde_json::from_slice(&bytes)
+                    .map_err(|e| ProxyError::Backend(format!("Invalid JSON from backend: {}", e)))?;
+                let json = translate_response(json, backend_protocol, protocol);
+                let json = match restore_pii {
+                    true => ctx.pii.restore_value(json),
+                    false => json,
+                };
+                let json = apply_body_transforms(json, &config.transforms.response)?;
+                Body::from(serde_json::to_vec(&json)?)
+            }
+        }
+    };
+
+    builder
+        .body(body)
+        .map_err(|e| ProxyError::Internal(format!("Failed to build response: {}", e)))
+}
+
+fn set_served_model(response: &mut Response, model: &str, variant: Option<&str>) {
+    if let Ok(value) = HeaderValue::from_str(model) {
+        response
+            .headers_mut()
+            .insert(HeaderName::from_static(SERVED_MODEL_HEADER), value);
+    }
+    if let Some(Ok(value)) = variant.map(HeaderValue::from_str) {
+        response
+            .headers_mut()
+            .insert(HeaderName::from_static(SERVED_VARIANT_HEADER), value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{
+        AuthConfig, BackendType, CircuitBreakerConfig, Config, EndpointConfig, HedgingConfig, LoggingConfig,
+        ModelConfig, PriorityConfig, RetryConfig, ServerConfig, PiiConfig, PiiDetector, SpendConfig, StickyKey,
+    };
+    use serde_json::json;
+    use std::collections::HashMap;
+
+    fn model(backend_type: BackendType, endpoint: String, fallbacks: Vec<String>) -> ModelConfig {
+        ModelConfig {
+            api_key: Some("key".to_string()),
+            timeout_seconds: 5,
+            retry: RetryConfig {
+                max_attempts: 1,
+                backoff_ms: 1,
+                max_backoff_ms: 1,
+                ..RetryConfig::default()
+            },
+            fallbacks,
+            ..ModelConfig::test(backend_type, endpoint)
+        }
+    }
+
+    fn state(models: HashMap<String, ModelConfig>) -> AppState {
+        let config = Config {
+            server: ServerConfig {
+                host: "127.0.0.1".to_string(),
+                port: 0,
+                drain_timeout_seconds: 30,
+                tls: None,
+            },
+            logging: LoggingConfig::default(),
+            models,
+            routing_rules: Vec::new(),
+            priority: PriorityConfig::default(),
+            auth: AuthConfig::default(),
+            accounts: HashMap::new(),
+            spend: SpendConfig::default(),
+        };
+        AppState::new(config).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_client_keys_gate_models_and_stay_local() {
+        let mut server = mockito::Server::new_async().await;
+        let mini = server
+            .mock("POST", "/mini")
+            .match_header("authorization", "Bearer key")
+            .match_header("x-api-key", mockito::Matcher::Missing)
+            .match_body(mockito::Matcher::PartialJson(json!({"model": "gpt-4-mini"})))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"id":"ok"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let mut models = HashMap::new();
+        models.insert("gpt-4".to_string(), model(BackendType::OpenAI, format!("{}/full", server.url()), Vec::new()));
+        models.insert("gpt-4-mini".to_string(), model(BackendType::OpenAI, format!("{}/mini", server.url()), Vec::new()));
+        let mut state = state(models);
+        state.keys = Arc::new(crate::proxy::KeyStore::new(&AuthConfig {
+            keys: vec![crate::config::ApiKeyConfig {
+                name: "interns".to_string(),
+                key: "sk-intern".to_string(),
+                allowed_models: vec!["gpt-4".to_string()],
+                aliases: [("gpt-4".to_string(), "gpt-4-mini".to_string())].into(),
+                metadata: HashMap::new(),
+                rate_limit: None,
+                priority: None,
+            }],
+            keys_file: None,
+            jwt: None,
+            client_certs: Vec::new(),
+        })
+        .unwrap());
+
+        let send = |headers: HeaderMap, model: &str| {
+            let state = state.clone();
+            let request = json!({"model": model, "messages": []});
+            async move { dispatch(&state, Protocol::OpenAI, "/v1/chat/completions", &headers, None, request).await }
+        };
+
+        let err = send(HeaderMap::new(), "gpt-4").await.unwrap_err();
+        assert!(matches!(err, ProxyError::Unauthorized(_)));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", "sk-intern".parse().unwrap());
+        let err = send(headers.clone(), "gpt-4-mini").await.unwrap_err();
+        assert!(matches!(err, ProxyError::Forbidden(_)));
+
+        // The alias sends "gpt-4" to the mini model; the backend sees its own key only
+        let response = send(headers, "gpt-4").await.unwrap();
+        assert_eq!(response.headers().get(SERVED_MODEL_HEADER).unwrap(), "gpt-4-mini");
+        mini.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_rate_limits_by_model_and_key() {
+        let mut server = mockito::Server::new_async().await;
+        let mut mocks = Vec::new();
+        for path in ["/primary", "/secondary"] {
+            let mock = server
+                .mock("POST", path)
+                .with_status(200)
+                .with_header("content-type", "application/json")
+                .with_body(r#"{"id":"ok","usage":{"prompt_tokens":5,"completion_tokens":2}}"#)
+                .expect(1)
+                .create_async()
+                .await;
+            mocks.push(mock);
+        }
+
+        let mut primary_config = model(
+            BackendType::OpenAI,
+            format!("{}/primary", server.url()),
+            vec!["secondary".to_string()],
+        );
+        primary_config.rate_limit = Some(crate::config::RateLimitConfig {
+            requests_per_minute: Some(1),
+            ..Default::default()
+        });
+        let mut models = HashMap::new();
+        models.insert("primary".to_string(), primary_config);
+        models.insert(
+            "secondary".to_string(),
+            model(BackendType::OpenAI, format!("{}/secondary", server.url()), Vec::new()),
+        );
+        let mut config = (*state(models).config).clone();
+        config.auth.keys.push(crate::config::ApiKeyConfig {
+            name: "tiny".to_string(),
+            key: "sk-tiny".to_string(),
+            allowed_models: vec!["*".to_string()],
+            aliases: HashMap::new(),
+            metadata: HashMap::new(),
+            rate_limit: Some(crate::config::RateLimitConfig {
+                requests_per_minute: Some(2),
+                ..Default::default()
+            }),
+            priority: None,
+        });
+        let state = AppState::new(config).unwrap();
+
+        let mut headers = HeaderMap::new();
+        headers.insert("x-api-key", "sk-tiny".parse().unwrap());
+        let mut served = Vec::new();
+        for _ in 0..2 {
+            let response = dispatch(
+                &state,
+                Protocol::OpenAI,
+                "/v1/chat/completions",
+                &headers,
+                None,
+                json!({"model": "primary", "messages": []}),
+            )
+            .await
+            .unwrap();
+            served.push(response.headers().get(SERVED_MODEL_HEADER).unwrap().clone());
+        }
+        // The primary's own limit moves the second request on to the fallback
+        assert_eq!(served, vec!["primary", "secondary"]);
+        for mock in mocks {
+            mock.assert_async().await;
+        }
+
+        // The key's limit rejects the third before routing
+        let err = dispatch(
+            &state,
+            Protocol::Anthropic,
+            "/v1/messages",
+            &headers,
+            None,
+            json!({"model": "primary", "messages": []}),
+        )
+        .await
+        .unwrap_err();
+        let response = err.into_protocol_response(Protocol::Anthropic);
+        assert_eq!(response.status(), 429);
+        assert!(response.headers().contains_key("retry-after"));
+        assert_eq!(response.headers()["anthropic-ratelimit-requests-limit"], "2");
+        assert_eq!(response.headers()["anthropic-ratelimit-requests-remaining"], "0");
+    }
+
+    #[tokio::test]
+    async fn test_budget_downgrades_then_rejects() {
+        let mut server = mockito::Server::new_async().await;
+        let mut mocks = Vec::new();
+        for path in ["/large", "/small"] {
+            let mock = server
+                .mock("POST", path)
+                .with_status(200)
+                .with_header("content-type", "application/json")
+                .with_body(r#"{"id":"ok","usage":{"prompt_tokens":1000000,"completion_tokens":0}}"#)
+                .expect(1)
+                .create_async()
+                .await;
+            mocks.push(mock);
+        }
+
+        let pricing = |input_per_million| crate::config::PricingConfig {
+            input_per_million,
+            ..Default::default()
+        };
+        let mut large = model(BackendType::OpenAI, format!("{}/large", server.url()), Vec::new());
+        large.pricing = Some(pricing(10.0));
+        let mut small = model(BackendType::OpenAI, format!("{}/small", server.url()), Vec::new());
+        small.pricing = Some(pricing(1.0));
+        let mut config = (*state(HashMap::from([
+            ("large".to_string(), large),
+            ("small".to_string(), small),
+        ]))
+        .config)
+            .clone();
+        config.spend.budgets = vec![
+            crate::config::BudgetConfig {
+                model: Some("large".to_string()),
+                daily_usd: Some(5.0),
+                downgrade_to: Some("small".to_string()),
+                ..Default::default()
+            },
+            crate::config::BudgetConfig {
+                model: Some("small".to_string()),
+                daily_usd: Some(1.0),
+                ..Default::default()
+            },
+        ];
+        let state = AppState::new(config).unwrap();
+
+        let headers = HeaderMap::new();
+        let send = || {
+            dispatch(
+                &state,
+                Protocol::OpenAI,
+                "/v1/chat/completions",
+                &headers,
+                None,
+                json!({"model": "large", "messages": []}),
+            )
+        };
+        let response = send().await.unwrap();
+        assert_eq!(response.headers().get(SERVED_MODEL_HEADER).unwrap(), "large");
+        assert_eq!(state.spend.spent("model:large"), (10.0, 10.0));
+
+        // Over budget, the request goes to the cheaper model
+        let response = send().await.unwrap();
+        assert_eq!(response.headers().get(SERVED_MODEL_HEADER).unwrap(), "small");
+        assert_eq!(state.spend.spent("model:small"), (1.0, 1.0));
+        for mock in mocks {
+            mock.assert_async().await;
+        }
+
+        // Which has now used up its own budget
+        let err = send().await.unwrap_err();
+        assert!(matches!(err, ProxyError::BudgetExceeded { .. }));
+        assert_eq!(err.status_code(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(err.retry_after().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_budget_holds_after_overflow_and_on_fallbacks() {
+        let mut server = mockito::Server::new_async().await;
+        let ok = r#"{"id":"ok","usage":{"prompt_tokens":1000000,"completion_tokens":0}}"#;
+        let mut mocks = Vec::new();
+        for (path, status) in [("/large", 200), ("/small", 200), ("/primary", 503)] {
+            let mock = server
+                .mock("POST", path)
+                .with_status(status)
+                .with_body(ok)
+                .expect(1)
+                .create_async()
+                .await;
+            mocks.push(mock);
+        }
+
+        let mut large = model(BackendType::OpenAI, format!("{}/large", server.url()), Vec::new());
+        large.pricing = Some(crate::config::PricingConfig {
+            input_per_million: 10.0,
+            ..Default::default()
+        });
+        let mut small = model(BackendType::OpenAI, format!("{}/small", server.url()), Vec::new());
+        small.context_window = Some(100);
+        small.overflow_model = Some("large".to_string());
+        let primary = model(
+            BackendType::OpenAI,
+            format!("{}/primary", server.url()),
+            vec!["large".to_string(), "small".to_string()],
+        );
+        let mut config = (*state(HashMap::from([
+            ("large".to_string(), large),
+            ("small".to_string(), small),
+            ("primary".to_string(), primary),
+        ]))
+        .config)
+            .clone();
+        config.spend.budgets = vec![crate::config::BudgetConfig {
+            model: Some("large".to_string()),
+            daily_usd: Some(5.0),
+            downgrade_to: Some("small".to_string()),
+            ..Default::default()
+        }];
+        let state = AppState::new(config).unwrap();
+        let headers = HeaderMap::new();
+        let send = |model: &str, content: String| {
+            dispatch(
+                &state,
+                Protocol::OpenAI,
+                "/v1/chat/completions",
+                &headers,
+                None,
+                json!({"model": model, "messages": [{"role": "user", "content": content}]}),
+            )
+        };
+        send("large", String::new()).await.unwrap();
+
+        // Downgraded to the small model, a long prompt would overflow back to the large one
+        let err = send("large", "word ".repeat(400)).await.unwrap_err();
+        assert!(matches!(err, ProxyError::BudgetExceeded { .. }));
+
+        // The large model is skipped as a fallback while over budget
+        let response = send("primary", String::new()).await.unwrap();
+        assert_eq!(response.headers().get(SERVED_MODEL_HEADER).unwrap(), "small");
+        for mock in mocks {
+            mock.assert_async().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fallback_to_other_protocol() {
+        let mut server = mockito::Server::new_async().await;
+        let primary = server
+            .mock("POST", "/primary")
+            .with_status(503)
+            .with_body("overloaded")
+            .create_async()
+            .await;
+        let secondary = server
+            .mock("POST", "/secondary")
+            .match_header("x-api-key", "key")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!({
+                    "id": "msg_1",
+                    "type": "message",
+                    "role": "assistant",
+                    "model": "claude",
+                    "content": [{"type": "text", "text": "Hi"}],
+                    "stop_reason": "end_turn",
+                    "usage": {"input_tokens": 1, "output_tokens": 1}
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let mut models = HashMap::new();
+        models.insert(
+            "primary".to_string(),
+            model(
+                BackendType::OpenAI,
+                format!("{}/primary", server.url()),
+                vec!["secondary".to_string()],
+            ),
+        );
+        models.insert(
+            "secondary".to_string(),
+            model(BackendType::Anthropic, format!("{}/secondary", server.url()), Vec::new()),
+        );
+
+        let request = json!({"model": "primary", "messages": [{"role": "user", "content": "Hello"}]});
+        let response = dispatch(
+            &state(models),
+            Protocol::OpenAI,
+            "/v1/chat/completions",
+            &HeaderMap::new(),
+            None,
+            request,
+        )
+        .await
+        .unwrap();
+
+        primary.assert_async().await;
+        secondary.assert_async().await;
+        assert_eq!(response.headers().get(SERVED_MODEL_HEADER).unwrap(), "secondary");
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["choices"][0]["message"]["content"], "Hi");
+    }
+
+    #[tokio::test]
+    async fn test_unservable_fallback_moves_down_the_chain() {
+        let mut server = mockito::Server::new_async().await;
+        let _primary = server.mock("POST", "/primary").with_status(503).create_async().await;
+        let secondary = server
+            .mock("POST", "/secondary")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(json!({"id": "c1", "choices": []}).to_string())
+            .create_async()
+            .await;
+
+        let url = server.url();
+        let fallbacks = vec!["ghost".to_string(), "secondary".to_string()];
+        let models = HashMap::from([
+            ("primary".to_string(), model(BackendType::OpenAI, format!("{}/primary", url), fallbacks)),
+            ("secondary".to_string(), model(BackendType::OpenAI, format!("{}/secondary", url), Vec::new())),
+        ]);
+        let response = dispatch(
+            &state(models),
+            Protocol::OpenAI,
+            "/v1/chat/completions",
+            &HeaderMap::new(),
+            None,
+            json!({"model": "primary", "messages": []}),
+        )
+        .await
+        .unwrap();
+
+        secondary.assert_async().await;
+        assert_eq!(response.headers()[SERVED_MODEL_HEADER], "secondary");
+    }
+
+    #[tokio::test]
+    async fn test_fallback_too_small_for_the_request_is_skipped() {
+        let mut server = mockito::Server::new_async().await;
+        let _primary = server.mock("POST", "/primary").with_status(503).create_async().await;
+        let small = server.mock("POST", "/small").expect(0).create_async().await;
+        let large = server
+            .mock("POST", "/large")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(json!({"id": "c1", "choices": []}).to_string())
+            .create_async()
+            .await;
+
+        let url = server.url();
+        let fallbacks = vec!["small".to_string(), "large".to_string()];
+        let mut primary = model(BackendType::OpenAI, format!("{}/primary", url), fallbacks);
+        primary.context_window = Some(100_000);
+        let mut small_model = model(BackendType::OpenAI, format!("{}/small", url), Vec::new());
+        small_model.context_window = Some(1000);
+        let models = HashMap::from([
+            ("primary".to_string(), primary),
+            ("small".to_string(), small_model),
+            ("large".to_string(), model(BackendType::OpenAI, format!("{}/large", url), Vec::new())),
+        ]);
+        // ~2500 prompt tokens: fit for the primary, too many for "small"
+        let request = json!({
+            "model": "primary",
+            "messages": [{"role": "user", "content": "a".repeat(10_000)}]
+        });
+        let response = dispatch(
+            &state(models),
+            Protocol::OpenAI,
+            "/v1/chat/completions",
+            &HeaderMap::new(),
+            None,
+            request,
+        )
+        .await
+        .unwrap();
+
+        small.assert_async().await;
+        large.assert_async().await;
+        assert_eq!(response.headers()[SERVED_MODEL_HEADER], "large");
+    }
+
+    #[tokio::test]
+    async fn test_relayed_upstream_error_keeps_retry_after() {
+        let mut server = mockito::Server::new_async().await;
+        let error = json!({"error": {"type": "rate_limit_error", "message": "Slow down"}});
+        let _backend = server
+            .mock("POST", "/primary")
+            .with_status(429)
+            .with_header("retry-after", "7")
+            .with_body(error.to_string())
+            .create_async()
+            .await;
+
+        // Left to the client rather than retried by the proxy
+        let mut primary = model(BackendType::OpenAI, format!("{}/primary", server.url()), Vec::new());
+        primary.retry.retryable_statuses = vec![503];
+        let models = HashMap::from([("primary".to_string(), primary)]);
+        let response = dispatch(
+            &state(models),
+            Protocol::OpenAI,
+            "/v1/chat/completions",
+            &HeaderMap::new(),
+            None,
+            json!({"model": "primary", "messages": []}),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), 429);
+        assert_eq!(response.headers()[http::header::RETRY_AFTER], "7");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(serde_json::from_slice::<Value>(&body).unwrap(), error);
+    }
+
+    #[tokio::test]
+    async fn test_pii_redacted_upstream_and_restored() {
+        let mut server = mockito::Server::new_async().await;
+        let redacted = json!({"messages": [{"role": "user", "content": "Email [EMAIL_1] from [IP_ADDRESS_1]"}]});
+        let buffered = server
+            .mock("POST", "/chat")
+            .match_body(mockito::Matcher::PartialJson(redacted.clone()))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!({
+                    "id": "c1",
+                    "choices": [{"index": 0, "message": {"role": "assistant", "content": "Sent to [EMAIL_1]"}}],
+                    "usage": {"prompt_tokens": 5, "completion_tokens": 3}
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let mut pii_model = model(BackendType::OpenAI, format!("{}/chat", server.url()), Vec::new());
+        pii_model.pii = Some(PiiConfig {
+            detectors: PiiDetector::ALL.to_vec(),
+            custom: Vec::new(),
+            restore: true,
+        });
+        let state = state(HashMap::from([("gpt-4".to_string(), pii_model)]));
+        let content = "Email jane@example.com from 10.1.2.3";
+
+        let response = dispatch(
+            &state,
+            Protocol::OpenAI,
+            "/v1/chat/completions",
+            &HeaderMap::new(),
+            None,
+            json!({"model": "gpt-4", "messages": [{"role": "user", "content": content}]}),
+        )
+        .await
+        .unwrap();
+        buffered.assert_async().await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["choices"][0]["message"]["content"], "Sent to jane@example.com");
+        buffered.remove_async().await;
+
+        // Placeholders split across streamed deltas come back whole
+        let chunks: String = ["Sent to [EMA", "IL_1]."]
+            .iter()
+            .map(|text| format!("data: {}\n\n", json!({"id": "c1", "choices": [{"index": 0, "delta": {"content": text}}]})))
+            .chain(["data: [DONE]\n\n".to_string()])
+            .collect();
+        let streamed = server
+            .mock("POST", "/chat")
+            .match_body(mockito::Matcher::PartialJson(redacted))
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(chunks)
+            .create_async()
+            .await;
+        let response = dispatch(
+            &state,
+            Protocol::OpenAI,
+            "/v1/chat/completions",
+            &HeaderMap::new(),
+            None,
+            json!({"model": "gpt-4", "messages": [{"role": "user", "content": content}], "stream": true}),
+        )
+        .await
+        .unwrap();
+        streamed.assert_async().await;
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let text: String = String::from_utf8_lossy(&body)
+            .lines()
+            .filter_map(|line| line.strip_prefix("data: "))
+            .filter_map(|data| serde_json::from_str::<Value>(data).ok())
+            .filter_map(|chunk| chunk["choices"][0]["delta"]["content"].as_str().map(str::to_string))
+            .collect();
+        assert_eq!(text, "Sent to jane@example.com.");
+    }
+
+    #[tokio::test]
+    async fn test_no_fallback_on_client_error() {
+        let mut server = mockito::Server::new_async().await;
+        let primary = server
+            .mock("POST", "/primary")
+            .with_status(400)
+            .with_body(r#"{"error":{"message":"bad temperature"}}"#)
+            .create_async()
+            .await;
+        let secondary = server.mock("POST", "/secondary").expect(0).create_async().await;
+
+        let mut models = HashMap::new();
+        models.insert(
+            "primary".to_string(),
+            model(
+                BackendType::OpenAI,
+                format!("{}/primary", server.url()),
+                vec!["secondary".to_string()],
+            ),
+        );
+        models.insert(
+            "secondary".to_string(),
+            model(BackendType::OpenAI, format!("{}/secondary", server.url()), Vec::new()),
+        );
+
+        let request = json!({"model": "primary", "messages": []});
+        let response = dispatch(
+            &state(models),
+            Protocol::OpenAI,
+            "/v1/chat/completions",
+            &HeaderMap::new(),
+            None,
+            request,
+        )
+        .await
+        .unwrap();
+
+        primary.assert_async().await;
+        secondary.assert_async().await;
+        assert_eq!(response.status(), 400);
+        assert_eq!(response.headers().get(SERVED_MODEL_HEADER).unwrap(), "primary");
+    }
+
+    #[tokio::test]
+    async fn test_retry_moves_to_another_replica() {
+        let mut server = mockito::Server::new_async().await;
+        let broken = server
+            .mock("POST", "/replica-a")
+            .with_status(502)
+            .expect(1)
+            .create_async()
+            .await;
+        let healthy = server
+            .mock("POST", "/replica-b")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"id":"ok"}"#)
+            .expect(1)
+            .create_async()
+            .await;
+
+        let mut config = model(BackendType::OpenAI, String::new(), Vec::new());
+        config.retry.max_attempts = 2;
+        config.endpoints = ["/replica-a", "/replica-b"]
+            .iter()
+            .map(|path| EndpointConfig {
+                url: format!("{}{}", server.url(), path),
+                api_key: None,
+                weight: 1,
+                max_concurrent: None,
+            })
+            .collect();
+
+        let mut models = HashMap::new();
+        models.insert("llama".to_string(), config);
+
+        let response = dispatch(
+            &state(models),
+            Protocol::OpenAI,
+            "/v1/chat/completions",
+            &HeaderMap::new(),
+            None,
+            json!({"model": "llama", "messages": []}),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), 200);
+        broken.assert_async().await;
+        healthy.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_open_circuit_skips_to_fallback() {
+        let mut server = mockito::Server::new_async().await;
+        let primary = server
+            .mock("POST", "/primary")
+            .with_status(500)
+            .expect(1)
+            .create_async()
+            .await;
+        let secondary = server
+            .mock("POST", "/secondary")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"id":"ok"}"#)
+            .expect(2)
+            .create_async()
+            .await;
+
+        let mut primary_config = model(
+            BackendType::OpenAI,
+            format!("{}/primary", server.url()),
+            vec!["secondary".to_string()],
+        );
+        primary_config.circuit_breaker = CircuitBreakerConfig {
+            enabled: true,
+            consecutive_failures: 1,
+            ..CircuitBreakerConfig::default()
+        };
+        let mut models = HashMap::new();
+        models.insert("primary".to_string(), primary_config);
+        models.insert(
+            "secondary".to_string(),
+            model(BackendType::OpenAI, format!("{}/secondary", server.url()), Vec::new()),
+        );
+        let state = state(models);
+
+        // First request trips the breaker, second one never reaches the primary
+        for _ in 0..2 {
+            let response = dispatch(
+                &state,
+                Protocol::OpenAI,
+                "/v1/chat/completions",
+                &HeaderMap::new(),
+                None,
+                json!({"model": "primary", "messages": []}),
+            )
+            .await
+            .unwrap();
+            assert_eq!(response.headers().get(SERVED_MODEL_HEADER).unwrap(), "secondary");
+        }
+
+        primary.assert_async().await;
+        secondary.assert_async().await;
+    }
+
+    /// Serve `body` after `delay`; with `stream` the headers go out immediately and
+    /// only the body is delayed
+    async fn delayed_server(delay: std::time::Duration, stream: bool, body: &'static str) -> String {
+        let app = axum::Router::new().fallback(move || async move {
+            if stream {
+                let chunks = futures::stream::once(async move {
+                    tokio::time::sleep(delay).await;
+                    Ok::<_, std::io::Error>(Bytes::from(body))
+                });
+                Response::builder()
+                    .header(CONTENT_TYPE, "text/event-stream")
+                    .body(Body::from_stream(chunks))
+                    .unwrap()
+            } else {
+                tokio::time::sleep(delay).await;
+                Response::builder()
+                    .header(CONTENT_TYPE, "application/json")
+                    .body(Body::from(body))
+                    .unwrap()
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}", addr)
+    }
+
+    #[tokio::test]
+    async fn test_hedge_to_second_endpoint() {
+        let slow = delayed_server(std::time::Duration::from_secs(5), false, r#"{"id":"slow"}"#).await;
+        let fast = delayed_server(std::time::Duration::ZERO, false, r#"{"id":"fast"}"#).await;
+
+        let mut config = model(BackendType::OpenAI, String::new(), Vec::new());
+        config.endpoints = [slow, fast]
+            .into_iter()
+            .map(|url| EndpointConfig {
+                url,
+                api_key: None,
+                weight: 1,
+                max_concurrent: None,
+            })
+            .collect();
+        config.hedging = HedgingConfig {
+            enabled: true,
+            delay_ms: 50,
+            model: None,
+        };
+        let mut models = HashMap::new();
+        models.insert("llama".to_string(), config);
+        let state = state(models);
+
+        let started = std::time::Instant::now();
+        let response = dispatch(
+            &state,
+            Protocol::OpenAI,
+            "/v1/chat/completions",
+            &HeaderMap::new(),
+            None,
+            json!({"model": "llama", "messages": []}),
+        )
+        .await
+        .unwrap();
+        assert!(started.elapsed() < std::time::Duration::from_secs(2));
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let json: Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["id"], "fast");
+
+        // The losing request was cancelled and released its endpoint
+        let client = state.router.get_client("llama").unwrap();
+        assert!(client.endpoints().iter().all(|e| e.in_flight() == 0));
+    }
+
+    #[tokio::test]
+    async fn test_hedge_stream_to_other_model() {
+        const CHUNK: &str = "data: {\"id\":\"c1\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hi\"}}]}\n\n";
+        let stalled = delayed_server(std::time::Duration::from_secs(5), true, CHUNK).await;
+        let quick = delayed_server(std::time::Duration::ZERO, true, CHUNK).await;
+
+        let mut primary = model(BackendType::OpenAI, stalled, Vec::new());
+        primary.hedging = HedgingConfig {
+            enabled: true,
+            delay_ms: 50,
+            model: Some("backup".to_string()),
+        };
+        let mut models = HashMap::new();
+        models.insert("primary".to_string(), primary);
+        models.insert("backup".to_string(), model(BackendType::OpenAI, quick, Vec::new()));
+
+        let started = std::time::Instant::now();
+        let response = dispatch(
+            &state(models),
+            Protocol::OpenAI,
+            "/v1/chat/completions",
+            &HeaderMap::new(),
+            None,
+            json!({"model": "primary", "messages": [], "stream": true}),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.headers().get(SERVED_MODEL_HEADER).unwrap(), "backup");
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("Hi"));
+        assert!(started.elapsed() < std::time::Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_variant_reported_in_header() {
+        let mut server = mockito::Server::new_async().await;
+        let stable = server.mock("POST", "/stable").expect(0).create_async().await;
+        let canary = server
+            .mock("POST", "/canary")
+            .match_body(mockito::Matcher::PartialJson(json!({"model": "gpt-4o-new"})))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(r#"{"id":"ok"}"#)
+            .create_async()
+            .await;
+
+        let mut config = model(BackendType::OpenAI, format!("{}/stable", server.url()), Vec::new());
+        config.sticky = StickyKey::User;
+        config.variants = serde_yaml::from_str(&format!(
+            r#"
+- name: stable
+  weight: 0
+- name: canary
+  weight: 100
+  endpoint: {}/canary
+  target_model: gpt-4o-new
+"#,
+            server.url()
+        ))
+        .unwrap();
+        let mut models = HashMap::new();
+        models.insert("chat".to_string(), config);
+
+        let response = dispatch(
+            &state(models),
+            Protocol::OpenAI,
+            "/v1/chat/completions",
+            &HeaderMap::new(),
+            None,
+            json!({"model": "chat", "messages": [], "user": "alice"}),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.headers().get(SERVED_MODEL_HEADER).unwrap(), "chat");
+        assert_eq!(response.headers().get(SERVED_VARIANT_HEADER).unwrap(), "canary");
+        stable.assert_async().await;
+        canary.assert_async().await;
+    }
+
+    #[tokio::test]
+    async fn test_first_byte_timeout_falls_back() {
+        let slow = delayed_server(std::time::Duration::from_secs(5), false, r#"{"id":"slow"}"#).await;
+        let fast = delayed_server(std::time::Duration::ZERO, false, r#"{"id":"fast"}"#).await;
+
+        let mut primary = model(BackendType::OpenAI, slow, vec!["backup".to_string()]);
+        primary.timeouts.first_byte_ms = Some(100);
+        let mut models = HashMap::new();
+        models.insert("primary".to_string(), primary);
+        models.insert("backup".to_string(), model(BackendType::OpenAI, fast, Vec::new()));
+
+        let response = dispatch(
+            &state(models),
+            Protocol::OpenAI,
+            "/v1/chat/completions",
+            &HeaderMap::new(),
+            None,
+            json!({"model": "primary", "messages": []}),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.headers().get(SERVED_MODEL_HEADER).unwrap(), "backup");
+    }
+
+    #[tokio::test]
+    async fn test_stream_stalled_before_first_chunk_is_retried() {
+        let stalled = delayed_server(std::time::Duration::from_secs(5), true, "data: stalled\n\n").await;
+        let healthy = delayed_server(std::time::Duration::ZERO, true, "data: [DONE]\n\n").await;
+
+        let mut config = model(BackendType::OpenAI, String::new(), Vec::new());
+        config.retry.max_attempts = 2;
+        config.timeouts.idle_stream_ms = Some(100);
+        config.endpoints = [stalled, healthy]
+            .into_iter()
+            .map(|url| EndpointConfig {
+                url,
+                api_key: None,
+                weight: 1,
+                max_concurrent: None,
+            })
+            .collect();
+        let mut models = HashMap::new();
+        models.insert("llama".to_string(), config.clone());
+        let state_retrying = state(models);
+
+        // Without retries before the first byte, the stalled stream is the error
+        config.retry.retry_streams_before_first_byte = false;
+        let mut models = HashMap::new();
+        models.insert("llama".to_string(), config);
+        let state_not_retrying = state(models);
+
+        let request = json!({"model": "llama", "stream": true, "messages": []});
+        for _ in 0..2 {
+            let response = dispatch(
+                &state_retrying,
+                Protocol::OpenAI,
+                "/v1/chat/completions",
+                &HeaderMap::new(),
+                None,
+                request.clone(),
+            )
+            .await
+            .unwrap();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+            assert!(std::str::from_utf8(&body).unwrap().contains("[DONE]"));
+        }
+
+        let mut failures = 0;
+        for _ in 0..2 {
+            failures += dispatch(
+                &state_not_retrying,
+                Protocol::OpenAI,
+                "/v1/chat/completions",
+                &HeaderMap::new(),
+                None,
+                request.clone(),
+            )
+            .await
+            .is_err() as usize;
+        }
+        // Round robin sends one of the two requests to the stalled replica
+        assert_eq!(failures, 1);
+    }
+
+    #[tokio::test]
+    async fn test_client_deadline_spans_fallbacks() {
+        let slow = delayed_server(std::time::Duration::from_secs(5), false, r#"{"id":"slow"}"#).await;
+
+        let mut models = HashMap::new();
+        models.insert(
+            "primary".to_string(),
+            model(BackendType::OpenAI, slow.clone(), vec!["backup".to_string()]),
+        );
+        models.insert("backup".to_string(), model(BackendType::OpenAI, slow, Vec::new()));
+
+        let mut headers = HeaderMap::new();
+        headers.insert(crate::proxy::TIMEOUT_HEADER, "200".parse().unwrap());
+        let started = std::time::Instant::now();
+        let err = dispatch(
+            &state(models),
+            Protocol::OpenAI,
+            "/v1/chat/completions",
+            &headers,
+            None,
+            json!({"model": "primary", "messages": []}),
+        )
+        .await
+        .unwrap_err();
+
+        assert!(matches!(err, ProxyError::Timeout(TimeoutPhase::Deadline)));
+        assert!(err.to_string().contains("request deadline"));
+        assert!(started.elapsed() < std::time::Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn test_client_disconnect_closes_upstream_stream() {
+        // Upstream that sends one chunk, then holds the stream open until it is dropped
+        let closed = Arc::new(tokio::sync::Notify::new());
+        let app = axum::Router::new().fallback({
+            let closed = closed.clone();
+            move || async move {
+                struct NotifyOnDrop(Arc<tokio::sync::Notify>);
+                impl Drop for NotifyOnDrop {
+                    fn drop(&mut self) {
+                        self.0.notify_one();
+                    }
+                }
+                let guard = NotifyOnDrop(closed);
+                let first = Bytes::from_static(b"data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hello\"}}]}\n\n");
+                let chunks = futures::stream::once(async move { Ok::<_, std::io::Error>(first) })
+                    .chain(futures::stream::pending())
+                    .map(move |chunk| {
+                        let _ = &guard;
+                        chunk
+                    });
+                Response::builder()
+                    .header(CONTENT_TYPE, "text/event-stream")
+                    .body(Body::from_stream(chunks))
+                    .unwrap()
+            }
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let mut models = HashMap::new();
+        models.insert("chat".to_string(), model(BackendType::OpenAI, url, Vec::new()));
+        let state = state(models);
+        let response = dispatch(
+            &state,
+            Protocol::OpenAI,
+            "/v1/chat/completions",
+            &HeaderMap::new(),
+            None,
+            json!({"model": "chat", "stream": true, "messages": []}),
+        )
+        .await
+        .unwrap();
+
+        let mut body = response.into_body().into_data_stream();
+        assert!(body.next().await.unwrap().unwrap().starts_with(b"data: "));
+        let endpoint = state.router.get_client("chat").unwrap().endpoints()[0].clone();
+        assert_eq!(endpoint.status().in_flight, 1);
+
+        // The client goes away mid-stream
+        drop(body);
+        assert_eq!(endpoint.status().in_flight, 0);
+        tokio::time::timeout(std::time::Duration::from_secs(5), closed.notified())
+            .await
+            .expect("upstream stream was not closed");
+    }
+
+    #[tokio::test]
+    async fn test_client_disconnect_aborts_pending_request() {
+        let slow = delayed_server(std::time::Duration::from_secs(5), false, r#"{"id":"slow"}"#).await;
+        let mut models = HashMap::new();
+        models.insert("chat".to_string(), model(BackendType::OpenAI, slow, Vec::new()));
+        let state = state(models);
+
+        // Dropping the request future is what the server does when the client disconnects
+        let headers = HeaderMap::new();
+        let request = dispatch(
+            &state,
+            Protocol::OpenAI,
+            "/v1/chat/completions",
+            &headers,
+            None,
+            json!({"model": "chat", "messages": []}),
+        );
+        let result = tokio::time::timeout(std::time::Duration::from_millis(200), request).await;
+        assert!(result.is_err());
+
+        let endpoint = state.router.get_client("chat").unwrap().endpoints()[0].clone();
+        assert_eq!(endpoint.status().in_flight, 0);
+        assert!(endpoint.limiter.has_capacity());
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_request_bills_its_prompt() {
+        let slow = delayed_server(std::time::Duration::from_secs(5), false, r#"{"id":"slow"}"#).await;
+        let mut chat = model(BackendType::OpenAI, slow, Vec::new());
+        // A dollar per prompt token
+        chat.pricing = Some(crate::config::PricingConfig {
+            input_per_million: 1_000_000.0,
+            ..Default::default()
+        });
+        chat.rate_limit = Some(crate::config::RateLimitConfig {
+            input_tokens_per_minute: Some(1000),
+            ..Default::default()
+        });
+        let state = state(HashMap::from([("chat".to_string(), chat)]));
+
+        let headers = HeaderMap::new();
+        let body = json!({"model": "chat", "messages": [{"role": "user", "content": "word ".repeat(400)}]});
+        let prompt_tokens = estimate_prompt_tokens(&body) as u64;
+        assert!(prompt_tokens > 100);
+        let request = dispatch(&state, Protocol::OpenAI, "/v1/chat/completions", &headers, None, body);
+        assert!(tokio::time::timeout(std::time::Duration::from_millis(200), request).await.is_err());
+
+        assert_eq!(state.spend.spent("model:chat").0, prompt_tokens as f64);
+        // The prompt stays charged to the model's rate limit rather than being given back
+        assert!(state.rate_limits.admit_model("chat", 1000 - prompt_tokens / 2).is_err());
+    }
+}
@@ -0,0 +1,143 @@
+use axum::{extract::State, http::StatusCode, Json};
+use serde_json::{json, Map, Value};
+
+use super::AppState;
+
+/// GET /health/live: the process is up and serving HTTP
+pub async fn live_handler() -> &'static str {
+    "OK"
+}
+
+/// GET /health/ready: per-model availability based on health checks and circuit breakers.
+///
+/// A model is `healthy` when all its endpoints are in rotation, `degraded` when only
+/// some are, and `unavailable` when none are. The proxy reports 503 only when no
+/// model can serve traffic, or while it drains for shutdown.
+pub async fn ready_handler(State(state): State<AppState>) -> (StatusCode, Json<Value>) {
+    if state.shutdown.is_draining() {
+        return (
+            StatusCode::SERVICE_UNAVAILABLE,
+            Json(json!({ "status": "draining" })),
+        );
+    }
+
+    let mut models = Map::new();
+    let mut available_models = 0;
+
+    for (name, client) in state.router.clients() {
+        let endpoints = client.endpoints();
+        let available = endpoints.iter().filter(|e| e.is_available()).count();
+        let status = if available == endpoints.len() {
+            "healthy"
+        } else if available > 0 {
+            "degraded"
+        } else {
+            "unavailable"
+        };
+        if available > 0 {
+            available_models += 1;
+        }
+
+        let endpoint_status: Vec<Value> = endpoints
+            .iter()
+            .map(|e| {
+                let health = e.health.status();
+                json!({
+                    "url": e.url,
+                    "available": e.is_available(),
+                    "healthy": health.healthy,
+                    "circuit": e.circuit.state(),
+                    "last_error": health.last_error,
+                    "last_checked": health.last_checked,
+                })
+            })
+            .collect();
+
+        models.insert(
+            client.label(name),
+            json!({
+                "status": status,
+                "available_endpoints": available,
+                "total_endpoints": endpoints.len(),
+                "endpoints": endpoint_status,
+            }),
+        );
+    }
+
+    let (code, status) = if available_models == models.len() {
+        (StatusCode::OK, "ready")
+    } else if available_models > 0 {
+        (StatusCode::OK, "degraded")
+    } else {
+        (StatusCode::SERVICE_UNAVAILABLE, "unavailable")
+    };
+
+    (code, Json(json!({ "status": status, "models": models })))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{Config, HealthCheckConfig};
+
+    fn state() -> AppState {
+        let config: Config = serde_yaml::from_str(
+            r#"
+server: {}
+models:
+  llama:
+    backend_type: openai
+    endpoints:
+      - url: http://replica-a/v1/chat/completions
+      - url: http://replica-b/v1/chat/completions
+  claude:
+    backend_type: anthropic
+    endpoint: http://claude/v1/messages
+"#,
+        )
+        .unwrap();
+        AppState::new(config).unwrap()
+    }
+
+    fn mark_unhealthy(state: &AppState, model: &str, idx: usize) {
+        let config = HealthCheckConfig {
+            unhealthy_threshold: 1,
+            ..HealthCheckConfig::default()
+        };
+        let client = state.router.get_client(model).unwrap();
+        client.endpoints()[idx]
+            .health
+            .record(Err("down".to_string()), &config);
+    }
+
+    #[tokio::test]
+    async fn test_ready_reports_per_model_status() {
+        let state = state();
+        let (code, Json(body)) = ready_handler(State(state.clone())).await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(body["status"], "ready");
+
+        mark_unhealthy(&state, "llama", 0);
+        mark_unhealthy(&state, "claude", 0);
+        let (code, Json(body)) = ready_handler(State(state.clone())).await;
+        assert_eq!(code, StatusCode::OK);
+        assert_eq!(body["status"], "degraded");
+        assert_eq!(body["models"]["llama"]["status"], "degraded");
+        assert_eq!(body["models"]["llama"]["endpoints"][0]["last_error"], "down");
+        assert_eq!(body["models"]["claude"]["status"], "unavailable");
+
+        mark_unhealthy(&state, "llama", 1);
+        let (code, Json(body)) = ready_handler(State(state)).await;
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "unavailable");
+    }
+
+    #[tokio::test]
+    async fn test_ready_fails_while_draining() {
+        let state = state();
+        state.shutdown.begin_drain(std::time::Duration::from_secs(30));
+        let (code, Json(body)) = ready_handler(State(state)).await;
+        assert_eq!(code, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(body["status"], "draining");
+    }
+}
@@ -1,5 +1,65 @@
-pub mod openai;
 pub mod anthropic;
+pub mod cancel;
+pub mod dispatch;
+pub mod health;
+pub mod openai;
+pub mod shadow;
+pub mod shutdown;
+pub mod status;
+pub mod tls;
 
-pub use openai::*;
 pub use anthropic::*;
+pub use dispatch::{SERVED_MODEL_HEADER, SERVED_VARIANT_HEADER};
+pub use health::{live_handler, ready_handler};
+pub use openai::*;
+pub use shutdown::{shutdown_signal, Shutdown};
+pub use status::status_handler;
+pub use tls::{serve_tls, spawn_tls_reload, TlsListener};
+
+use crate::{
+    config::Config,
+    logging::ShadowLog,
+    proxy::{KeyStore, ModelRouter, RateLimits, SpendTracker},
+    types::Result,
+};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+#[derive(Clone)]
+pub struct AppState {
+    pub router: Arc<ModelRouter>,
+    pub config: Arc<Config>,
+    /// Shadow comparison logs by output path, shared by models writing the same file
+    pub shadow_logs: Arc<HashMap<String, Arc<ShadowLog>>>,
+    pub shutdown: Arc<Shutdown>,
+    /// Client API keys; empty when the proxy is open
+    pub keys: Arc<KeyStore>,
+    pub rate_limits: Arc<RateLimits>,
+    pub spend: Arc<SpendTracker>,
+}
+
+impl AppState {
+    pub fn new(config: Config) -> Result<Self> {
+        let router = Arc::new(ModelRouter::new(&config)?);
+        let shadow_logs = config
+            .models
+            .values()
+            .filter_map(|model| model.shadow.as_ref())
+            .map(|shadow| (shadow.output.clone(), Arc::new(ShadowLog::new(&shadow.output))))
+            .collect();
+
+        let keys = Arc::new(KeyStore::new(&config.auth)?);
+        let rate_limits = Arc::new(RateLimits::new(&config));
+        let spend = Arc::new(SpendTracker::new(&config.spend));
+
+        Ok(Self {
+            router,
+            config: Arc::new(config),
+            shadow_logs: Arc::new(shadow_logs),
+            shutdown: Arc::new(Shutdown::new()),
+            keys,
+            rate_limits,
+            spend,
+        })
+    }
+}
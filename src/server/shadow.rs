@@ -0,0 +1,529 @@
+use axum::{body::Body, http::HeaderMap, response::Response};
+use bytes::Bytes;
+use chrono::Utc;
+use futures::Stream;
+use http::header::CONTENT_TYPE;
+use serde_json::Value;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tokio::sync::oneshot;
+
+use crate::{
+    config::Priority,
+    logging::{RequestLogger, ShadowOutcome, ShadowRecord},
+    proxy::{estimate_prompt_tokens, RetryBudget},
+    streaming::StreamSummary,
+    transform::{PiiRedactor, PiiVault},
+    types::{response_text, Protocol, Result, TokenUsage},
+};
+
+use super::{
+    dispatch::{serve_model, RequestContext},
+    AppState,
+};
+
+/// Response bytes kept per side for the comparison record; longer bodies are cut off
+const MAX_CAPTURE_BYTES: usize = 1024 * 1024;
+
+/// A shadow request in flight, waiting for the primary response to compare against
+pub struct ShadowHandle {
+    primary_model: String,
+    primary: oneshot::Sender<ShadowOutcome>,
+}
+
+/// Mirror the request to the model's shadow model if it has one and the request is
+/// sampled. The shadow request runs in the background; its failures are only logged.
+pub fn start(
+    state: &AppState,
+    model: &str,
+    protocol: Protocol,
+    headers: &HeaderMap,
+    request: &Value,
+) -> Option<ShadowHandle> {
+    let shadow = state.router.get_config(model).ok()?.shadow.as_ref()?;
+    if rand::random::<f64>() >= shadow.sample_rate {
+        return None;
+    }
+    let log = state.shadow_logs.get(&shadow.output)?.clone();
+    // Personal data the primary model's backend may not see doesn't reach the shadow
+    // backend or the record either. One vault serves the request and both responses,
+    // so a value gets the same placeholder everywhere in the record.
+    let pii = state.router.get_client(model).ok().and_then(|client| client.pii().cloned());
+    let vault = Arc::new(PiiVault::default());
+    let mut request = request.clone();
+    if let Some(redactor) = &pii {
+        redactor.redact_request(&mut request, &vault);
+    }
+
+    let (primary_tx, primary_rx) = oneshot::channel();
+    let run = run(
+        state.clone(),
+        shadow.model.clone(),
+        protocol,
+        headers.clone(),
+        request,
+        vault.clone(),
+        primary_rx,
+    );
+    tokio::spawn(async move {
+        let mut record = run.await;
+        if let Some(redactor) = pii {
+            redact(&mut record, &redactor, &vault);
+        }
+        if let Err(e) = log.append(&record).await {
+            tracing::warn!(path = %log.path().display(), error = %e, "Failed to write shadow record");
+        }
+    });
+
+    Some(ShadowHandle {
+        primary_model: model.to_string(),
+        primary: primary_tx,
+    })
+}
+
+impl ShadowHandle {
+    /// Pass the primary result through unchanged, recording what it contained once the
+    /// client has received the whole body
+    pub fn observe(self, result: Result<Response>, started: Instant) -> Result<Response> {
+        let response = match result {
+            Ok(response) => response,
+            Err(e) => {
+                let _ = self.primary.send(ShadowOutcome {
+                    model: self.primary_model,
+                    status: Some(e.status_code().as_u16()),
+                    latency_ms: started.elapsed().as_millis() as u64,
+                    error: Some(e.to_string()),
+                    ..ShadowOutcome::default()
+                });
+                return Err(e);
+            }
+        };
+
+        let (parts, body) = response.into_parts();
+        let tap = Tap {
+            inner: body.into_data_stream(),
+            captured: Vec::new(),
+            outcome: Some(ShadowOutcome {
+                model: self.primary_model,
+                status: Some(parts.status.as_u16()),
+                stream: is_event_stream(&parts.headers),
+                ..ShadowOutcome::default()
+            }),
+            started,
+            tx: Some(self.primary),
+        };
+        Ok(Response::from_parts(parts, Body::from_stream(tap)))
+    }
+}
+
+/// Send the shadow request and wait for the primary outcome to compare it with
+async fn run(
+    state: AppState,
+    model: String,
+    protocol: Protocol,
+    headers: HeaderMap,
+    request: Value,
+    pii: Arc<PiiVault>,
+    primary: oneshot::Receiver<ShadowOutcome>,
+) -> ShadowRecord {
+    let logger = Arc::new(RequestLogger::new(state.config.logging.clone()));
+    let started = Instant::now();
+    // The shadow model's own deadline applies; the client's timeout header is for the primary
+    let ctx = RequestContext {
+        protocol,
+        path: protocol.path(),
+        headers: &headers,
+        forward_headers: &headers,
+        request: &request,
+        logger: &logger,
+        deadline: state
+            .router
+            .get_config(&model)
+            .ok()
+            .and_then(|config| config.deadline())
+            .map(|deadline| started + deadline),
+        // Mirrored traffic never displaces real requests at a busy backend
+        priority: Priority::Low,
+        retry_budget: RetryBudget::default(),
+        prompt_tokens: estimate_prompt_tokens(&request) as u64,
+        // Mirrored traffic is charged to the shadow model's spend only
+        client_key: None,
+        claims: None,
+        pii,
+    };
+
+    let mut shadow = ShadowOutcome {
+        model: model.clone(),
+        ..ShadowOutcome::default()
+    };
+    match serve_model(&state, &model, &ctx).await {
+        Ok(response) => {
+            shadow.status = Some(response.status().as_u16());
+            shadow.stream = is_event_stream(response.headers());
+            match axum::body::to_bytes(response.into_body(), usize::MAX).await {
+                Ok(bytes) => summarize(&mut shadow, &bytes[..bytes.len().min(MAX_CAPTURE_BYTES)]),
+                Err(e) => shadow.error = Some(format!("Failed to read response: {}", e)),
+            }
+        }
+        Err(e) => {
+            shadow.status = Some(e.status_code().as_u16());
+            shadow.error = Some(e.to_string());
+        }
+    }
+    shadow.latency_ms = started.elapsed().as_millis() as u64;
+
+    let primary = primary.await.unwrap_or_else(|_| ShadowOutcome {
+        error: Some("Primary response was not observed".to_string()),
+        ..ShadowOutcome::default()
+    });
+    if let Some(error) = &shadow.error {
+        tracing::warn!(shadow_model = %model, error = %error, "Shadow request failed");
+    }
+
+    ShadowRecord {
+        timestamp: Utc::now(),
+        request,
+        primary,
+        shadow,
+    }
+}
+
+/// Replace personal data in both responses' text and bodies; the request was redacted
+/// before it was mirrored
+fn redact(record: &mut ShadowRecord, redactor: &PiiRedactor, vault: &PiiVault) {
+    for outcome in [&mut record.primary, &mut record.shadow] {
+        if let Some(text) = &mut outcome.text {
+            *text = redactor.redact(text, vault);
+        }
+        if let Some(body) = &mut outcome.body {
+            redactor.redact_value(body, vault);
+        }
+    }
+}
+
+/// Fill in the text, usage and (for JSON responses) body of an outcome
+fn summarize(outcome: &mut ShadowOutcome, bytes: &[u8]) {
+    if outcome.stream {
+        let mut summary = StreamSummary::new();
+        summary.push(bytes);
+        summary.finish();
+        outcome.text = Some(summary.text);
+        outcome.usage = summary.usage;
+    } else if let Ok(json) = serde_json::from_slice::<Value>(bytes) {
+        outcome.text = response_text(&json);
+        outcome.usage = TokenUsage::from_response(&json);
+        outcome.body = Some(json);
+    }
+}
+
+fn is_event_stream(headers: &HeaderMap) -> bool {
+    headers
+        .get(CONTENT_TYPE)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.starts_with("text/event-stream"))
+}
+
+/// Relays the primary body to the client while keeping a copy for the shadow record.
+/// The record is sent when the body ends or the client goes away, whichever is first.
+struct Tap<S> {
+    inner: S,
+    captured: Vec<u8>,
+    outcome: Option<ShadowOutcome>,
+    started: Instant,
+    tx: Option<oneshot::Sender<ShadowOutcome>>,
+}
+
+impl<S> Tap<S> {
+    fn send(&mut self, error: Option<String>) {
+        let (Some(mut outcome), Some(tx)) = (self.outcome.take(), self.tx.take()) else {
+            return;
+        };
+        outcome.latency_ms = self.started.elapsed().as_millis() as u64;
+        summarize(&mut outcome, &self.captured);
+        outcome.error = error;
+        let _ = tx.send(outcome);
+    }
+}
+
+impl<S, E> Stream for Tap<S>
+where
+    S: Stream<Item = std::result::Result<Bytes, E>> + Unpin,
+    E: std::fmt::Display,
+{
+    type Item = std::result::Result<Bytes, E>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let polled = Pin::new(&mut self.inner).poll_next(cx);
+        match &polled {
+            Poll::Ready(Some(Ok(chunk))) => {
+                let room = MAX_CAPTURE_BYTES.saturating_sub(self.captured.len());
+                let end = chunk.len().min(room);
+                self.captured.extend_from_slice(&chunk[..end]);
+            }
+            Poll::Ready(Some(Err(e))) => {
+                let error = e.to_string();
+                self.send(Some(error));
+            }
+            Poll::Ready(None) => self.send(None),
+            Poll::Pending => {}
+        }
+        polled
+    }
+}
+
+impl<S> Drop for Tap<S> {
+    fn drop(&mut self) {
+        if self.outcome.is_some() {
+            self.send(Some("Client disconnected before the response completed".to_string()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::server::dispatch::dispatch;
+    use serde_json::json;
+
+    fn config(server_url: &str, output: &std::path::Path) -> Config {
+        serde_yaml::from_str(&format!(
+            r#"
+server: {{}}
+models:
+  primary:
+    backend_type: openai
+    endpoint: {url}/primary
+    retry:
+      max_attempts: 1
+    shadow:
+      model: candidate
+      output: {output}
+  candidate:
+    backend_type: anthropic
+    endpoint: {url}/candidate
+    retry:
+      max_attempts: 1
+"#,
+            url = server_url,
+            output = output.display()
+        ))
+        .unwrap()
+    }
+
+    fn state(server_url: &str, output: &std::path::Path) -> AppState {
+        AppState::new(config(server_url, output)).unwrap()
+    }
+
+    async fn read_records(path: &std::path::Path) -> Vec<Value> {
+        for _ in 0..100 {
+            if let Ok(contents) = std::fs::read_to_string(path) {
+                if !contents.is_empty() {
+                    return contents.lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+                }
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        panic!("no shadow record written to {}", path.display());
+    }
+
+    fn output_path(name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("{}-{}.jsonl", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[tokio::test]
+    async fn test_shadow_records_both_responses() {
+        let mut server = mockito::Server::new_async().await;
+        let _primary = server
+            .mock("POST", "/primary")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!({
+                    "id": "chatcmpl-1",
+                    "object": "chat.completion",
+                    "choices": [{"index": 0, "message": {"role": "assistant", "content": "Hello"}, "finish_reason": "stop"}],
+                    "usage": {"prompt_tokens": 5, "completion_tokens": 1, "total_tokens": 6}
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+        let candidate = server
+            .mock("POST", "/candidate")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!({
+                    "id": "msg_1",
+                    "type": "message",
+                    "role": "assistant",
+                    "model": "claude",
+                    "content": [{"type": "text", "text": "Hi there"}],
+                    "stop_reason": "end_turn",
+                    "usage": {"input_tokens": 6, "output_tokens": 2}
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+
+        let path = output_path("shadow-both");
+        let response = dispatch(
+            &state(&server.url(), &path),
+            Protocol::OpenAI,
+            "/v1/chat/completions",
+            &HeaderMap::new(),
+            None,
+            json!({"model": "primary", "messages": [{"role": "user", "content": "Hi"}]}),
+        )
+        .await
+        .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(serde_json::from_slice::<Value>(&body).unwrap()["id"], "chatcmpl-1");
+
+        let records = read_records(&path).await;
+        candidate.assert_async().await;
+        assert_eq!(records.len(), 1);
+        let record = &records[0];
+        assert_eq!(record["request"]["messages"][0]["content"], "Hi");
+        assert_eq!(record["primary"]["model"], "primary");
+        assert_eq!(record["primary"]["text"], "Hello");
+        assert_eq!(record["primary"]["usage"]["output_tokens"], 1);
+        // The shadow response is recorded in the client's protocol
+        assert_eq!(record["shadow"]["model"], "candidate");
+        assert_eq!(record["shadow"]["status"], 200);
+        assert_eq!(record["shadow"]["text"], "Hi there");
+        assert_eq!(record["shadow"]["usage"]["input_tokens"], 6);
+        assert!(record["shadow"]["body"]["choices"].is_array());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_shadow_failure_does_not_affect_client() {
+        let mut server = mockito::Server::new_async().await;
+        let _primary = server
+            .mock("POST", "/primary")
+            .with_status(200)
+            .with_header("content-type", "text/event-stream")
+            .with_body(concat!(
+                "data: {\"id\":\"c1\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hel\"}}]}\n\n",
+                "data: {\"id\":\"c1\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"lo\"}}]}\n\n",
+                "data: [DONE]\n\n"
+            ))
+            .create_async()
+            .await;
+        let _candidate = server
+            .mock("POST", "/candidate")
+            .with_status(500)
+            .with_body("boom")
+            .create_async()
+            .await;
+
+        let path = output_path("shadow-failure");
+        let response = dispatch(
+            &state(&server.url(), &path),
+            Protocol::OpenAI,
+            "/v1/chat/completions",
+            &HeaderMap::new(),
+            None,
+            json!({"model": "primary", "messages": [], "stream": true}),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), 200);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("lo"));
+
+        let records = read_records(&path).await;
+        let record = &records[0];
+        assert_eq!(record["primary"]["stream"], true);
+        assert_eq!(record["primary"]["text"], "Hello");
+        assert!(record["shadow"]["status"].as_u64().unwrap() >= 500);
+        assert!(record["shadow"]["error"].is_string());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_shadow_record_redacted_with_primary_pii_config() {
+        let mut server = mockito::Server::new_async().await;
+        let _primary = server
+            .mock("POST", "/primary")
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!({
+                    "id": "chatcmpl-1",
+                    "object": "chat.completion",
+                    "choices": [{"index": 0, "message": {"role": "assistant", "content": "Mailed [EMAIL_1]"}, "finish_reason": "stop"}],
+                    "usage": {"prompt_tokens": 5, "completion_tokens": 2, "total_tokens": 7}
+                })
+                .to_string(),
+            )
+            .create_async()
+            .await;
+        // The shadow model has no pii config of its own, yet only gets the placeholder
+        let candidate = server
+            .mock("POST", "/candidate")
+            .match_body(mockito::Matcher::Regex(r"Mail \[EMAIL_1\]".to_string()))
+            .with_status(200)
+            .with_header("content-type", "application/json")
+            .with_body(
+                json!({
+                    "id": "msg_1",
+                    "type": "message",
+                    "role": "assistant",
+                    "model": "claude",
+                    "content": [{"type": "text", "text": "Mailed [EMAIL_1]"}],
+                    "stop_reason": "end_turn",
+                    "usage": {"input_tokens": 6, "output_tokens": 2}
+                })
+                .to_string(),
+            )
+            .expect(1)
+            .create_async()
+            .await;
+        let raw = server
+            .mock("POST", "/candidate")
+            .match_body(mockito::Matcher::Regex("jane@example.com".to_string()))
+            .expect(0)
+            .create_async()
+            .await;
+
+        let path = output_path("shadow-pii");
+        let mut config = config(&server.url(), &path);
+        config.models.get_mut("primary").unwrap().pii = Some(crate::config::PiiConfig {
+            detectors: vec![crate::config::PiiDetector::Email],
+            custom: Vec::new(),
+            restore: true,
+        });
+        let response = dispatch(
+            &AppState::new(config).unwrap(),
+            Protocol::OpenAI,
+            "/v1/chat/completions",
+            &HeaderMap::new(),
+            None,
+            json!({"model": "primary", "messages": [{"role": "user", "content": "Mail jane@example.com"}]}),
+        )
+        .await
+        .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("Mailed jane@example.com"));
+
+        let records = read_records(&path).await;
+        candidate.assert_async().await;
+        raw.assert_async().await;
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert!(!contents.contains("jane@example.com"), "{}", contents);
+        let record = &records[0];
+        assert_eq!(record["request"]["messages"][0]["content"], "Mail [EMAIL_1]");
+        assert_eq!(record["primary"]["text"], "Mailed [EMAIL_1]");
+        assert_eq!(record["shadow"]["text"], "Mailed [EMAIL_1]");
+        assert_eq!(record["shadow"]["body"]["choices"][0]["message"]["content"], "Mailed [EMAIL_1]");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,174 @@
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+
+use crate::{
+    streaming::stream_error_event,
+    types::{Protocol, ProxyError, Result},
+};
+
+/// Shutdown state shared by every request.
+///
+/// On a shutdown signal the server stops accepting connections and readiness starts
+/// failing (draining). Requests and streams still running when the drain timeout
+/// expires are terminated.
+#[derive(Debug)]
+pub struct Shutdown {
+    draining: AtomicBool,
+    terminated: watch::Sender<bool>,
+}
+
+impl Default for Shutdown {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Shutdown {
+    pub fn new() -> Self {
+        Self {
+            draining: AtomicBool::new(false),
+            terminated: watch::Sender::new(false),
+        }
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Start draining and terminate whatever is still running after `drain_timeout`
+    pub fn begin_drain(self: &Arc<Self>, drain_timeout: Duration) {
+        if self.draining.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        tracing::info!(
+            drain_timeout_secs = drain_timeout.as_secs(),
+            "Shutting down, draining in-flight requests"
+        );
+        let shutdown = self.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(drain_timeout).await;
+            shutdown.terminate();
+        });
+    }
+
+    /// Cut off everything still running
+    pub fn terminate(&self) {
+        if !self.terminated.send_replace(true) {
+            tracing::warn!("Drain timeout expired, terminating remaining requests");
+        }
+    }
+
+    /// Resolves once remaining requests are to be terminated
+    pub async fn terminated(&self) {
+        let mut receiver = self.terminated.subscribe();
+        // The sender lives as long as `self`, so this only returns once terminated
+        let _ = receiver.wait_for(|terminated| *terminated).await;
+    }
+
+    /// Relay a client stream until termination, then end it with an error event in the
+    /// client's protocol
+    pub fn guard_stream<S>(
+        self: &Arc<Self>,
+        stream: S,
+        protocol: Protocol,
+    ) -> impl Stream<Item = Result<Bytes>> + Send + 'static
+    where
+        S: Stream<Item = Result<Bytes>> + Send + 'static,
+    {
+        let shutdown = self.clone();
+        async_stream::stream! {
+            futures::pin_mut!(stream);
+            let terminated = shutdown.terminated();
+            futures::pin_mut!(terminated);
+            loop {
+                tokio::select! {
+                    chunk = stream.next() => match chunk {
+                        Some(chunk) => yield chunk,
+                        None => return,
+                    },
+                    _ = &mut terminated => {
+                        yield Ok(stream_error_event(&ProxyError::ShuttingDown, protocol).to_bytes());
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Resolves on SIGTERM or SIGINT (Ctrl-C)
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(e) = tokio::signal::ctrl_c().await {
+            tracing::error!(error = %e, "Failed to listen for SIGINT");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to listen for SIGTERM");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => tracing::info!("Received SIGINT"),
+        _ = terminate => tracing::info!("Received SIGTERM"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_terminated_stream_ends_with_protocol_error() {
+        let shutdown = Arc::new(Shutdown::new());
+        let (sender, receiver) = futures::channel::mpsc::unbounded::<Result<Bytes>>();
+        let mut stream = Box::pin(shutdown.guard_stream(receiver, Protocol::Anthropic));
+
+        sender
+            .unbounded_send(Ok(Bytes::from_static(b"event: ping\ndata: {}\n\n")))
+            .unwrap();
+        assert_eq!(
+            stream.next().await.unwrap().unwrap(),
+            Bytes::from_static(b"event: ping\ndata: {}\n\n")
+        );
+
+        shutdown.begin_drain(Duration::from_millis(10));
+        assert!(shutdown.is_draining());
+        let last = stream.next().await.unwrap().unwrap();
+        let last = std::str::from_utf8(&last).unwrap();
+        assert!(last.starts_with("event: error\n"));
+        assert!(last.contains("\"api_error\""));
+        assert!(stream.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_openai_stream_error_event() {
+        let shutdown = Arc::new(Shutdown::new());
+        shutdown.terminate();
+        let mut stream = Box::pin(
+            shutdown.guard_stream(futures::stream::pending::<Result<Bytes>>(), Protocol::OpenAI),
+        );
+        let event = stream.next().await.unwrap().unwrap();
+        let event = std::str::from_utf8(&event).unwrap();
+        let body: serde_json::Value =
+            serde_json::from_str(event.strip_prefix("data: ").unwrap().trim()).unwrap();
+        assert_eq!(body["error"]["type"], "shutting_down");
+        assert_eq!(body["error"]["code"], 503);
+    }
+}
@@ -0,0 +1,41 @@
+use axum::{extract::State, Json};
+use serde_json::{json, Value};
+
+use super::AppState;
+
+/// GET /status: per-model endpoint statistics (in-flight requests, latency, circuit state)
+pub async fn status_handler(State(state): State<AppState>) -> Json<Value> {
+    Json(json!({ "models": state.router.status() }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[tokio::test]
+    async fn test_status_reports_endpoints() {
+        let config: Config = serde_yaml::from_str(
+            r#"
+server: {}
+models:
+  llama:
+    backend_type: openai
+    endpoints:
+      - url: http://replica-a/v1/chat/completions
+      - url: http://replica-b/v1/chat/completions
+    circuit_breaker:
+      enabled: true
+"#,
+        )
+        .unwrap();
+        let state = AppState::new(config).unwrap();
+
+        let Json(body) = status_handler(State(state)).await;
+        let endpoints = body["models"]["llama"]["endpoints"].as_array().unwrap();
+        assert_eq!(endpoints.len(), 2);
+        assert_eq!(endpoints[0]["circuit"]["state"], "closed");
+        assert_eq!(endpoints[0]["in_flight"], 0);
+        assert_eq!(body["models"]["llama"]["load_balancing"], "round_robin");
+    }
+}
@@ -0,0 +1,327 @@
+use axum::{extract::Request, Router};
+use hyper::body::Incoming;
+use hyper_util::{
+    rt::{TokioExecutor, TokioIo},
+    server::{conn::auto, graceful::GracefulShutdown},
+    service::TowerToHyperService,
+};
+use std::future::Future;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::net::TcpListener;
+use tokio::task::JoinHandle;
+use tokio_rustls::rustls::{
+    self,
+    pki_types::CertificateDer,
+    server::WebPkiClientVerifier,
+    RootCertStore,
+};
+use tokio_rustls::TlsAcceptor;
+use tower::ServiceExt;
+
+use crate::{
+    config::TlsConfig,
+    proxy::{
+        tls::{read_certs, read_key},
+        ClientIdentity,
+    },
+    types::{ProxyError, Result},
+};
+
+/// Longest a client may take to complete the TLS handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The listener's TLS settings, loaded again when the certificate, key or client CA
+/// files change. New connections use the latest settings; open ones keep theirs.
+pub struct TlsListener {
+    config: TlsConfig,
+    current: RwLock<Arc<rustls::ServerConfig>>,
+    /// Modification times of the files the current settings were loaded from
+    loaded: Mutex<Vec<Option<SystemTime>>>,
+}
+
+impl TlsListener {
+    pub fn new(config: &TlsConfig) -> Result<Self> {
+        let loaded = modified(config);
+        Ok(Self {
+            current: RwLock::new(Arc::new(server_config(config)?)),
+            config: config.clone(),
+            loaded: Mutex::new(loaded),
+        })
+    }
+
+    pub fn acceptor(&self) -> TlsAcceptor {
+        TlsAcceptor::from(self.current.read().unwrap().clone())
+    }
+
+    /// Load the files again if any changed since they were last loaded, returning
+    /// whether they did. Files that fail to load leave the current settings in place
+    /// and are tried again on the next call.
+    pub fn reload_if_changed(&self) -> Result<bool> {
+        let modified = modified(&self.config);
+        if *self.loaded.lock().unwrap() == modified {
+            return Ok(false);
+        }
+        let server = server_config(&self.config)?;
+        *self.current.write().unwrap() = Arc::new(server);
+        *self.loaded.lock().unwrap() = modified;
+        Ok(true)
+    }
+}
+
+impl std::fmt::Debug for TlsListener {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("TlsListener").field("config", &self.config).finish()
+    }
+}
+
+fn modified(config: &TlsConfig) -> Vec<Option<SystemTime>> {
+    [Some(&config.cert_path), Some(&config.key_path), config.client_ca_path.as_ref()]
+        .into_iter()
+        .flatten()
+        .map(|path| std::fs::metadata(path).and_then(|meta| meta.modified()).ok())
+        .collect()
+}
+
+fn server_config(config: &TlsConfig) -> Result<rustls::ServerConfig> {
+    let tls_error = |e: rustls::Error| ProxyError::Config(format!("Invalid server TLS settings: {}", e));
+    let certs = read_certs(&config.cert_path)?;
+    let key = read_key(&config.key_path)?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let builder = rustls::ServerConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()
+        .map_err(tls_error)?;
+    let builder = match &config.client_ca_path {
+        Some(path) => {
+            let mut roots = RootCertStore::empty();
+            for cert in read_certs(path)? {
+                roots.add(cert).map_err(tls_error)?;
+            }
+            let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+            let verifier = match config.require_client_cert {
+                true => verifier,
+                false => verifier.allow_unauthenticated(),
+            };
+            let verifier = verifier
+                .build()
+                .map_err(|e| ProxyError::Config(format!("Invalid client CA '{}': {}", path, e)))?;
+            builder.with_client_cert_verifier(verifier)
+        }
+        None => builder.with_no_client_auth(),
+    };
+    let mut server = builder.with_single_cert(certs, key).map_err(tls_error)?;
+    server.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+    Ok(server)
+}
+
+/// The subject of a verified client certificate chain's leaf
+fn client_identity(certs: &[CertificateDer<'_>]) -> Option<ClientIdentity> {
+    let (_, cert) = x509_parser::parse_x509_certificate(certs.first()?).ok()?;
+    let subject = cert.subject();
+    let common_name = subject
+        .iter_common_name()
+        .next()
+        .and_then(|name| name.as_str().ok())
+        .map(str::to_string);
+    Some(ClientIdentity {
+        subject: subject.to_string(),
+        common_name,
+    })
+}
+
+/// Check the TLS files for changes periodically
+pub fn spawn_tls_reload(tls: Arc<TlsListener>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut ticker = tokio::time::interval(Duration::from_secs(tls.config.reload_interval_seconds));
+        ticker.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            ticker.tick().await;
+            match tls.reload_if_changed() {
+                Ok(true) => tracing::info!("Reloaded server TLS certificates"),
+                Ok(false) => {}
+                Err(e) => tracing::warn!(error = %e, "Failed to reload server TLS certificates"),
+            }
+        }
+    })
+}
+
+/// Serve `app` over TLS until `signal` resolves, then stop accepting connections and
+/// wait for open ones to finish, like `axum::serve(..).with_graceful_shutdown(..)`.
+/// Requests on connections with a client certificate carry its [`ClientIdentity`].
+pub async fn serve_tls(
+    listener: TcpListener,
+    app: Router,
+    tls: Arc<TlsListener>,
+    signal: impl Future<Output = ()>,
+) -> std::io::Result<()> {
+    let graceful = GracefulShutdown::new();
+    tokio::pin!(signal);
+    loop {
+        let (stream, peer) = tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok(accepted) => accepted,
+                Err(e) => {
+                    // Typically out of file descriptors; back off instead of spinning
+                    tracing::warn!(error = %e, "Failed to accept connection");
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            },
+            _ = &mut signal => break,
+        };
+        let acceptor = tls.acceptor();
+        let app = app.clone();
+        let watcher = graceful.watcher();
+        tokio::spawn(async move {
+            let stream = match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                Ok(Ok(stream)) => stream,
+                Ok(Err(e)) => {
+                    tracing::debug!(%peer, error = %e, "TLS handshake failed");
+                    return;
+                }
+                Err(_) => {
+                    tracing::debug!(%peer, "TLS handshake timed out");
+                    return;
+                }
+            };
+            let identity = stream.get_ref().1.peer_certificates().and_then(client_identity);
+            let service = app.map_request(move |mut request: Request<Incoming>| {
+                if let Some(identity) = &identity {
+                    request.extensions_mut().insert(identity.clone());
+                }
+                request
+            });
+            let builder = auto::Builder::new(TokioExecutor::new());
+            let connection =
+                builder.serve_connection_with_upgrades(TokioIo::new(stream), TowerToHyperService::new(service));
+            if let Err(e) = watcher.watch(connection).await {
+                tracing::debug!(%peer, error = %e, "Connection closed with error");
+            }
+        });
+    }
+    drop(listener);
+    graceful.shutdown().await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{routing::get, Extension};
+    use tokio_rustls::rustls::pki_types::ServerName;
+
+    const FIXTURES: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/fixtures/tls");
+
+    fn fixture(name: &str) -> String {
+        format!("{}/{}", FIXTURES, name)
+    }
+
+    /// A copy of the fixtures the test may replace
+    fn tls_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("llm-proxy-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        for file in ["server.pem", "server.key", "ca.pem"] {
+            std::fs::copy(fixture(file), dir.join(file)).unwrap();
+        }
+        dir
+    }
+
+    fn tls_config(dir: &std::path::Path, client_ca: bool) -> TlsConfig {
+        let path = |file: &str| dir.join(file).to_string_lossy().into_owned();
+        TlsConfig {
+            cert_path: path("server.pem"),
+            key_path: path("server.key"),
+            client_ca_path: client_ca.then(|| path("ca.pem")),
+            require_client_cert: true,
+            reload_interval_seconds: 10,
+        }
+    }
+
+    async fn start(app: Router, tls: Arc<TlsListener>) -> (String, tokio::sync::oneshot::Sender<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("https://localhost:{}", listener.local_addr().unwrap().port());
+        let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+        tokio::spawn(serve_tls(listener, app, tls, async move {
+            let _ = stopped.await;
+        }));
+        (url, stop)
+    }
+
+    /// The leaf certificate the server presents to a new connection
+    async fn served_cert(addr: &str) -> CertificateDer<'static> {
+        let mut roots = RootCertStore::empty();
+        roots.add(read_certs(&fixture("ca.pem")).unwrap().remove(0)).unwrap();
+        let client = rustls::ClientConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+        let stream = tokio_rustls::TlsConnector::from(Arc::new(client))
+            .connect(ServerName::try_from("localhost").unwrap(), stream)
+            .await
+            .unwrap();
+        stream.get_ref().1.peer_certificates().unwrap()[0].clone().into_owned()
+    }
+
+    #[tokio::test]
+    async fn test_client_certificate_identity() {
+        let dir = tls_dir("mtls");
+        let tls = Arc::new(TlsListener::new(&tls_config(&dir, true)).unwrap());
+        let app = Router::new().route(
+            "/whoami",
+            get(|identity: Option<Extension<ClientIdentity>>| async move {
+                identity.map(|Extension(identity)| identity.subject).unwrap_or_default()
+            }),
+        );
+        let (url, _stop) = start(app, tls).await;
+        let ca = reqwest::Certificate::from_pem(&std::fs::read(fixture("ca.pem")).unwrap()).unwrap();
+
+        let pem = [std::fs::read(fixture("client.key")).unwrap(), std::fs::read(fixture("client.pem")).unwrap()].concat();
+        let client = reqwest::Client::builder()
+            .add_root_certificate(ca.clone())
+            .identity(reqwest::Identity::from_pem(&pem).unwrap())
+            .build()
+            .unwrap();
+        let subject = client.get(format!("{}/whoami", url)).send().await.unwrap().text().await.unwrap();
+        assert_eq!(subject, "O=Acme, CN=svc-search");
+
+        // Without a certificate the handshake is refused
+        let anonymous = reqwest::Client::builder().add_root_certificate(ca).build().unwrap();
+        assert!(anonymous.get(format!("{}/whoami", url)).send().await.is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reload_on_change() {
+        let dir = tls_dir("reload");
+        let tls = Arc::new(TlsListener::new(&tls_config(&dir, false)).unwrap());
+        let (url, _stop) = start(Router::new(), tls.clone()).await;
+        let addr = url.trim_start_matches("https://").replace("localhost", "127.0.0.1");
+        let original = read_certs(&fixture("server.pem")).unwrap().remove(0);
+        let renewed = read_certs(&fixture("server-renewed.pem")).unwrap().remove(0);
+        assert_eq!(served_cert(&addr).await, original);
+        assert!(!tls.reload_if_changed().unwrap());
+
+        // A certificate that does not match the key is not taken
+        std::fs::copy(fixture("server-renewed.pem"), dir.join("server.pem")).unwrap();
+        assert!(tls.reload_if_changed().is_err());
+        assert_eq!(served_cert(&addr).await, original);
+
+        std::fs::copy(fixture("server-renewed.key"), dir.join("server.key")).unwrap();
+        assert!(tls.reload_if_changed().unwrap());
+        assert_eq!(served_cert(&addr).await, renewed);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_missing_files() {
+        let config = TlsConfig {
+            cert_path: fixture("missing.pem"),
+            ..tls_config(std::path::Path::new(FIXTURES), false)
+        };
+        let err = TlsListener::new(&config).unwrap_err();
+        assert!(err.to_string().contains("missing.pem"));
+    }
+}
@@ -1,2 +1,9 @@
-// SSE streaming support - to be implemented
-// This will handle Server-Sent Events for streaming responses
+pub mod pii;
+pub mod sse;
+pub mod summary;
+pub mod translate;
+
+pub use pii::{restore_pii_stream, PiiStreamRestorer};
+pub use sse::{SseEvent, SseParser};
+pub use summary::StreamSummary;
+pub use translate::{stream_error_event, translate_sse_stream, StreamTranslator};
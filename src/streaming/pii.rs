@@ -0,0 +1,232 @@
+use super::sse::{SseEvent, SseParser};
+use crate::transform::pii::{partial_placeholder_len, PiiVault};
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+/// Text held back on one channel (a choice or content block) because it ends in what
+/// may be the start of a placeholder
+struct Held {
+    event: Option<String>,
+    /// A delta event for the channel to carry the text if no further delta arrives
+    template: Value,
+    pointer: &'static str,
+    text: String,
+}
+
+/// Restores placeholders in the text deltas of a client-protocol stream. A placeholder
+/// split across deltas is held back until the delta that completes it, or until the
+/// channel sees a non-text event. Streamed tool call arguments are passed through as is.
+pub struct PiiStreamRestorer {
+    vault: Arc<PiiVault>,
+    held: BTreeMap<u64, Held>,
+}
+
+impl PiiStreamRestorer {
+    pub fn new(vault: Arc<PiiVault>) -> Self {
+        Self {
+            vault,
+            held: BTreeMap::new(),
+        }
+    }
+
+    pub fn restore(&mut self, event: SseEvent) -> Vec<SseEvent> {
+        let Ok(mut data) = serde_json::from_str::<Value>(&event.data) else {
+            return self.finish().into_iter().chain([event]).collect();
+        };
+
+        // Anthropic: one channel per content block
+        if data.get("type").and_then(Value::as_str).is_some() {
+            let index = data.get("index").and_then(Value::as_u64);
+            let is_text = data.get("type").and_then(Value::as_str) == Some("content_block_delta")
+                && data.pointer("/delta/text").is_some_and(Value::is_string);
+            let mut out = Vec::new();
+            match index {
+                Some(index) if is_text => {
+                    let text = data.pointer("/delta/text").and_then(Value::as_str).unwrap_or_default();
+                    let ready = self.hold(index, &event, &data, "/delta/text", text);
+                    if ready.is_empty() {
+                        return out;
+                    }
+                    data["delta"]["text"] = Value::String(ready);
+                }
+                Some(index) => out.extend(self.flush(index)),
+                None => out.extend(self.finish()),
+            }
+            out.push(SseEvent {
+                event: event.event,
+                data: data.to_string(),
+            });
+            return out;
+        }
+
+        // OpenAI: one channel per choice. Chunks are sent even when all their text is
+        // held back, as they may also carry the role, a finish reason or usage
+        let mut out = Vec::new();
+        let template = data.clone();
+        if let Some(choices) = data.get_mut("choices").and_then(Value::as_array_mut) {
+            for (position, choice) in choices.iter_mut().enumerate() {
+                let index = choice.get("index").and_then(Value::as_u64).unwrap_or(position as u64);
+                match choice.pointer("/delta/content").and_then(Value::as_str) {
+                    Some(text) => {
+                        let mut single = template.clone();
+                        single["choices"] = Value::Array(vec![template["choices"][position].clone()]);
+                        let text = text.to_string();
+                        let ready = self.hold(index, &event, &single, "/choices/0/delta/content", &text);
+                        choice["delta"]["content"] = Value::String(ready);
+                    }
+                    None => out.extend(self.flush(index)),
+                }
+            }
+        }
+        out.push(SseEvent {
+            event: event.event,
+            data: data.to_string(),
+        });
+        out
+    }
+
+    /// Release everything still held back, at the end of the stream
+    pub fn finish(&mut self) -> Vec<SseEvent> {
+        let channels: Vec<u64> = self.held.keys().copied().collect();
+        channels.into_iter().filter_map(|index| self.flush(index)).collect()
+    }
+
+    /// Append a delta to the channel's held text and return the restored part that can
+    /// be sent now
+    fn hold(&mut self, index: u64, event: &SseEvent, template: &Value, pointer: &'static str, text: &str) -> String {
+        let mut text = self.held.remove(&index).map(|held| held.text).unwrap_or_default() + text;
+        let pending = text.split_off(text.len() - partial_placeholder_len(&text));
+        if !pending.is_empty() {
+            self.held.insert(
+                index,
+                Held {
+                    event: event.event.clone(),
+                    template: template.clone(),
+                    pointer,
+                    text: pending,
+                },
+            );
+        }
+        self.vault.restore(&text)
+    }
+
+    fn flush(&mut self, index: u64) -> Option<SseEvent> {
+        let mut held = self.held.remove(&index)?;
+        *held.template.pointer_mut(held.pointer)? = Value::String(self.vault.restore(&held.text));
+        Some(SseEvent {
+            event: held.event,
+            data: held.template.to_string(),
+        })
+    }
+}
+
+/// Restore placeholders in a client-protocol SSE byte stream
+pub fn restore_pii_stream<S, E>(upstream: S, vault: Arc<PiiVault>) -> impl Stream<Item = std::result::Result<Bytes, E>>
+where
+    S: Stream<Item = std::result::Result<Bytes, E>> + Send + 'static,
+    E: Send + 'static,
+{
+    async_stream::stream! {
+        let mut parser = SseParser::new();
+        let mut restorer = PiiStreamRestorer::new(vault);
+        futures::pin_mut!(upstream);
+
+        while let Some(chunk) = upstream.next().await {
+            match chunk {
+                Ok(bytes) => {
+                    for event in parser.push(&bytes) {
+                        for out in restorer.restore(event) {
+                            yield Ok(out.to_bytes());
+                        }
+                    }
+                }
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            }
+        }
+
+        if let Some(event) = parser.finish() {
+            for out in restorer.restore(event) {
+                yield Ok(out.to_bytes());
+            }
+        }
+        for out in restorer.finish() {
+            yield Ok(out.to_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{PiiConfig, PiiDetector};
+    use crate::transform::PiiRedactor;
+    use serde_json::json;
+
+    fn vault() -> Arc<PiiVault> {
+        let vault = Arc::new(PiiVault::default());
+        let redactor = PiiRedactor::new(&PiiConfig {
+            detectors: PiiDetector::ALL.to_vec(),
+            custom: Vec::new(),
+            restore: true,
+        })
+        .unwrap();
+        assert_eq!(redactor.redact("jane@example.com", &vault), "[EMAIL_1]");
+        vault
+    }
+
+    fn text_of(events: &[SseEvent], pointer: &str) -> String {
+        events
+            .iter()
+            .filter_map(|e| serde_json::from_str::<Value>(&e.data).ok())
+            .filter_map(|data| data.pointer(pointer).and_then(Value::as_str).map(str::to_string))
+            .collect()
+    }
+
+    #[test]
+    fn test_restore_split_placeholder_anthropic() {
+        let mut restorer = PiiStreamRestorer::new(vault());
+        let mut out = Vec::new();
+        for text in ["Hello [EM", "AIL_", "1], and [EMAIL_9] or [ema", "il]. Bye ["] {
+            let delta = json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": text}});
+            out.extend(restorer.restore(SseEvent::named("content_block_delta", delta.to_string())));
+        }
+        let stop = json!({"type": "content_block_stop", "index": 0});
+        out.extend(restorer.restore(SseEvent::named("content_block_stop", stop.to_string())));
+
+        assert_eq!(
+            text_of(&out, "/delta/text"),
+            "Hello jane@example.com, and [EMAIL_9] or [email]. Bye ["
+        );
+        // The held back "[" goes out before the block is closed
+        assert_eq!(out[out.len() - 2].event.as_deref(), Some("content_block_delta"));
+        assert_eq!(out.last().unwrap().event.as_deref(), Some("content_block_stop"));
+    }
+
+    #[test]
+    fn test_restore_split_placeholder_openai() {
+        let mut restorer = PiiStreamRestorer::new(vault());
+        let mut out = Vec::new();
+        for content in ["Hi [EMA", "IL_1]!", " [EMAIL"] {
+            let chunk = json!({"id": "c1", "choices": [{"index": 0, "delta": {"content": content}, "finish_reason": null}]});
+            out.extend(restorer.restore(SseEvent::data(chunk.to_string())));
+        }
+        let done = json!({"id": "c1", "choices": [{"index": 0, "delta": {}, "finish_reason": "stop"}]});
+        out.extend(restorer.restore(SseEvent::data(done.to_string())));
+        out.extend(restorer.restore(SseEvent::data("[DONE]")));
+
+        assert_eq!(text_of(&out, "/choices/0/delta/content"), "Hi jane@example.com! [EMAIL");
+        let last: Vec<Value> = out[out.len() - 3..out.len() - 1]
+            .iter()
+            .map(|e| serde_json::from_str(&e.data).unwrap())
+            .collect();
+        assert_eq!(last[0]["choices"][0]["delta"]["content"], "[EMAIL");
+        assert_eq!(last[1]["choices"][0]["finish_reason"], "stop");
+        assert_eq!(out.last().unwrap().data, "[DONE]");
+    }
+}
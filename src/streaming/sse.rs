@@ -0,0 +1,141 @@
+use bytes::Bytes;
+
+/// A single Server-Sent Event
+#[derive(Debug, Clone, PartialEq)]
+pub struct SseEvent {
+    pub event: Option<String>,
+    pub data: String,
+}
+
+impl SseEvent {
+    pub fn data(data: impl Into<String>) -> Self {
+        Self {
+            event: None,
+            data: data.into(),
+        }
+    }
+
+    pub fn named(event: impl Into<String>, data: impl Into<String>) -> Self {
+        Self {
+            event: Some(event.into()),
+            data: data.into(),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Bytes {
+        let mut out = String::new();
+        if let Some(event) = &self.event {
+            out.push_str("event: ");
+            out.push_str(event);
+            out.push('\n');
+        }
+        for line in self.data.split('\n') {
+            out.push_str("data: ");
+            out.push_str(line);
+            out.push('\n');
+        }
+        out.push('\n');
+        Bytes::from(out)
+    }
+}
+
+/// Incremental SSE parser; upstream chunks may split events (and UTF-8 sequences) anywhere
+#[derive(Default)]
+pub struct SseParser {
+    buffer: Vec<u8>,
+}
+
+impl SseParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed a chunk of bytes and return every event completed by it
+    pub fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buffer.extend_from_slice(chunk);
+
+        let mut events = Vec::new();
+        while let Some((end, sep_len)) = Self::find_boundary(&self.buffer) {
+            let raw: Vec<u8> = self.buffer.drain(..end + sep_len).take(end).collect();
+            if let Some(event) = Self::parse_event(&String::from_utf8_lossy(&raw)) {
+                events.push(event);
+            }
+        }
+        events
+    }
+
+    /// Flush a trailing event that was not terminated by a blank line
+    pub fn finish(&mut self) -> Option<SseEvent> {
+        let raw = std::mem::take(&mut self.buffer);
+        Self::parse_event(&String::from_utf8_lossy(&raw))
+    }
+
+    fn find_boundary(buffer: &[u8]) -> Option<(usize, usize)> {
+        let lf = buffer.windows(2).position(|w| w == b"\n\n").map(|i| (i, 2));
+        let crlf = buffer.windows(4).position(|w| w == b"\r\n\r\n").map(|i| (i, 4));
+        match (lf, crlf) {
+            (Some(a), Some(b)) => Some(if a.0 <= b.0 { a } else { b }),
+            (a, b) => a.or(b),
+        }
+    }
+
+    fn parse_event(raw: &str) -> Option<SseEvent> {
+        let mut event = None;
+        let mut data: Vec<&str> = Vec::new();
+
+        for line in raw.lines() {
+            if let Some(value) = line.strip_prefix("event:") {
+                event = Some(value.trim().to_string());
+            } else if let Some(value) = line.strip_prefix("data:") {
+                data.push(value.strip_prefix(' ').unwrap_or(value));
+            }
+        }
+
+        if event.is_none() && data.is_empty() {
+            return None;
+        }
+
+        Some(SseEvent {
+            event,
+            data: data.join("\n"),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_split_events() {
+        let mut parser = SseParser::new();
+        assert!(parser.push(b"event: ping\nda").is_empty());
+
+        let events = parser.push(b"ta: {}\n\ndata: [DONE]\n\n");
+        assert_eq!(
+            events,
+            vec![SseEvent::named("ping", "{}"), SseEvent::data("[DONE]")]
+        );
+    }
+
+    #[test]
+    fn test_parse_crlf_and_comments() {
+        let mut parser = SseParser::new();
+        let events = parser.push(b": keep-alive\r\n\r\ndata: hello\r\n\r\n");
+        assert_eq!(events, vec![SseEvent::data("hello")]);
+    }
+
+    #[test]
+    fn test_finish_flushes_trailing_event() {
+        let mut parser = SseParser::new();
+        assert!(parser.push(b"data: tail").is_empty());
+        assert_eq!(parser.finish(), Some(SseEvent::data("tail")));
+    }
+
+    #[test]
+    fn test_to_bytes_round_trip() {
+        let event = SseEvent::named("message_stop", "{\"type\":\"message_stop\"}");
+        let mut parser = SseParser::new();
+        assert_eq!(parser.push(&event.to_bytes()), vec![event]);
+    }
+}
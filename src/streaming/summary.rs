@@ -0,0 +1,114 @@
+use serde_json::Value;
+
+use super::{SseEvent, SseParser};
+use crate::types::TokenUsage;
+
+/// Accumulates the assistant text and token usage of an SSE response in either
+/// protocol, e.g. to record what a streamed response contained once it ends
+#[derive(Default)]
+pub struct StreamSummary {
+    parser: SseParser,
+    pub text: String,
+    pub usage: Option<TokenUsage>,
+}
+
+impl StreamSummary {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Feed raw stream bytes
+    pub fn push(&mut self, chunk: &[u8]) {
+        for event in self.parser.push(chunk) {
+            self.observe(&event);
+        }
+    }
+
+    /// Flush a trailing unterminated event
+    pub fn finish(&mut self) {
+        if let Some(event) = self.parser.finish() {
+            self.observe(&event);
+        }
+    }
+
+    pub fn observe(&mut self, event: &SseEvent) {
+        let Ok(data) = serde_json::from_str::<Value>(&event.data) else {
+            // `[DONE]` and keep-alives
+            return;
+        };
+
+        match data.get("type").and_then(Value::as_str) {
+            // Anthropic
+            Some("message_start") => {
+                if let Some(usage) = data.pointer("/message/usage").and_then(TokenUsage::from_usage) {
+                    self.usage = Some(usage);
+                }
+            }
+            Some("content_block_delta") => {
+                if let Some(text) = data.pointer("/delta/text").and_then(Value::as_str) {
+                    self.text.push_str(text);
+                }
+            }
+            Some("message_delta") => {
+                if let Some(output) = data.pointer("/usage/output_tokens").and_then(Value::as_u64) {
+                    self.usage.get_or_insert_with(TokenUsage::default).output_tokens = output;
+                }
+            }
+            Some(_) => {}
+            // OpenAI chunks carry no `type`
+            None => {
+                if let Some(text) = data.pointer("/choices/0/delta/content").and_then(Value::as_str) {
+                    self.text.push_str(text);
+                }
+                if let Some(usage) = data.get("usage").and_then(TokenUsage::from_usage) {
+                    self.usage = Some(usage);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_openai_stream() {
+        let mut summary = StreamSummary::new();
+        summary.push(b"data: {\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"He\"}}]}\n\n");
+        summary.push(b"data: {\"choices\":[{\"index\":0,\"delta\":{\"content\":\"llo\"}}]}\n\ndata: {\"choices\":[],");
+        summary.push(b"\"usage\":{\"prompt_tokens\":5,\"completion_tokens\":2}}\n\ndata: [DONE]\n\n");
+        summary.finish();
+        assert_eq!(summary.text, "Hello");
+        assert_eq!(
+            summary.usage,
+            Some(TokenUsage {
+                input_tokens: 5,
+                output_tokens: 2,
+                cached_input_tokens: 0,
+            })
+        );
+    }
+
+    #[test]
+    fn test_anthropic_stream() {
+        let mut summary = StreamSummary::new();
+        let events = [
+            SseEvent::named("message_start", r#"{"type":"message_start","message":{"usage":{"input_tokens":7,"output_tokens":1}}}"#),
+            SseEvent::named("content_block_delta", r#"{"type":"content_block_delta","index":0,"delta":{"type":"text_delta","text":"Hi"}}"#),
+            SseEvent::named("message_delta", r#"{"type":"message_delta","delta":{"stop_reason":"end_turn"},"usage":{"output_tokens":4}}"#),
+        ];
+        for event in &events {
+            summary.push(&event.to_bytes());
+        }
+        assert_eq!(summary.text, "Hi");
+        assert_eq!(
+            summary.usage,
+            Some(TokenUsage {
+                input_tokens: 7,
+                output_tokens: 4,
+                cached_input_tokens: 0,
+            })
+        );
+    }
+}
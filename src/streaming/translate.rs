@@ -0,0 +1,448 @@
+use super::sse::{SseEvent, SseParser};
+use crate::transform::protocol::{anthropic_stop_reason_to_openai, openai_finish_reason_to_anthropic};
+use crate::types::{Protocol, ProxyError};
+use bytes::Bytes;
+use futures::{Stream, StreamExt};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// Converts a stream of SSE events from one protocol's streaming format into another's
+pub enum StreamTranslator {
+    Passthrough,
+    AnthropicToOpenAi(AnthropicToOpenAi),
+    OpenAiToAnthropic(OpenAiToAnthropic),
+}
+
+impl StreamTranslator {
+    pub fn new(from: Protocol, to: Protocol) -> Self {
+        match (from, to) {
+            (Protocol::Anthropic, Protocol::OpenAI) => {
+                StreamTranslator::AnthropicToOpenAi(AnthropicToOpenAi::default())
+            }
+            (Protocol::OpenAI, Protocol::Anthropic) => {
+                StreamTranslator::OpenAiToAnthropic(OpenAiToAnthropic::default())
+            }
+            _ => StreamTranslator::Passthrough,
+        }
+    }
+
+    pub fn translate(&mut self, event: SseEvent) -> Vec<SseEvent> {
+        match self {
+            StreamTranslator::Passthrough => vec![event],
+            StreamTranslator::AnthropicToOpenAi(t) => t.translate(event),
+            StreamTranslator::OpenAiToAnthropic(t) => t.translate(event),
+        }
+    }
+}
+
+/// Re-frame an upstream SSE byte stream into the client protocol's streaming format
+pub fn translate_sse_stream<S, E>(
+    upstream: S,
+    from: Protocol,
+    to: Protocol,
+) -> impl Stream<Item = std::result::Result<Bytes, E>>
+where
+    S: Stream<Item = std::result::Result<Bytes, E>> + Send + 'static,
+    E: Send + 'static,
+{
+    async_stream::stream! {
+        let mut parser = SseParser::new();
+        let mut translator = StreamTranslator::new(from, to);
+        futures::pin_mut!(upstream);
+
+        while let Some(chunk) = upstream.next().await {
+            match chunk {
+                Ok(bytes) => {
+                    for event in parser.push(&bytes) {
+                        for out in translator.translate(event) {
+                            yield Ok(out.to_bytes());
+                        }
+                    }
+                }
+                Err(e) => {
+                    yield Err(e);
+                    return;
+                }
+            }
+        }
+
+        if let Some(event) = parser.finish() {
+            for out in translator.translate(event) {
+                yield Ok(out.to_bytes());
+            }
+        }
+    }
+}
+
+/// An error event in the client protocol's streaming format, for ending a stream early
+pub fn stream_error_event(error: &ProxyError, protocol: Protocol) -> SseEvent {
+    let body = error.protocol_body(protocol).to_string();
+    match protocol {
+        Protocol::OpenAI => SseEvent::data(body),
+        Protocol::Anthropic => SseEvent::named("error", body),
+    }
+}
+
+#[derive(Default)]
+pub struct AnthropicToOpenAi {
+    id: String,
+    model: String,
+    created: i64,
+    input_tokens: u64,
+    /// Anthropic content block index -> OpenAI tool call index
+    tool_indices: HashMap<u64, u64>,
+}
+
+impl AnthropicToOpenAi {
+    fn chunk_value(&self, delta: Value, finish_reason: Option<&str>) -> Value {
+        json!({
+            "id": self.id,
+            "object": "chat.completion.chunk",
+            "created": self.created,
+            "model": self.model,
+            "choices": [{
+                "index": 0,
+                "delta": delta,
+                "finish_reason": finish_reason,
+            }]
+        })
+    }
+
+    fn chunk(&self, delta: Value, finish_reason: Option<&str>) -> SseEvent {
+        SseEvent::data(self.chunk_value(delta, finish_reason).to_string())
+    }
+
+    fn translate(&mut self, event: SseEvent) -> Vec<SseEvent> {
+        let Ok(data) = serde_json::from_str::<Value>(&event.data) else {
+            return Vec::new();
+        };
+
+        match data.get("type").and_then(Value::as_str) {
+            Some("message_start") => {
+                let message = &data["message"];
+                self.id = message["id"].as_str().unwrap_or_default().to_string();
+                self.model = message["model"].as_str().unwrap_or_default().to_string();
+                self.created = chrono::Utc::now().timestamp();
+                self.input_tokens = message.pointer("/usage/input_tokens").and_then(Value::as_u64).unwrap_or(0);
+                vec![self.chunk(json!({"role": "assistant", "content": ""}), None)]
+            }
+            Some("content_block_start") => {
+                let block = &data["content_block"];
+                if block["type"] != "tool_use" {
+                    return Vec::new();
+                }
+                let tool_index = self.tool_indices.len() as u64;
+                self.tool_indices.insert(data["index"].as_u64().unwrap_or(0), tool_index);
+                vec![self.chunk(
+                    json!({"tool_calls": [{
+                        "index": tool_index,
+                        "id": block["id"],
+                        "type": "function",
+                        "function": {"name": block["name"], "arguments": ""}
+                    }]}),
+                    None,
+                )]
+            }
+            Some("content_block_delta") => {
+                let delta = &data["delta"];
+                match delta["type"].as_str() {
+                    Some("text_delta") => vec![self.chunk(json!({"content": delta["text"]}), None)],
+                    Some("input_json_delta") => {
+                        let index = data["index"].as_u64().unwrap_or(0);
+                        let tool_index = self.tool_indices.get(&index).copied().unwrap_or(0);
+                        vec![self.chunk(
+                            json!({"tool_calls": [{
+                                "index": tool_index,
+                                "function": {"arguments": delta["partial_json"]}
+                            }]}),
+                            None,
+                        )]
+                    }
+                    _ => Vec::new(),
+                }
+            }
+            Some("message_delta") => {
+                let reason = data
+                    .pointer("/delta/stop_reason")
+                    .and_then(Value::as_str)
+                    .map(anthropic_stop_reason_to_openai)
+                    .unwrap_or("stop");
+                let output_tokens = data.pointer("/usage/output_tokens").and_then(Value::as_u64).unwrap_or(0);
+
+                let mut chunk = self.chunk_value(json!({}), Some(reason));
+                chunk["usage"] = json!({
+                    "prompt_tokens": self.input_tokens,
+                    "completion_tokens": output_tokens,
+                    "total_tokens": self.input_tokens + output_tokens,
+                });
+                vec![SseEvent::data(chunk.to_string())]
+            }
+            Some("message_stop") => vec![SseEvent::data("[DONE]")],
+            Some("error") => vec![SseEvent::data(json!({"error": data["error"]}).to_string())],
+            _ => Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum OpenBlock {
+    Text,
+    Tool(u64),
+}
+
+#[derive(Default)]
+pub struct OpenAiToAnthropic {
+    started: bool,
+    finished: bool,
+    open_block: Option<OpenBlock>,
+    block_index: u64,
+    stop_reason: Option<&'static str>,
+    input_tokens: u64,
+    output_tokens: u64,
+}
+
+impl OpenAiToAnthropic {
+    fn event(kind: &str, data: Value) -> SseEvent {
+        SseEvent::named(kind, data.to_string())
+    }
+
+    fn close_block(&mut self, out: &mut Vec<SseEvent>) {
+        if self.open_block.take().is_some() {
+            out.push(Self::event(
+                "content_block_stop",
+                json!({"type": "content_block_stop", "index": self.block_index}),
+            ));
+            self.block_index += 1;
+        }
+    }
+
+    fn finish(&mut self, out: &mut Vec<SseEvent>) {
+        if self.finished || !self.started {
+            return;
+        }
+        self.finished = true;
+        self.close_block(out);
+        out.push(Self::event(
+            "message_delta",
+            json!({
+                "type": "message_delta",
+                "delta": {"stop_reason": self.stop_reason.unwrap_or("end_turn"), "stop_sequence": null},
+                "usage": {"input_tokens": self.input_tokens, "output_tokens": self.output_tokens}
+            }),
+        ));
+        out.push(Self::event("message_stop", json!({"type": "message_stop"})));
+    }
+
+    fn translate(&mut self, event: SseEvent) -> Vec<SseEvent> {
+        let mut out = Vec::new();
+
+        if event.data.trim() == "[DONE]" {
+            self.finish(&mut out);
+            return out;
+        }
+
+        let Ok(chunk) = serde_json::from_str::<Value>(&event.data) else {
+            return out;
+        };
+
+        if let Some(error) = chunk.get("error") {
+            out.push(Self::event(
+                "error",
+                json!({"type": "error", "error": {"type": "api_error", "message": error["message"]}}),
+            ));
+            return out;
+        }
+
+        if !self.started {
+            self.started = true;
+            out.push(Self::event(
+                "message_start",
+                json!({
+                    "type": "message_start",
+                    "message": {
+                        "id": chunk["id"],
+                        "type": "message",
+                        "role": "assistant",
+                        "content": [],
+                        "model": chunk["model"],
+                        "stop_reason": null,
+                        "stop_sequence": null,
+                        "usage": {"input_tokens": 0, "output_tokens": 0}
+                    }
+                }),
+            ));
+        }
+
+        if let Some(usage) = chunk.get("usage").filter(|u| !u.is_null()) {
+            self.input_tokens = usage["prompt_tokens"].as_u64().unwrap_or(self.input_tokens);
+            self.output_tokens = usage["completion_tokens"].as_u64().unwrap_or(self.output_tokens);
+        }
+
+        let choice = &chunk["choices"][0];
+        let delta = &choice["delta"];
+
+        if let Some(text) = delta["content"].as_str().filter(|t| !t.is_empty()) {
+            if self.open_block != Some(OpenBlock::Text) {
+                self.close_block(&mut out);
+                self.open_block = Some(OpenBlock::Text);
+                out.push(Self::event(
+                    "content_block_start",
+                    json!({
+                        "type": "content_block_start",
+                        "index": self.block_index,
+                        "content_block": {"type": "text", "text": ""}
+                    }),
+                ));
+            }
+            out.push(Self::event(
+                "content_block_delta",
+                json!({
+                    "type": "content_block_delta",
+                    "index": self.block_index,
+                    "delta": {"type": "text_delta", "text": text}
+                }),
+            ));
+        }
+
+        for call in delta["tool_calls"].as_array().into_iter().flatten() {
+            let tool_index = call["index"].as_u64().unwrap_or(0);
+            if self.open_block != Some(OpenBlock::Tool(tool_index)) {
+                self.close_block(&mut out);
+                self.open_block = Some(OpenBlock::Tool(tool_index));
+                out.push(Self::event(
+                    "content_block_start",
+                    json!({
+                        "type": "content_block_start",
+                        "index": self.block_index,
+                        "content_block": {
+                            "type": "tool_use",
+                            "id": call["id"],
+                            "name": call.pointer("/function/name").cloned().unwrap_or(Value::Null),
+                            "input": {}
+                        }
+                    }),
+                ));
+            }
+            if let Some(arguments) = call.pointer("/function/arguments").and_then(Value::as_str) {
+                if !arguments.is_empty() {
+                    out.push(Self::event(
+                        "content_block_delta",
+                        json!({
+                            "type": "content_block_delta",
+                            "index": self.block_index,
+                            "delta": {"type": "input_json_delta", "partial_json": arguments}
+                        }),
+                    ));
+                }
+            }
+        }
+
+        if let Some(reason) = choice["finish_reason"].as_str() {
+            self.stop_reason = Some(openai_finish_reason_to_anthropic(reason));
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data_of(events: &[SseEvent]) -> Vec<Value> {
+        events
+            .iter()
+            .filter(|e| e.data != "[DONE]")
+            .map(|e| serde_json::from_str(&e.data).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_anthropic_to_openai_text_stream() {
+        let mut translator = StreamTranslator::new(Protocol::Anthropic, Protocol::OpenAI);
+        let mut out = Vec::new();
+        for data in [
+            json!({"type": "message_start", "message": {"id": "msg_1", "model": "claude-3", "usage": {"input_tokens": 5}}}),
+            json!({"type": "content_block_start", "index": 0, "content_block": {"type": "text", "text": ""}}),
+            json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": "Hi"}}),
+            json!({"type": "message_delta", "delta": {"stop_reason": "end_turn"}, "usage": {"output_tokens": 1}}),
+            json!({"type": "message_stop"}),
+        ] {
+            out.extend(translator.translate(SseEvent::data(data.to_string())));
+        }
+
+        assert_eq!(out.last().unwrap().data, "[DONE]");
+        let chunks = data_of(&out);
+        assert_eq!(chunks[0]["choices"][0]["delta"]["role"], "assistant");
+        assert_eq!(chunks[1]["choices"][0]["delta"]["content"], "Hi");
+        assert_eq!(chunks[2]["choices"][0]["finish_reason"], "stop");
+        assert_eq!(chunks[2]["usage"]["total_tokens"], 6);
+    }
+
+    #[test]
+    fn test_openai_to_anthropic_text_stream() {
+        let mut translator = StreamTranslator::new(Protocol::OpenAI, Protocol::Anthropic);
+        let mut out = Vec::new();
+        for data in [
+            json!({"id": "c1", "model": "gpt-4", "choices": [{"index": 0, "delta": {"role": "assistant", "content": ""}}]}).to_string(),
+            json!({"id": "c1", "model": "gpt-4", "choices": [{"index": 0, "delta": {"content": "Hi"}}]}).to_string(),
+            json!({"id": "c1", "model": "gpt-4", "choices": [{"index": 0, "delta": {}, "finish_reason": "stop"}]}).to_string(),
+            "[DONE]".to_string(),
+        ] {
+            out.extend(translator.translate(SseEvent::data(data)));
+        }
+
+        let kinds: Vec<_> = out.iter().map(|e| e.event.clone().unwrap()).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                "message_start",
+                "content_block_start",
+                "content_block_delta",
+                "content_block_stop",
+                "message_delta",
+                "message_stop"
+            ]
+        );
+        let events = data_of(&out);
+        assert_eq!(events[2]["delta"]["text"], "Hi");
+        assert_eq!(events[4]["delta"]["stop_reason"], "end_turn");
+    }
+
+    #[test]
+    fn test_openai_to_anthropic_tool_stream() {
+        let mut translator = StreamTranslator::new(Protocol::OpenAI, Protocol::Anthropic);
+        let mut out = Vec::new();
+        for data in [
+            json!({"id": "c1", "model": "gpt-4", "choices": [{"index": 0, "delta": {"tool_calls": [
+                {"index": 0, "id": "call_1", "type": "function", "function": {"name": "lookup", "arguments": ""}}
+            ]}}]}),
+            json!({"id": "c1", "model": "gpt-4", "choices": [{"index": 0, "delta": {"tool_calls": [
+                {"index": 0, "function": {"arguments": "{\"q\":1}"}}
+            ]}, "finish_reason": "tool_calls"}]}),
+        ] {
+            out.extend(translator.translate(SseEvent::data(data.to_string())));
+        }
+        out.extend(translator.translate(SseEvent::data("[DONE]")));
+
+        let events = data_of(&out);
+        assert_eq!(events[1]["content_block"]["type"], "tool_use");
+        assert_eq!(events[1]["content_block"]["name"], "lookup");
+        assert_eq!(events[2]["delta"]["partial_json"], "{\"q\":1}");
+        assert_eq!(events[4]["delta"]["stop_reason"], "tool_use");
+    }
+
+    #[tokio::test]
+    async fn test_translate_sse_stream() {
+        let upstream = futures::stream::iter(vec![
+            Ok::<_, std::io::Error>(Bytes::from("data: {\"type\":\"message_start\",\"message\":{\"id\":\"m\",\"model\":\"c\"}}\n\n")),
+            Ok(Bytes::from("data: {\"type\":\"message_stop\"}\n\n")),
+        ]);
+
+        let out: Vec<_> = translate_sse_stream(upstream, Protocol::Anthropic, Protocol::OpenAI)
+            .collect()
+            .await;
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[1].as_ref().unwrap(), &Bytes::from("data: [DONE]\n\n"));
+    }
+}
@@ -0,0 +1,112 @@
+use regex::{Captures, Regex};
+use serde_json::Value;
+use std::sync::LazyLock;
+
+/// `{{jwt.<claim>}}`, where `<claim>` may be a dotted path into nested claims
+static PLACEHOLDER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\{\{\s*jwt\.([^{}\s]+)\s*\}\}").expect("valid placeholder regex"));
+
+/// Look up a claim by name or by dotted path (`realm_access.roles`)
+pub fn claim<'a>(claims: &'a Value, path: &str) -> Option<&'a Value> {
+    // Claim names may themselves contain dots (URL-style namespaced claims)
+    if let Some(value) = claims.get(path) {
+        return Some(value);
+    }
+    path.split('.').try_fold(claims, |value, key| value.get(key))
+}
+
+/// A claim as text: strings as they are, lists joined with commas
+fn claim_text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Array(items) => items.iter().map(claim_text).collect::<Vec<_>>().join(","),
+        Value::Null => String::new(),
+        other => other.to_string(),
+    }
+}
+
+/// Fill in `{{jwt.<claim>}}` placeholders with the request's token claims. Without
+/// claims (or without the claim) a placeholder becomes empty.
+pub fn render_claims(text: &str, claims: Option<&Value>) -> String {
+    if !text.contains("{{") {
+        return text.to_string();
+    }
+    PLACEHOLDER
+        .replace_all(text, |caps: &Captures| {
+            claims
+                .and_then(|claims| claim(claims, &caps[1]))
+                .map(claim_text)
+                .unwrap_or_default()
+        })
+        .into_owned()
+}
+
+/// Fill in placeholders in the strings of a JSON value. A string that is only a
+/// placeholder takes the claim's JSON value, so lists stay lists.
+pub fn render_claims_value(value: &Value, claims: Option<&Value>) -> Value {
+    match value {
+        Value::String(text) => {
+            let whole = PLACEHOLDER
+                .captures(text)
+                .filter(|caps| caps[0].len() == text.len());
+            match whole {
+                Some(caps) => claims
+                    .and_then(|claims| claim(claims, &caps[1]))
+                    .cloned()
+                    .unwrap_or(Value::Null),
+                None => Value::String(render_claims(text, claims)),
+            }
+        }
+        Value::Array(items) => Value::Array(items.iter().map(|item| render_claims_value(item, claims)).collect()),
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, value)| (key.clone(), render_claims_value(value, claims)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn claims() -> Value {
+        json!({
+            "sub": "svc-search",
+            "groups": ["search", "ml"],
+            "realm_access": {"roles": ["reader"]},
+            "https://example.com/tenant": "acme"
+        })
+    }
+
+    #[test]
+    fn test_claim_paths() {
+        let claims = claims();
+        assert_eq!(claim(&claims, "sub"), Some(&json!("svc-search")));
+        assert_eq!(claim(&claims, "realm_access.roles"), Some(&json!(["reader"])));
+        assert_eq!(claim(&claims, "https://example.com/tenant"), Some(&json!("acme")));
+        assert_eq!(claim(&claims, "missing.path"), None);
+    }
+
+    #[test]
+    fn test_render_text() {
+        let claims = claims();
+        assert_eq!(render_claims("user={{jwt.sub}}", Some(&claims)), "user=svc-search");
+        assert_eq!(render_claims("{{ jwt.groups }}", Some(&claims)), "search,ml");
+        assert_eq!(render_claims("x{{jwt.missing}}y", Some(&claims)), "xy");
+        assert_eq!(render_claims("{{jwt.sub}}", None), "");
+        assert_eq!(render_claims("plain", None), "plain");
+    }
+
+    #[test]
+    fn test_render_json() {
+        let claims = claims();
+        let template = json!({"user": "{{jwt.sub}}", "groups": "{{jwt.groups}}", "note": "by {{jwt.sub}}", "n": 1});
+        assert_eq!(
+            render_claims_value(&template, Some(&claims)),
+            json!({"user": "svc-search", "groups": ["search", "ml"], "note": "by svc-search", "n": 1})
+        );
+    }
+}
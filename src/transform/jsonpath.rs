@@ -1,5 +1,5 @@
 use crate::config::Transform;
-use crate::types::{ProxyError, Result};
+use crate::types::Result;
 use serde_json::Value;
 
 pub struct JsonPathTransformer {
@@ -1,9 +1,15 @@
+pub mod claims;
 pub mod headers;
 pub mod regex;
 pub mod jsonpath;
 pub mod model;
+pub mod pii;
+pub mod protocol;
 
+pub use claims::{claim, render_claims, render_claims_value};
 pub use headers::apply_header_transforms;
 pub use regex::{RegexTransformer, RegexTransformCache};
 pub use jsonpath::JsonPathTransformer;
 pub use model::rewrite_model_field;
+pub use pii::{PiiRedactor, PiiVault};
+pub use protocol::{translate_request, translate_response};
@@ -0,0 +1,400 @@
+use crate::config::{PiiConfig, PiiDetector};
+use crate::types::{ProxyError, Result};
+use regex::{Captures, Regex};
+use serde_json::Value;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::sync::{LazyLock, Mutex};
+
+static EMAIL: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?i)\b[a-z0-9._%+-]+@[a-z0-9-]+(?:\.[a-z0-9-]+)*\.[a-z]{2,}\b").expect("valid email regex")
+});
+/// Digit groups separated by spaces, dots or dashes, with an optional country code
+/// and area code in parentheses
+static PHONE: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"(?:\+\d{1,3}[ .-]?)?(?:\(\d{1,4}\)[ .-]?)?\b\d{2,4}(?:[ .-]\d{2,4}){1,4}\b")
+        .expect("valid phone regex")
+});
+static CREDIT_CARD: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\b\d(?:[ -]?\d){12,18}\b").expect("valid credit card regex"));
+/// Compact or printed in groups of four
+static IBAN: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r"\b[A-Z]{2}\d{2}(?: ?[A-Z0-9]{4}){2,7}(?: ?[A-Z0-9]{1,3})?\b").expect("valid IBAN regex")
+});
+static IPV4: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\b(?:\d{1,3}\.){3}\d{1,3}\b").expect("valid IPv4 regex"));
+static IPV6: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?i)(?:[0-9a-f]{0,4}:){2,7}[0-9a-f]{0,4}").expect("valid IPv6 regex"));
+/// A placeholder handed out by a [`PiiVault`]
+static PLACEHOLDER: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"\[[A-Z0-9_]+_\d+\]").expect("valid placeholder regex"));
+
+/// Longest text a stream holds back because it may be the start of a placeholder
+const MAX_PLACEHOLDER_LEN: usize = 64;
+
+struct Detector {
+    label: String,
+    regex: Regex,
+    /// Rejects matches that only look like the kind of value, e.g. failing a checksum
+    check: fn(&str) -> bool,
+    /// Replace only the first capture group rather than the whole match
+    group: bool,
+    /// Reject matches that run into neighbouring letters, digits or `:`
+    bounded: bool,
+}
+
+/// Finds personal data in text and replaces it with placeholders from a [`PiiVault`]
+pub struct PiiRedactor {
+    detectors: Vec<Detector>,
+}
+
+impl PiiRedactor {
+    pub fn new(config: &PiiConfig) -> Result<Self> {
+        let mut detectors = Vec::new();
+        for detector in &config.detectors {
+            let builtin = |regex: &Regex, check: fn(&str) -> bool| Detector {
+                label: detector.label().to_string(),
+                regex: regex.clone(),
+                check,
+                group: false,
+                bounded: true,
+            };
+            match detector {
+                PiiDetector::Email => detectors.push(builtin(&EMAIL, |_| true)),
+                PiiDetector::Phone => detectors.push(builtin(&PHONE, phone_valid)),
+                PiiDetector::CreditCard => detectors.push(builtin(&CREDIT_CARD, luhn_valid)),
+                PiiDetector::Iban => detectors.push(builtin(&IBAN, iban_valid)),
+                PiiDetector::IpAddress => {
+                    detectors.push(builtin(&IPV4, |ip| ip.parse::<Ipv4Addr>().is_ok()));
+                    detectors.push(builtin(&IPV6, ipv6_valid));
+                }
+            }
+        }
+        for custom in &config.custom {
+            let regex = Regex::new(&custom.pattern).map_err(ProxyError::Regex)?;
+            detectors.push(Detector {
+                label: custom.label(),
+                group: regex.captures_len() > 1,
+                regex,
+                check: |_| true,
+                bounded: false,
+            });
+        }
+        Ok(Self { detectors })
+    }
+
+    /// Replace every detected value in `text`. Where detections overlap, the one
+    /// starting first (then the longest, then the first configured) wins.
+    pub fn redact(&self, text: &str, vault: &PiiVault) -> String {
+        let mut found = Vec::new();
+        for detector in &self.detectors {
+            for caps in detector.regex.captures_iter(text) {
+                let Some(m) = caps.get(if detector.group { 1 } else { 0 }) else {
+                    continue;
+                };
+                if (detector.check)(m.as_str()) && (!detector.bounded || is_bounded(text, m.start(), m.end())) {
+                    found.push((m.start(), m.end(), detector.label.as_str()));
+                }
+            }
+        }
+        if found.is_empty() {
+            return text.to_string();
+        }
+        found.sort_by_key(|&(start, end, _)| (start, Reverse(end)));
+
+        let mut out = String::with_capacity(text.len());
+        let mut pos = 0;
+        for (start, end, label) in found {
+            if start < pos {
+                continue;
+            }
+            out.push_str(&text[pos..start]);
+            out.push_str(&vault.placeholder(label, &text[start..end]));
+            pos = end;
+        }
+        out.push_str(&text[pos..]);
+        out
+    }
+
+    /// Redact the message content and system prompt of a request in either protocol,
+    /// including text parts and tool results
+    pub fn redact_request(&self, request: &mut Value, vault: &PiiVault) {
+        if let Some(messages) = request.get_mut("messages").and_then(Value::as_array_mut) {
+            for message in messages {
+                if let Some(content) = message.get_mut("content") {
+                    self.redact_content(content, vault);
+                }
+            }
+        }
+        if let Some(system) = request.get_mut("system") {
+            self.redact_content(system, vault);
+        }
+    }
+
+    /// Redact every string in a JSON value, such as a response body kept for a log
+    pub fn redact_value(&self, value: &mut Value, vault: &PiiVault) {
+        match value {
+            Value::String(text) => *text = self.redact(text, vault),
+            Value::Array(items) => items.iter_mut().for_each(|item| self.redact_value(item, vault)),
+            Value::Object(fields) => fields.values_mut().for_each(|field| self.redact_value(field, vault)),
+            _ => {}
+        }
+    }
+
+    fn redact_content(&self, content: &mut Value, vault: &PiiVault) {
+        match content {
+            Value::String(text) => *text = self.redact(text, vault),
+            Value::Array(parts) => {
+                for part in parts {
+                    self.redact_content(part, vault);
+                }
+            }
+            Value::Object(part) => {
+                if let Some(Value::String(text)) = part.get_mut("text") {
+                    *text = self.redact(text, vault);
+                }
+                if let Some(content) = part.get_mut("content") {
+                    self.redact_content(content, vault);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+fn is_bounded(text: &str, start: usize, end: usize) -> bool {
+    let joined = |c: char| c.is_alphanumeric() || c == '_' || c == ':';
+    !text[..start].chars().next_back().is_some_and(joined) && !text[end..].chars().next().is_some_and(joined)
+}
+
+fn digits(value: &str) -> Vec<u32> {
+    value.chars().filter_map(|c| c.to_digit(10)).collect()
+}
+
+/// Separated digit groups pass for a phone number with 10 to 15 digits, or 7 and more
+/// when written with a country or area code
+fn phone_valid(value: &str) -> bool {
+    let count = digits(value).len();
+    let prefixed = value.starts_with('+') || value.starts_with('(');
+    count <= 15 && (count >= 10 || (prefixed && count >= 7))
+}
+
+fn luhn_valid(value: &str) -> bool {
+    let digits = digits(value);
+    if !(13..=19).contains(&digits.len()) {
+        return false;
+    }
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| match i % 2 {
+            1 if d * 2 > 9 => d * 2 - 9,
+            1 => d * 2,
+            _ => d,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/// ISO 13616 check: the country code and check digits moved to the end, letters as
+/// numbers from 10, leaves 1 modulo 97
+fn iban_valid(value: &str) -> bool {
+    let compact: String = value.chars().filter(|c| !c.is_whitespace()).collect();
+    if !(15..=34).contains(&compact.len()) {
+        return false;
+    }
+    let (head, tail) = compact.split_at(4);
+    tail.chars()
+        .chain(head.chars())
+        .try_fold(0u32, |rem, c| {
+            let n = c.to_digit(36)?;
+            Some(if n < 10 { (rem * 10 + n) % 97 } else { (rem * 100 + n) % 97 })
+        })
+        == Some(1)
+}
+
+/// Requires two non-empty groups, so `a::` in `a::b` paths is not an address
+fn ipv6_valid(value: &str) -> bool {
+    value.split(':').filter(|group| !group.is_empty()).count() >= 2 && value.parse::<Ipv6Addr>().is_ok()
+}
+
+/// The placeholders handed out for one request's personal data and the values they
+/// stand for. The same value always gets the same placeholder, across the request's
+/// messages and the models it is sent to.
+#[derive(Debug, Default)]
+pub struct PiiVault {
+    entries: Mutex<VaultEntries>,
+}
+
+#[derive(Debug, Default)]
+struct VaultEntries {
+    placeholders: HashMap<String, String>,
+    values: HashMap<String, String>,
+    counts: HashMap<String, usize>,
+}
+
+impl PiiVault {
+    fn placeholder(&self, label: &str, value: &str) -> String {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(placeholder) = entries.placeholders.get(value) {
+            return placeholder.clone();
+        }
+        let count = entries.counts.entry(label.to_string()).or_default();
+        *count += 1;
+        let placeholder = format!("[{}_{}]", label, count);
+        entries.placeholders.insert(value.to_string(), placeholder.clone());
+        entries.values.insert(placeholder.clone(), value.to_string());
+        placeholder
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.lock().unwrap().values.is_empty()
+    }
+
+    /// Put the original values back in place of this vault's placeholders
+    pub fn restore(&self, text: &str) -> String {
+        if !text.contains('[') {
+            return text.to_string();
+        }
+        let entries = self.entries.lock().unwrap();
+        PLACEHOLDER
+            .replace_all(text, |caps: &Captures| {
+                entries.values.get(&caps[0]).cloned().unwrap_or_else(|| caps[0].to_string())
+            })
+            .into_owned()
+    }
+
+    /// Restore placeholders in every string of a JSON value
+    pub fn restore_value(&self, value: Value) -> Value {
+        match value {
+            Value::String(text) => Value::String(self.restore(&text)),
+            Value::Array(items) => Value::Array(items.into_iter().map(|item| self.restore_value(item)).collect()),
+            Value::Object(map) => Value::Object(
+                map.into_iter()
+                    .map(|(key, value)| (key, self.restore_value(value)))
+                    .collect(),
+            ),
+            other => other,
+        }
+    }
+}
+
+/// Length of the end of `text` that could still grow into a placeholder with the
+/// next chunk of a stream
+pub fn partial_placeholder_len(text: &str) -> usize {
+    let Some(start) = text.rfind('[') else {
+        return 0;
+    };
+    let tail = &text[start..];
+    let partial = tail.len() < MAX_PLACEHOLDER_LEN
+        && tail[1..].chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_');
+    if partial {
+        tail.len()
+    } else {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::CustomPiiDetector;
+    use serde_json::json;
+
+    fn redactor(custom: Vec<CustomPiiDetector>) -> PiiRedactor {
+        PiiRedactor::new(&PiiConfig {
+            detectors: PiiDetector::ALL.to_vec(),
+            custom,
+            restore: true,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_builtin_detectors() {
+        let redactor = redactor(Vec::new());
+        let vault = PiiVault::default();
+        let text = "Mail jane.doe@example.com or call +44 20 7946 0958 / (555) 123-4567. \
+                    Card 4111 1111 1111 1111, IBAN DE89 3704 0044 0532 0130 00, \
+                    from 192.168.1.20 and 2001:db8::1.";
+        assert_eq!(
+            redactor.redact(text, &vault),
+            "Mail [EMAIL_1] or call [PHONE_1] / [PHONE_2]. \
+             Card [CREDIT_CARD_1], IBAN [IBAN_1], \
+             from [IP_ADDRESS_1] and [IP_ADDRESS_2]."
+        );
+        assert_eq!(vault.restore(&redactor.redact(text, &vault)), text);
+    }
+
+    #[test]
+    fn test_lookalikes_are_kept() {
+        let redactor = redactor(Vec::new());
+        let vault = PiiVault::default();
+        let text = "Card 4111 1111 1111 1112, IBAN DE00 3704 0044 0532 0130 00, on 2024-01-15 \
+                    at 10:30:00, version 1.2.3.4567, std::sync::Mutex, order 1234567890123";
+        assert_eq!(redactor.redact(text, &vault), text);
+        assert!(vault.is_empty());
+    }
+
+    #[test]
+    fn test_custom_detectors_and_stable_placeholders() {
+        let redactor = redactor(vec![
+            CustomPiiDetector {
+                name: "employee_id".to_string(),
+                pattern: r"EMP-\d{6}".to_string(),
+            },
+            CustomPiiDetector {
+                name: "secret".to_string(),
+                pattern: r"(?i)password:\s*(\S+)".to_string(),
+            },
+        ]);
+        let vault = PiiVault::default();
+        assert_eq!(
+            redactor.redact("EMP-123456 (a@b.io) password: hunter2", &vault),
+            "[EMPLOYEE_ID_1] ([EMAIL_1]) password: [SECRET_1]"
+        );
+        // The same value keeps its placeholder within the request
+        assert_eq!(redactor.redact("a@b.io, c@d.io, a@b.io", &vault), "[EMAIL_1], [EMAIL_2], [EMAIL_1]");
+    }
+
+    #[test]
+    fn test_redact_request_content_only() {
+        let redactor = redactor(Vec::new());
+        let vault = PiiVault::default();
+        let mut request = json!({
+            "model": "gpt-4",
+            "system": [{"type": "text", "text": "Owner: ops@example.com"}],
+            "messages": [
+                {"role": "user", "content": "I am jane@example.com"},
+                {"role": "user", "content": [
+                    {"type": "text", "text": "and jane@example.com again"},
+                    {"type": "tool_result", "tool_use_id": "t1", "content": "ip 10.0.0.8"}
+                ]}
+            ],
+            "metadata": {"user_id": "jane@example.com"}
+        });
+        redactor.redact_request(&mut request, &vault);
+        assert_eq!(request["messages"][0]["content"], "I am [EMAIL_1]");
+        assert_eq!(request["messages"][1]["content"][0]["text"], "and [EMAIL_1] again");
+        assert_eq!(request["system"][0]["text"], "Owner: [EMAIL_2]");
+        assert_eq!(request["messages"][1]["content"][1]["content"], "ip [IP_ADDRESS_1]");
+        assert_eq!(request["metadata"]["user_id"], "jane@example.com");
+
+        let response = json!({"content": [{"type": "text", "text": "Hi [EMAIL_1], see [EMAIL_9]"}]});
+        assert_eq!(
+            vault.restore_value(response),
+            json!({"content": [{"type": "text", "text": "Hi jane@example.com, see [EMAIL_9]"}]})
+        );
+    }
+
+    #[test]
+    fn test_partial_placeholder_len() {
+        assert_eq!(partial_placeholder_len("Hi [EMA"), 4);
+        assert_eq!(partial_placeholder_len("Hi ["), 1);
+        assert_eq!(partial_placeholder_len("Hi [EMAIL_1]"), 0);
+        assert_eq!(partial_placeholder_len("a [link](x"), 0);
+        assert_eq!(partial_placeholder_len("plain"), 0);
+    }
+}
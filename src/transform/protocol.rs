@@ -0,0 +1,601 @@
+use crate::types::{Protocol, ProxyError, Result};
+use serde_json::{json, Map, Value};
+
+/// Anthropic requires `max_tokens`; OpenAI requests that omit it get this default
+const DEFAULT_ANTHROPIC_MAX_TOKENS: u64 = 4096;
+
+/// Translate a request body from the client protocol to the backend protocol
+pub fn translate_request(body: Value, from: Protocol, to: Protocol) -> Result<Value> {
+    match (from, to) {
+        (Protocol::OpenAI, Protocol::Anthropic) => openai_to_anthropic_request(&body),
+        (Protocol::Anthropic, Protocol::OpenAI) => anthropic_to_openai_request(&body),
+        _ => Ok(body),
+    }
+}
+
+/// Translate a non-streaming response body from the backend protocol to the client protocol
+pub fn translate_response(body: Value, from: Protocol, to: Protocol) -> Value {
+    match (from, to) {
+        (Protocol::Anthropic, Protocol::OpenAI) => anthropic_to_openai_response(&body),
+        (Protocol::OpenAI, Protocol::Anthropic) => openai_to_anthropic_response(&body),
+        _ => body,
+    }
+}
+
+pub fn openai_to_anthropic_request(body: &Value) -> Result<Value> {
+    let obj = body
+        .as_object()
+        .ok_or_else(|| ProxyError::InvalidRequest("Request body must be an object".to_string()))?;
+
+    let mut out = Map::new();
+    copy_field(obj, &mut out, "model", "model");
+    out.insert(
+        "max_tokens".to_string(),
+        obj.get("max_tokens")
+            .or_else(|| obj.get("max_completion_tokens"))
+            .cloned()
+            .unwrap_or_else(|| json!(DEFAULT_ANTHROPIC_MAX_TOKENS)),
+    );
+    copy_field(obj, &mut out, "temperature", "temperature");
+    copy_field(obj, &mut out, "top_p", "top_p");
+    copy_field(obj, &mut out, "stream", "stream");
+
+    match obj.get("stop") {
+        Some(Value::String(stop)) => {
+            out.insert("stop_sequences".to_string(), json!([stop]));
+        }
+        Some(stop @ Value::Array(_)) => {
+            out.insert("stop_sequences".to_string(), stop.clone());
+        }
+        _ => {}
+    }
+
+    if let Some(user) = obj.get("user") {
+        out.insert("metadata".to_string(), json!({ "user_id": user }));
+    }
+
+    let mut system_parts = Vec::new();
+    let mut messages: Vec<Value> = Vec::new();
+
+    for message in obj.get("messages").and_then(Value::as_array).into_iter().flatten() {
+        let role = message.get("role").and_then(Value::as_str).unwrap_or("user");
+        match role {
+            "system" | "developer" => {
+                system_parts.push(text_of_openai_content(message.get("content")));
+            }
+            "tool" => {
+                let block = json!({
+                    "type": "tool_result",
+                    "tool_use_id": message.get("tool_call_id").cloned().unwrap_or(Value::Null),
+                    "content": text_of_openai_content(message.get("content")),
+                });
+                push_anthropic_message(&mut messages, "user", vec![block]);
+            }
+            "assistant" => {
+                let mut blocks = openai_content_to_blocks(message.get("content"));
+                for call in message.get("tool_calls").and_then(Value::as_array).into_iter().flatten() {
+                    let arguments = call
+                        .pointer("/function/arguments")
+                        .and_then(Value::as_str)
+                        .unwrap_or("{}");
+                    blocks.push(json!({
+                        "type": "tool_use",
+                        "id": call.get("id").cloned().unwrap_or(Value::Null),
+                        "name": call.pointer("/function/name").cloned().unwrap_or(Value::Null),
+                        "input": serde_json::from_str::<Value>(arguments).unwrap_or_else(|_| json!({})),
+                    }));
+                }
+                push_anthropic_message(&mut messages, "assistant", blocks);
+            }
+            _ => {
+                let blocks = openai_content_to_blocks(message.get("content"));
+                push_anthropic_message(&mut messages, "user", blocks);
+            }
+        }
+    }
+
+    if !system_parts.is_empty() {
+        out.insert("system".to_string(), Value::String(system_parts.join("\n\n")));
+    }
+    out.insert("messages".to_string(), Value::Array(messages));
+
+    if let Some(tools) = obj.get("tools").and_then(Value::as_array) {
+        let tools: Vec<Value> = tools
+            .iter()
+            .filter_map(|tool| tool.get("function").and_then(Value::as_object))
+            .map(|function| {
+                let mut tool = Map::new();
+                copy_field(function, &mut tool, "name", "name");
+                copy_field(function, &mut tool, "description", "description");
+                tool.insert(
+                    "input_schema".to_string(),
+                    function
+                        .get("parameters")
+                        .cloned()
+                        .unwrap_or_else(|| json!({"type": "object", "properties": {}})),
+                );
+                Value::Object(tool)
+            })
+            .collect();
+        out.insert("tools".to_string(), Value::Array(tools));
+    }
+
+    match obj.get("tool_choice") {
+        Some(Value::String(choice)) => {
+            let mapped = match choice.as_str() {
+                "required" => json!({"type": "any"}),
+                "none" => json!({"type": "none"}),
+                _ => json!({"type": "auto"}),
+            };
+            out.insert("tool_choice".to_string(), mapped);
+        }
+        Some(Value::Object(choice)) => {
+            if let Some(name) = choice.get("function").and_then(|f| f.get("name")) {
+                out.insert("tool_choice".to_string(), json!({"type": "tool", "name": name}));
+            }
+        }
+        _ => {}
+    }
+
+    Ok(Value::Object(out))
+}
+
+pub fn anthropic_to_openai_request(body: &Value) -> Result<Value> {
+    let obj = body
+        .as_object()
+        .ok_or_else(|| ProxyError::InvalidRequest("Request body must be an object".to_string()))?;
+
+    let mut out = Map::new();
+    copy_field(obj, &mut out, "model", "model");
+    copy_field(obj, &mut out, "max_tokens", "max_tokens");
+    copy_field(obj, &mut out, "temperature", "temperature");
+    copy_field(obj, &mut out, "top_p", "top_p");
+    copy_field(obj, &mut out, "stop_sequences", "stop");
+    copy_field(obj, &mut out, "stream", "stream");
+
+    if let Some(user) = obj.get("metadata").and_then(|m| m.get("user_id")) {
+        out.insert("user".to_string(), user.clone());
+    }
+
+    let mut messages = Vec::new();
+    if let Some(system) = obj.get("system") {
+        let text = text_of_anthropic_content(Some(system));
+        if !text.is_empty() {
+            messages.push(json!({"role": "system", "content": text}));
+        }
+    }
+
+    for message in obj.get("messages").and_then(Value::as_array).into_iter().flatten() {
+        let role = message.get("role").and_then(Value::as_str).unwrap_or("user");
+        let content = message.get("content");
+
+        let blocks = match content {
+            Some(Value::String(text)) => {
+                messages.push(json!({"role": role, "content": text}));
+                continue;
+            }
+            Some(Value::Array(blocks)) => blocks.as_slice(),
+            _ => &[],
+        };
+
+        if role == "assistant" {
+            let text: String = blocks
+                .iter()
+                .filter(|b| b.get("type").and_then(Value::as_str) == Some("text"))
+                .filter_map(|b| b.get("text").and_then(Value::as_str))
+                .collect();
+            let tool_calls: Vec<Value> = blocks
+                .iter()
+                .filter(|b| b.get("type").and_then(Value::as_str) == Some("tool_use"))
+                .map(|b| {
+                    json!({
+                        "id": b.get("id").cloned().unwrap_or(Value::Null),
+                        "type": "function",
+                        "function": {
+                            "name": b.get("name").cloned().unwrap_or(Value::Null),
+                            "arguments": b.get("input").map(Value::to_string).unwrap_or_else(|| "{}".to_string()),
+                        }
+                    })
+                })
+                .collect();
+
+            let mut assistant = json!({"role": "assistant"});
+            assistant["content"] = if text.is_empty() { Value::Null } else { Value::String(text) };
+            if !tool_calls.is_empty() {
+                assistant["tool_calls"] = Value::Array(tool_calls);
+            }
+            messages.push(assistant);
+            continue;
+        }
+
+        // User turns: tool results become standalone tool messages, everything else content parts
+        let mut parts = Vec::new();
+        for block in blocks {
+            match block.get("type").and_then(Value::as_str) {
+                Some("text") => parts.push(json!({
+                    "type": "text",
+                    "text": block.get("text").cloned().unwrap_or(Value::Null),
+                })),
+                Some("image") => {
+                    if let Some(url) = anthropic_image_to_url(block.get("source")) {
+                        parts.push(json!({"type": "image_url", "image_url": {"url": url}}));
+                    }
+                }
+                Some("tool_result") => messages.push(json!({
+                    "role": "tool",
+                    "tool_call_id": block.get("tool_use_id").cloned().unwrap_or(Value::Null),
+                    "content": text_of_anthropic_content(block.get("content")),
+                })),
+                _ => {}
+            }
+        }
+        if !parts.is_empty() {
+            messages.push(json!({"role": role, "content": parts}));
+        }
+    }
+    out.insert("messages".to_string(), Value::Array(messages));
+
+    if let Some(tools) = obj.get("tools").and_then(Value::as_array) {
+        let tools: Vec<Value> = tools
+            .iter()
+            .filter_map(Value::as_object)
+            .map(|tool| {
+                let mut function = Map::new();
+                copy_field(tool, &mut function, "name", "name");
+                copy_field(tool, &mut function, "description", "description");
+                copy_field(tool, &mut function, "input_schema", "parameters");
+                json!({"type": "function", "function": function})
+            })
+            .collect();
+        out.insert("tools".to_string(), Value::Array(tools));
+    }
+
+    if let Some(choice) = obj.get("tool_choice") {
+        let mapped = match choice.get("type").and_then(Value::as_str) {
+            Some("any") => json!("required"),
+            Some("none") => json!("none"),
+            Some("tool") => json!({
+                "type": "function",
+                "function": {"name": choice.get("name").cloned().unwrap_or(Value::Null)}
+            }),
+            _ => json!("auto"),
+        };
+        out.insert("tool_choice".to_string(), mapped);
+    }
+
+    Ok(Value::Object(out))
+}
+
+pub fn anthropic_to_openai_response(body: &Value) -> Value {
+    let blocks = body.get("content").and_then(Value::as_array).cloned().unwrap_or_default();
+
+    let text: String = blocks
+        .iter()
+        .filter(|b| b.get("type").and_then(Value::as_str) == Some("text"))
+        .filter_map(|b| b.get("text").and_then(Value::as_str))
+        .collect();
+    let tool_calls: Vec<Value> = blocks
+        .iter()
+        .filter(|b| b.get("type").and_then(Value::as_str) == Some("tool_use"))
+        .map(|b| {
+            json!({
+                "id": b.get("id").cloned().unwrap_or(Value::Null),
+                "type": "function",
+                "function": {
+                    "name": b.get("name").cloned().unwrap_or(Value::Null),
+                    "arguments": b.get("input").map(Value::to_string).unwrap_or_else(|| "{}".to_string()),
+                }
+            })
+        })
+        .collect();
+
+    let mut message = json!({
+        "role": "assistant",
+        "content": if text.is_empty() && !tool_calls.is_empty() { Value::Null } else { Value::String(text) },
+    });
+    if !tool_calls.is_empty() {
+        message["tool_calls"] = Value::Array(tool_calls);
+    }
+
+    let input_tokens = body.pointer("/usage/input_tokens").and_then(Value::as_u64).unwrap_or(0);
+    let output_tokens = body.pointer("/usage/output_tokens").and_then(Value::as_u64).unwrap_or(0);
+
+    json!({
+        "id": body.get("id").cloned().unwrap_or(Value::Null),
+        "object": "chat.completion",
+        "created": chrono::Utc::now().timestamp(),
+        "model": body.get("model").cloned().unwrap_or(Value::Null),
+        "choices": [{
+            "index": 0,
+            "message": message,
+            "finish_reason": body
+                .get("stop_reason")
+                .and_then(Value::as_str)
+                .map(anthropic_stop_reason_to_openai),
+        }],
+        "usage": {
+            "prompt_tokens": input_tokens,
+            "completion_tokens": output_tokens,
+            "total_tokens": input_tokens + output_tokens,
+        }
+    })
+}
+
+pub fn openai_to_anthropic_response(body: &Value) -> Value {
+    let message = body.pointer("/choices/0/message").cloned().unwrap_or(Value::Null);
+
+    let mut content = Vec::new();
+    let text = text_of_openai_content(message.get("content"));
+    if !text.is_empty() {
+        content.push(json!({"type": "text", "text": text}));
+    }
+    for call in message.get("tool_calls").and_then(Value::as_array).into_iter().flatten() {
+        let arguments = call.pointer("/function/arguments").and_then(Value::as_str).unwrap_or("{}");
+        content.push(json!({
+            "type": "tool_use",
+            "id": call.get("id").cloned().unwrap_or(Value::Null),
+            "name": call.pointer("/function/name").cloned().unwrap_or(Value::Null),
+            "input": serde_json::from_str::<Value>(arguments).unwrap_or_else(|_| json!({})),
+        }));
+    }
+
+    json!({
+        "id": body.get("id").cloned().unwrap_or(Value::Null),
+        "type": "message",
+        "role": "assistant",
+        "model": body.get("model").cloned().unwrap_or(Value::Null),
+        "content": content,
+        "stop_reason": body
+            .pointer("/choices/0/finish_reason")
+            .and_then(Value::as_str)
+            .map(openai_finish_reason_to_anthropic),
+        "stop_sequence": Value::Null,
+        "usage": {
+            "input_tokens": body.pointer("/usage/prompt_tokens").and_then(Value::as_u64).unwrap_or(0),
+            "output_tokens": body.pointer("/usage/completion_tokens").and_then(Value::as_u64).unwrap_or(0),
+        }
+    })
+}
+
+pub fn anthropic_stop_reason_to_openai(reason: &str) -> &'static str {
+    match reason {
+        "max_tokens" => "length",
+        "tool_use" => "tool_calls",
+        _ => "stop",
+    }
+}
+
+pub fn openai_finish_reason_to_anthropic(reason: &str) -> &'static str {
+    match reason {
+        "length" => "max_tokens",
+        "tool_calls" | "function_call" => "tool_use",
+        _ => "end_turn",
+    }
+}
+
+fn copy_field(from: &Map<String, Value>, to: &mut Map<String, Value>, src: &str, dst: &str) {
+    if let Some(value) = from.get(src) {
+        if !value.is_null() {
+            to.insert(dst.to_string(), value.clone());
+        }
+    }
+}
+
+/// Append content blocks, merging into the previous message when roles repeat
+/// (Anthropic requires alternating user/assistant turns)
+fn push_anthropic_message(messages: &mut Vec<Value>, role: &str, blocks: Vec<Value>) {
+    if blocks.is_empty() {
+        return;
+    }
+    if let Some(last) = messages.last_mut() {
+        if last.get("role").and_then(Value::as_str) == Some(role) {
+            if let Some(Value::Array(existing)) = last.get_mut("content") {
+                existing.extend(blocks);
+                return;
+            }
+        }
+    }
+    messages.push(json!({"role": role, "content": blocks}));
+}
+
+fn openai_content_to_blocks(content: Option<&Value>) -> Vec<Value> {
+    match content {
+        Some(Value::String(text)) if !text.is_empty() => vec![json!({"type": "text", "text": text})],
+        Some(Value::Array(parts)) => parts
+            .iter()
+            .filter_map(|part| match part.get("type").and_then(Value::as_str) {
+                Some("text") => Some(json!({
+                    "type": "text",
+                    "text": part.get("text").cloned().unwrap_or(Value::Null),
+                })),
+                Some("image_url") => part
+                    .pointer("/image_url/url")
+                    .and_then(Value::as_str)
+                    .map(openai_image_url_to_source)
+                    .map(|source| json!({"type": "image", "source": source})),
+                _ => None,
+            })
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn openai_image_url_to_source(url: &str) -> Value {
+    // data:<media_type>;base64,<data>
+    if let Some(rest) = url.strip_prefix("data:") {
+        if let Some((media_type, data)) = rest.split_once(";base64,") {
+            return json!({"type": "base64", "media_type": media_type, "data": data});
+        }
+    }
+    json!({"type": "url", "url": url})
+}
+
+fn anthropic_image_to_url(source: Option<&Value>) -> Option<String> {
+    let source = source?;
+    match source.get("type").and_then(Value::as_str) {
+        Some("base64") => Some(format!(
+            "data:{};base64,{}",
+            source.get("media_type").and_then(Value::as_str).unwrap_or("image/png"),
+            source.get("data").and_then(Value::as_str).unwrap_or_default()
+        )),
+        Some("url") => source.get("url").and_then(Value::as_str).map(str::to_string),
+        _ => None,
+    }
+}
+
+fn text_of_openai_content(content: Option<&Value>) -> String {
+    match content {
+        Some(Value::String(text)) => text.clone(),
+        Some(Value::Array(parts)) => parts
+            .iter()
+            .filter_map(|p| p.get("text").and_then(Value::as_str))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+fn text_of_anthropic_content(content: Option<&Value>) -> String {
+    match content {
+        Some(Value::String(text)) => text.clone(),
+        Some(Value::Array(blocks)) => blocks
+            .iter()
+            .filter_map(|b| b.get("text").and_then(Value::as_str))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_openai_to_anthropic_request() {
+        let input = json!({
+            "model": "gpt-4",
+            "messages": [
+                {"role": "system", "content": "Be brief."},
+                {"role": "user", "content": "Hello"},
+                {"role": "assistant", "content": null, "tool_calls": [{
+                    "id": "call_1",
+                    "type": "function",
+                    "function": {"name": "lookup", "arguments": "{\"q\":\"x\"}"}
+                }]},
+                {"role": "tool", "tool_call_id": "call_1", "content": "42"}
+            ],
+            "stop": "END",
+            "user": "alice"
+        });
+
+        let output = openai_to_anthropic_request(&input).unwrap();
+
+        assert_eq!(output["system"], "Be brief.");
+        assert_eq!(output["max_tokens"], DEFAULT_ANTHROPIC_MAX_TOKENS);
+        assert_eq!(output["stop_sequences"], json!(["END"]));
+        assert_eq!(output["metadata"]["user_id"], "alice");
+
+        let messages = output["messages"].as_array().unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0]["content"][0]["text"], "Hello");
+        assert_eq!(messages[1]["content"][0]["type"], "tool_use");
+        assert_eq!(messages[1]["content"][0]["input"]["q"], "x");
+        assert_eq!(messages[2]["role"], "user");
+        assert_eq!(messages[2]["content"][0]["tool_use_id"], "call_1");
+    }
+
+    #[test]
+    fn test_openai_image_to_anthropic() {
+        let input = json!({
+            "model": "gpt-4",
+            "messages": [{"role": "user", "content": [
+                {"type": "text", "text": "What is this?"},
+                {"type": "image_url", "image_url": {"url": "data:image/png;base64,AAAA"}}
+            ]}]
+        });
+
+        let output = openai_to_anthropic_request(&input).unwrap();
+        let source = &output["messages"][0]["content"][1]["source"];
+        assert_eq!(source["type"], "base64");
+        assert_eq!(source["media_type"], "image/png");
+        assert_eq!(source["data"], "AAAA");
+    }
+
+    #[test]
+    fn test_anthropic_to_openai_request() {
+        let input = json!({
+            "model": "claude-3",
+            "max_tokens": 100,
+            "system": "Be brief.",
+            "messages": [
+                {"role": "user", "content": "Hello"},
+                {"role": "assistant", "content": [
+                    {"type": "tool_use", "id": "tu_1", "name": "lookup", "input": {"q": "x"}}
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "tu_1", "content": "42"}
+                ]}
+            ],
+            "stop_sequences": ["END"]
+        });
+
+        let output = anthropic_to_openai_request(&input).unwrap();
+        let messages = output["messages"].as_array().unwrap();
+
+        assert_eq!(messages[0], json!({"role": "system", "content": "Be brief."}));
+        assert_eq!(messages[1], json!({"role": "user", "content": "Hello"}));
+        assert_eq!(messages[2]["tool_calls"][0]["function"]["name"], "lookup");
+        assert_eq!(messages[3]["role"], "tool");
+        assert_eq!(messages[3]["tool_call_id"], "tu_1");
+        assert_eq!(output["stop"], json!(["END"]));
+        assert_eq!(output["max_tokens"], 100);
+    }
+
+    #[test]
+    fn test_anthropic_to_openai_response() {
+        let input = json!({
+            "id": "msg_1",
+            "type": "message",
+            "role": "assistant",
+            "model": "claude-3",
+            "content": [{"type": "text", "text": "Hi there"}],
+            "stop_reason": "end_turn",
+            "usage": {"input_tokens": 10, "output_tokens": 3}
+        });
+
+        let output = anthropic_to_openai_response(&input);
+        assert_eq!(output["object"], "chat.completion");
+        assert_eq!(output["choices"][0]["message"]["content"], "Hi there");
+        assert_eq!(output["choices"][0]["finish_reason"], "stop");
+        assert_eq!(output["usage"]["total_tokens"], 13);
+    }
+
+    #[test]
+    fn test_openai_to_anthropic_response() {
+        let input = json!({
+            "id": "chatcmpl-1",
+            "object": "chat.completion",
+            "model": "gpt-4",
+            "choices": [{
+                "index": 0,
+                "message": {"role": "assistant", "content": "Hi there"},
+                "finish_reason": "length"
+            }],
+            "usage": {"prompt_tokens": 10, "completion_tokens": 3, "total_tokens": 13}
+        });
+
+        let output = openai_to_anthropic_response(&input);
+        assert_eq!(output["type"], "message");
+        assert_eq!(output["content"][0]["text"], "Hi there");
+        assert_eq!(output["stop_reason"], "max_tokens");
+        assert_eq!(output["usage"]["input_tokens"], 10);
+    }
+
+    #[test]
+    fn test_same_protocol_is_passthrough() {
+        let input = json!({"model": "gpt-4", "messages": []});
+        let output = translate_request(input.clone(), Protocol::OpenAI, Protocol::OpenAI).unwrap();
+        assert_eq!(output, input);
+    }
+}
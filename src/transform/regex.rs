@@ -14,7 +14,7 @@ impl RegexTransformer {
         for transform in transforms {
             if let Transform::Regex { pattern, replacement } = transform {
                 let regex = Regex::new(pattern)
-                    .map_err(|e| ProxyError::Regex(e))?;
+                    .map_err(ProxyError::Regex)?;
                 patterns.push((regex, replacement.clone()));
             }
         }
@@ -37,6 +37,7 @@ impl RegexTransformer {
     }
 }
 
+#[derive(Default)]
 pub struct RegexTransformCache {
     request_transformers: HashMap<String, RegexTransformer>,
     response_transformers: HashMap<String, RegexTransformer>,
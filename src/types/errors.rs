@@ -3,9 +3,54 @@ use axum::{
     response::{IntoResponse, Response},
     Json,
 };
-use serde_json::json;
+use chrono::{SecondsFormat, Utc};
+use http::{HeaderMap, HeaderName, HeaderValue};
+use serde_json::{json, Value};
+use std::time::Duration;
 use thiserror::Error;
 
+use super::Protocol;
+
+/// Which timeout ended a request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeoutPhase {
+    Connect,
+    FirstByte,
+    IdleStream,
+    Total,
+    Deadline,
+}
+
+impl std::fmt::Display for TimeoutPhase {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            TimeoutPhase::Connect => "connect timeout",
+            TimeoutPhase::FirstByte => "first byte timeout",
+            TimeoutPhase::IdleStream => "idle stream timeout",
+            TimeoutPhase::Total => "total timeout",
+            TimeoutPhase::Deadline => "request deadline",
+        })
+    }
+}
+
+/// What a rate limit counts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateLimitKind {
+    Requests,
+    InputTokens,
+    OutputTokens,
+}
+
+/// One rate limit at the time a request was rejected, reported in rate-limit headers
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimitState {
+    pub kind: RateLimitKind,
+    pub limit: u64,
+    pub remaining: u64,
+    /// Until the limit is fully replenished
+    pub reset: Duration,
+}
+
 #[derive(Debug, Error)]
 pub enum ProxyError {
     #[error("Configuration error: {0}")]
@@ -17,14 +62,47 @@ pub enum ProxyError {
     #[error("Backend error: {0}")]
     Backend(String),
 
+    #[error("Stream failed before first chunk: {0}")]
+    StreamStart(String),
+
     #[error("Upstream error: {status} - {message}")]
-    Upstream { status: u16, message: String },
+    Upstream {
+        status: u16,
+        message: String,
+        /// Delay the server advised before retrying (Retry-After or rate-limit reset headers)
+        retry_after: Option<Duration>,
+    },
 
     #[error("Transformation error: {0}")]
     Transform(String),
 
-    #[error("Request timeout")]
-    Timeout,
+    #[error("Request timed out: {0} expired")]
+    Timeout(TimeoutPhase),
+
+    #[error("Circuit open: {0}")]
+    CircuitOpen(String),
+
+    #[error("No healthy endpoint: {0}")]
+    NoHealthyEndpoint(String),
+
+    #[error("Queue full for {endpoint}")]
+    QueueFull { endpoint: String, retry_after: Duration },
+
+    #[error("Timed out waiting in queue for {endpoint}")]
+    QueueTimeout { endpoint: String, retry_after: Duration },
+
+    #[error("Rate limit exceeded for {scope}")]
+    RateLimited {
+        scope: String,
+        retry_after: Duration,
+        limits: Vec<RateLimitState>,
+    },
+
+    #[error("Budget exceeded for {scope}")]
+    BudgetExceeded { scope: String, retry_after: Duration },
+
+    #[error("Server is shutting down")]
+    ShuttingDown,
 
     #[error("Max retries exceeded after {0} attempts")]
     MaxRetriesExceeded(usize),
@@ -32,6 +110,12 @@ pub enum ProxyError {
     #[error("Invalid request: {0}")]
     InvalidRequest(String),
 
+    #[error("Unauthorized: {0}")]
+    Unauthorized(String),
+
+    #[error("Forbidden: {0}")]
+    Forbidden(String),
+
     #[error("HTTP error: {0}")]
     Http(#[from] reqwest::Error),
 
@@ -62,14 +146,23 @@ impl ProxyError {
         match self {
             ProxyError::Config(_) => StatusCode::INTERNAL_SERVER_ERROR,
             ProxyError::ModelNotFound(_) => StatusCode::NOT_FOUND,
-            ProxyError::Backend(_) => StatusCode::BAD_GATEWAY,
+            ProxyError::Backend(_) | ProxyError::StreamStart(_) => StatusCode::BAD_GATEWAY,
             ProxyError::Upstream { status, .. } => {
                 StatusCode::from_u16(*status).unwrap_or(StatusCode::BAD_GATEWAY)
             }
             ProxyError::Transform(_) => StatusCode::INTERNAL_SERVER_ERROR,
-            ProxyError::Timeout => StatusCode::GATEWAY_TIMEOUT,
+            ProxyError::Timeout(_) => StatusCode::GATEWAY_TIMEOUT,
+            ProxyError::CircuitOpen(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ProxyError::NoHealthyEndpoint(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ProxyError::QueueFull { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ProxyError::QueueTimeout { .. } => StatusCode::SERVICE_UNAVAILABLE,
+            ProxyError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ProxyError::BudgetExceeded { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ProxyError::ShuttingDown => StatusCode::SERVICE_UNAVAILABLE,
             ProxyError::MaxRetriesExceeded(_) => StatusCode::BAD_GATEWAY,
             ProxyError::InvalidRequest(_) => StatusCode::BAD_REQUEST,
+            ProxyError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ProxyError::Forbidden(_) => StatusCode::FORBIDDEN,
             ProxyError::Http(_) => StatusCode::BAD_GATEWAY,
             ProxyError::Json(_) => StatusCode::BAD_REQUEST,
             ProxyError::Yaml(_) => StatusCode::INTERNAL_SERVER_ERROR,
@@ -85,12 +178,21 @@ impl ProxyError {
         match self {
             ProxyError::Config(_) => "configuration_error",
             ProxyError::ModelNotFound(_) => "model_not_found",
-            ProxyError::Backend(_) => "backend_error",
+            ProxyError::Backend(_) | ProxyError::StreamStart(_) => "backend_error",
             ProxyError::Upstream { .. } => "upstream_error",
             ProxyError::Transform(_) => "transformation_error",
-            ProxyError::Timeout => "timeout",
+            ProxyError::Timeout(_) => "timeout",
+            ProxyError::CircuitOpen(_) => "circuit_open",
+            ProxyError::NoHealthyEndpoint(_) => "no_healthy_endpoint",
+            ProxyError::QueueFull { .. } => "queue_full",
+            ProxyError::QueueTimeout { .. } => "queue_timeout",
+            ProxyError::RateLimited { .. } => "rate_limit_exceeded",
+            ProxyError::BudgetExceeded { .. } => "budget_exceeded",
+            ProxyError::ShuttingDown => "shutting_down",
             ProxyError::MaxRetriesExceeded(_) => "max_retries_exceeded",
             ProxyError::InvalidRequest(_) => "invalid_request",
+            ProxyError::Unauthorized(_) => "authentication_error",
+            ProxyError::Forbidden(_) => "permission_error",
             ProxyError::Http(_) => "http_error",
             ProxyError::Json(_) => "json_error",
             ProxyError::Yaml(_) => "yaml_error",
@@ -101,32 +203,265 @@ impl ProxyError {
             ProxyError::Internal(_) => "internal_error",
         }
     }
+
+    /// Anthropic `error.type` value for this error, derived from the HTTP status
+    pub fn anthropic_error_type(&self) -> &'static str {
+        match self.status_code().as_u16() {
+            400 | 413 | 422 => "invalid_request_error",
+            401 => "authentication_error",
+            403 => "permission_error",
+            404 => "not_found_error",
+            429 => "rate_limit_error",
+            529 => "overloaded_error",
+            _ => "api_error",
+        }
+    }
+
+    /// How long the client should wait before retrying, sent as `Retry-After`
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            ProxyError::QueueFull { retry_after, .. }
+            | ProxyError::QueueTimeout { retry_after, .. }
+            | ProxyError::RateLimited { retry_after, .. }
+            | ProxyError::BudgetExceeded { retry_after, .. } => Some(*retry_after),
+            ProxyError::Upstream { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// Rate-limit headers in the style of the client protocol's provider, so SDKs back
+    /// off as they would against the provider itself: `x-ratelimit-*` for OpenAI (whose
+    /// token limit is the tighter of the input and output limits) and
+    /// `anthropic-ratelimit-*` for Anthropic
+    pub fn rate_limit_headers(&self, protocol: Protocol) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        let ProxyError::RateLimited { limits, .. } = self else {
+            return headers;
+        };
+        let mut insert = |name: String, value: String| {
+            if let (Ok(name), Ok(value)) = (HeaderName::try_from(name), HeaderValue::try_from(value)) {
+                headers.insert(name, value);
+            }
+        };
+
+        match protocol {
+            Protocol::OpenAI => {
+                let requests = limits.iter().find(|l| l.kind == RateLimitKind::Requests);
+                let tokens = limits
+                    .iter()
+                    .filter(|l| l.kind != RateLimitKind::Requests)
+                    .min_by_key(|l| l.remaining);
+                for (suffix, limit) in [("requests", requests), ("tokens", tokens)] {
+                    if let Some(limit) = limit {
+                        insert(format!("x-ratelimit-limit-{}", suffix), limit.limit.to_string());
+                        insert(format!("x-ratelimit-remaining-{}", suffix), limit.remaining.to_string());
+                        insert(
+                            format!("x-ratelimit-reset-{}", suffix),
+                            format!("{}ms", limit.reset.as_millis()),
+                        );
+                    }
+                }
+            }
+            Protocol::Anthropic => {
+                for limit in limits {
+                    let prefix = match limit.kind {
+                        RateLimitKind::Requests => "anthropic-ratelimit-requests",
+                        RateLimitKind::InputTokens => "anthropic-ratelimit-input-tokens",
+                        RateLimitKind::OutputTokens => "anthropic-ratelimit-output-tokens",
+                    };
+                    let reset = Utc::now() + chrono::Duration::from_std(limit.reset).unwrap_or_default();
+                    insert(format!("{}-limit", prefix), limit.limit.to_string());
+                    insert(format!("{}-remaining", prefix), limit.remaining.to_string());
+                    insert(
+                        format!("{}-reset", prefix),
+                        reset.to_rfc3339_opts(SecondsFormat::Secs, true),
+                    );
+                }
+            }
+        }
+        headers
+    }
+
+    /// Error body shaped like the client protocol's own errors, for responses and
+    /// stream error events alike
+    pub fn protocol_body(&self, protocol: Protocol) -> Value {
+        match protocol {
+            Protocol::OpenAI => json!({
+                "error": {
+                    "type": self.error_type(),
+                    "message": self.to_string(),
+                    "code": self.status_code().as_u16(),
+                }
+            }),
+            Protocol::Anthropic => json!({
+                "type": "error",
+                "error": {
+                    "type": self.anthropic_error_type(),
+                    "message": self.to_string(),
+                }
+            }),
+        }
+    }
+
+    /// Convert the error into a response shaped like the client protocol's own errors
+    pub fn into_protocol_response(self, protocol: Protocol) -> Response {
+        match protocol {
+            Protocol::OpenAI => self.into_response(),
+            Protocol::Anthropic => {
+                let status = self.status_code();
+                let retry_after = self.retry_after();
+
+                tracing::error!(
+                    error_type = self.error_type(),
+                    status = status.as_u16(),
+                    message = %self,
+                    "Request failed"
+                );
+
+                let body = Json(self.protocol_body(protocol));
+                let mut response = with_retry_after((status, body).into_response(), retry_after);
+                response.headers_mut().extend(self.rate_limit_headers(protocol));
+                response
+            }
+        }
+    }
 }
 
 // Implement IntoResponse for ProxyError to convert errors into HTTP responses
 impl IntoResponse for ProxyError {
     fn into_response(self) -> Response {
         let status = self.status_code();
-        let error_type = self.error_type();
-        let message = self.to_string();
+        let retry_after = self.retry_after();
 
         tracing::error!(
-            error_type = error_type,
+            error_type = self.error_type(),
             status = status.as_u16(),
-            message = %message,
+            message = %self,
             "Request failed"
         );
 
-        let body = Json(json!({
-            "error": {
-                "type": error_type,
-                "message": message,
-                "code": status.as_u16(),
-            }
-        }));
+        let body = Json(self.protocol_body(Protocol::OpenAI));
+        let mut response = with_retry_after((status, body).into_response(), retry_after);
+        response.headers_mut().extend(self.rate_limit_headers(Protocol::OpenAI));
+        response
+    }
+}
 
-        (status, body).into_response()
+/// Add a `Retry-After` header in whole seconds, rounded up
+pub fn with_retry_after(mut response: Response, retry_after: Option<Duration>) -> Response {
+    if let Some(retry_after) = retry_after {
+        let secs = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        response
+            .headers_mut()
+            .insert(http::header::RETRY_AFTER, http::HeaderValue::from(secs));
     }
+    response
 }
 
 pub type Result<T> = std::result::Result<T, ProxyError>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_anthropic_error_type_mapping() {
+        assert_eq!(
+            ProxyError::InvalidRequest("bad".to_string()).anthropic_error_type(),
+            "invalid_request_error"
+        );
+        assert_eq!(
+            ProxyError::ModelNotFound("x".to_string()).anthropic_error_type(),
+            "not_found_error"
+        );
+        assert_eq!(
+            ProxyError::Upstream {
+                status: 429,
+                message: "slow down".to_string(),
+                retry_after: None
+            }
+            .anthropic_error_type(),
+            "rate_limit_error"
+        );
+        assert_eq!(
+            ProxyError::Timeout(TimeoutPhase::FirstByte).anthropic_error_type(),
+            "api_error"
+        );
+        assert_eq!(
+            ProxyError::Unauthorized("Missing API key".to_string()).anthropic_error_type(),
+            "authentication_error"
+        );
+        assert_eq!(
+            ProxyError::Forbidden("no".to_string()).anthropic_error_type(),
+            "permission_error"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_anthropic_error_response_shape() {
+        let response = ProxyError::ModelNotFound("x".to_string())
+            .into_protocol_response(Protocol::Anthropic);
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(json["type"], "error");
+        assert_eq!(json["error"]["type"], "not_found_error");
+    }
+
+    #[test]
+    fn test_rate_limit_headers() {
+        let error = ProxyError::RateLimited {
+            scope: "API key 'batch'".to_string(),
+            retry_after: Duration::from_millis(1500),
+            limits: vec![
+                RateLimitState {
+                    kind: RateLimitKind::Requests,
+                    limit: 60,
+                    remaining: 12,
+                    reset: Duration::from_secs(48),
+                },
+                RateLimitState {
+                    kind: RateLimitKind::InputTokens,
+                    limit: 10000,
+                    remaining: 0,
+                    reset: Duration::from_secs(60),
+                },
+                RateLimitState {
+                    kind: RateLimitKind::OutputTokens,
+                    limit: 2000,
+                    remaining: 800,
+                    reset: Duration::from_secs(36),
+                },
+            ],
+        };
+
+        let response = error.into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let headers = response.headers();
+        assert_eq!(headers["retry-after"], "2");
+        assert_eq!(headers["x-ratelimit-limit-requests"], "60");
+        assert_eq!(headers["x-ratelimit-remaining-requests"], "12");
+        assert_eq!(headers["x-ratelimit-reset-requests"], "48000ms");
+        // The tighter token limit is reported
+        assert_eq!(headers["x-ratelimit-limit-tokens"], "10000");
+        assert_eq!(headers["x-ratelimit-remaining-tokens"], "0");
+
+        let anthropic = ProxyError::RateLimited {
+            scope: "model 'claude'".to_string(),
+            retry_after: Duration::from_secs(1),
+            limits: vec![RateLimitState {
+                kind: RateLimitKind::OutputTokens,
+                limit: 2000,
+                remaining: 0,
+                reset: Duration::from_secs(30),
+            }],
+        }
+        .rate_limit_headers(Protocol::Anthropic);
+        assert_eq!(anthropic["anthropic-ratelimit-output-tokens-limit"], "2000");
+        let reset = anthropic["anthropic-ratelimit-output-tokens-reset"].to_str().unwrap();
+        assert!(chrono::DateTime::parse_from_rfc3339(reset).is_ok());
+    }
+}
@@ -1,5 +1,9 @@
 pub mod errors;
 pub mod openai;
 pub mod anthropic;
+pub mod protocol;
+pub mod usage;
 
-pub use errors::{ProxyError, Result};
+pub use errors::{with_retry_after, ProxyError, RateLimitKind, RateLimitState, Result, TimeoutPhase};
+pub use protocol::Protocol;
+pub use usage::{response_text, TokenUsage};
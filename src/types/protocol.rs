@@ -0,0 +1,26 @@
+use serde::Serialize;
+
+/// Wire protocol spoken by a client endpoint or an upstream backend
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    OpenAI,
+    Anthropic,
+}
+
+impl Protocol {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Protocol::OpenAI => "openai",
+            Protocol::Anthropic => "anthropic",
+        }
+    }
+
+    /// Path of the proxy endpoint serving this protocol
+    pub fn path(&self) -> &'static str {
+        match self {
+            Protocol::OpenAI => "/v1/chat/completions",
+            Protocol::Anthropic => "/v1/messages",
+        }
+    }
+}
@@ -0,0 +1,124 @@
+use serde::Serialize;
+use serde_json::Value;
+
+/// Token counts reported by a backend, normalized across protocols
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct TokenUsage {
+    /// All prompt tokens, including those read from or written to a prompt cache
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    /// Prompt tokens read from the backend's prompt cache, usually billed at a discount
+    #[serde(skip_serializing_if = "is_zero")]
+    pub cached_input_tokens: u64,
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+impl TokenUsage {
+    /// Read a `usage` object in either OpenAI (`prompt_tokens`/`completion_tokens`)
+    /// or Anthropic (`input_tokens`/`output_tokens`) form
+    pub fn from_usage(usage: &Value) -> Option<Self> {
+        let field = |openai: &str, anthropic: &str| {
+            usage
+                .get(openai)
+                .or_else(|| usage.get(anthropic))
+                .and_then(Value::as_u64)
+        };
+        let input = field("prompt_tokens", "input_tokens");
+        let output = field("completion_tokens", "output_tokens");
+        if input.is_none() && output.is_none() {
+            return None;
+        }
+        // Anthropic counts cache reads and writes separately from `input_tokens`
+        let cache_read = usage.get("cache_read_input_tokens").and_then(Value::as_u64);
+        let cache_write = usage.get("cache_creation_input_tokens").and_then(Value::as_u64);
+        let cached = usage
+            .pointer("/prompt_tokens_details/cached_tokens")
+            .and_then(Value::as_u64)
+            .or(cache_read);
+        Some(Self {
+            input_tokens: input.unwrap_or(0) + cache_read.unwrap_or(0) + cache_write.unwrap_or(0),
+            output_tokens: output.unwrap_or(0),
+            cached_input_tokens: cached.unwrap_or(0),
+        })
+    }
+
+    /// Usage of a complete (non-streaming) response body in either protocol
+    pub fn from_response(body: &Value) -> Option<Self> {
+        body.get("usage").and_then(Self::from_usage)
+    }
+
+    pub fn total(&self) -> u64 {
+        self.input_tokens + self.output_tokens
+    }
+}
+
+/// Assistant text of a complete response body in either protocol
+pub fn response_text(body: &Value) -> Option<String> {
+    if let Some(content) = body.pointer("/choices/0/message/content") {
+        return content.as_str().map(str::to_string);
+    }
+    let blocks = body.get("content")?.as_array()?;
+    Some(
+        blocks
+            .iter()
+            .filter(|block| block.get("type").and_then(Value::as_str) == Some("text"))
+            .filter_map(|block| block.get("text").and_then(Value::as_str))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_usage_both_protocols() {
+        let openai = json!({"usage": {"prompt_tokens": 10, "completion_tokens": 3, "total_tokens": 13}});
+        let anthropic = json!({"usage": {"input_tokens": 10, "output_tokens": 3}});
+        let expected = TokenUsage {
+            input_tokens: 10,
+            output_tokens: 3,
+            cached_input_tokens: 0,
+        };
+        assert_eq!(TokenUsage::from_response(&openai), Some(expected));
+        assert_eq!(TokenUsage::from_response(&anthropic), Some(expected));
+        assert_eq!(TokenUsage::from_response(&json!({})), None);
+        assert_eq!(expected.total(), 13);
+    }
+
+    #[test]
+    fn test_cached_prompt_tokens() {
+        let openai = json!({"usage": {
+            "prompt_tokens": 1000, "completion_tokens": 20,
+            "prompt_tokens_details": {"cached_tokens": 800}
+        }});
+        let anthropic = json!({"usage": {
+            "input_tokens": 100, "output_tokens": 20,
+            "cache_read_input_tokens": 800, "cache_creation_input_tokens": 100
+        }});
+        let expected = TokenUsage {
+            input_tokens: 1000,
+            output_tokens: 20,
+            cached_input_tokens: 800,
+        };
+        assert_eq!(TokenUsage::from_response(&openai), Some(expected));
+        assert_eq!(TokenUsage::from_response(&anthropic), Some(expected));
+    }
+
+    #[test]
+    fn test_response_text() {
+        let openai = json!({"choices": [{"message": {"role": "assistant", "content": "Hi"}}]});
+        let anthropic = json!({"content": [
+            {"type": "text", "text": "Hi"},
+            {"type": "tool_use", "id": "t", "name": "f", "input": {}},
+            {"type": "text", "text": " there"}
+        ]});
+        assert_eq!(response_text(&openai).as_deref(), Some("Hi"));
+        assert_eq!(response_text(&anthropic).as_deref(), Some("Hi there"));
+        assert_eq!(response_text(&json!({"error": {}})), None);
+    }
+}